@@ -0,0 +1,352 @@
+use std::collections::BTreeMap;
+
+use nalgebra::{Complex, DMatrix, SymmetricEigen};
+use serde::{Deserialize, Serialize};
+
+use crate::simulator::Simulator;
+
+/// Eigenvalues below this are treated as zero when taking logarithms and
+/// square roots of density matrices.
+const EIGENVALUE_EPSILON: f64 = 1e-12;
+
+/// Most qubits a density matrix or entanglement entropy is taken over, a
+/// 1024 by 1024 matrix.
+pub const MAX_DENSITY_QUBITS: usize = 10;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalysisKind {
+    Probs,
+    Bloch,
+    Density,
+    Entropy,
+    Concurrence,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BasisProbability {
+    /// Basis state label, the first listed qubit is the leftmost character.
+    pub state: String,
+    pub probability: f64,
+}
+
+/// Structured result of a `print <analysis> ...;` statement.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum StateAnalysis {
+    Probs {
+        qubits: Vec<String>,
+        probabilities: Vec<BasisProbability>,
+    },
+    Bloch {
+        qubit: String,
+        x: f64,
+        y: f64,
+        z: f64,
+    },
+    Density {
+        qubits: Vec<String>,
+        real: Vec<Vec<f64>>,
+        imag: Vec<Vec<f64>>,
+    },
+    Entropy {
+        qubits: Vec<String>,
+        entropy: f64,
+    },
+    Concurrence {
+        qubits: Vec<String>,
+        concurrence: f64,
+    },
+}
+
+/// Marginal probabilities of the basis states of `qubits`, skipping
+/// outcomes that cannot occur.
+pub fn probabilities<S: Simulator + ?Sized>(register: &S, qubits: &[usize]) -> Vec<(usize, f64)> {
+    let mut distribution: BTreeMap<usize, f64> = BTreeMap::new();
+    for (basis_state, amplitude) in register.amplitudes() {
+        *distribution
+            .entry(sub_index(basis_state, qubits))
+            .or_default() += amplitude.norm_sqr();
+    }
+    distribution
+        .into_iter()
+        .filter(|(_, p)| *p > EIGENVALUE_EPSILON)
+        .collect()
+}
+
+/// Density matrix of `qubits` after tracing out the rest of the register.
+/// `qubits[0]` is the most significant qubit of the result.
+pub fn reduced_density_matrix<S: Simulator + ?Sized>(
+    register: &S,
+    qubits: &[usize],
+) -> DMatrix<Complex<f64>> {
+    let dim = 1 << qubits.len();
+    let mask = qubits.iter().fold(0, |mask, &q| mask | (1 << q));
+
+    // Group the amplitudes by the state of the traced-out qubits; every
+    // group contributes the outer product of its partial state vector.
+    let mut groups: BTreeMap<usize, Vec<(usize, Complex<f64>)>> = BTreeMap::new();
+    for (basis_state, amplitude) in register.amplitudes() {
+        groups
+            .entry(basis_state & !mask)
+            .or_default()
+            .push((sub_index(basis_state, qubits), amplitude));
+    }
+
+    let mut rho = DMatrix::zeros(dim, dim);
+    for partial in groups.values() {
+        for &(row, a) in partial {
+            for &(col, b) in partial {
+                rho[(row, col)] += a * b.conj();
+            }
+        }
+    }
+    rho
+}
+
+/// Bloch coordinates `(x, y, z)` of a single qubit. The vector is shorter
+/// than 1 when the qubit is entangled with the rest of the register.
+pub fn bloch_vector<S: Simulator + ?Sized>(register: &S, qubit: usize) -> (f64, f64, f64) {
+    let rho = reduced_density_matrix(register, &[qubit]);
+    (
+        2.0 * rho[(0, 1)].re,
+        2.0 * rho[(1, 0)].im,
+        rho[(0, 0)].re - rho[(1, 1)].re,
+    )
+}
+
+/// Von Neumann entropy (in bits) of `qubits` against the rest of the
+/// register. For a pure global state this is the entanglement entropy of
+/// the bipartition.
+pub fn entanglement_entropy<S: Simulator + ?Sized>(register: &S, qubits: &[usize]) -> f64 {
+    let rho = reduced_density_matrix(register, qubits);
+    von_neumann_entropy(&rho)
+}
+
+pub fn von_neumann_entropy(rho: &DMatrix<Complex<f64>>) -> f64 {
+    SymmetricEigen::new(rho.clone())
+        .eigenvalues
+        .iter()
+        .filter(|&&lambda| lambda > EIGENVALUE_EPSILON)
+        .map(|&lambda| -lambda * lambda.log2())
+        .sum::<f64>()
+        .max(0.0)
+}
+
+/// Wootters concurrence of two qubits, which may be part of a larger
+/// register (in which case their reduced state is mixed).
+pub fn concurrence<S: Simulator + ?Sized>(register: &S, qubits: [usize; 2]) -> f64 {
+    let rho = reduced_density_matrix(register, &qubits);
+    two_qubit_concurrence(&rho)
+}
+
+/// `C = max(0, l1 - l2 - l3 - l4)` where `l_i` are the decreasing square
+/// roots of the eigenvalues of `rho * (Y⊗Y) rho* (Y⊗Y)`. They are computed
+/// from the Hermitian form `sqrt(rho) rho~ sqrt(rho)`, which has the same
+/// spectrum.
+pub fn two_qubit_concurrence(rho: &DMatrix<Complex<f64>>) -> f64 {
+    let zero = Complex::new(0.0, 0.0);
+    let one = Complex::new(1.0, 0.0);
+    let y_y = DMatrix::from_row_slice(
+        4,
+        4,
+        &[
+            zero, zero, zero, -one, //
+            zero, zero, one, zero, //
+            zero, one, zero, zero, //
+            -one, zero, zero, zero,
+        ],
+    );
+    let rho_tilde = &y_y * rho.conjugate() * &y_y;
+
+    let eigen = SymmetricEigen::new(rho.clone());
+    let sqrt_eigenvalues = eigen.eigenvalues.map(|lambda| {
+        Complex::new(
+            if lambda > EIGENVALUE_EPSILON {
+                lambda.sqrt()
+            } else {
+                0.0
+            },
+            0.0,
+        )
+    });
+    let sqrt_rho = &eigen.eigenvectors
+        * DMatrix::from_diagonal(&sqrt_eigenvalues)
+        * eigen.eigenvectors.adjoint();
+
+    let hermitian = &sqrt_rho * rho_tilde * &sqrt_rho;
+    let hermitian = (&hermitian + hermitian.adjoint()) * Complex::new(0.5, 0.0);
+    let mut l: Vec<f64> = SymmetricEigen::new(hermitian)
+        .eigenvalues
+        .iter()
+        .map(|&lambda| lambda.max(0.0).sqrt())
+        .collect();
+    l.sort_by(|a, b| b.total_cmp(a));
+
+    (l[0] - l[1] - l[2] - l[3]).max(0.0)
+}
+
+/// Splits a complex matrix into rows of one component for JSON output.
+pub fn matrix_rows(
+    matrix: &DMatrix<Complex<f64>>,
+    part: fn(&Complex<f64>) -> f64,
+) -> Vec<Vec<f64>> {
+    matrix
+        .row_iter()
+        .map(|row| row.iter().map(part).collect())
+        .collect()
+}
+
+/// Label of a basis state of `qubits`, first qubit leftmost.
+pub fn basis_label(value: usize, width: usize) -> String {
+    (0..width)
+        .map(|bit| {
+            if (value >> (width - 1 - bit)) & 1 == 1 {
+                '1'
+            } else {
+                '0'
+            }
+        })
+        .collect()
+}
+
+/// Index of `basis_state` restricted to `qubits`, `qubits[0]` being the most
+/// significant bit.
+fn sub_index(basis_state: usize, qubits: &[usize]) -> usize {
+    qubits
+        .iter()
+        .fold(0, |sub, &q| (sub << 1) | ((basis_state >> q) & 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gate::{CNot, Gate, Hadamard, PauliX};
+    use crate::quantum_register::QuantumRegister;
+    use crate::sparse_register::SparseRegister;
+    use float_cmp::assert_approx_eq;
+
+    fn bell_pair<S: Simulator>(qubits: usize) -> S {
+        let mut register = S::empty();
+        for _ in 0..qubits {
+            register
+                .add_qubit(crate::qubit::Qubit::basis0().state())
+                .unwrap();
+        }
+        register.apply_gate(&Hadamard::new().matrix_representation(), &[0]);
+        register.apply_gate(&CNot::new().matrix_representation(), &[0, 1]);
+        register
+    }
+
+    #[test]
+    fn test_marginal_probabilities() {
+        let mut register: QuantumRegister = bell_pair(3);
+        register.apply_gate(&PauliX::new().matrix_representation(), &[2]);
+
+        let all = probabilities(&register, &[0, 1, 2]);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].0, 0b001);
+        assert_eq!(all[1].0, 0b111);
+        assert_approx_eq!(f64, all[0].1, 0.5, epsilon = 1e-12);
+
+        let marginal = probabilities(&register, &[2]);
+        assert_eq!(marginal.len(), 1);
+        assert_approx_eq!(f64, marginal[0].1, 1.0, epsilon = 1e-12);
+        assert_eq!(basis_label(0b001, 3), "001");
+    }
+
+    #[test]
+    fn test_bloch_vectors() {
+        let mut register = QuantumRegister::<f64>::new(2);
+        register.apply_gate(&Hadamard::new().matrix_representation(), &[0]);
+        register.apply_gate(&PauliX::new().matrix_representation(), &[1]);
+
+        let (x, y, z) = bloch_vector(&register, 0);
+        assert_approx_eq!(f64, x, 1.0, epsilon = 1e-12);
+        assert_approx_eq!(f64, y, 0.0, epsilon = 1e-12);
+        assert_approx_eq!(f64, z, 0.0, epsilon = 1e-12);
+        assert_approx_eq!(f64, bloch_vector(&register, 1).2, -1.0, epsilon = 1e-12);
+
+        let entangled: QuantumRegister = bell_pair(2);
+        let (x, y, z) = bloch_vector(&entangled, 0);
+        assert_approx_eq!(f64, (x * x + y * y + z * z).sqrt(), 0.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_reduced_density_matrix_of_bell_pair() {
+        let register: SparseRegister = bell_pair(3);
+
+        let rho = reduced_density_matrix(&register, &[0]);
+        assert_approx_eq!(f64, rho[(0, 0)].re, 0.5, epsilon = 1e-12);
+        assert_approx_eq!(f64, rho[(0, 1)].norm(), 0.0, epsilon = 1e-12);
+
+        let rho = reduced_density_matrix(&register, &[0, 1]);
+        assert_approx_eq!(f64, rho[(0, 3)].re, 0.5, epsilon = 1e-12);
+        assert_approx_eq!(f64, rho.trace().re, 1.0, epsilon = 1e-12);
+    }
+
+    #[test]
+    fn test_entanglement_entropy() {
+        let register: QuantumRegister = bell_pair(3);
+        assert_approx_eq!(
+            f64,
+            entanglement_entropy(&register, &[0]),
+            1.0,
+            epsilon = 1e-9
+        );
+        assert_approx_eq!(
+            f64,
+            entanglement_entropy(&register, &[0, 1]),
+            0.0,
+            epsilon = 1e-9
+        );
+        assert_approx_eq!(
+            f64,
+            entanglement_entropy(&register, &[2]),
+            0.0,
+            epsilon = 1e-9
+        );
+    }
+
+    #[test]
+    fn test_concurrence() {
+        let bell: QuantumRegister = bell_pair(2);
+        assert_approx_eq!(f64, concurrence(&bell, [0, 1]), 1.0, epsilon = 1e-6);
+
+        let mut product = QuantumRegister::<f64>::new(2);
+        product.apply_gate(&Hadamard::new().matrix_representation(), &[0]);
+        assert_approx_eq!(f64, concurrence(&product, [0, 1]), 0.0, epsilon = 1e-6);
+
+        // Qubits 0 and 1 of a GHZ state are classically correlated only.
+        let mut ghz: QuantumRegister = bell_pair(3);
+        ghz.apply_gate(&CNot::new().matrix_representation(), &[1, 2]);
+        assert_approx_eq!(f64, concurrence(&ghz, [0, 1]), 0.0, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn test_partial_entanglement_concurrence() {
+        // cos(t)|00> + sin(t)|11> has concurrence sin(2t).
+        let theta: f64 = 0.3;
+        let mut register = QuantumRegister::<f64>::new(2);
+        let (s, c) = theta.sin_cos();
+        let rotation = DMatrix::from_row_slice(
+            2,
+            2,
+            &[
+                Complex::new(c, 0.0),
+                Complex::new(-s, 0.0),
+                Complex::new(s, 0.0),
+                Complex::new(c, 0.0),
+            ],
+        );
+        register.apply_gate(&rotation, &[0]);
+        register.apply_gate(&CNot::new().matrix_representation(), &[0, 1]);
+
+        assert_approx_eq!(
+            f64,
+            concurrence(&register, [0, 1]),
+            (2.0 * theta).sin(),
+            epsilon = 1e-6
+        );
+    }
+}
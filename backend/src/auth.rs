@@ -0,0 +1,334 @@
+//! Password hashing and signed session tokens.
+//!
+//! A token is `payload.signature`, both base64url: the payload is the JSON
+//! of [`Claims`] and the signature its HMAC-SHA256 under the server's key.
+//! Tokens are not stored, so they stay valid until they expire, the key
+//! changes or their account is deleted.
+
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{
+        header::{AUTHORIZATION, COOKIE},
+        request::Parts,
+        HeaderMap, StatusCode,
+    },
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::handler::AppState;
+
+pub const SESSION_COOKIE: &str = "session";
+pub const DEFAULT_TOKEN_LIFETIME: u64 = 7 * 24 * 60 * 60;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Claims {
+    /// The user name.
+    pub sub: String,
+    /// The account's token nonce, which a new account of the same name does
+    /// not share.
+    pub nonce: String,
+    /// Expiry, in seconds since the Unix epoch.
+    pub exp: u64,
+}
+
+/// Issues and checks session tokens.
+#[derive(Clone)]
+pub struct Auth {
+    key: Arc<[u8]>,
+    lifetime: u64,
+}
+
+impl std::fmt::Debug for Auth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Auth")
+            .field("lifetime", &self.lifetime)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Auth {
+    pub fn new(key: &[u8], lifetime: u64) -> Auth {
+        Auth {
+            key: key.into(),
+            lifetime,
+        }
+    }
+
+    /// Signs with a random key, so tokens do not outlive the process.
+    pub fn random(lifetime: u64) -> Auth {
+        Auth::new(&rand::thread_rng().gen::<[u8; 32]>(), lifetime)
+    }
+
+    /// Seconds a token stays valid.
+    pub fn lifetime(&self) -> u64 {
+        self.lifetime
+    }
+
+    pub fn issue_token(&self, user_name: &str, nonce: &str) -> String {
+        let claims = Claims {
+            sub: user_name.to_string(),
+            nonce: nonce.to_string(),
+            exp: now() + self.lifetime,
+        };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Returns the claims of a well-signed token that has not expired.
+    pub fn verify_token(&self, token: &str) -> Option<Claims> {
+        let (payload, signature) = token.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.mac(payload).verify_slice(&signature).ok()?;
+        let claims: Claims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+        (claims.exp > now()).then_some(claims)
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+/// Hashes a password with Argon2id and a random salt, as a PHC string.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!(
+            "Passwords need at least {} characters",
+            MIN_PASSWORD_LENGTH
+        ));
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|error| error.to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// The session token of a request, from an `Authorization: Bearer` header or
+/// else the session cookie.
+pub fn request_token(headers: &HeaderMap) -> Option<&str> {
+    if let Some(value) = headers.get(AUTHORIZATION) {
+        return value.to_str().ok()?.strip_prefix("Bearer ");
+    }
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|cookie| {
+            let (name, value) = cookie.trim().split_once('=')?;
+            (name == SESSION_COOKIE).then_some(value)
+        })
+}
+
+/// The signed-in user, put into the request extensions by [`resolve_user`].
+/// As an extractor it rejects anonymous requests with 401.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CurrentUser(pub String);
+
+/// Resolves the user of a request's token. Requests without a valid token,
+/// such as one whose cookie outlived it or whose account was deleted, go
+/// through anonymously.
+pub async fn resolve_user(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if let Some(claims) =
+        request_token(request.headers()).and_then(|token| state.auth.verify_token(token))
+    {
+        if state
+            .store
+            .token_nonce(&claims.sub)
+            .await
+            .is_ok_and(|nonce| nonce == claims.nonce)
+        {
+            request.extensions_mut().insert(CurrentUser(claims.sub));
+        }
+    }
+    next.run(request).await
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CurrentUser {
+    type Rejection = (StatusCode, Json<serde_json::Value>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<CurrentUser>()
+            .cloned()
+            .ok_or_else(|| unauthorized("Sign in to use this endpoint"))
+    }
+}
+
+pub fn unauthorized(message: &str) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(serde_json::json!({ "error": message })),
+    )
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{HeaderValue, Method};
+    use serde_json::{json, Value};
+
+    use crate::{
+        handler::testing::{send, state_with},
+        limits::Limits,
+    };
+
+    #[test]
+    fn test_tokens() {
+        let auth = Auth::new(b"secret", 60);
+        let token = auth.issue_token("alice", "nonce");
+        let claims = auth.verify_token(&token).unwrap();
+        assert_eq!(
+            (claims.sub.as_str(), claims.nonce.as_str()),
+            ("alice", "nonce")
+        );
+
+        assert!(Auth::new(b"other", 60).verify_token(&token).is_none());
+        let (payload, signature) = token.split_once('.').unwrap();
+        let forged = URL_SAFE_NO_PAD.encode(br#"{"sub":"bob","nonce":"nonce","exp":99999999999}"#);
+        assert!(auth
+            .verify_token(&format!("{}.{}", forged, signature))
+            .is_none());
+        assert!(auth.verify_token(payload).is_none());
+        assert!(Auth::new(b"secret", 0)
+            .verify_token(&Auth::new(b"secret", 0).issue_token("alice", "nonce"))
+            .is_none());
+    }
+
+    async fn me(state: &AppState, token: &str) -> StatusCode {
+        let bearer = format!("Bearer {}", token);
+        let (status, _, _) = send(
+            state,
+            Method::GET,
+            "/api/auth/me",
+            &[(AUTHORIZATION.as_str(), &bearer)],
+            Value::Null,
+        )
+        .await;
+        status
+    }
+
+    async fn register(state: &AppState) -> String {
+        let (_, _, body) = send(
+            state,
+            Method::POST,
+            "/api/auth/register",
+            &[],
+            json!({"userName": "alice", "password": "correct horse"}),
+        )
+        .await;
+        body["token"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_invalid_tokens_are_anonymous() {
+        let mut state = state_with(Limits::default()).await;
+        let token = register(&state).await;
+        assert_eq!(me(&state, &token).await, StatusCode::OK);
+
+        let (payload, signature) = token.split_once('.').unwrap();
+        let first = if signature.starts_with('A') { 'B' } else { 'A' };
+        let tampered = format!("{}.{}{}", payload, first, &signature[1..]);
+        assert_eq!(me(&state, &tampered).await, StatusCode::UNAUTHORIZED);
+
+        state.auth = Auth::new(b"secret", 0);
+        let (status, _, body) = send(
+            &state,
+            Method::POST,
+            "/api/auth/login",
+            &[],
+            json!({"userName": "alice", "password": "correct horse"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let expired = body["token"].as_str().unwrap();
+        assert_eq!(me(&state, expired).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_deleting_a_user_revokes_their_tokens() {
+        let state = state_with(Limits::default()).await;
+        let token = register(&state).await;
+        let bearer = format!("Bearer {}", token);
+        let (status, _, _) = send(
+            &state,
+            Method::DELETE,
+            "/api/users/alice",
+            &[(AUTHORIZATION.as_str(), &bearer)],
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(me(&state, &token).await, StatusCode::UNAUTHORIZED);
+
+        let renewed = register(&state).await;
+        assert_eq!(me(&state, &token).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(me(&state, &renewed).await, StatusCode::OK);
+    }
+
+    #[test]
+    fn test_passwords() {
+        let hash = hash_password("correct horse").unwrap();
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+        assert!(hash_password("short").is_err());
+    }
+
+    #[test]
+    fn test_request_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(request_token(&headers), None);
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_static("theme=dark; session=abc.def"),
+        );
+        assert_eq!(request_token(&headers), Some("abc.def"));
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer xyz.uvw"));
+        assert_eq!(request_token(&headers), Some("xyz.uvw"));
+    }
+}
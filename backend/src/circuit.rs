@@ -0,0 +1,248 @@
+//! Flat circuit representation shared by the simulators, the unitary
+//! builder and anything else that needs to look at a whole program.
+//!
+//! A [`Circuit`] is produced by [`crate::lower::lower_program`]: composite
+//! gates are expanded, `repeat` loops unrolled, every operand is resolved to
+//! a qubit or classical bit position and `if` blocks become conditions on
+//! the instructions they guard.
+
+use std::ops::Range;
+
+use nalgebra::{Complex, DMatrix, Vector2};
+use serde::{Deserialize, Serialize};
+
+use crate::{analysis::AnalysisKind, gate::standard_gate, observable::Observable};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Circuit {
+    /// Qubits in register order; qubit `i` is bit `i` of a basis index.
+    pub qubits: Vec<QubitInfo>,
+    /// Classical registers; their bits are numbered consecutively in
+    /// declaration order.
+    #[serde(default)]
+    pub classical: Vec<ClassicalRegister>,
+    pub instructions: Vec<Instruction>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QubitInfo {
+    /// `q1` for a single qubit, `q[2]` for a register element.
+    pub label: String,
+    /// `[re, im]` of the `|0>` and `|1>` amplitudes before the first
+    /// instruction.
+    pub initial: [[f64; 2]; 2],
+}
+
+impl QubitInfo {
+    pub fn zero(label: String) -> Self {
+        Self {
+            label,
+            initial: [[1.0, 0.0], [0.0, 0.0]],
+        }
+    }
+
+    pub fn state(&self) -> Vector2<Complex<f64>> {
+        let [zero, one] = self.initial;
+        Vector2::new(Complex::new(zero[0], zero[1]), Complex::new(one[0], one[1]))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClassicalRegister {
+    pub name: String,
+    pub size: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Instruction {
+    #[serde(flatten)]
+    pub operation: Operation,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub qubits: Vec<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub clbits: Vec<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Condition>,
+}
+
+/// Runs an instruction only while a classical register holds `value`, read
+/// with its bit 0 as the least significant bit.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub register: String,
+    pub value: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum Operation {
+    /// A built-in gate, or a user matrix gate carrying its matrix. The
+    /// first qubit is the most significant qubit of the matrix.
+    Gate {
+        name: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        parameters: Vec<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        matrix: Option<GateMatrix>,
+    },
+    /// Measures each qubit into the matching clbit. With `echo` the
+    /// outcome is also printed, as the `measure` statement does.
+    Measure {
+        #[serde(default)]
+        echo: bool,
+    },
+    Reset,
+    Barrier,
+    /// Prints a qubit (when it has a qubit operand), the bits of a classical
+    /// register (clbit operands), a fixed `text`, or else the value of an
+    /// earlier `expect`.
+    Display {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+    },
+    Expect {
+        identifier: String,
+        /// How the observable was written in the program.
+        label: String,
+        observable: Observable,
+    },
+    Print {
+        analysis: AnalysisKind,
+    },
+    /// A statement that could not be lowered; running it prints `message`.
+    Error {
+        #[serde(default)]
+        kind: ProgramErrorKind,
+        message: String,
+    },
+}
+
+/// The kind of mistake an [`Operation::Error`] reports.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgramErrorKind {
+    /// A gate that is neither built in nor defined.
+    UnknownGate,
+    /// A qubit, register, parameter or observable that was not declared.
+    UnknownIdentifier,
+    /// An index past the end of a register, or a qubit that does not exist.
+    BadIndex,
+    /// A name declared or listed twice.
+    Redeclared,
+    /// Operands or parameters that do not fit the statement.
+    WrongArguments,
+    /// A gate definition or matrix that cannot be used as a gate.
+    BadGateDefinition,
+    /// A parameter or observable that does not evaluate.
+    BadExpression,
+    /// A statement in a place where it is not supported.
+    Unsupported,
+    #[default]
+    Other,
+}
+
+impl std::fmt::Display for ProgramErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ProgramErrorKind::UnknownGate => "unknown_gate",
+            ProgramErrorKind::UnknownIdentifier => "unknown_identifier",
+            ProgramErrorKind::BadIndex => "bad_index",
+            ProgramErrorKind::Redeclared => "redeclared",
+            ProgramErrorKind::WrongArguments => "wrong_arguments",
+            ProgramErrorKind::BadGateDefinition => "bad_gate_definition",
+            ProgramErrorKind::BadExpression => "bad_expression",
+            ProgramErrorKind::Unsupported => "unsupported",
+            ProgramErrorKind::Other => "other",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A gate matrix split into real and imaginary rows.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GateMatrix {
+    pub real: Vec<Vec<f64>>,
+    pub imag: Vec<Vec<f64>>,
+}
+
+impl GateMatrix {
+    pub fn from_matrix(matrix: &DMatrix<Complex<f64>>) -> Self {
+        Self {
+            real: crate::analysis::matrix_rows(matrix, |c| c.re),
+            imag: crate::analysis::matrix_rows(matrix, |c| c.im),
+        }
+    }
+
+    pub fn to_matrix(&self) -> DMatrix<Complex<f64>> {
+        let size = self.real.len();
+        DMatrix::from_fn(size, size, |row, col| {
+            Complex::new(self.real[row][col], self.imag[row][col])
+        })
+    }
+}
+
+impl Instruction {
+    pub fn new(operation: Operation, qubits: Vec<usize>) -> Self {
+        Self {
+            operation,
+            qubits,
+            clbits: vec![],
+            condition: None,
+        }
+    }
+
+    pub fn gate(name: &str, parameters: Vec<f64>, qubits: Vec<usize>) -> Self {
+        Self::new(
+            Operation::Gate {
+                name: name.to_string(),
+                parameters,
+                matrix: None,
+            },
+            qubits,
+        )
+    }
+
+    pub fn error(kind: ProgramErrorKind, message: String) -> Self {
+        Self::new(Operation::Error { kind, message }, vec![])
+    }
+
+    /// The unitary of a gate instruction.
+    pub fn matrix(&self) -> Option<DMatrix<Complex<f64>>> {
+        match &self.operation {
+            Operation::Gate {
+                matrix: Some(matrix),
+                ..
+            } => Some(matrix.to_matrix()),
+            Operation::Gate {
+                name, parameters, ..
+            } => standard_gate(name, parameters).map(|gate| gate.matrix_representation()),
+            _ => None,
+        }
+    }
+}
+
+impl Circuit {
+    pub fn labels(&self) -> Vec<String> {
+        self.qubits
+            .iter()
+            .map(|qubit| qubit.label.clone())
+            .collect()
+    }
+
+    pub fn num_clbits(&self) -> usize {
+        self.classical.iter().map(|register| register.size).sum()
+    }
+
+    /// Positions of a classical register's bits.
+    pub fn classical_range(&self, name: &str) -> Option<Range<usize>> {
+        let mut offset = 0;
+        for register in &self.classical {
+            if register.name == name {
+                return Some(offset..offset + register.size);
+            }
+            offset += register.size;
+        }
+        None
+    }
+}
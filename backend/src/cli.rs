@@ -0,0 +1,456 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    circuit::{Circuit, Operation},
+    decompose::{decompose_circuit, Basis},
+    diagram::{
+        circuit_latex, circuit_svg, draw_circuit, gate_label, DiagramOptions, DrawingFormat,
+        LatexOptions,
+    },
+    equivalence::{check_equivalence, EquivalenceOptions},
+    gate::Gate,
+    interpreter::{simulate_program, SimulationOptions, DEFAULT_SHOTS},
+    lower::lower_program,
+    metrics::circuit_metrics,
+    models::ProgramNode,
+    observable::ExpectationMode,
+    optimize::optimize_circuit,
+    precision::Precision,
+    qasm::{export_qasm, parse_qasm, QasmVersion},
+    routing::{route_circuit, Topology},
+    simulator::BackendKind,
+    unitary::{program_unitary, unitary_result},
+};
+
+#[derive(Parser, Debug)]
+#[command(name = "quantum_simulator", about = "QuanVi quantum circuit simulator")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// TOML file with the server settings
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Start the HTTP server (default when no command is given)
+    Serve,
+    /// Simulate a program stored as a JSON AST or OpenQASM 2.0 and print its output
+    Run {
+        /// Path to the program file; `.qasm` files are read as OpenQASM
+        file: PathBuf,
+        /// Read the file as OpenQASM 2.0 whatever its extension
+        #[arg(long)]
+        qasm: bool,
+        #[command(flatten)]
+        simulation: SimulationArgs,
+        /// Print the full result, including state analyses, as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the unitary of a measurement-free program
+    Unitary {
+        /// Path to the program file; `.qasm` files are read as OpenQASM
+        file: PathBuf,
+        /// Read the file as OpenQASM 2.0 whatever its extension
+        #[arg(long)]
+        qasm: bool,
+        /// Print the matrix as JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the lowered circuit of a program as JSON
+    Circuit {
+        /// Path to the program file; `.qasm` files are read as OpenQASM
+        file: PathBuf,
+        /// Read the file as OpenQASM 2.0 whatever its extension
+        #[arg(long)]
+        qasm: bool,
+    },
+    /// Print the depth, gate counts, T-count and critical path of a program
+    Metrics {
+        /// Path to the program file; `.qasm` files are read as OpenQASM
+        file: PathBuf,
+        /// Read the file as OpenQASM 2.0 whatever its extension
+        #[arg(long)]
+        qasm: bool,
+        /// Print the metrics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check whether two programs apply the same unitary up to global phase
+    Equivalent {
+        /// The reference program; `.qasm` files are read as OpenQASM
+        reference: PathBuf,
+        /// The program compared with it
+        candidate: PathBuf,
+        /// Read both files as OpenQASM 2.0 whatever their extension
+        #[arg(long)]
+        qasm: bool,
+        /// Treat qubits only one program has as ancillas starting and ending in |0>
+        #[arg(long)]
+        ignore_ancillas: bool,
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Optimise the lowered circuit and draw the result
+    Optimize {
+        /// Path to the program file; `.qasm` files are read as OpenQASM
+        file: PathBuf,
+        /// Read the file as OpenQASM 2.0 whatever its extension
+        #[arg(long)]
+        qasm: bool,
+        /// Print the optimised circuit and gate counts as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Rewrite the lowered circuit into a native gate basis and draw it
+    Decompose {
+        /// Path to the program file; `.qasm` files are read as OpenQASM
+        file: PathBuf,
+        /// Read the file as OpenQASM 2.0 whatever its extension
+        #[arg(long)]
+        qasm: bool,
+        /// Native gates: cx,rz,sx,x or cz,u3
+        #[arg(long, default_value = "cx,rz,sx,x")]
+        basis: Basis,
+        /// Print the decomposed circuit and gate counts as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Route the lowered circuit onto a device's coupling map, inserting SWAPs
+    Route {
+        /// Path to the program file; `.qasm` files are read as OpenQASM
+        file: PathBuf,
+        /// Read the file as OpenQASM 2.0 whatever its extension
+        #[arg(long)]
+        qasm: bool,
+        /// linear:N, ring:N, grid:RxC, heavy-hex:RxC or a JSON file with a
+        /// topology or an edge list
+        #[arg(long)]
+        coupling: String,
+        /// Print the routed circuit, layouts and SWAP count as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Draw the lowered circuit of a program as text, SVG or LaTeX
+    Draw {
+        /// Path to the program file; `.qasm` files are read as OpenQASM
+        file: PathBuf,
+        /// Read the file as OpenQASM 2.0 whatever its extension
+        #[arg(long)]
+        qasm: bool,
+        /// Drawing format: text, svg or latex (quantikz)
+        #[arg(long, default_value = "text")]
+        format: DrawingFormat,
+        /// Fold the drawing into pages at most this many characters wide
+        #[arg(long)]
+        width: Option<usize>,
+        /// Use plain ASCII instead of box-drawing characters
+        #[arg(long)]
+        ascii: bool,
+        /// Only write the quantikz environment, without a document around it
+        #[arg(long)]
+        fragment: bool,
+    },
+    /// Print a program as OpenQASM
+    Export {
+        /// Path to the program file; `.qasm` files are read as OpenQASM
+        file: PathBuf,
+        /// Read the file as OpenQASM 2.0 whatever its extension
+        #[arg(long)]
+        qasm: bool,
+        /// OpenQASM version to write: 2 or 3
+        #[arg(long, default_value = "2")]
+        version: QasmVersion,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct SimulationArgs {
+    /// Floating point precision of the state vector: f32 or f64
+    #[arg(long, default_value = "f64")]
+    pub precision: Precision,
+    /// State representation: dense or sparse
+    #[arg(long, default_value = "dense")]
+    pub backend: BackendKind,
+    /// How `expect` statements are evaluated: exact or shots
+    #[arg(long, default_value = "exact")]
+    pub expectation: ExpectationMode,
+    /// Measurements per Pauli term in shots mode
+    #[arg(long, default_value_t = DEFAULT_SHOTS)]
+    pub shots: usize,
+}
+
+impl SimulationArgs {
+    pub fn options(&self) -> SimulationOptions {
+        SimulationOptions {
+            precision: self.precision,
+            backend: self.backend,
+            expectation: self.expectation,
+            shots: self.shots,
+        }
+    }
+}
+
+fn read_program(file: &PathBuf, qasm: bool) -> Result<ProgramNode, String> {
+    let source = std::fs::read_to_string(file)
+        .map_err(|e| format!("Cannot read {}: {}", file.display(), e))?;
+    if qasm
+        || file
+            .extension()
+            .is_some_and(|extension| extension == "qasm")
+    {
+        parse_qasm(&source).map_err(|e| format!("Invalid OpenQASM in {}: {}", file.display(), e))
+    } else {
+        serde_json::from_str(&source)
+            .map_err(|e| format!("Invalid program in {}: {}", file.display(), e))
+    }
+}
+
+pub fn print_unitary(file: &PathBuf, qasm: bool, json: bool) -> Result<(), String> {
+    let program = read_program(file, qasm)?;
+    let (qubits, gate) = program_unitary(&program)?;
+    let gate: &dyn Gate = &gate;
+
+    if json {
+        let result = unitary_result(qubits, gate);
+        let json = serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?;
+        println!("{}", json);
+    } else {
+        println!("{}: {:?}", qubits.join(", "), gate);
+    }
+    Ok(())
+}
+
+pub fn print_circuit(file: &PathBuf, qasm: bool) -> Result<(), String> {
+    let circuit = lower_program(&read_program(file, qasm)?);
+    let json = serde_json::to_string_pretty(&circuit).map_err(|e| e.to_string())?;
+    println!("{}", json);
+    Ok(())
+}
+
+pub fn print_metrics(file: &PathBuf, qasm: bool, json: bool) -> Result<(), String> {
+    let circuit = lower_program(&read_program(file, qasm)?);
+    let metrics = circuit_metrics(&circuit);
+    if json {
+        let json = serde_json::to_string_pretty(&metrics).map_err(|e| e.to_string())?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    let labels = circuit.labels();
+    println!("Qubits: {}", metrics.num_qubits);
+    println!("Clbits: {}", metrics.num_clbits);
+    let depths: Vec<String> = labels
+        .iter()
+        .zip(&metrics.qubit_depths)
+        .map(|(label, depth)| format!("{}: {}", label, depth))
+        .collect();
+    println!("Depth: {} ({})", metrics.depth, depths.join(", "));
+    let counts: Vec<String> = metrics
+        .gate_counts
+        .iter()
+        .map(|(name, count)| format!("{}: {}", name, count))
+        .collect();
+    println!("Gates: {} ({})", metrics.gates, counts.join(", "));
+    println!("Two-qubit gates: {}", metrics.two_qubit_gates);
+    if metrics.clifford_t {
+        println!("T-count: {}", metrics.t_count);
+        println!("T-depth: {}", metrics.t_depth);
+    } else {
+        println!("T-count: {} (not a Clifford+T circuit)", metrics.t_count);
+    }
+    let path: Vec<String> = metrics
+        .critical_path
+        .iter()
+        .map(|&index| describe_instruction(&circuit, index))
+        .collect();
+    println!("Critical path: {}", path.join(" -> "));
+    Ok(())
+}
+
+/// `cnot q[0], q[1]`-style description of an instruction.
+fn describe_instruction(circuit: &Circuit, index: usize) -> String {
+    let instruction = &circuit.instructions[index];
+    let name = match &instruction.operation {
+        Operation::Gate {
+            name, parameters, ..
+        } => gate_label(name, parameters),
+        Operation::Measure { .. } => "measure".to_string(),
+        Operation::Reset => "reset".to_string(),
+        _ => "barrier".to_string(),
+    };
+    let qubits: Vec<&str> = instruction
+        .qubits
+        .iter()
+        .map(|&q| circuit.qubits[q].label.as_str())
+        .collect();
+    format!("{} {}", name, qubits.join(", "))
+}
+
+pub fn print_equivalence(
+    reference: &PathBuf,
+    candidate: &PathBuf,
+    qasm: bool,
+    options: EquivalenceOptions,
+    json: bool,
+) -> Result<(), String> {
+    let reference = lower_program(&read_program(reference, qasm)?);
+    let candidate = lower_program(&read_program(candidate, qasm)?);
+    let result = check_equivalence(&reference, &candidate, options)?;
+    if json {
+        let json = serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?;
+        println!("{}", json);
+    } else if let Some(example) = &result.counter_example {
+        println!("Not equivalent: {}", example.reason);
+        let input = example.label.clone().unwrap_or_else(|| {
+            let states: Vec<String> = example
+                .input
+                .iter()
+                .map(|[zero, one]| {
+                    format!(
+                        "({:.3}{:+.3}i, {:.3}{:+.3}i)",
+                        zero[0], zero[1], one[0], one[1]
+                    )
+                })
+                .collect();
+            states.join(" ")
+        });
+        println!("Input: {}", input);
+        println!("Fidelity of the outputs: {:.4}", example.fidelity);
+    } else {
+        println!(
+            "Equivalent up to a global phase of {:.4}",
+            result.global_phase.unwrap_or(0.0)
+        );
+    }
+    Ok(())
+}
+
+pub fn print_optimized(file: &PathBuf, qasm: bool, json: bool) -> Result<(), String> {
+    let result = optimize_circuit(&lower_program(&read_program(file, qasm)?));
+    if json {
+        let json = serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?;
+        println!("{}", json);
+    } else {
+        print!(
+            "{}",
+            draw_circuit(&result.circuit, DiagramOptions::default())
+        );
+        println!("Gates: {} -> {}", result.gates_before, result.gates_after);
+    }
+    Ok(())
+}
+
+pub fn print_decomposed(
+    file: &PathBuf,
+    qasm: bool,
+    basis: Basis,
+    json: bool,
+) -> Result<(), String> {
+    let result = decompose_circuit(&lower_program(&read_program(file, qasm)?), basis)?;
+    if json {
+        let json = serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?;
+        println!("{}", json);
+    } else {
+        print!(
+            "{}",
+            draw_circuit(&result.circuit, DiagramOptions::default())
+        );
+        println!("Gates: {} -> {}", result.gates_before, result.gates_after);
+        if result.ancillas > 0 {
+            println!("Ancillas: {}", result.ancillas);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a coupling map given as `linear:5`-style text, or a JSON file
+/// holding a topology or a bare list of edges.
+fn read_topology(coupling: &str) -> Result<Topology, String> {
+    if let Ok(topology) = coupling.parse() {
+        return Ok(topology);
+    }
+    let source = std::fs::read_to_string(coupling)
+        .map_err(|e| format!("Cannot read coupling map {}: {}", coupling, e))?;
+    serde_json::from_str(&source)
+        .or_else(|_| serde_json::from_str(&source).map(|edges| Topology::Custom { edges }))
+        .map_err(|e| format!("Invalid coupling map in {}: {}", coupling, e))
+}
+
+pub fn print_routed(file: &PathBuf, qasm: bool, coupling: &str, json: bool) -> Result<(), String> {
+    let coupling = read_topology(coupling)?.coupling_map()?;
+    let circuit = lower_program(&read_program(file, qasm)?);
+    let result = route_circuit(&circuit, &coupling)?;
+    if json {
+        let json = serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?;
+        println!("{}", json);
+    } else {
+        print!(
+            "{}",
+            draw_circuit(&result.circuit, DiagramOptions::default())
+        );
+        println!("Swaps: {}", result.swaps);
+        let layout = |layout: &[usize]| {
+            let entries: Vec<String> = circuit
+                .labels()
+                .iter()
+                .zip(layout)
+                .map(|(label, physical)| format!("{} -> p[{}]", label, physical))
+                .collect();
+            entries.join(", ")
+        };
+        println!("Initial layout: {}", layout(&result.initial_layout));
+        println!("Final layout: {}", layout(&result.final_layout));
+    }
+    Ok(())
+}
+
+pub fn print_diagram(
+    file: &PathBuf,
+    qasm: bool,
+    format: DrawingFormat,
+    options: DiagramOptions,
+    latex: LatexOptions,
+) -> Result<(), String> {
+    let circuit = lower_program(&read_program(file, qasm)?);
+    match format {
+        DrawingFormat::Text => print!("{}", draw_circuit(&circuit, options)),
+        DrawingFormat::Svg => print!("{}", circuit_svg(&circuit)),
+        DrawingFormat::Latex => print!("{}", circuit_latex(&circuit, latex)),
+    }
+    Ok(())
+}
+
+pub fn print_qasm(file: &PathBuf, qasm: bool, version: QasmVersion) -> Result<(), String> {
+    let program = read_program(file, qasm)?;
+    print!("{}", export_qasm(&program, version)?);
+    Ok(())
+}
+
+pub fn run_file(
+    file: &PathBuf,
+    qasm: bool,
+    options: SimulationOptions,
+    json: bool,
+) -> Result<(), String> {
+    let program = read_program(file, qasm)?;
+    let result = simulate_program(program, options);
+    if json {
+        let json = serde_json::to_string_pretty(&result).map_err(|e| e.to_string())?;
+        println!("{}", json);
+    } else {
+        for line in result.output {
+            println!("{}", line);
+        }
+        for (register, bits) in result.memory {
+            println!("{}: {}", register, bits);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,341 @@
+//! Server settings, read from a TOML file and then the environment.
+//!
+//! Every setting has a default, so the file only needs the ones that
+//! change. Environment variables override the file; each is `QUANVI_`
+//! followed by the setting's name in capitals, e.g. `QUANVI_PORT`,
+//! `QUANVI_DATABASE_URL` or `QUANVI_MAX_QUBITS` for `limits.max_qubits`.
+
+use std::{net::IpAddr, path::Path, time::Duration};
+
+use axum::http::HeaderValue;
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    limits::Limits,
+    session::{DEFAULT_MAX_SESSIONS, DEFAULT_SESSION_TTL},
+    simulator::BackendKind,
+    store::DEFAULT_DATABASE_URL,
+};
+
+const ENV_PREFIX: &str = "QUANVI_";
+
+/// Shortest secret that may sign login tokens.
+const MIN_AUTH_SECRET_BYTES: usize = 32;
+
+pub const LOG_LEVELS: [&str; 5] = ["trace", "debug", "info", "warn", "error"];
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address the server listens on.
+    pub host: String,
+    pub port: u16,
+    /// Origins allowed to call the API from a browser, or `["*"]` for any.
+    /// None by default, so that only pages of the API's own origin can.
+    pub cors_origins: Vec<String>,
+    pub database_url: String,
+    /// `trace`, `debug`, `info`, `warn` or `error`.
+    pub log_level: String,
+    /// Key that signs login tokens. Without one a random key is made at
+    /// startup, so tokens stop working when the server restarts.
+    pub auth_secret: Option<String>,
+    /// Step-by-step sessions kept at once.
+    pub max_sessions: usize,
+    /// How long a session is kept without being used.
+    #[serde(rename = "session_ttl_secs", deserialize_with = "seconds")]
+    pub session_ttl: Duration,
+    pub limits: Limits,
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: "0.0.0.0".to_string(),
+            port: 8000,
+            cors_origins: vec![],
+            database_url: DEFAULT_DATABASE_URL.to_string(),
+            log_level: "info".to_string(),
+            auth_secret: None,
+            max_sessions: DEFAULT_MAX_SESSIONS,
+            session_ttl: DEFAULT_SESSION_TTL,
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the settings of a file, if given, then the environment, and
+    /// checks them.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let mut config = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+                Config::from_toml(&text)
+                    .map_err(|error| format!("Invalid {}: {}", path.display(), error))?
+            }
+            None => Config::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Config, String> {
+        toml::from_str(text).map_err(|error| error.to_string())
+    }
+
+    /// Overrides settings with the variables `var` finds.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        let var = |name: &str| var(&format!("{}{}", ENV_PREFIX, name));
+        if let Some(host) = var("HOST") {
+            self.host = host;
+        }
+        set(&mut self.port, "PORT", var)?;
+        if let Some(origins) = var("CORS_ORIGINS") {
+            self.cors_origins = list(&origins).map(str::to_string).collect();
+        }
+        if let Some(url) = var("DATABASE_URL") {
+            self.database_url = url;
+        }
+        if let Some(level) = var("LOG_LEVEL") {
+            self.log_level = level;
+        }
+        if let Some(secret) = var("AUTH_SECRET") {
+            self.auth_secret = Some(secret);
+        }
+        set(&mut self.max_sessions, "MAX_SESSIONS", var)?;
+        let mut session_ttl = self.session_ttl.as_secs();
+        set(&mut session_ttl, "SESSION_TTL_SECS", var)?;
+        self.session_ttl = Duration::from_secs(session_ttl);
+        let limits = &mut self.limits;
+        set(&mut limits.max_qubits, "MAX_QUBITS", var)?;
+        set(&mut limits.max_sparse_qubits, "MAX_SPARSE_QUBITS", var)?;
+        set(&mut limits.max_instructions, "MAX_INSTRUCTIONS", var)?;
+        set(&mut limits.max_clbits, "MAX_CLBITS", var)?;
+        set(&mut limits.max_shots, "MAX_SHOTS", var)?;
+        set(&mut limits.max_body_bytes, "MAX_BODY_BYTES", var)?;
+        let mut timeout = limits.timeout.as_millis() as u64;
+        set(&mut timeout, "TIMEOUT_MS", var)?;
+        limits.timeout = Duration::from_millis(timeout);
+        let mut stream_timeout = limits.stream_timeout.as_millis() as u64;
+        set(&mut stream_timeout, "STREAM_TIMEOUT_MS", var)?;
+        limits.stream_timeout = Duration::from_millis(stream_timeout);
+        if let Some(backends) = var("BACKENDS") {
+            limits.backends = list(&backends)
+                .map(|backend| backend.parse::<BackendKind>())
+                .collect::<Result<_, _>>()
+                .map_err(|error| format!("{}BACKENDS: {}", ENV_PREFIX, error))?;
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.host
+            .parse::<IpAddr>()
+            .map_err(|_| format!("host '{}' is not an IP address", self.host))?;
+        if self.port == 0 {
+            return Err("port must be greater than 0".to_string());
+        }
+        if self.cors_origins.iter().any(|origin| origin == "*") {
+            if self.cors_origins.len() > 1 {
+                return Err("cors_origins cannot mix \"*\" with other origins".to_string());
+            }
+        } else {
+            for origin in &self.cors_origins {
+                let scheme = origin.starts_with("http://") || origin.starts_with("https://");
+                if !scheme || origin.ends_with('/') || HeaderValue::from_str(origin).is_err() {
+                    return Err(format!(
+                        "cors_origins: '{}' is not an origin such as https://example.com",
+                        origin
+                    ));
+                }
+            }
+        }
+        if !self.database_url.starts_with("sqlite:")
+            && !self.database_url.starts_with("postgres:")
+            && !self.database_url.starts_with("postgresql:")
+        {
+            return Err("database_url must start with sqlite: or postgres:".to_string());
+        }
+        if !LOG_LEVELS.contains(&self.log_level.as_str()) {
+            return Err(format!(
+                "log_level '{}' is not one of {}",
+                self.log_level,
+                LOG_LEVELS.join(", ")
+            ));
+        }
+        if let Some(secret) = &self.auth_secret {
+            if secret.len() < MIN_AUTH_SECRET_BYTES {
+                return Err(format!(
+                    "auth_secret must be at least {} bytes long",
+                    MIN_AUTH_SECRET_BYTES
+                ));
+            }
+        }
+        if self.max_sessions == 0 {
+            return Err("max_sessions must be greater than 0".to_string());
+        }
+        if self.session_ttl.is_zero() {
+            return Err("session_ttl_secs must be greater than 0".to_string());
+        }
+        self.limits.validate()
+    }
+
+    /// The address to listen on, as `host:port`.
+    pub fn address(&self) -> String {
+        match self.host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => format!("[{}]:{}", self.host, self.port),
+            _ => format!("{}:{}", self.host, self.port),
+        }
+    }
+
+    /// The allowed origins as headers, or `None` when any origin is allowed.
+    pub fn allowed_origins(&self) -> Option<Vec<HeaderValue>> {
+        if self.cors_origins.iter().any(|origin| origin == "*") {
+            return None;
+        }
+        Some(
+            self.cors_origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok())
+                .collect(),
+        )
+    }
+}
+
+fn set<T: std::str::FromStr>(
+    field: &mut T,
+    name: &str,
+    var: impl Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    if let Some(value) = var(name) {
+        *field = value
+            .trim()
+            .parse()
+            .map_err(|_| format!("{}{}: '{}' is not a valid value", ENV_PREFIX, name, value))?;
+    }
+    Ok(())
+}
+
+/// The items of a comma-separated list.
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_file_settings_keep_defaults() {
+        let config = Config::from_toml(
+            r#"
+port = 9000
+cors_origins = ["https://quanvi.example.com"]
+max_sessions = 8
+session_ttl_secs = 300
+
+[limits]
+max_qubits = 20
+timeout_ms = 2500
+backends = ["sparse"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.host, "0.0.0.0");
+        assert_eq!(config.max_sessions, 8);
+        assert_eq!(config.session_ttl, Duration::from_secs(300));
+        assert_eq!(config.auth_secret, None);
+        assert_eq!(config.limits.max_qubits, 20);
+        assert_eq!(config.limits.timeout, Duration::from_millis(2500));
+        assert_eq!(config.limits.backends, [BackendKind::Sparse]);
+        assert_eq!(config.limits.max_shots, Limits::default().max_shots);
+        assert!(config.validate().is_ok());
+        assert_eq!(config.allowed_origins().unwrap().len(), 1);
+
+        assert!(Config::from_toml("prot = 9000").is_err());
+        assert!(Config::from_toml("[limits]\nmax_qbits = 3").is_err());
+    }
+
+    #[test]
+    fn test_environment_overrides() {
+        let vars: HashMap<&str, &str> = [
+            ("QUANVI_DATABASE_URL", "postgres://localhost/quanvi"),
+            ("QUANVI_HOST", "::1"),
+            (
+                "QUANVI_CORS_ORIGINS",
+                "http://localhost:3000, https://a.example",
+            ),
+            ("QUANVI_MAX_SHOTS", "500"),
+            ("QUANVI_STREAM_TIMEOUT_MS", "60000"),
+            ("QUANVI_BACKENDS", "dense"),
+            ("QUANVI_AUTH_SECRET", "0123456789abcdef0123456789abcdef"),
+            ("QUANVI_MAX_SESSIONS", "4"),
+            ("QUANVI_SESSION_TTL_SECS", "60"),
+        ]
+        .into();
+        let mut config = Config::default();
+        config
+            .apply_env(|name| vars.get(name).map(|value| value.to_string()))
+            .unwrap();
+
+        assert_eq!(config.database_url, "postgres://localhost/quanvi");
+        assert_eq!(config.address(), "[::1]:8000");
+        assert_eq!(config.cors_origins.len(), 2);
+        assert_eq!(config.limits.max_shots, 500);
+        assert_eq!(config.limits.stream_timeout, Duration::from_secs(60));
+        assert_eq!(config.limits.backends, [BackendKind::Dense]);
+        assert_eq!(
+            config.auth_secret.as_deref(),
+            Some("0123456789abcdef0123456789abcdef")
+        );
+        assert_eq!(config.max_sessions, 4);
+        assert_eq!(config.session_ttl, Duration::from_secs(60));
+        assert!(config.validate().is_ok());
+
+        let mut config = Config::default();
+        config
+            .apply_env(|name| (name == "DATABASE_URL").then(|| "sqlite::memory:".to_string()))
+            .unwrap();
+        assert_eq!(config, Config::default());
+        let error = config
+            .apply_env(|name| (name == "QUANVI_PORT").then(|| "eighty".to_string()))
+            .unwrap_err();
+        assert!(error.contains("QUANVI_PORT"));
+    }
+
+    #[test]
+    fn test_validation() {
+        let invalid = [
+            "host = \"localhost\"",
+            "port = 0",
+            "cors_origins = [\"*\", \"https://a.example\"]",
+            "cors_origins = [\"a.example\"]",
+            "database_url = \"mysql://localhost\"",
+            "log_level = \"verbose\"",
+            "auth_secret = \"short\"",
+            "max_sessions = 0",
+            "session_ttl_secs = 0",
+            "[limits]\nbackends = []",
+        ];
+        for text in invalid {
+            let config = Config::from_toml(text).unwrap();
+            assert!(config.validate().is_err(), "{}", text);
+        }
+        assert!(Config::default().validate().is_ok());
+        assert_eq!(Config::default().allowed_origins(), Some(vec![]));
+        let any = Config::from_toml("cors_origins = [\"*\"]").unwrap();
+        assert_eq!(any.allowed_origins(), None);
+    }
+}
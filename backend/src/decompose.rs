@@ -0,0 +1,720 @@
+//! Rewriting circuits into a native gate basis.
+//!
+//! Gates are first broken down into single-qubit unitaries and CNOTs:
+//! controlled gates with the ABC construction, two-qubit gates with a KAK
+//! decomposition and gates with more controls with a chain of Toffolis over
+//! ancilla qubits. Runs of single-qubit unitaries are then fused and written
+//! in the target basis using their Euler angles.
+
+use std::{f64::consts::PI, str::FromStr, sync::atomic::AtomicBool};
+
+use nalgebra::{Complex, DMatrix, SymmetricEigen};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    circuit::{Circuit, Condition, Instruction, Operation, QubitInfo},
+    gate::{standard_gate, u_angles},
+    limits::check_cancelled,
+    optimize::count_gates,
+};
+
+const TOLERANCE: f64 = 1e-9;
+
+/// A native gate set.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(try_from = "String")]
+pub enum Basis {
+    /// `cnot`, `rz`, `sqrtX` and `pauliX`.
+    #[default]
+    CxRzSxX,
+    /// `cz` and `u`.
+    CzU3,
+}
+
+impl Basis {
+    /// Names of the gates the basis is made of.
+    pub fn gates(self) -> &'static [&'static str] {
+        match self {
+            Basis::CxRzSxX => &["cnot", "rz", "sqrtX", "pauliX"],
+            Basis::CzU3 => &["cz", "u"],
+        }
+    }
+}
+
+impl FromStr for Basis {
+    type Err = String;
+
+    /// Parses a comma-separated gate set such as `cx,rz,sx,x` or `cz,u3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut gates: Vec<String> = s
+            .split(',')
+            .map(|gate| gate.trim().to_lowercase())
+            .collect();
+        gates.sort();
+        match gates.join(",").as_str() {
+            "cx,rz,sx,x" | "cnot,paulix,rz,sqrtx" => Ok(Basis::CxRzSxX),
+            "cz,u3" | "cz,u" => Ok(Basis::CzU3),
+            _ => Err(format!(
+                "Unsupported basis '{}': expected cx,rz,sx,x or cz,u3",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Basis {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DecompositionResult {
+    pub circuit: Circuit,
+    /// Qubits added after the circuit's own, starting and ending in `|0>`.
+    pub ancillas: usize,
+    pub gates_before: usize,
+    pub gates_after: usize,
+}
+
+pub fn decompose_circuit(circuit: &Circuit, basis: Basis) -> Result<DecompositionResult, String> {
+    decompose_circuit_cancellable(circuit, basis, &AtomicBool::new(false))
+}
+
+/// Decomposes a circuit unless `cancelled` is set first, which is checked
+/// between instructions.
+pub fn decompose_circuit_cancellable(
+    circuit: &Circuit,
+    basis: Basis,
+    cancelled: &AtomicBool,
+) -> Result<DecompositionResult, String> {
+    let mut builder = Builder {
+        steps: vec![],
+        first_ancilla: circuit.qubits.len(),
+        ancillas: 0,
+    };
+    let mut instructions = vec![];
+    let mut condition: Option<Condition> = None;
+
+    for instruction in &circuit.instructions {
+        check_cancelled(cancelled)?;
+        let Operation::Gate { name, .. } = &instruction.operation else {
+            builder.flush(basis, &condition, &mut instructions);
+            instructions.push(instruction.clone());
+            continue;
+        };
+        if instruction.condition != condition {
+            builder.flush(basis, &condition, &mut instructions);
+            condition = instruction.condition.clone();
+        }
+        let matrix = instruction
+            .matrix()
+            .ok_or_else(|| format!("Cannot resolve gate '{}'", name))?;
+        builder.gate(name, &matrix, &instruction.qubits)?;
+    }
+    builder.flush(basis, &condition, &mut instructions);
+
+    let mut qubits = circuit.qubits.clone();
+    qubits.extend((0..builder.ancillas).map(|i| QubitInfo::zero(format!("ancilla[{}]", i))));
+    let decomposed = Circuit {
+        qubits,
+        classical: circuit.classical.clone(),
+        instructions,
+    };
+    Ok(DecompositionResult {
+        ancillas: builder.ancillas,
+        gates_before: count_gates(circuit),
+        gates_after: count_gates(&decomposed),
+        circuit: decomposed,
+    })
+}
+
+/// A single-qubit unitary or a CNOT, what every gate is broken down into.
+enum Step {
+    One(usize, DMatrix<Complex<f64>>),
+    Cx(usize, usize),
+    Cz(usize, usize),
+}
+
+struct Builder {
+    steps: Vec<Step>,
+    first_ancilla: usize,
+    /// Ancillas needed so far.
+    ancillas: usize,
+}
+
+impl Builder {
+    fn one(&mut self, qubit: usize, matrix: DMatrix<Complex<f64>>) {
+        self.steps.push(Step::One(qubit, matrix));
+    }
+
+    fn named(&mut self, qubit: usize, name: &str, parameters: &[f64]) {
+        self.one(qubit, gate_matrix(name, parameters));
+    }
+
+    fn cx(&mut self, control: usize, target: usize) {
+        self.steps.push(Step::Cx(control, target));
+    }
+
+    fn gate(
+        &mut self,
+        name: &str,
+        matrix: &DMatrix<Complex<f64>>,
+        qubits: &[usize],
+    ) -> Result<(), String> {
+        match (name, qubits) {
+            (_, [qubit]) => self.one(*qubit, matrix.clone()),
+            ("swap", &[a, b]) => {
+                self.cx(a, b);
+                self.cx(b, a);
+                self.cx(a, b);
+            }
+            ("toffoli", &[a, b, target]) => self.toffoli(a, b, target),
+            ("fredkin", &[control, a, b]) => {
+                self.cx(b, a);
+                self.toffoli(control, a, b);
+                self.cx(b, a);
+            }
+            (_, &[a, b]) => match controlled_block(matrix) {
+                Some(block) => self.controlled(a, b, &block),
+                None => self.two_qubit(a, b, matrix),
+            },
+            _ => match controlled_block(matrix) {
+                Some(block) => {
+                    let (target, controls) = qubits.split_last().unwrap();
+                    self.multi_controlled(controls, *target, &block);
+                }
+                None => {
+                    return Err(format!(
+                        "Cannot decompose {}: only multi-controlled gates are supported on more than two qubits",
+                        name
+                    ))
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// A single-qubit unitary on `target` controlled by `control`.
+    fn controlled(&mut self, control: usize, target: usize, unitary: &DMatrix<Complex<f64>>) {
+        if approx_equal(unitary, &gate_matrix("pauliX", &[])) {
+            return self.cx(control, target);
+        }
+        if approx_equal(unitary, &gate_matrix("pauliZ", &[])) {
+            self.named(target, "hadamard", &[]);
+            self.cx(control, target);
+            return self.named(target, "hadamard", &[]);
+        }
+
+        // unitary = e^(i alpha) Rz(beta) Ry(gamma) Rz(delta) = e^(i alpha) A X B X C
+        // with A B C = I (Nielsen & Chuang, corollary 4.2).
+        let (theta, phi, lambda, phase) = u_angles(unitary);
+        let (alpha, beta, gamma, delta) = (phase + (phi + lambda) / 2.0, phi, theta, lambda);
+        let rz = |angle: f64| gate_matrix("rz", &[angle]);
+        let ry = |angle: f64| gate_matrix("ry", &[angle]);
+
+        self.one(target, rz((delta - beta) / 2.0));
+        self.cx(control, target);
+        self.one(target, ry(-gamma / 2.0) * rz(-(delta + beta) / 2.0));
+        self.cx(control, target);
+        self.one(target, rz(beta) * ry(gamma / 2.0));
+        self.named(control, "phase", &[alpha]);
+    }
+
+    /// The textbook Toffoli circuit with six CNOTs.
+    fn toffoli(&mut self, a: usize, b: usize, target: usize) {
+        self.named(target, "hadamard", &[]);
+        self.cx(b, target);
+        self.named(target, "tDagger", &[]);
+        self.cx(a, target);
+        self.named(target, "t", &[]);
+        self.cx(b, target);
+        self.named(target, "tDagger", &[]);
+        self.cx(a, target);
+        self.named(b, "t", &[]);
+        self.named(target, "t", &[]);
+        self.named(target, "hadamard", &[]);
+        self.cx(a, b);
+        self.named(a, "t", &[]);
+        self.named(b, "tDagger", &[]);
+        self.cx(a, b);
+    }
+
+    /// Computes the AND of the controls into a chain of ancillas, applies
+    /// the controlled unitary from the last one and uncomputes the chain.
+    fn multi_controlled(
+        &mut self,
+        controls: &[usize],
+        target: usize,
+        unitary: &DMatrix<Complex<f64>>,
+    ) {
+        if controls.len() == 2 && approx_equal(unitary, &gate_matrix("pauliX", &[])) {
+            return self.toffoli(controls[0], controls[1], target);
+        }
+
+        let needed = controls.len() - 1;
+        self.ancillas = self.ancillas.max(needed);
+        let first_ancilla = self.first_ancilla;
+        let ancilla = |i: usize| first_ancilla + i;
+
+        let mut chain = vec![(controls[0], controls[1], ancilla(0))];
+        for (i, control) in controls.iter().enumerate().skip(2) {
+            chain.push((*control, ancilla(i - 2), ancilla(i - 1)));
+        }
+        for &(a, b, target) in &chain {
+            self.toffoli(a, b, target);
+        }
+        self.controlled(ancilla(needed - 1), target, unitary);
+        for &(a, b, target) in chain.iter().rev() {
+            self.toffoli(a, b, target);
+        }
+    }
+
+    /// KAK decomposition: `unitary = (A1 ⊗ B1) exp(i(a XX + b YY + c ZZ)) (A2 ⊗ B2)`
+    /// up to a global phase, found by diagonalising the unitary in the magic
+    /// basis where the local parts become real orthogonal matrices.
+    fn two_qubit(&mut self, first: usize, second: usize, unitary: &DMatrix<Complex<f64>>) {
+        let magic = magic_basis();
+        let det = unitary.determinant();
+        let special = unitary / det.powf(0.25);
+        let u = magic.adjoint() * special * &magic;
+        let p = u.transpose() * &u;
+
+        let (o, d) = diagonalize_symmetric_unitary(&p);
+        let mut a: Vec<Complex<f64>> = d.iter().map(|value| value.sqrt()).collect();
+        let product: Complex<f64> = a.iter().product();
+        if product.re < 0.0 {
+            a[0] = -a[0];
+        }
+        let a_inverse = DMatrix::from_diagonal(&nalgebra::DVector::from_iterator(
+            4,
+            a.iter().map(|value| value.inv()),
+        ));
+        let k1 = &u * &o * a_inverse;
+        let k2 = o.transpose();
+
+        let angles: Vec<f64> = a.iter().map(|value| value.arg()).collect();
+        let xx = (angles[0] - angles[1] + angles[2] - angles[3]) / 4.0;
+        let yy = (-angles[0] + angles[1] + angles[2] - angles[3]) / 4.0;
+        let zz = (angles[0] + angles[1] - angles[2] - angles[3]) / 4.0;
+
+        let (a2, b2) = tensor_factors(&(&magic * k2 * magic.adjoint()));
+        let (a1, b1) = tensor_factors(&(&magic * k1 * magic.adjoint()));
+        self.one(first, a2);
+        self.one(second, b2);
+        self.rxx(first, second, -2.0 * xx);
+        self.ryy(first, second, -2.0 * yy);
+        self.rzz(first, second, -2.0 * zz);
+        self.one(first, a1);
+        self.one(second, b1);
+    }
+
+    /// `exp(-iθ/2 Z⊗Z)` as CNOT, Rz, CNOT.
+    fn rzz(&mut self, a: usize, b: usize, theta: f64) {
+        let theta = self.ising_turn(a, b, theta, "pauliZ");
+        if theta.abs() > TOLERANCE {
+            self.cx(a, b);
+            self.named(b, "rz", &[theta]);
+            self.cx(a, b);
+        }
+    }
+
+    fn rxx(&mut self, a: usize, b: usize, theta: f64) {
+        let theta = self.ising_turn(a, b, theta, "pauliX");
+        if theta.abs() > TOLERANCE {
+            self.named(a, "hadamard", &[]);
+            self.named(b, "hadamard", &[]);
+            self.rzz(a, b, theta);
+            self.named(a, "hadamard", &[]);
+            self.named(b, "hadamard", &[]);
+        }
+    }
+
+    fn ryy(&mut self, a: usize, b: usize, theta: f64) {
+        let theta = self.ising_turn(a, b, theta, "pauliY");
+        if theta.abs() > TOLERANCE {
+            self.named(a, "sDagger", &[]);
+            self.named(b, "sDagger", &[]);
+            self.rxx(a, b, theta);
+            self.named(a, "s", &[]);
+            self.named(b, "s", &[]);
+        }
+    }
+
+    /// Brings an Ising angle into `(-π/2, π/2]`: a rotation by π is the
+    /// local gate `P ⊗ P` up to a phase, applied here.
+    fn ising_turn(&mut self, a: usize, b: usize, theta: f64, pauli: &str) -> f64 {
+        let theta = normalize_angle(theta);
+        if theta.abs() <= PI / 2.0 + TOLERANCE && theta > -PI / 2.0 + TOLERANCE {
+            return theta;
+        }
+        self.named(a, pauli, &[]);
+        self.named(b, pauli, &[]);
+        if theta > 0.0 {
+            theta - PI
+        } else {
+            theta + PI
+        }
+    }
+
+    /// Writes the pending steps as basis gates guarded by `condition`.
+    fn flush(
+        &mut self,
+        basis: Basis,
+        condition: &Option<Condition>,
+        instructions: &mut Vec<Instruction>,
+    ) {
+        let mut steps = vec![];
+        for step in self.steps.drain(..) {
+            match (basis, step) {
+                (Basis::CzU3, Step::Cx(control, target)) => {
+                    steps.push(Step::One(target, gate_matrix("hadamard", &[])));
+                    steps.push(Step::Cz(control, target));
+                    steps.push(Step::One(target, gate_matrix("hadamard", &[])));
+                }
+                (_, step) => steps.push(step),
+            }
+        }
+
+        for step in fuse(steps) {
+            let gates = match step {
+                Step::Cx(control, target) => {
+                    vec![Instruction::gate("cnot", vec![], vec![control, target])]
+                }
+                Step::Cz(a, b) => vec![Instruction::gate("cz", vec![], vec![a, b])],
+                Step::One(qubit, matrix) => single_qubit(basis, qubit, &matrix),
+            };
+            instructions.extend(gates.into_iter().map(|mut gate| {
+                gate.condition = condition.clone();
+                gate
+            }));
+        }
+    }
+}
+
+/// Merges consecutive single-qubit steps on the same qubit.
+fn fuse(steps: Vec<Step>) -> Vec<Step> {
+    let mut fused: Vec<Step> = vec![];
+    for step in steps {
+        if let Step::One(qubit, matrix) = &step {
+            let last = fused.iter_mut().rev().find(|earlier| match earlier {
+                Step::One(q, _) => q == qubit,
+                Step::Cx(a, b) | Step::Cz(a, b) => a == qubit || b == qubit,
+            });
+            if let Some(Step::One(_, earlier)) = last {
+                *earlier = matrix * &*earlier;
+                continue;
+            }
+        }
+        fused.push(step);
+    }
+    fused
+}
+
+/// A single-qubit unitary in the basis, dropping its global phase.
+fn single_qubit(basis: Basis, qubit: usize, matrix: &DMatrix<Complex<f64>>) -> Vec<Instruction> {
+    let identity = DMatrix::identity(2, 2);
+    if equal_up_to_phase(matrix, &identity) {
+        return vec![];
+    }
+    let (theta, phi, lambda, _) = u_angles(matrix);
+    let gate = |name: &str, parameters: Vec<f64>| Instruction::gate(name, parameters, vec![qubit]);
+
+    match basis {
+        Basis::CzU3 => vec![gate("u", vec![theta, phi, lambda])],
+        Basis::CxRzSxX => {
+            if equal_up_to_phase(matrix, &gate_matrix("pauliX", &[])) {
+                return vec![gate("pauliX", vec![])];
+            }
+            let rz = |angle: f64| {
+                let angle = normalize_angle(angle);
+                (angle.abs() > TOLERANCE).then(|| gate("rz", vec![angle]))
+            };
+            let sx = || Some(gate("sqrtX", vec![]));
+            // U(θ, φ, λ) = Rz(φ + π) SX Rz(θ + π) SX Rz(λ) up to a global phase.
+            let sequence = if theta.abs() < TOLERANCE {
+                vec![rz(phi + lambda)]
+            } else if (theta - PI / 2.0).abs() < TOLERANCE {
+                vec![rz(lambda - PI / 2.0), sx(), rz(phi + PI / 2.0)]
+            } else {
+                vec![rz(lambda), sx(), rz(theta + PI), sx(), rz(phi + PI)]
+            };
+            sequence.into_iter().flatten().collect()
+        }
+    }
+}
+
+/// The bottom-right 2x2 block of a gate that only acts when all qubits but
+/// the last are `|1>`.
+fn controlled_block(matrix: &DMatrix<Complex<f64>>) -> Option<DMatrix<Complex<f64>>> {
+    let size = matrix.nrows();
+    for row in 0..size {
+        for col in 0..size {
+            if row >= size - 2 && col >= size - 2 {
+                continue;
+            }
+            let expected = if row == col { 1.0 } else { 0.0 };
+            if (matrix[(row, col)] - Complex::new(expected, 0.0)).norm() > TOLERANCE {
+                return None;
+            }
+        }
+    }
+    Some(matrix.view((size - 2, size - 2), (2, 2)).into_owned())
+}
+
+/// Columns are the Bell states `Φ+`, `iΦ-`, `iΨ+` and `Ψ-`.
+fn magic_basis() -> DMatrix<Complex<f64>> {
+    let h = Complex::new(std::f64::consts::FRAC_1_SQRT_2, 0.0);
+    let i = Complex::new(0.0, std::f64::consts::FRAC_1_SQRT_2);
+    let o = Complex::new(0.0, 0.0);
+    DMatrix::from_row_slice(4, 4, &[h, i, o, o, o, o, i, h, o, o, i, -h, h, -i, o, o])
+}
+
+/// Finds a real orthogonal `O` with determinant 1 and the diagonal `D` of
+/// `O^T P O` for a symmetric unitary `P`. The real and imaginary parts of
+/// `P` commute, so the eigenvectors of a generic real combination of them
+/// diagonalise both.
+fn diagonalize_symmetric_unitary(
+    p: &DMatrix<Complex<f64>>,
+) -> (DMatrix<Complex<f64>>, Vec<Complex<f64>>) {
+    let real = p.map(|entry| entry.re);
+    let imag = p.map(|entry| entry.im);
+    for weight in [0.5772, 1.3247, 2.5029, 0.2923] {
+        let eigen = SymmetricEigen::new(&real + &imag * weight);
+        let mut o = eigen.eigenvectors;
+        if o.determinant() < 0.0 {
+            o.column_mut(0).neg_mut();
+        }
+        let o = o.map(|entry| Complex::new(entry, 0.0));
+        let d = o.transpose() * p * &o;
+        let off_diagonal = (0..4)
+            .flat_map(|row| (0..4).map(move |col| (row, col)))
+            .filter(|(row, col)| row != col)
+            .map(|index| d[index].norm())
+            .fold(0.0, f64::max);
+        if off_diagonal < 1e-7 {
+            return (o, (0..4).map(|k| d[(k, k)]).collect());
+        }
+    }
+    unreachable!("the real and imaginary parts of a symmetric unitary commute")
+}
+
+/// Splits `matrix = A ⊗ B` into two unitaries, up to a global phase.
+fn tensor_factors(
+    matrix: &DMatrix<Complex<f64>>,
+) -> (DMatrix<Complex<f64>>, DMatrix<Complex<f64>>) {
+    let (index, _) = matrix
+        .iter()
+        .enumerate()
+        .max_by(|x, y| x.1.norm().total_cmp(&y.1.norm()))
+        .unwrap();
+    let (row, col) = (index % 4, index / 4);
+    let (a_row, b_row, a_col, b_col) = (row / 2, row % 2, col / 2, col % 2);
+
+    let b = DMatrix::from_fn(2, 2, |r, c| matrix[(2 * a_row + r, 2 * a_col + c)]);
+    let a = DMatrix::from_fn(2, 2, |r, c| {
+        matrix[(2 * r + b_row, 2 * c + b_col)] / b[(b_row, b_col)]
+    });
+    let scale = b.determinant().norm().sqrt();
+    (a * Complex::new(scale, 0.0), b / Complex::new(scale, 0.0))
+}
+
+fn gate_matrix(name: &str, parameters: &[f64]) -> DMatrix<Complex<f64>> {
+    standard_gate(name, parameters)
+        .expect("built-in gate")
+        .matrix_representation()
+}
+
+/// The angle in `(-π, π]`.
+fn normalize_angle(angle: f64) -> f64 {
+    let angle = angle.rem_euclid(2.0 * PI);
+    if angle > PI {
+        angle - 2.0 * PI
+    } else {
+        angle
+    }
+}
+
+fn approx_equal(a: &DMatrix<Complex<f64>>, b: &DMatrix<Complex<f64>>) -> bool {
+    a.iter()
+        .zip(b.iter())
+        .all(|(x, y)| (x - y).norm() < TOLERANCE)
+}
+
+fn equal_up_to_phase(a: &DMatrix<Complex<f64>>, b: &DMatrix<Complex<f64>>) -> bool {
+    crate::unitary::equal_up_to_phase(a, b, TOLERANCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        circuit::GateMatrix,
+        gate::{Gate, STANDARD_GATES},
+        unitary::circuit_unitary,
+    };
+
+    fn circuit_with(gates: Vec<Instruction>, num_qubits: usize) -> Circuit {
+        Circuit {
+            qubits: (0..num_qubits)
+                .map(|i| QubitInfo::zero(format!("q[{}]", i)))
+                .collect(),
+            classical: vec![],
+            instructions: gates,
+        }
+    }
+
+    fn unitary_of(circuit: &Circuit) -> DMatrix<Complex<f64>> {
+        circuit_unitary(circuit).unwrap().1.matrix_representation()
+    }
+
+    /// Decomposes the circuit in both bases and checks the gates used and
+    /// the unitary, restricted to ancillas starting in `|0>`.
+    fn assert_decomposes(circuit: &Circuit) -> Vec<DecompositionResult> {
+        let expected = unitary_of(circuit);
+        [Basis::CxRzSxX, Basis::CzU3]
+            .into_iter()
+            .map(|basis| {
+                let result = decompose_circuit(circuit, basis).unwrap();
+                for instruction in &result.circuit.instructions {
+                    let Operation::Gate { name, .. } = &instruction.operation else {
+                        panic!("unexpected instruction {:?}", instruction);
+                    };
+                    assert!(basis.gates().contains(&name.as_str()), "{}", name);
+                }
+
+                let full = unitary_of(&result.circuit);
+                let shift = result.ancillas;
+                let size = expected.nrows();
+                let actual =
+                    DMatrix::from_fn(size, size, |row, col| full[(row << shift, col << shift)]);
+                assert!(
+                    equal_up_to_phase(&expected, &actual),
+                    "{:?} in {:?}: expected {} got {}",
+                    circuit.instructions,
+                    basis,
+                    expected,
+                    actual
+                );
+                result
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_basis_parsing() {
+        assert_eq!("cx,rz,sx,x".parse(), Ok(Basis::CxRzSxX));
+        assert_eq!("X, SX, RZ, CX".parse(), Ok(Basis::CxRzSxX));
+        assert_eq!("cz,u3".parse(), Ok(Basis::CzU3));
+        assert!("cz,rx".parse::<Basis>().is_err());
+    }
+
+    #[test]
+    fn test_standard_gates_decompose() {
+        let parameters = [0.7, -1.3, 2.1];
+        for (name, count) in STANDARD_GATES {
+            let num_qubits = standard_gate(name, &parameters[..*count])
+                .unwrap()
+                .num_qubits();
+            let gate = Instruction::gate(
+                name,
+                parameters[..*count].to_vec(),
+                (0..num_qubits).rev().collect(),
+            );
+            assert_decomposes(&circuit_with(vec![gate], num_qubits));
+        }
+    }
+
+    #[test]
+    fn test_euler_angles_of_special_cases() {
+        use std::f64::consts::FRAC_PI_2;
+
+        for (theta, phi, lambda) in [
+            (0.0, 0.3, 0.4),
+            (FRAC_PI_2, 0.3, -2.0),
+            (PI, 0.0, PI),
+            (PI, 1.0, 0.2),
+            (2.5, -3.0, 3.1),
+        ] {
+            let gate = Instruction::gate("u", vec![theta, phi, lambda], vec![0]);
+            assert_decomposes(&circuit_with(vec![gate], 1));
+        }
+    }
+
+    #[test]
+    fn test_matrix_gates_decompose() {
+        // A random two-qubit unitary from the QR decomposition of a fixed
+        // complex matrix.
+        let entries: Vec<Complex<f64>> = (0..16)
+            .map(|k| {
+                let k = k as f64;
+                Complex::new((1.3 * k + 0.2).sin(), (0.7 * k * k + 1.1).cos())
+            })
+            .collect();
+        let random = DMatrix::from_row_slice(4, 4, &entries).qr().q();
+        let gate = |matrix: &DMatrix<Complex<f64>>, qubits: Vec<usize>| Instruction {
+            operation: Operation::Gate {
+                name: "custom".to_string(),
+                parameters: vec![],
+                matrix: Some(GateMatrix::from_matrix(matrix)),
+            },
+            ..Instruction::gate("custom", vec![], qubits)
+        };
+
+        let results = assert_decomposes(&circuit_with(
+            vec![gate(&random, vec![0, 2]), gate(&random, vec![2, 1])],
+            3,
+        ));
+        assert_eq!(results[0].ancillas, 0);
+
+        // A local gate needs no CNOTs.
+        let local = gate_matrix("hadamard", &[]).kronecker(&gate_matrix("t", &[]));
+        let results = assert_decomposes(&circuit_with(vec![gate(&local, vec![1, 0])], 2));
+        assert_eq!(results[1].gates_after, 2);
+    }
+
+    #[test]
+    fn test_multi_controlled_gates_use_ancillas() {
+        let h = gate_matrix("hadamard", &[]);
+        let mut matrix: DMatrix<Complex<f64>> = DMatrix::identity(16, 16);
+        matrix.view_mut((14, 14), (2, 2)).copy_from(&h);
+        let gate = Instruction {
+            operation: Operation::Gate {
+                name: "c3h".to_string(),
+                parameters: vec![],
+                matrix: Some(GateMatrix::from_matrix(&matrix)),
+            },
+            ..Instruction::gate("c3h", vec![], vec![3, 1, 0, 2])
+        };
+
+        let results = assert_decomposes(&circuit_with(vec![gate], 4));
+        assert_eq!(results[0].ancillas, 2);
+        assert_eq!(results[0].circuit.qubits[5].label, "ancilla[1]");
+    }
+
+    #[test]
+    fn test_conditions_and_measurements_are_kept() {
+        let mut circuit = crate::lower::lower_program(
+            &crate::qasm::parse_qasm(
+                "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\ncreg c[1];\nh q[0];\n\
+                 measure q[0] -> c[0];\nif(c==1) swap q[0], q[1];",
+            )
+            .unwrap(),
+        );
+        circuit.instructions.truncate(3);
+        let result = decompose_circuit(&circuit, Basis::CxRzSxX).unwrap();
+
+        let instructions = &result.circuit.instructions;
+        assert!(matches!(
+            instructions[3].operation,
+            Operation::Measure { .. }
+        ));
+        assert_eq!(instructions.len(), 7);
+        assert!(instructions[4..]
+            .iter()
+            .all(|instruction| instruction.condition.is_some()));
+    }
+}
@@ -0,0 +1,163 @@
+//! LaTeX source for the `quantikz` TikZ library.
+
+use serde::Deserialize;
+
+use super::{format_angle, pack, wire_labels, Label, Symbol};
+use crate::circuit::Circuit;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct LatexOptions {
+    /// Only write the `quantikz` environment, for pasting into a document.
+    #[serde(default)]
+    pub fragment: bool,
+}
+
+const PREAMBLE: &str = "\\documentclass[border=6pt]{standalone}
+\\usepackage{tikz}
+\\usetikzlibrary{quantikz2}
+\\begin{document}
+";
+
+/// Renders the circuit as a `quantikz` environment, wrapped in a standalone
+/// document unless `options.fragment` is set.
+pub fn circuit_latex(circuit: &Circuit, options: LatexOptions) -> String {
+    let num_qubits = circuit.qubits.len();
+    let labels = wire_labels(circuit);
+    let columns = pack(circuit);
+
+    let mut rows: Vec<Vec<String>> = vec![vec![String::new(); columns.len()]; labels.len()];
+    for (index, column) in columns.iter().enumerate() {
+        for element in column {
+            let (lo, hi) = element.quantum_span(num_qubits);
+            for (wire, symbol) in &element.symbols {
+                let distance = hi - lo;
+                let top = *wire == lo && element.connected && distance > 0;
+                let mut cell = match symbol {
+                    Symbol::Control if top => format!("\\ctrl{{{}}}", distance),
+                    Symbol::Control => "\\control{}".to_string(),
+                    Symbol::Swap if top => format!("\\swap{{{}}}", distance),
+                    Symbol::Swap => "\\targX{}".to_string(),
+                    Symbol::Target => "\\targ{}".to_string(),
+                    Symbol::Box(label) => format!("\\gate{{{}}}", latex_label(label)),
+                    Symbol::Meter => "\\meter{}".to_string(),
+                    Symbol::Reset => "\\gate{\\ket{0}}".to_string(),
+                    Symbol::Condition(value) => format!("\\gate{{={}}}", value),
+                    Symbol::ClassicalEnd(_) => String::new(),
+                    Symbol::Barrier if *wire == lo => "\\slice{}".to_string(),
+                    Symbol::Barrier => String::new(),
+                };
+                if top && matches!(symbol, Symbol::Target | Symbol::Box(_)) {
+                    cell.push_str(&format!(" \\wire[d][{}]{{q}}", distance));
+                }
+                if *wire == hi && *wire < num_qubits {
+                    if let Some((classical, _)) =
+                        element.symbols.iter().find(|(w, _)| *w >= num_qubits)
+                    {
+                        cell.push_str(&format!(" \\wire[d][{}]{{c}}", classical - hi));
+                    }
+                }
+                rows[*wire][index] = cell;
+            }
+        }
+    }
+
+    let mut latex = String::new();
+    if !options.fragment {
+        latex.push_str(PREAMBLE);
+    }
+    latex.push_str("\\begin{quantikz}\n");
+    let lines: Vec<String> = rows
+        .iter()
+        .enumerate()
+        .map(|(wire, cells)| {
+            let mut line = format!("\\lstick{{${}$}}", escape(&labels[wire]));
+            if wire >= num_qubits {
+                line.push_str(" \\setwiretype{c}");
+            }
+            for cell in cells {
+                line.push_str(" &");
+                if !cell.is_empty() {
+                    line.push(' ');
+                    line.push_str(cell);
+                }
+            }
+            line.push_str(" &");
+            line
+        })
+        .collect();
+    latex.push_str(&lines.join(" \\\\\n"));
+    latex.push_str("\n\\end{quantikz}\n");
+    if !options.fragment {
+        latex.push_str("\\end{document}\n");
+    }
+    latex
+}
+
+/// The label of a gate box in math mode, e.g. `R_z(\pi/4)`.
+fn latex_label(label: &Label) -> String {
+    let base = match label.base.as_str() {
+        "S†" => "S^\\dagger".to_string(),
+        "T†" => "T^\\dagger".to_string(),
+        "√X" => "\\sqrt{X}".to_string(),
+        "√X†" => "\\sqrt{X}^\\dagger".to_string(),
+        "Rx" | "Ry" | "Rz" => format!("R_{}", &label.base[1..]),
+        "Rxx" | "Rzz" => format!("R_{{{}}}", &label.base[1..]),
+        "I" | "X" | "Y" | "Z" | "H" | "S" | "T" | "P" | "U" => label.base.clone(),
+        other => format!("\\mathrm{{{}}}", escape(other)),
+    };
+    if label.parameters.is_empty() {
+        return base;
+    }
+    let parameters: Vec<String> = label
+        .parameters
+        .iter()
+        .map(|p| format_angle(*p).replace('π', "\\pi"))
+        .collect();
+    format!("{}({})", base, parameters.join(", "))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagram::tests::lower_qasm;
+
+    #[test]
+    fn test_standard_gates_snapshot() {
+        let circuit = lower_qasm(
+            "qreg q[3];\ncreg c[2];\nh q[0];\nsdg q[1];\nsx q[2];\nrz(pi/4) q[0];\n\
+             u3(pi,0,pi/2) q[1];\ncx q[0], q[1];\ncz q[1], q[2];\nccx q[0], q[1], q[2];\n\
+             cswap q[0], q[1], q[2];\nrzz(0.5) q[0], q[2];\nbarrier q;\nreset q[2];\n\
+             measure q[0] -> c[0];\nif(c==1) x q[1];",
+        );
+        let latex = circuit_latex(&circuit, LatexOptions::default());
+
+        let expected = r"\documentclass[border=6pt]{standalone}
+\usepackage{tikz}
+\usetikzlibrary{quantikz2}
+\begin{document}
+\begin{quantikz}
+\lstick{$q[0]$} & \gate{H} & \gate{R_z(\pi/4)} & \ctrl{1} & & \ctrl{2} & \ctrl{2} & \gate{R_{zz}(0.5)} \wire[d][2]{q} & \slice{} & & \meter{} \wire[d][3]{c} & & \\
+\lstick{$q[1]$} & \gate{S^\dagger} & \gate{U(\pi, 0, \pi/2)} & \targ{} & \ctrl{1} & \control{} & \targX{} & & & & & \gate{X} \wire[d][2]{c} & \\
+\lstick{$q[2]$} & \gate{\sqrt{X}} & & & \control{} & \targ{} & \targX{} & \gate{R_{zz}(0.5)} & & \gate{\ket{0}} & & & \\
+\lstick{$c$} \setwiretype{c} & & & & & & & & & & & \gate{=1} &
+\end{quantikz}
+\end{document}
+";
+        assert_eq!(latex, expected);
+    }
+
+    #[test]
+    fn test_fragment_and_names() {
+        let circuit = lower_qasm("qreg my_q[1];\ngate my_gate a { h a; }\nmy_gate my_q[0];");
+        let latex = circuit_latex(&circuit, LatexOptions { fragment: true });
+
+        assert_eq!(
+            latex,
+            "\\begin{quantikz}\n\\lstick{$my\\_q[0]$} & \\gate{H} &\n\\end{quantikz}\n"
+        );
+    }
+}
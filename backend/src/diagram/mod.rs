@@ -0,0 +1,312 @@
+//! Drawings of lowered circuits as text, SVG and LaTeX (quantikz).
+//!
+//! All three share the same layout: qubits come first in register order,
+//! then one wire per classical register, and instructions are packed left
+//! into the first column where all the wires they span are free.
+
+mod latex;
+mod svg;
+mod text;
+
+pub use latex::{circuit_latex, LatexOptions};
+pub use svg::circuit_svg;
+pub use text::{draw_circuit, DiagramOptions};
+
+use std::str::FromStr;
+
+use crate::circuit::{Circuit, Instruction, Operation};
+
+/// Output format of a circuit drawing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DrawingFormat {
+    #[default]
+    Text,
+    Svg,
+    Latex,
+}
+
+impl FromStr for DrawingFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(DrawingFormat::Text),
+            "svg" => Ok(DrawingFormat::Svg),
+            "latex" | "quantikz" => Ok(DrawingFormat::Latex),
+            _ => Err(format!(
+                "Unknown drawing format '{}': expected text, svg or latex",
+                s
+            )),
+        }
+    }
+}
+
+/// Gate name and parameters as shown in a box, e.g. `Rz` and `[π/4]`.
+#[derive(Debug, Clone, PartialEq)]
+struct Label {
+    base: String,
+    parameters: Vec<f64>,
+}
+
+impl Label {
+    fn text(&self) -> String {
+        gate_label(&self.base, &self.parameters)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Symbol {
+    Control,
+    Target,
+    Swap,
+    Box(Label),
+    Meter,
+    Reset,
+    /// Where a measurement lands on a classical wire, with the bit index.
+    ClassicalEnd(usize),
+    /// The value a conditional instruction compares its register with.
+    Condition(u64),
+    Barrier,
+}
+
+/// What one instruction draws in its column.
+struct Element {
+    symbols: Vec<(usize, Symbol)>,
+    /// Draw a vertical line between the quantum symbols.
+    connected: bool,
+}
+
+impl Element {
+    fn span(&self) -> (usize, usize) {
+        let wires = self.symbols.iter().map(|(wire, _)| *wire);
+        (wires.clone().min().unwrap(), wires.max().unwrap())
+    }
+
+    /// Lowest and highest qubit wire, ignoring classical wires.
+    fn quantum_span(&self, num_qubits: usize) -> (usize, usize) {
+        let wires = self
+            .symbols
+            .iter()
+            .map(|(wire, _)| *wire)
+            .filter(|wire| *wire < num_qubits);
+        (wires.clone().min().unwrap_or(0), wires.max().unwrap_or(0))
+    }
+}
+
+/// Wire labels: qubit labels followed by classical register names.
+fn wire_labels(circuit: &Circuit) -> Vec<String> {
+    let mut labels = circuit.labels();
+    labels.extend(
+        circuit
+            .classical
+            .iter()
+            .map(|register| register.name.clone()),
+    );
+    labels
+}
+
+/// Groups the drawable instructions into columns.
+fn pack(circuit: &Circuit) -> Vec<Vec<Element>> {
+    let mut columns: Vec<Vec<Element>> = vec![];
+    let mut next_free = vec![0; circuit.qubits.len() + circuit.classical.len()];
+
+    for instruction in &circuit.instructions {
+        for element in elements(circuit, instruction) {
+            let (lo, hi) = element.span();
+            let column = next_free[lo..=hi].iter().copied().max().unwrap_or(0);
+            for free in &mut next_free[lo..=hi] {
+                *free = column + 1;
+            }
+            if column == columns.len() {
+                columns.push(vec![]);
+            }
+            columns[column].push(element);
+        }
+    }
+    columns
+}
+
+fn elements(circuit: &Circuit, instruction: &Instruction) -> Vec<Element> {
+    let qubits = &instruction.qubits;
+    let num_qubits = circuit.qubits.len();
+    // Wire and index within its register of every classical bit.
+    let clbit = |bit: usize| {
+        let mut offset = 0;
+        for (i, register) in circuit.classical.iter().enumerate() {
+            if bit < offset + register.size {
+                return (num_qubits + i, bit - offset);
+            }
+            offset += register.size;
+        }
+        unreachable!("clbit {} is out of range", bit)
+    };
+
+    let mut elements = match &instruction.operation {
+        Operation::Gate {
+            name, parameters, ..
+        } => vec![Element {
+            symbols: gate_symbols(name, parameters, qubits),
+            connected: true,
+        }],
+        Operation::Measure { .. } => qubits
+            .iter()
+            .enumerate()
+            .map(|(i, &qubit)| {
+                let mut symbols = vec![(qubit, Symbol::Meter)];
+                if let Some(&bit) = instruction.clbits.get(i) {
+                    let (wire, index) = clbit(bit);
+                    symbols.push((wire, Symbol::ClassicalEnd(index)));
+                }
+                Element {
+                    symbols,
+                    connected: false,
+                }
+            })
+            .collect(),
+        Operation::Reset => vec![Element {
+            symbols: qubits.iter().map(|&q| (q, Symbol::Reset)).collect(),
+            connected: false,
+        }],
+        Operation::Barrier if !qubits.is_empty() => vec![Element {
+            symbols: qubits.iter().map(|&q| (q, Symbol::Barrier)).collect(),
+            connected: false,
+        }],
+        _ => vec![],
+    };
+
+    if let Some(condition) = &instruction.condition {
+        if let Some(index) = circuit
+            .classical
+            .iter()
+            .position(|register| register.name == condition.register)
+        {
+            for element in &mut elements {
+                element
+                    .symbols
+                    .push((num_qubits + index, Symbol::Condition(condition.value)));
+            }
+        }
+    }
+    elements
+}
+
+fn gate_symbols(name: &str, parameters: &[f64], qubits: &[usize]) -> Vec<(usize, Symbol)> {
+    let label = |base: &str| {
+        Symbol::Box(Label {
+            base: base.to_string(),
+            parameters: parameters.to_vec(),
+        })
+    };
+    let controls = |count: usize, rest: Vec<Symbol>| {
+        let mut symbols = vec![Symbol::Control; count];
+        symbols.extend(rest);
+        symbols
+    };
+
+    let symbols = match name {
+        "cnot" => controls(1, vec![Symbol::Target]),
+        "toffoli" => controls(2, vec![Symbol::Target]),
+        "cz" => controls(2, vec![]),
+        "swap" => vec![Symbol::Swap, Symbol::Swap],
+        "fredkin" => controls(1, vec![Symbol::Swap, Symbol::Swap]),
+        "cy" => controls(1, vec![label("Y")]),
+        "ch" => controls(1, vec![label("H")]),
+        "controlledPhase" => controls(1, vec![label("P")]),
+        "crx" => controls(1, vec![label("Rx")]),
+        "cry" => controls(1, vec![label("Ry")]),
+        "crz" => controls(1, vec![label("Rz")]),
+        "cu" => controls(1, vec![label("U")]),
+        _ => {
+            let base = match name {
+                "identity" => "I",
+                "pauliX" => "X",
+                "pauliY" => "Y",
+                "pauliZ" => "Z",
+                "hadamard" => "H",
+                "s" => "S",
+                "sDagger" => "S†",
+                "t" => "T",
+                "tDagger" => "T†",
+                "sqrtX" => "√X",
+                "sqrtXDagger" => "√X†",
+                "phase" => "P",
+                "rx" => "Rx",
+                "ry" => "Ry",
+                "rz" => "Rz",
+                "u" => "U",
+                "rxx" => "Rxx",
+                "rzz" => "Rzz",
+                other => other,
+            };
+            vec![label(base); qubits.len()]
+        }
+    };
+    qubits.iter().copied().zip(symbols).collect()
+}
+
+/// `Rz(π/4)`-style label of a gate with its parameters.
+pub fn gate_label(base: &str, parameters: &[f64]) -> String {
+    if parameters.is_empty() {
+        base.to_string()
+    } else {
+        let parameters: Vec<String> = parameters.iter().map(|p| format_angle(*p)).collect();
+        format!("{}({})", base, parameters.join(","))
+    }
+}
+
+/// Writes simple fractions of π as `π/4`, `-3π/2`, ...; other angles with
+/// up to three decimals.
+pub fn format_angle(angle: f64) -> String {
+    const EPSILON: f64 = 1e-9;
+    let ratio = angle / std::f64::consts::PI;
+
+    for denominator in [1i64, 2, 3, 4, 6, 8, 12, 16] {
+        let numerator = ratio * denominator as f64;
+        if (numerator - numerator.round()).abs() > EPSILON {
+            continue;
+        }
+        let numerator = numerator.round() as i64;
+        let prefix = match numerator {
+            0 => return "0".to_string(),
+            1 => "π".to_string(),
+            -1 => "-π".to_string(),
+            n => format!("{}π", n),
+        };
+        return if denominator == 1 {
+            prefix
+        } else {
+            format!("{}/{}", prefix, denominator)
+        };
+    }
+
+    let text = format!("{:.3}", angle);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lower::lower_program, qasm::parse_qasm};
+
+    /// Lowers an OpenQASM 2.0 body that uses the standard gate library.
+    pub(super) fn lower_qasm(source: &str) -> Circuit {
+        let program = parse_qasm(&format!(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\n{}",
+            source
+        ))
+        .unwrap();
+        lower_program(&program)
+    }
+
+    #[test]
+    fn test_format_angle() {
+        use std::f64::consts::PI;
+
+        assert_eq!(format_angle(PI / 4.0), "π/4");
+        assert_eq!(format_angle(-3.0 * PI / 2.0), "-3π/2");
+        assert_eq!(format_angle(2.0 * PI), "2π");
+        assert_eq!(format_angle(0.0), "0");
+        assert_eq!(format_angle(0.5), "0.5");
+        assert_eq!(gate_label("U", &[PI, 0.0, 1.25]), "U(π,0,1.25)");
+    }
+}
@@ -0,0 +1,274 @@
+//! Standalone SVG drawings.
+
+use std::fmt::Write;
+
+use super::{pack, wire_labels, Element, Symbol};
+use crate::circuit::Circuit;
+
+/// Distance between two wires.
+const ROW: i64 = 48;
+const MARGIN: i64 = 16;
+/// Width of one character of the monospace font.
+const CHAR: i64 = 9;
+const BOX_HEIGHT: i64 = 32;
+/// Space around every column.
+const PADDING: i64 = 12;
+
+const STYLE: &str = "<style>
+  text { font-family: monospace; font-size: 14px; dominant-baseline: central; }
+  .label { text-anchor: end; }
+  .gate-text { text-anchor: middle; }
+  .bit { text-anchor: middle; font-size: 10px; }
+  .wire { stroke: black; stroke-width: 1; }
+  .gate { fill: white; stroke: black; stroke-width: 1; }
+  .dot { fill: black; }
+  .barrier { stroke: gray; stroke-width: 2; stroke-dasharray: 4 3; }
+</style>";
+
+/// Renders the circuit as a standalone SVG document.
+pub fn circuit_svg(circuit: &Circuit) -> String {
+    let num_qubits = circuit.qubits.len();
+    let labels = wire_labels(circuit);
+    let columns = pack(circuit);
+
+    let label_width = labels
+        .iter()
+        .map(|label| label.chars().count() as i64)
+        .max()
+        .unwrap_or(0)
+        * CHAR
+        + MARGIN;
+    let widths: Vec<i64> = columns
+        .iter()
+        .map(|column| column.iter().map(element_width).max().unwrap_or(0) + 2 * PADDING)
+        .collect();
+    let width = label_width + MARGIN + widths.iter().sum::<i64>() + MARGIN;
+    let height = 2 * MARGIN + labels.len() as i64 * ROW;
+    let y = |wire: usize| MARGIN + ROW / 2 + wire as i64 * ROW;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">",
+        width, height
+    );
+    let _ = writeln!(svg, "{}", STYLE);
+    let _ = writeln!(
+        svg,
+        "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>",
+        width, height
+    );
+
+    let start = label_width + MARGIN / 2;
+    let end = width - MARGIN;
+    for (wire, label) in labels.iter().enumerate() {
+        let _ = writeln!(
+            svg,
+            "<text class=\"label\" x=\"{}\" y=\"{}\">{}</text>",
+            label_width,
+            y(wire),
+            escape(label)
+        );
+        if wire < num_qubits {
+            line(&mut svg, start, y(wire), end, y(wire), "wire");
+        } else {
+            line(&mut svg, start, y(wire) - 2, end, y(wire) - 2, "wire");
+            line(&mut svg, start, y(wire) + 2, end, y(wire) + 2, "wire");
+        }
+    }
+
+    let mut x = label_width + MARGIN;
+    for (column, column_width) in columns.iter().zip(&widths) {
+        let cx = x + column_width / 2;
+        let box_width = column_width - 2 * PADDING;
+        for element in column {
+            let (lo, hi) = element.quantum_span(num_qubits);
+            if element.connected && lo < hi {
+                line(&mut svg, cx, y(lo), cx, y(hi), "wire");
+            }
+            for (wire, _) in element.symbols.iter().filter(|(w, _)| *w >= num_qubits) {
+                line(&mut svg, cx - 2, y(hi), cx - 2, y(*wire), "wire");
+                line(&mut svg, cx + 2, y(hi), cx + 2, y(*wire), "wire");
+            }
+            for (wire, symbol) in &element.symbols {
+                draw_symbol(&mut svg, symbol, cx, y(*wire), box_width);
+            }
+        }
+        x += column_width;
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Width a symbol needs, without the column padding.
+fn element_width(element: &Element) -> i64 {
+    element
+        .symbols
+        .iter()
+        .map(|(_, symbol)| match symbol {
+            Symbol::Box(label) => text_width(&label.text()),
+            Symbol::Condition(value) => text_width(&format!("={}", value)),
+            Symbol::Reset => text_width("|0⟩"),
+            _ => BOX_HEIGHT,
+        })
+        .max()
+        .unwrap_or(BOX_HEIGHT)
+}
+
+/// Width of a box around `text`, even so that it centres on a pixel.
+fn text_width(text: &str) -> i64 {
+    let width = text.chars().count() as i64 * CHAR + 2 * PADDING;
+    (width + width % 2).max(BOX_HEIGHT)
+}
+
+fn draw_symbol(svg: &mut String, symbol: &Symbol, cx: i64, cy: i64, box_width: i64) {
+    match symbol {
+        Symbol::Control => circle(svg, cx, cy, 5, "dot"),
+        Symbol::Target => {
+            circle(svg, cx, cy, 10, "gate");
+            line(svg, cx - 10, cy, cx + 10, cy, "wire");
+            line(svg, cx, cy - 10, cx, cy + 10, "wire");
+        }
+        Symbol::Swap => {
+            line(svg, cx - 7, cy - 7, cx + 7, cy + 7, "wire");
+            line(svg, cx - 7, cy + 7, cx + 7, cy - 7, "wire");
+        }
+        Symbol::Box(label) => gate_box(svg, cx, cy, box_width, &label.text()),
+        Symbol::Reset => gate_box(svg, cx, cy, box_width, "|0⟩"),
+        Symbol::Condition(value) => gate_box(svg, cx, cy, box_width, &format!("={}", value)),
+        Symbol::Meter => {
+            gate_box(svg, cx, cy, BOX_HEIGHT, "");
+            let _ = writeln!(
+                svg,
+                "<path class=\"wire\" fill=\"none\" d=\"M {} {} A 10 10 0 0 1 {} {}\"/>",
+                cx - 10,
+                cy + 6,
+                cx + 10,
+                cy + 6
+            );
+            line(svg, cx, cy + 6, cx + 7, cy - 8, "wire");
+        }
+        Symbol::ClassicalEnd(bit) => {
+            let _ = writeln!(
+                svg,
+                "<polygon class=\"dot\" points=\"{},{} {},{} {},{}\"/>",
+                cx - 5,
+                cy - 8,
+                cx + 5,
+                cy - 8,
+                cx,
+                cy
+            );
+            let _ = writeln!(
+                svg,
+                "<text class=\"bit\" x=\"{}\" y=\"{}\">{}</text>",
+                cx,
+                cy + 12,
+                bit
+            );
+        }
+        Symbol::Barrier => line(svg, cx, cy - ROW / 2, cx, cy + ROW / 2, "barrier"),
+    }
+}
+
+fn gate_box(svg: &mut String, cx: i64, cy: i64, width: i64, text: &str) {
+    let _ = writeln!(
+        svg,
+        "<rect class=\"gate\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>",
+        cx - width / 2,
+        cy - BOX_HEIGHT / 2,
+        width,
+        BOX_HEIGHT
+    );
+    if !text.is_empty() {
+        let _ = writeln!(
+            svg,
+            "<text class=\"gate-text\" x=\"{}\" y=\"{}\">{}</text>",
+            cx,
+            cy,
+            escape(text)
+        );
+    }
+}
+
+fn line(svg: &mut String, x1: i64, y1: i64, x2: i64, y2: i64, class: &str) {
+    let _ = writeln!(
+        svg,
+        "<line class=\"{}\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>",
+        class, x1, y1, x2, y2
+    );
+}
+
+fn circle(svg: &mut String, cx: i64, cy: i64, r: i64, class: &str) {
+    let _ = writeln!(
+        svg,
+        "<circle class=\"{}\" cx=\"{}\" cy=\"{}\" r=\"{}\"/>",
+        class, cx, cy, r
+    );
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagram::tests::lower_qasm;
+
+    #[test]
+    fn test_single_gate_snapshot() {
+        let svg = circuit_svg(&lower_qasm("qreg q[1];\nh q[0];"));
+
+        let expected = r#"<svg xmlns="http://www.w3.org/2000/svg" width="142" height="80" viewBox="0 0 142 80">
+<style>
+  text { font-family: monospace; font-size: 14px; dominant-baseline: central; }
+  .label { text-anchor: end; }
+  .gate-text { text-anchor: middle; }
+  .bit { text-anchor: middle; font-size: 10px; }
+  .wire { stroke: black; stroke-width: 1; }
+  .gate { fill: white; stroke: black; stroke-width: 1; }
+  .dot { fill: black; }
+  .barrier { stroke: gray; stroke-width: 2; stroke-dasharray: 4 3; }
+</style>
+<rect width="142" height="80" fill="white"/>
+<text class="label" x="52" y="40">q[0]</text>
+<line class="wire" x1="60" y1="40" x2="126" y2="40"/>
+<rect class="gate" x="80" y="24" width="34" height="32"/>
+<text class="gate-text" x="97" y="40">H</text>
+</svg>
+"#;
+        assert_eq!(svg, expected);
+    }
+
+    #[test]
+    fn test_standard_gate_labels() {
+        let svg = circuit_svg(&lower_qasm(
+            "qreg q[3];\ncreg c[3];\nh q[0];\nsdg q[1];\nsx q[2];\nrz(pi/4) q[0];\n\
+             u3(pi,0,pi/2) q[1];\ncx q[0], q[1];\nccx q[0], q[1], q[2];\nswap q[0], q[2];\n\
+             rxx(pi/2) q[0], q[1];\nbarrier q;\nreset q[2];\nmeasure q -> c;\nif(c==5) x q[0];",
+        ));
+
+        for label in [
+            ">H<",
+            ">S†<",
+            ">√X<",
+            ">Rz(π/4)<",
+            ">U(π,0,π/2)<",
+            ">Rxx(π/2)<",
+            ">|0⟩<",
+            ">=5<",
+            ">c<",
+        ] {
+            assert!(svg.contains(label), "missing {}", label);
+        }
+        assert_eq!(svg.matches("class=\"dot\" cx").count(), 3);
+        assert_eq!(svg.matches("<circle class=\"gate\"").count(), 2);
+        assert_eq!(svg.matches("<polygon").count(), 3);
+        assert_eq!(svg.matches("class=\"barrier\"").count(), 3);
+    }
+}
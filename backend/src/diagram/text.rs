@@ -0,0 +1,349 @@
+//! Text drawings built from box-drawing characters.
+//!
+//! Every wire takes three lines: the line above it, the wire itself and the
+//! line below it, so boxes on neighbouring wires never overlap.
+
+use serde::Deserialize;
+
+use super::{pack, wire_labels, Element, Symbol};
+use crate::circuit::Circuit;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+pub struct DiagramOptions {
+    /// Fold the drawing into pages at most this many characters wide.
+    #[serde(default)]
+    pub width: Option<usize>,
+    /// Use plain ASCII instead of box-drawing characters.
+    #[serde(default)]
+    pub ascii: bool,
+}
+
+/// Box-drawing characters and their ASCII replacements.
+const ASCII: &[(char, char)] = &[
+    ('─', '-'),
+    ('═', '='),
+    ('│', '|'),
+    ('║', '|'),
+    ('┌', '+'),
+    ('┐', '+'),
+    ('└', '+'),
+    ('┘', '+'),
+    ('┤', '|'),
+    ('├', '|'),
+    ('╡', '|'),
+    ('╞', '|'),
+    ('┴', '+'),
+    ('┬', '+'),
+    ('╨', '+'),
+    ('╥', '+'),
+    ('┼', '+'),
+    ('╪', '+'),
+    ('╫', '+'),
+    ('╬', '+'),
+    ('╩', 'v'),
+    ('●', '*'),
+    ('⊕', 'X'),
+    ('✕', 'x'),
+    ('░', '#'),
+    ('π', 'p'),
+    ('√', 'V'),
+    ('†', '~'),
+    ('»', '>'),
+    ('«', '<'),
+];
+
+/// Characters an element needs, odd so that vertical lines are centred.
+fn element_width(element: &Element) -> usize {
+    let width = element
+        .symbols
+        .iter()
+        .map(|(_, symbol)| match symbol {
+            Symbol::Box(label) => label.text().chars().count() + 4,
+            Symbol::Condition(value) => value.to_string().len() + 5,
+            Symbol::Reset => 7,
+            Symbol::Meter => 5,
+            Symbol::ClassicalEnd(bit) => bit.to_string().len(),
+            _ => 1,
+        })
+        .max()
+        .unwrap_or(1);
+    width | 1
+}
+
+pub fn draw_circuit(circuit: &Circuit, options: DiagramOptions) -> String {
+    let num_qubits = circuit.qubits.len();
+    let labels = wire_labels(circuit);
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let columns = pack(circuit);
+    let rendered: Vec<Vec<Vec<char>>> = columns
+        .iter()
+        .map(|column| render_column(column, labels.len(), num_qubits))
+        .collect();
+
+    let label_width = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let pages = fold(&rendered, label_width + 3, options.width);
+
+    let mut output = vec![];
+    for (page, range) in pages.iter().enumerate() {
+        if page > 0 {
+            output.push(String::new());
+        }
+        for line in 0..labels.len() * 3 {
+            let wire = line / 3;
+            let mid = line % 3 == 1;
+            let mut text = String::new();
+            if page > 0 {
+                text.push(if mid { '«' } else { ' ' });
+            }
+            if mid {
+                text.push_str(&format!("{:>width$}: ", labels[wire], width = label_width));
+            } else {
+                text.push_str(&" ".repeat(label_width + 2));
+            }
+            for column in &rendered[range.clone()] {
+                text.extend(&column[line]);
+            }
+            if page + 1 < pages.len() && mid {
+                text.push('»');
+            }
+            output.push(text.trim_end().to_string());
+        }
+    }
+
+    let mut text = output.join("\n");
+    if options.ascii {
+        text = text
+            .chars()
+            .map(|c| {
+                ASCII
+                    .iter()
+                    .find(|(unicode, _)| *unicode == c)
+                    .map_or(c, |(_, ascii)| *ascii)
+            })
+            .collect();
+    }
+    text + "\n"
+}
+
+/// Splits the columns into pages that fit `width` next to the labels.
+fn fold(
+    columns: &[Vec<Vec<char>>],
+    prefix: usize,
+    width: Option<usize>,
+) -> Vec<std::ops::Range<usize>> {
+    let width = width.unwrap_or(usize::MAX);
+    let mut pages = vec![];
+    let mut start = 0;
+    let mut used = prefix + 1;
+    for (i, column) in columns.iter().enumerate() {
+        let column_width = column.first().map_or(0, Vec::len);
+        if i > start && used.saturating_add(column_width) > width {
+            pages.push(start..i);
+            start = i;
+            used = prefix + 1;
+        }
+        used += column_width;
+    }
+    pages.push(start..columns.len());
+    pages
+}
+
+/// Sides of boxes on quantum and classical wires.
+const QUANTUM: (char, char) = ('┤', '├');
+const CLASSICAL: (char, char) = ('╡', '╞');
+
+/// Draws one column as `3 * num_wires` lines of equal width.
+fn render_column(elements: &[Element], num_wires: usize, num_qubits: usize) -> Vec<Vec<char>> {
+    let width = elements.iter().map(element_width).max().unwrap_or(1) + 2;
+    let center = width / 2;
+
+    let mut lines: Vec<Vec<char>> = (0..num_wires * 3)
+        .map(|line| {
+            let fill = match (line % 3, line / 3 < num_qubits) {
+                (1, true) => '─',
+                (1, false) => '═',
+                _ => ' ',
+            };
+            vec![fill; width]
+        })
+        .collect();
+
+    for element in elements {
+        let (lo, hi) = element.quantum_span(num_qubits);
+        if element.connected && lo < hi {
+            connect(&mut lines, lo, hi, false, num_qubits, center);
+        }
+        for (wire, _) in element.symbols.iter().filter(|(w, _)| *w >= num_qubits) {
+            connect(&mut lines, hi, *wire, true, num_qubits, center);
+        }
+
+        for (wire, symbol) in &element.symbols {
+            let mid = wire * 3 + 1;
+            match symbol {
+                Symbol::Control => lines[mid][center] = '●',
+                Symbol::Target => lines[mid][center] = '⊕',
+                Symbol::Swap => lines[mid][center] = '✕',
+                Symbol::ClassicalEnd(bit) => {
+                    lines[mid][center] = '╩';
+                    let text: Vec<char> = bit.to_string().chars().collect();
+                    let start = center - (text.len() - 1) / 2;
+                    lines[mid + 1][start..start + text.len()].copy_from_slice(&text);
+                }
+                Symbol::Barrier => {
+                    for line in &mut lines[mid - 1..=mid + 1] {
+                        line[center] = '░';
+                    }
+                }
+                Symbol::Box(label) => draw_box(&mut lines, mid, &label.text(), QUANTUM, width),
+                Symbol::Meter => draw_box(&mut lines, mid, "M", QUANTUM, width),
+                Symbol::Reset => draw_box(&mut lines, mid, "|0>", QUANTUM, width),
+                Symbol::Condition(value) => {
+                    draw_box(&mut lines, mid, &format!("={}", value), CLASSICAL, width)
+                }
+            }
+        }
+    }
+    lines
+}
+
+/// Draws a vertical line between the wires `from` and `to`, crossing the
+/// wires in between.
+fn connect(
+    lines: &mut [Vec<char>],
+    from: usize,
+    to: usize,
+    double: bool,
+    num_qubits: usize,
+    center: usize,
+) {
+    let (from, to) = (from.min(to), from.max(to));
+    for (line, text) in lines
+        .iter_mut()
+        .enumerate()
+        .take(to * 3 + 1)
+        .skip(from * 3 + 2)
+    {
+        let wire = line / 3;
+        text[center] = match (line % 3 == 1, wire < num_qubits, double) {
+            (false, _, false) => '│',
+            (false, _, true) => '║',
+            (true, true, false) => '┼',
+            (true, true, true) => '╫',
+            (true, false, false) => '╪',
+            (true, false, true) => '╬',
+        };
+    }
+}
+
+/// Draws a box around `label` on the wire at line `mid`, joining any
+/// vertical line that meets its top or bottom edge.
+fn draw_box(lines: &mut [Vec<char>], mid: usize, label: &str, sides: (char, char), width: usize) {
+    let inner = width - 6;
+    let label: Vec<char> = label.chars().collect();
+    let padding = inner - label.len();
+    let center = width / 2;
+
+    let joined = |c: char, single: char, double: char| match c {
+        '│' => single,
+        '║' => double,
+        _ => '─',
+    };
+    let top_join = joined(lines[mid - 1][center], '┴', '╨');
+    let bottom_join = joined(lines[mid + 1][center], '┬', '╥');
+
+    let mut top = vec!['─'; width - 2];
+    let mut bottom = vec!['─'; width - 2];
+    top[0] = '┌';
+    top[width - 3] = '┐';
+    bottom[0] = '└';
+    bottom[width - 3] = '┘';
+    top[center - 1] = top_join;
+    bottom[center - 1] = bottom_join;
+    lines[mid - 1][1..width - 1].copy_from_slice(&top);
+    lines[mid + 1][1..width - 1].copy_from_slice(&bottom);
+
+    let mut text = vec![sides.0, ' '];
+    text.extend(std::iter::repeat_n(' ', padding / 2));
+    text.extend(&label);
+    text.extend(std::iter::repeat_n(' ', padding - padding / 2));
+    text.extend([' ', sides.1]);
+    lines[mid][1..width - 1].copy_from_slice(&text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagram::tests::lower_qasm;
+
+    fn draw(source: &str, options: DiagramOptions) -> String {
+        draw_circuit(&lower_qasm(source), options)
+    }
+
+    #[test]
+    fn test_bell_circuit() {
+        let diagram = draw(
+            "qreg q[2];\ncreg c[2];\nh q[0];\ncx q[0], q[1];\nmeasure q -> c;",
+            DiagramOptions::default(),
+        );
+
+        assert_eq!(
+            diagram,
+            [
+                "       ┌───┐     ┌───┐",
+                "q[0]: ─┤ H ├──●──┤ M ├────────",
+                "       └───┘  │  └─╥─┘",
+                "              │    ║    ┌───┐",
+                "q[1]: ────────⊕────╫────┤ M ├─",
+                "                   ║    └─╥─┘",
+                "                   ║      ║",
+                "   c: ═════════════╩══════╩═══",
+                "                   0      1",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_parallel_gates_share_a_column() {
+        let diagram = draw(
+            "qreg q[3];\nh q[0];\nx q[1];\nrz(pi/4) q[2];\nswap q[0], q[2];",
+            DiagramOptions::default(),
+        );
+
+        assert_eq!(
+            diagram,
+            [
+                "       ┌─────────┐",
+                "q[0]: ─┤    H    ├──✕─",
+                "       └─────────┘  │",
+                "       ┌─────────┐  │",
+                "q[1]: ─┤    X    ├──┼─",
+                "       └─────────┘  │",
+                "       ┌─────────┐  │",
+                "q[2]: ─┤ Rz(π/4) ├──✕─",
+                "       └─────────┘",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_folding_and_ascii() {
+        let options = DiagramOptions {
+            width: Some(20),
+            ascii: true,
+        };
+        let diagram = draw("qreg q[1];\nh q[0];\nx q[0];\nt q[0];", options);
+
+        let pages: Vec<&str> = diagram.split("\n\n").collect();
+        assert_eq!(pages.len(), 3);
+        assert!(pages[0].contains("q[0]: -| H |->"));
+        assert!(pages[1].contains("<q[0]: -| X |->"));
+        assert!(diagram.is_ascii());
+    }
+}
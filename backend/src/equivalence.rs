@@ -0,0 +1,491 @@
+//! Checking whether two circuits implement the same unitary up to a global
+//! phase.
+//!
+//! Circuits up to [`MAX_EXACT_QUBITS`] wide are compared column by column
+//! through their unitaries. Wider ones are run on random product states: if
+//! both circuits map a random state to the same output up to phase, they
+//! are equal with probability one, and the states tried double as
+//! counter-examples that are cheap to describe.
+//!
+//! With `ignore_ancillas`, the qubits past the narrower circuit's width are
+//! ancillas: they start in `|0>` and both circuits must return them there.
+
+use std::sync::atomic::AtomicBool;
+
+use nalgebra::{Complex, DMatrix, DVector, Vector2};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    circuit::{Circuit, Operation},
+    limits::check_cancelled,
+    quantum_register::QuantumRegister,
+    simulator::Simulator,
+    unitary::{compose_gates_cancellable, GateSequence},
+};
+
+/// Widest circuit, ancillas included, compared through its unitary.
+pub const MAX_EXACT_QUBITS: usize = 8;
+/// Random product states tried on wider circuits.
+const TRIALS: usize = 8;
+const TOLERANCE: f64 = 1e-6;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct EquivalenceOptions {
+    /// Treat the qubits only one of the circuits has as ancillas instead of
+    /// rejecting circuits of different widths.
+    #[serde(default)]
+    pub ignore_ancillas: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Method {
+    Unitary,
+    RandomStates,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EquivalenceResult {
+    pub equivalent: bool,
+    pub method: Method,
+    /// `φ` such that the second circuit is `e^{iφ}` times the first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub global_phase: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counter_example: Option<CounterExample>,
+}
+
+/// A product input state on which the circuits differ.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CounterExample {
+    /// `[re, im]` of the `|0>` and `|1>` amplitudes of every compared qubit,
+    /// like the initial states of a circuit.
+    pub input: Vec<[[f64; 2]; 2]>,
+    /// The input written as `|0+1>`, first qubit first, when every qubit is
+    /// in `|0>`, `|1>` or `|+>`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Overlap `|<a|b>|^2` of the two outputs.
+    pub fidelity: f64,
+    pub reason: String,
+}
+
+pub fn check_equivalence(
+    first: &Circuit,
+    second: &Circuit,
+    options: EquivalenceOptions,
+) -> Result<EquivalenceResult, String> {
+    check_equivalence_cancellable(first, second, options, &AtomicBool::new(false))
+}
+
+/// Compares two circuits unless `cancelled` is set first, which is checked
+/// between gates.
+pub fn check_equivalence_cancellable(
+    first: &Circuit,
+    second: &Circuit,
+    options: EquivalenceOptions,
+    cancelled: &AtomicBool,
+) -> Result<EquivalenceResult, String> {
+    let widths = (first.qubits.len(), second.qubits.len());
+    if widths.0 != widths.1 && !options.ignore_ancillas {
+        return Err(format!(
+            "The circuits have {} and {} qubits; allow ancillas to compare them",
+            widths.0, widths.1
+        ));
+    }
+    let data = widths.0.min(widths.1);
+    let gates = (gate_sequence(first)?, gate_sequence(second)?);
+
+    if widths.0.max(widths.1) <= MAX_EXACT_QUBITS {
+        let a = restricted_unitary(widths.0, &gates.0, data, cancelled)?;
+        let b = restricted_unitary(widths.1, &gates.1, data, cancelled)?;
+        Ok(compare_unitaries(&a, &b, data))
+    } else {
+        compare_on_random_states((widths.0, &gates.0), (widths.1, &gates.1), data, cancelled)
+    }
+}
+
+/// The gates of a circuit, which must not measure, reset or branch.
+fn gate_sequence(circuit: &Circuit) -> Result<GateSequence, String> {
+    let mut gates = vec![];
+    for instruction in &circuit.instructions {
+        match &instruction.operation {
+            Operation::Gate { name, .. } => {
+                if instruction.condition.is_some() {
+                    return Err(format!(
+                        "Cannot compare circuits: gate {} is classically controlled",
+                        name
+                    ));
+                }
+                let matrix = instruction
+                    .matrix()
+                    .ok_or_else(|| format!("Cannot resolve gate '{}'", name))?;
+                gates.push((matrix, instruction.qubits.clone()));
+            }
+            Operation::Measure { .. } | Operation::Reset => {
+                return Err(format!(
+                    "Cannot compare circuits: '{}' is measured or reset",
+                    circuit.qubits[instruction.qubits[0]].label
+                ));
+            }
+            Operation::Error { message, .. } => return Err(message.clone()),
+            Operation::Barrier
+            | Operation::Display { .. }
+            | Operation::Expect { .. }
+            | Operation::Print { .. } => {}
+        }
+    }
+    Ok(gates)
+}
+
+/// The columns of the unitary for inputs with the ancillas in `|0>`,
+/// restricted to outputs with the ancillas in `|0>`. Qubit `i` is bit `i`.
+fn restricted_unitary(
+    width: usize,
+    gates: &GateSequence,
+    data: usize,
+    cancelled: &AtomicBool,
+) -> Result<DMatrix<Complex<f64>>, String> {
+    let full = compose_gates_cancellable(width, gates, cancelled)?;
+    let size = 1 << data;
+    Ok(full.view((0, 0), (size, size)).into_owned())
+}
+
+fn compare_unitaries(
+    a: &DMatrix<Complex<f64>>,
+    b: &DMatrix<Complex<f64>>,
+    data: usize,
+) -> EquivalenceResult {
+    let differ = |input: Vec<[[f64; 2]; 2]>, fidelity: f64, reason: String| EquivalenceResult {
+        equivalent: false,
+        method: Method::Unitary,
+        global_phase: None,
+        counter_example: Some(CounterExample {
+            label: product_label(&input),
+            input,
+            fidelity,
+            reason,
+        }),
+    };
+    let basis_input = |index: usize| -> Vec<[[f64; 2]; 2]> {
+        (0..data)
+            .map(|qubit| basis_state(index >> qubit & 1 == 1))
+            .collect()
+    };
+
+    let mut phases = vec![];
+    for column in 0..a.ncols() {
+        let (x, y) = (a.column(column), b.column(column));
+        for (output, name) in [(x, "first"), (y, "second")] {
+            if (output.norm_squared() - 1.0).abs() > TOLERANCE {
+                return differ(
+                    basis_input(column),
+                    x.dotc(&y).norm_sqr(),
+                    format!("The {} circuit leaves an ancilla outside |0>", name),
+                );
+            }
+        }
+        let overlap = x.dotc(&y);
+        if (overlap.norm() - 1.0).abs() > TOLERANCE {
+            return differ(
+                basis_input(column),
+                overlap.norm_sqr(),
+                "The outputs differ on a basis state".to_string(),
+            );
+        }
+        phases.push(overlap);
+    }
+
+    // Every column agrees up to its own phase. If the phases are not all
+    // equal, two inputs that differ in one qubit have different phases,
+    // and that qubit in |+> tells the circuits apart.
+    for column in 0..a.ncols() {
+        for qubit in 0..data {
+            let other = column | 1 << qubit;
+            if other == column || (phases[column] - phases[other]).norm() < TOLERANCE {
+                continue;
+            }
+            let mut input = basis_input(column);
+            input[qubit] = plus_state();
+            let overlap = (phases[column] + phases[other]) / 2.0;
+            return differ(
+                input,
+                overlap.norm_sqr(),
+                "The outputs differ by a relative phase".to_string(),
+            );
+        }
+    }
+
+    EquivalenceResult {
+        equivalent: true,
+        method: Method::Unitary,
+        global_phase: Some(phases.first().map_or(0.0, |phase| phase.arg())),
+        counter_example: None,
+    }
+}
+
+fn compare_on_random_states(
+    first: (usize, &GateSequence),
+    second: (usize, &GateSequence),
+    data: usize,
+    cancelled: &AtomicBool,
+) -> Result<EquivalenceResult, String> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut global_phase = 0.0;
+
+    for _ in 0..TRIALS {
+        let input: Vec<[[f64; 2]; 2]> = (0..data).map(|_| random_state(&mut rng)).collect();
+        let a = run_on(first, &input, cancelled)?;
+        let b = run_on(second, &input, cancelled)?;
+        let overlap = a.dotc(&b);
+
+        let reason = if (a.norm_squared() - 1.0).abs() > TOLERANCE {
+            Some("The first circuit leaves an ancilla outside |0>")
+        } else if (b.norm_squared() - 1.0).abs() > TOLERANCE {
+            Some("The second circuit leaves an ancilla outside |0>")
+        } else if (overlap.norm() - 1.0).abs() > TOLERANCE {
+            Some("The outputs differ on a random product state")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            return Ok(EquivalenceResult {
+                equivalent: false,
+                method: Method::RandomStates,
+                global_phase: None,
+                counter_example: Some(CounterExample {
+                    label: product_label(&input),
+                    input,
+                    fidelity: overlap.norm_sqr(),
+                    reason: reason.to_string(),
+                }),
+            });
+        }
+        global_phase = overlap.arg();
+    }
+
+    Ok(EquivalenceResult {
+        equivalent: true,
+        method: Method::RandomStates,
+        global_phase: Some(global_phase),
+        counter_example: None,
+    })
+}
+
+/// Runs the gates on `input` with the ancillas in `|0>` and returns the
+/// amplitudes with the ancillas in `|0>`.
+fn run_on(
+    (width, gates): (usize, &GateSequence),
+    input: &[[[f64; 2]; 2]],
+    cancelled: &AtomicBool,
+) -> Result<DVector<Complex<f64>>, String> {
+    let mut register = QuantumRegister::<f64>::empty();
+    for qubit in 0..width {
+        let [zero, one] = input.get(qubit).copied().unwrap_or(basis_state(false));
+        register
+            .add_qubit(Vector2::new(
+                Complex::new(zero[0], zero[1]),
+                Complex::new(one[0], one[1]),
+            ))
+            .expect("a dense register can always grow");
+    }
+    for (matrix, targets) in gates {
+        check_cancelled(cancelled)?;
+        register.apply_gate(matrix, targets);
+    }
+    let size = 1 << input.len();
+    Ok(register.state().rows(0, size).into_owned())
+}
+
+fn basis_state(one: bool) -> [[f64; 2]; 2] {
+    if one {
+        [[0.0, 0.0], [1.0, 0.0]]
+    } else {
+        [[1.0, 0.0], [0.0, 0.0]]
+    }
+}
+
+fn plus_state() -> [[f64; 2]; 2] {
+    let amplitude = std::f64::consts::FRAC_1_SQRT_2;
+    [[amplitude, 0.0], [amplitude, 0.0]]
+}
+
+/// A uniformly random point on the Bloch sphere.
+fn random_state(rng: &mut impl Rng) -> [[f64; 2]; 2] {
+    let theta = (1.0 - 2.0 * rng.gen::<f64>()).acos();
+    let phi = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
+    let (cos, sin) = ((theta / 2.0).cos(), (theta / 2.0).sin());
+    [[cos, 0.0], [sin * phi.cos(), sin * phi.sin()]]
+}
+
+fn product_label(input: &[[[f64; 2]; 2]]) -> Option<String> {
+    let symbols: Option<String> = input
+        .iter()
+        .map(|state| match state {
+            state if *state == basis_state(false) => Some('0'),
+            state if *state == basis_state(true) => Some('1'),
+            state if *state == plus_state() => Some('+'),
+            _ => None,
+        })
+        .collect();
+    symbols.map(|symbols| format!("|{}>", symbols))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        circuit::{GateMatrix, Instruction},
+        decompose,
+        gate::{Gate, PauliX},
+        lower::lower_program,
+        qasm::parse_qasm,
+    };
+
+    fn lower_qasm(source: &str) -> Circuit {
+        let program = parse_qasm(&format!(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\n{}",
+            source
+        ))
+        .unwrap();
+        lower_program(&program)
+    }
+
+    fn compare(first: &str, second: &str) -> EquivalenceResult {
+        check_equivalence(
+            &lower_qasm(first),
+            &lower_qasm(second),
+            EquivalenceOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_equal_up_to_global_phase() {
+        let result = compare(
+            "qreg q[1];\nz q[0];",
+            "qreg q[1];\nh q[0];\nx q[0];\nh q[0];",
+        );
+        assert!(result.equivalent);
+        assert_eq!(result.method, Method::Unitary);
+
+        let result = compare("qreg q[1];\nrz(pi) q[0];", "qreg q[1];\nz q[0];");
+        assert!(result.equivalent);
+        let phase = result.global_phase.unwrap();
+        assert!((phase.abs() - std::f64::consts::FRAC_PI_2).abs() < 1e-9);
+
+        let result = compare(
+            "qreg q[2];\ncx q[0], q[1];",
+            "qreg q[2];\nh q[1];\ncz q[0], q[1];\nh q[1];",
+        );
+        assert!(result.equivalent);
+    }
+
+    #[test]
+    fn test_counter_examples() {
+        let result = compare("qreg q[2];\ncx q[0], q[1];", "qreg q[2];\ncx q[1], q[0];");
+        assert!(!result.equivalent);
+        let example = result.counter_example.unwrap();
+        assert!(example.label.unwrap().contains('1'));
+        assert!(example.fidelity < 1e-9);
+
+        // Z and I agree on both basis states, only up to different phases.
+        let result = compare("qreg q[2];\nz q[1];", "qreg q[2];\nid q[1];");
+        let example = result.counter_example.unwrap();
+        assert_eq!(example.label.as_deref(), Some("|0+>"));
+        assert!(example.fidelity.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ancillas() {
+        let mut matrix: DMatrix<Complex<f64>> = DMatrix::identity(16, 16);
+        matrix
+            .view_mut((14, 14), (2, 2))
+            .copy_from(&PauliX::new().matrix_representation());
+        let mut reference = lower_qasm("qreg q[4];\nh q[3];");
+        reference.instructions.push(Instruction {
+            operation: Operation::Gate {
+                name: "c3x".to_string(),
+                parameters: vec![],
+                matrix: Some(GateMatrix::from_matrix(&matrix)),
+            },
+            ..Instruction::gate("c3x", vec![], vec![0, 1, 2, 3])
+        });
+        let decomposed = decompose::decompose_circuit(&reference, decompose::Basis::CxRzSxX)
+            .unwrap()
+            .circuit;
+        assert!(decomposed.qubits.len() > 4);
+        assert!(check_equivalence(&reference, &decomposed, Default::default()).is_err());
+        let options = EquivalenceOptions {
+            ignore_ancillas: true,
+        };
+        assert!(
+            check_equivalence(&reference, &decomposed, options)
+                .unwrap()
+                .equivalent
+        );
+
+        let dirty = lower_qasm("qreg q[2];\ncx q[0], q[1];");
+        let clean = lower_qasm("qreg q[1];");
+        let result = check_equivalence(&clean, &dirty, options).unwrap();
+        let example = result.counter_example.unwrap();
+        assert_eq!(example.label.as_deref(), Some("|1>"));
+        assert!(example.reason.contains("ancilla"));
+    }
+
+    #[test]
+    fn test_wide_circuits_use_random_states() {
+        let width = MAX_EXACT_QUBITS + 2;
+        let mut source = format!("qreg q[{}];\nh q[0];\n", width);
+        for i in 1..width {
+            source.push_str(&format!("cx q[{}], q[{}];\n", i - 1, i));
+        }
+        let first = lower_qasm(&source);
+
+        let mut second = first.clone();
+        second.instructions.reverse();
+        let result = check_equivalence(&first, &second, Default::default()).unwrap();
+        assert_eq!(result.method, Method::RandomStates);
+        assert!(!result.equivalent);
+        assert_eq!(result.counter_example.unwrap().input.len(), width);
+
+        let mut third = first.clone();
+        third
+            .instructions
+            .insert(0, Instruction::gate("rz", vec![0.4], vec![5]));
+        third
+            .instructions
+            .insert(0, Instruction::gate("rz", vec![-0.4], vec![5]));
+        assert!(
+            check_equivalence(&first, &third, Default::default())
+                .unwrap()
+                .equivalent
+        );
+    }
+
+    #[test]
+    fn test_cancelled_comparisons_stop() {
+        let cancelled = AtomicBool::new(true);
+        for width in [2, MAX_EXACT_QUBITS + 2] {
+            let circuit = lower_qasm(&format!("qreg q[{}];\nh q[0];", width));
+            let error = check_equivalence_cancellable(
+                &circuit,
+                &circuit,
+                EquivalenceOptions::default(),
+                &cancelled,
+            )
+            .unwrap_err();
+            assert_eq!(error, crate::limits::CANCELLED);
+        }
+    }
+
+    #[test]
+    fn test_measurements_are_rejected() {
+        let circuit = lower_qasm("qreg q[1];\ncreg c[1];\nmeasure q[0] -> c[0];");
+        assert!(check_equivalence(&circuit, &circuit, Default::default()).is_err());
+    }
+}
@@ -1,137 +1,213 @@
-use nalgebra::{Complex, DMatrix, Matrix3, Matrix4};
-use nalgebra::Matrix2;
+use nalgebra::{Complex, DMatrix};
 use std::fmt::Debug;
 
 pub trait Gate {
     fn matrix_representation(&self) -> DMatrix<Complex<f64>>;
+
+    fn num_qubits(&self) -> usize {
+        self.matrix_representation().nrows().trailing_zeros() as usize
+    }
 }
 
 impl Debug for dyn Gate {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        let matrix = self.matrix_representation();
-        let m = [
-            [matrix[(0, 0)], matrix[(0, 1)]],
-            [matrix[(1, 0)], matrix[(1, 1)]],
-        ];
+        write!(f, "{}", format_matrix(&self.matrix_representation()))
+    }
+}
+
+/// Formats a matrix of any size row by row, e.g.
+/// `[[0.71+0.00i, 0.71+0.00i], [0.71+0.00i, -0.71+0.00i]]`.
+pub fn format_matrix(matrix: &DMatrix<Complex<f64>>) -> String {
+    let rows: Vec<String> = matrix
+        .row_iter()
+        .map(|row| {
+            let entries: Vec<String> = row
+                .iter()
+                .map(|c| {
+                    if c.im >= 0.0 {
+                        format!("{:.2}+{:.2}i", c.re, c.im)
+                    } else {
+                        format!("{:.2}{:.2}i", c.re, c.im)
+                    }
+                })
+                .collect();
+            format!("[{}]", entries.join(", "))
+        })
+        .collect();
+    format!("[{}]", rows.join(", "))
+}
+
+/// A gate given directly by its unitary matrix, e.g. the composition of a
+/// whole circuit.
+pub struct MatrixGate {
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl MatrixGate {
+    pub fn new(matrix: DMatrix<Complex<f64>>) -> Self {
+        assert!(
+            matrix.is_square() && matrix.nrows().is_power_of_two(),
+            "gate matrix must be 2^n x 2^n"
+        );
+        Self {
+            matrix_form: matrix,
+        }
+    }
+}
 
-        write!(
-            f,
-            "[[{:.2}+{:.2}i, {:.2}+{:.2}i], [{:.2}+{:.2}i, {:.2}+{:.2}i]]",
-            m[0][0].re,
-            m[0][0].im,
-            m[0][1].re,
-            m[0][1].im,
-            m[1][0].re,
-            m[1][0].im,
-            m[1][1].re,
-            m[1][1].im,
-        )
+impl Gate for MatrixGate {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
     }
 }
 
 pub struct Identity {
-    matrix_form: Matrix2<Complex<f64>>,
+    matrix_form: DMatrix<Complex<f64>>,
 }
 
 impl Identity {
     pub fn new() -> Self {
         Self {
-            matrix_form: Matrix2::new(
-                Complex::new(1.0, 0.0),
-                Complex::new(0.0, 0.0),
-                Complex::new(0.0, 0.0),
-                Complex::new(1.0, 0.0),
+            matrix_form: DMatrix::from_row_slice(
+                2,
+                2,
+                &[
+                    Complex::new(1.0, 0.0),
+                    Complex::new(0.0, 0.0),
+                    Complex::new(0.0, 0.0),
+                    Complex::new(1.0, 0.0),
+                ],
             ),
         }
     }
 }
 
+impl Default for Identity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Gate for Identity {
-    fn matrix_representation(&self) -> Matrix2<Complex<f64>> {
-        self.matrix_form
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
     }
 }
 
 pub struct PauliX {
-    matrix_form: Matrix2<Complex<f64>>,
+    matrix_form: DMatrix<Complex<f64>>,
 }
 
 impl PauliX {
     pub fn new() -> Self {
         Self {
-            matrix_form: Matrix2::new(
-                Complex::new(0.0, 0.0),
-                Complex::new(1.0, 0.0),
-                Complex::new(1.0, 0.0),
-                Complex::new(0.0, 0.0),
+            matrix_form: DMatrix::from_row_slice(
+                2,
+                2,
+                &[
+                    Complex::new(0.0, 0.0),
+                    Complex::new(1.0, 0.0),
+                    Complex::new(1.0, 0.0),
+                    Complex::new(0.0, 0.0),
+                ],
             ),
         }
     }
 }
 
+impl Default for PauliX {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Gate for PauliX {
-    fn matrix_representation(&self) -> Matrix2<Complex<f64>> {
-        self.matrix_form
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
     }
 }
 
 pub struct PauliY {
-    matrix_form: Matrix2<Complex<f64>>,
+    matrix_form: DMatrix<Complex<f64>>,
 }
 
 impl PauliY {
     pub fn new() -> Self {
         Self {
-            matrix_form: Matrix2::new(
-                Complex::new(0.0, 0.0),
-                Complex::new(0.0, -1.0),
-                Complex::new(0.0, 1.0),
-                Complex::new(0.0, 0.0),
+            matrix_form: DMatrix::from_row_slice(
+                2,
+                2,
+                &[
+                    Complex::new(0.0, 0.0),
+                    Complex::new(0.0, -1.0),
+                    Complex::new(0.0, 1.0),
+                    Complex::new(0.0, 0.0),
+                ],
             ),
         }
     }
 }
 
+impl Default for PauliY {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Gate for PauliY {
-    fn matrix_representation(&self) -> Matrix2<Complex<f64>> {
-        self.matrix_form
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
     }
 }
 
 pub struct PauliZ {
-    matrix_form: Matrix2<Complex<f64>>,
+    matrix_form: DMatrix<Complex<f64>>,
 }
 
 impl PauliZ {
     pub fn new() -> Self {
         Self {
-            matrix_form: Matrix2::new(
-                Complex::new(1.0, 0.0),
-                Complex::new(0.0, 0.0),
-                Complex::new(0.0, 0.0),
-                Complex::new(-1.0, 0.0),
+            matrix_form: DMatrix::from_row_slice(
+                2,
+                2,
+                &[
+                    Complex::new(1.0, 0.0),
+                    Complex::new(0.0, 0.0),
+                    Complex::new(0.0, 0.0),
+                    Complex::new(-1.0, 0.0),
+                ],
             ),
         }
     }
 }
 
+impl Default for PauliZ {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Gate for PauliZ {
-    fn matrix_representation(&self) -> Matrix2<Complex<f64>> {
-        self.matrix_form
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
     }
 }
 
 pub struct Hadamard {
-    matrix_form: Matrix2<Complex<f64>>,
+    matrix_form: DMatrix<Complex<f64>>,
 }
 
 impl Hadamard {
     pub fn new() -> Self {
-        let mut matrix = Matrix2::new(
-            Complex::new(1.0, 0.0),
-            Complex::new(1.0, 0.0),
-            Complex::new(1.0, 0.0),
-            Complex::new(-1.0, 0.0),
+        let mut matrix = DMatrix::from_row_slice(
+            2,
+            2,
+            &[
+                Complex::new(1.0, 0.0),
+                Complex::new(1.0, 0.0),
+                Complex::new(1.0, 0.0),
+                Complex::new(-1.0, 0.0),
+            ],
         );
 
         matrix *= Complex::new(1.0 / 2.0_f64.sqrt(), 0.0);
@@ -142,44 +218,449 @@ impl Hadamard {
     }
 }
 
+impl Default for Hadamard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Gate for Hadamard {
-    fn matrix_representation(&self) -> Matrix2<Complex<f64>> {
-        self.matrix_form
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
     }
 }
 
 pub struct CNot {
-    matrix_form: Matrix4<Complex<f64>>
-}
-
-impl CNot{
-    pub fn new() -> Self{
-        let mut matrix = Matrix4::new(
-            Complex::new(1.0, 0.0),
-            Complex::new(0.0,0.0 ),
-            Complex::new(0.0,0.0),
-            Complex::new(0.0, 0.0),
-            Complex::new(0.0, 0.0),
-            Complex::new(1.0, 0.0),
-            Complex::new(0.0,0.0),
-            Complex::new(0.0, 0.0),
-            Complex::new(0.0,0.0),
-            Complex::new(0.0,0.0),
-            Complex::new(0.0,0.0),
-            Complex::new(1.0, 0.0),
-            Complex::new(0.0,0.0),
-            Complex::new(0.0,0.0),
-            Complex::new(1.0, 0.0),
-            Complex::new(0.0,0.0)
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl CNot {
+    pub fn new() -> Self {
+        let matrix = DMatrix::from_row_slice(
+            4,
+            4,
+            &[
+                Complex::new(1.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(1.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(1.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(1.0, 0.0),
+                Complex::new(0.0, 0.0),
+            ],
         );
 
-        Self {matrix_form: matrix}
+        Self {
+            matrix_form: matrix,
+        }
+    }
+}
+
+impl Default for CNot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gate for CNot {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
+    }
+}
+
+pub struct PhaseShift {
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl PhaseShift {
+    pub fn new(lambda: f64) -> Self {
+        Self {
+            matrix_form: DMatrix::from_row_slice(
+                2,
+                2,
+                &[
+                    Complex::new(1.0, 0.0),
+                    Complex::new(0.0, 0.0),
+                    Complex::new(0.0, 0.0),
+                    Complex::from_polar(1.0, lambda),
+                ],
+            ),
+        }
+    }
+}
+
+impl Gate for PhaseShift {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
     }
 }
 
-impl Gate for CNot{
-    fn matrix_representation(&self) -> Matrix4<Complex<f64>> {
-        self.matrix_form
+pub struct RotationX {
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl RotationX {
+    pub fn new(theta: f64) -> Self {
+        let (s, c) = (theta / 2.0).sin_cos();
+        Self {
+            matrix_form: DMatrix::from_row_slice(
+                2,
+                2,
+                &[
+                    Complex::new(c, 0.0),
+                    Complex::new(0.0, -s),
+                    Complex::new(0.0, -s),
+                    Complex::new(c, 0.0),
+                ],
+            ),
+        }
+    }
+}
+
+impl Gate for RotationX {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
+    }
+}
+
+pub struct RotationY {
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl RotationY {
+    pub fn new(theta: f64) -> Self {
+        let (s, c) = (theta / 2.0).sin_cos();
+        Self {
+            matrix_form: DMatrix::from_row_slice(
+                2,
+                2,
+                &[
+                    Complex::new(c, 0.0),
+                    Complex::new(-s, 0.0),
+                    Complex::new(s, 0.0),
+                    Complex::new(c, 0.0),
+                ],
+            ),
+        }
+    }
+}
+
+impl Gate for RotationY {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
+    }
+}
+
+pub struct RotationZ {
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl RotationZ {
+    pub fn new(theta: f64) -> Self {
+        Self {
+            matrix_form: DMatrix::from_row_slice(
+                2,
+                2,
+                &[
+                    Complex::from_polar(1.0, -theta / 2.0),
+                    Complex::new(0.0, 0.0),
+                    Complex::new(0.0, 0.0),
+                    Complex::from_polar(1.0, theta / 2.0),
+                ],
+            ),
+        }
+    }
+}
+
+impl Gate for RotationZ {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
+    }
+}
+
+/// General single-qubit gate `U(theta, phi, lambda)` as defined by OpenQASM.
+pub struct UGate {
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl UGate {
+    pub fn new(theta: f64, phi: f64, lambda: f64) -> Self {
+        let (s, c) = (theta / 2.0).sin_cos();
+        Self {
+            matrix_form: DMatrix::from_row_slice(
+                2,
+                2,
+                &[
+                    Complex::new(c, 0.0),
+                    -Complex::from_polar(s, lambda),
+                    Complex::from_polar(s, phi),
+                    Complex::from_polar(c, phi + lambda),
+                ],
+            ),
+        }
+    }
+}
+
+impl Gate for UGate {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
+    }
+}
+
+pub struct SqrtX {
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl SqrtX {
+    pub fn new() -> Self {
+        Self {
+            matrix_form: DMatrix::from_row_slice(
+                2,
+                2,
+                &[
+                    Complex::new(0.5, 0.5),
+                    Complex::new(0.5, -0.5),
+                    Complex::new(0.5, -0.5),
+                    Complex::new(0.5, 0.5),
+                ],
+            ),
+        }
+    }
+}
+
+impl Default for SqrtX {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gate for SqrtX {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
+    }
+}
+
+pub struct Swap {
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl Swap {
+    pub fn new() -> Self {
+        let mut matrix = DMatrix::zeros(4, 4);
+        matrix[(0, 0)] = Complex::new(1.0, 0.0);
+        matrix[(1, 2)] = Complex::new(1.0, 0.0);
+        matrix[(2, 1)] = Complex::new(1.0, 0.0);
+        matrix[(3, 3)] = Complex::new(1.0, 0.0);
+        Self {
+            matrix_form: matrix,
+        }
+    }
+}
+
+impl Default for Swap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gate for Swap {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
+    }
+}
+
+/// `exp(-i theta/2 P⊗P)` for `P` = X or Z, the two-qubit Ising couplings.
+pub struct IsingCoupling {
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl IsingCoupling {
+    pub fn xx(theta: f64) -> Self {
+        let (s, c) = (theta / 2.0).sin_cos();
+        let mut matrix = DMatrix::from_diagonal_element(4, 4, Complex::new(c, 0.0));
+        for i in 0..4 {
+            matrix[(i, 3 - i)] = Complex::new(0.0, -s);
+        }
+        Self {
+            matrix_form: matrix,
+        }
+    }
+
+    pub fn zz(theta: f64) -> Self {
+        let matrix = DMatrix::from_diagonal(&nalgebra::DVector::from_vec(vec![
+            Complex::from_polar(1.0, -theta / 2.0),
+            Complex::from_polar(1.0, theta / 2.0),
+            Complex::from_polar(1.0, theta / 2.0),
+            Complex::from_polar(1.0, -theta / 2.0),
+        ]));
+        Self {
+            matrix_form: matrix,
+        }
+    }
+}
+
+impl Gate for IsingCoupling {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
+    }
+}
+
+/// Adds a control qubit to any gate. The control becomes the first target,
+/// i.e. the most significant qubit of the matrix.
+pub struct Controlled {
+    matrix_form: DMatrix<Complex<f64>>,
+}
+
+impl Controlled {
+    pub fn new(gate: &dyn Gate) -> Self {
+        let target = gate.matrix_representation();
+        let dim = target.nrows();
+        let mut matrix = DMatrix::identity(2 * dim, 2 * dim);
+        matrix.view_mut((dim, dim), (dim, dim)).copy_from(&target);
+        Self {
+            matrix_form: matrix,
+        }
+    }
+}
+
+impl Gate for Controlled {
+    fn matrix_representation(&self) -> DMatrix<Complex<f64>> {
+        self.matrix_form.clone()
+    }
+}
+
+/// Names of the built-in gates and the number of angle parameters they take.
+pub const STANDARD_GATES: &[(&str, usize)] = &[
+    ("identity", 0),
+    ("pauliX", 0),
+    ("pauliY", 0),
+    ("pauliZ", 0),
+    ("hadamard", 0),
+    ("s", 0),
+    ("sDagger", 0),
+    ("t", 0),
+    ("tDagger", 0),
+    ("sqrtX", 0),
+    ("sqrtXDagger", 0),
+    ("phase", 1),
+    ("rx", 1),
+    ("ry", 1),
+    ("rz", 1),
+    ("u", 3),
+    ("cnot", 0),
+    ("cy", 0),
+    ("cz", 0),
+    ("ch", 0),
+    ("swap", 0),
+    ("controlledPhase", 1),
+    ("crx", 1),
+    ("cry", 1),
+    ("crz", 1),
+    ("cu", 3),
+    ("toffoli", 0),
+    ("fredkin", 0),
+    ("rxx", 1),
+    ("rzz", 1),
+];
+
+pub fn parameter_count(name: &str) -> Option<usize> {
+    STANDARD_GATES
+        .iter()
+        .find(|(gate, _)| *gate == name)
+        .map(|(_, count)| *count)
+}
+
+/// Builds a built-in gate. Returns `None` for unknown names and when the
+/// number of parameters does not match.
+pub fn standard_gate(name: &str, parameters: &[f64]) -> Option<Box<dyn Gate>> {
+    use std::f64::consts::PI;
+
+    if parameter_count(name)? != parameters.len() {
+        return None;
+    }
+    let p = parameters;
+
+    let gate: Box<dyn Gate> = match name {
+        "identity" => Box::new(Identity::new()),
+        "pauliX" => Box::new(PauliX::new()),
+        "pauliY" => Box::new(PauliY::new()),
+        "pauliZ" => Box::new(PauliZ::new()),
+        "hadamard" => Box::new(Hadamard::new()),
+        "s" => Box::new(PhaseShift::new(PI / 2.0)),
+        "sDagger" => Box::new(PhaseShift::new(-PI / 2.0)),
+        "t" => Box::new(PhaseShift::new(PI / 4.0)),
+        "tDagger" => Box::new(PhaseShift::new(-PI / 4.0)),
+        "sqrtX" => Box::new(SqrtX::new()),
+        "sqrtXDagger" => Box::new(MatrixGate::new(
+            SqrtX::new().matrix_representation().adjoint(),
+        )),
+        "phase" => Box::new(PhaseShift::new(p[0])),
+        "rx" => Box::new(RotationX::new(p[0])),
+        "ry" => Box::new(RotationY::new(p[0])),
+        "rz" => Box::new(RotationZ::new(p[0])),
+        "u" => Box::new(UGate::new(p[0], p[1], p[2])),
+        "cnot" => Box::new(CNot::new()),
+        "cy" => Box::new(Controlled::new(&PauliY::new())),
+        "cz" => Box::new(Controlled::new(&PauliZ::new())),
+        "ch" => Box::new(Controlled::new(&Hadamard::new())),
+        "swap" => Box::new(Swap::new()),
+        "controlledPhase" => Box::new(Controlled::new(&PhaseShift::new(p[0]))),
+        "crx" => Box::new(Controlled::new(&RotationX::new(p[0]))),
+        "cry" => Box::new(Controlled::new(&RotationY::new(p[0]))),
+        "crz" => Box::new(Controlled::new(&RotationZ::new(p[0]))),
+        "cu" => Box::new(Controlled::new(&UGate::new(p[0], p[1], p[2]))),
+        "toffoli" => Box::new(Controlled::new(&CNot::new())),
+        "fredkin" => Box::new(Controlled::new(&Swap::new())),
+        "rxx" => Box::new(IsingCoupling::xx(p[0])),
+        "rzz" => Box::new(IsingCoupling::zz(p[0])),
+        _ => return None,
+    };
+    Some(gate)
+}
+
+/// Whether `matrix` is unitary up to `tolerance` in every entry of `U^† U - I`.
+pub fn is_unitary(matrix: &DMatrix<Complex<f64>>, tolerance: f64) -> bool {
+    if !matrix.is_square() {
+        return false;
+    }
+    let product = matrix.adjoint() * matrix;
+    let identity = DMatrix::<Complex<f64>>::identity(matrix.nrows(), matrix.ncols());
+    (product - identity).iter().all(|c| c.norm() <= tolerance)
+}
+
+/// Angles `(theta, phi, lambda, phase)` with
+/// `matrix = e^(i phase) U(theta, phi, lambda)` for a 2x2 unitary.
+pub fn u_angles(matrix: &DMatrix<Complex<f64>>) -> (f64, f64, f64, f64) {
+    const EPSILON: f64 = 1e-12;
+    let (m00, m01, m10, m11) = (
+        matrix[(0, 0)],
+        matrix[(0, 1)],
+        matrix[(1, 0)],
+        matrix[(1, 1)],
+    );
+    let theta = 2.0 * m10.norm().atan2(m00.norm());
+
+    if m00.norm() > EPSILON {
+        let phase = m00.arg();
+        let (phi, lambda) = if m10.norm() > EPSILON {
+            (m10.arg() - phase, (-m01).arg() - phase)
+        } else {
+            (0.0, m11.arg() - phase)
+        };
+        (theta, phi, lambda, phase)
+    } else {
+        // cos(theta / 2) vanishes, so only phi + phase is fixed; pick lambda = 0.
+        let phase = (-m01).arg();
+        (theta, m10.arg() - phase, 0.0, phase)
     }
 }
 
@@ -302,4 +783,91 @@ mod tests {
             expected_state.y.imaginary()
         );
     }
+
+    #[test]
+    fn test_debug_single_qubit_gate() {
+        let gate: Box<dyn Gate> = Box::new(PauliY::new());
+        assert_eq!(
+            format!("{:?}", gate),
+            "[[0.00+0.00i, 0.00-1.00i], [0.00+1.00i, 0.00+0.00i]]"
+        );
+    }
+
+    #[test]
+    fn test_debug_two_qubit_gate() {
+        let gate: Box<dyn Gate> = Box::new(CNot::new());
+        let debug = format!("{:?}", gate);
+
+        assert_eq!(gate.num_qubits(), 2);
+        assert_eq!(debug.matches('[').count(), 5);
+        assert!(debug.ends_with("[0.00+0.00i, 0.00+0.00i, 1.00+0.00i, 0.00+0.00i]]"));
+    }
+
+    fn assert_matrix_eq(actual: &DMatrix<Complex<f64>>, expected: &DMatrix<Complex<f64>>) {
+        assert_eq!(actual.shape(), expected.shape());
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).norm() < 1e-12, "{} != {}", a, e);
+        }
+    }
+
+    #[test]
+    fn test_standard_gates_are_unitary() {
+        for (name, count) in STANDARD_GATES {
+            let parameters = vec![0.7; *count];
+            let gate = standard_gate(name, &parameters).unwrap();
+            assert!(
+                is_unitary(&gate.matrix_representation(), 1e-12),
+                "{} is not unitary",
+                name
+            );
+        }
+        assert!(standard_gate("rx", &[]).is_none());
+        assert!(standard_gate("unknown", &[]).is_none());
+    }
+
+    #[test]
+    fn test_u_gate_special_cases() {
+        use std::f64::consts::PI;
+
+        assert_matrix_eq(
+            &UGate::new(PI, 0.0, PI).matrix_representation(),
+            &PauliX::new().matrix_representation(),
+        );
+        assert_matrix_eq(
+            &UGate::new(PI / 2.0, 0.0, PI).matrix_representation(),
+            &Hadamard::new().matrix_representation(),
+        );
+    }
+
+    #[test]
+    fn test_sqrt_x_squares_to_x() {
+        let sx = SqrtX::new().matrix_representation();
+        assert_matrix_eq(&(&sx * &sx), &PauliX::new().matrix_representation());
+    }
+
+    #[test]
+    fn test_controlled_gate_layout() {
+        let cx = Controlled::new(&PauliX::new()).matrix_representation();
+        assert_matrix_eq(&cx, &CNot::new().matrix_representation());
+        assert_eq!(standard_gate("toffoli", &[]).unwrap().num_qubits(), 3);
+    }
+
+    #[test]
+    fn test_u_angles_reconstruct_matrix() {
+        let matrices = [
+            Hadamard::new().matrix_representation(),
+            PauliY::new().matrix_representation(),
+            PhaseShift::new(0.3).matrix_representation(),
+            SqrtX::new().matrix_representation(),
+            RotationZ::new(1.1).matrix_representation()
+                * RotationY::new(0.4).matrix_representation(),
+        ];
+
+        for matrix in matrices {
+            let (theta, phi, lambda, phase) = u_angles(&matrix);
+            let rebuilt = UGate::new(theta, phi, lambda).matrix_representation()
+                * Complex::from_polar(1.0, phase);
+            assert_matrix_eq(&rebuilt, &matrix);
+        }
+    }
 }
@@ -0,0 +1,539 @@
+//! Handlers for user accounts, saved program files and level progress.
+//! Files and progress belong to the signed-in user.
+
+use axum::{
+    extract::{Path, State},
+    http::{header::SET_COOKIE, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::AppState;
+use crate::{
+    auth::{hash_password, unauthorized, verify_password, CurrentUser, SESSION_COOKIE},
+    levels::find_level,
+    store::{FileInfo, StoreError, User},
+};
+
+type ErrorResponse = (StatusCode, Json<serde_json::Value>);
+
+fn store_error(error: StoreError) -> ErrorResponse {
+    let status = match error {
+        StoreError::NotFound(_) => StatusCode::NOT_FOUND,
+        StoreError::Conflict(_) => StatusCode::CONFLICT,
+        StoreError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        StoreError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (
+        status,
+        Json(serde_json::json!({ "error": error.to_string() })),
+    )
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Credentials {
+    pub user_name: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionResponse {
+    pub user: User,
+    pub token: String,
+}
+
+/// Responds with a new session token, in the body and as a cookie.
+async fn session_response(
+    state: &AppState,
+    status: StatusCode,
+    user: User,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let nonce = state
+        .store
+        .token_nonce(&user.user_name)
+        .await
+        .map_err(store_error)?;
+    let token = state.auth.issue_token(&user.user_name, &nonce);
+    let cookie = format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        SESSION_COOKIE,
+        token,
+        state.auth.lifetime()
+    );
+    Ok((
+        status,
+        [(SET_COOKIE, cookie)],
+        Json(SessionResponse { user, token }),
+    ))
+}
+
+/// Runs password hashing off the async runtime; it is slow on purpose.
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(work)
+        .await
+        .expect("password hashing panicked")
+}
+
+/// Creates a user and signs them in.
+pub async fn register_handler(
+    State(state): State<AppState>,
+    Json(body): Json<Credentials>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let hash = blocking(move || hash_password(&body.password))
+        .await
+        .map_err(|error| store_error(StoreError::Invalid(error)))?;
+    let user = state
+        .store
+        .create_user(&body.user_name, &hash)
+        .await
+        .map_err(store_error)?;
+    session_response(&state, StatusCode::CREATED, user).await
+}
+
+pub async fn login_handler(
+    State(state): State<AppState>,
+    Json(body): Json<Credentials>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let rejected = || unauthorized("Wrong user name or password");
+    let hash = match state.store.password_hash(&body.user_name).await {
+        Ok(hash) => hash,
+        Err(StoreError::NotFound(_)) => return Err(rejected()),
+        Err(error) => return Err(store_error(error)),
+    };
+    if !blocking(move || verify_password(&body.password, &hash)).await {
+        return Err(rejected());
+    }
+    let user = state
+        .store
+        .get_user(&body.user_name)
+        .await
+        .map_err(store_error)?;
+    session_response(&state, StatusCode::OK, user).await
+}
+
+/// Clears the session cookie. Bearer tokens stay valid until they expire.
+pub async fn logout_handler() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [(
+            SET_COOKIE,
+            format!(
+                "{}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0",
+                SESSION_COOKIE
+            ),
+        )],
+    )
+}
+
+pub async fn me_handler(
+    State(state): State<AppState>,
+    CurrentUser(user_name): CurrentUser,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let user = state
+        .store
+        .get_user(&user_name)
+        .await
+        .map_err(store_error)?;
+    Ok((StatusCode::OK, Json(user)))
+}
+
+pub async fn users_handler(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let users = state.store.list_users().await.map_err(store_error)?;
+    Ok((StatusCode::OK, Json(users)))
+}
+
+pub async fn user_handler(
+    State(state): State<AppState>,
+    Path(user_name): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let user = state
+        .store
+        .get_user(&user_name)
+        .await
+        .map_err(store_error)?;
+    Ok((StatusCode::OK, Json(user)))
+}
+
+/// Deletes the signed-in user with their files and progress.
+pub async fn delete_user_handler(
+    State(state): State<AppState>,
+    CurrentUser(current): CurrentUser,
+    Path(user_name): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if user_name != current {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "error": "Users can only delete themselves" })),
+        ));
+    }
+    state
+        .store
+        .delete_user(&user_name)
+        .await
+        .map_err(store_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewFile {
+    pub name: String,
+    /// The program, as OpenQASM source or the JSON of a program tree.
+    pub content: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FileUpdate {
+    pub name: Option<String>,
+    pub content: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FileResponse {
+    #[serde(flatten)]
+    pub info: FileInfo,
+    pub content: String,
+}
+
+/// Looks up a file of the signed-in user. Other users' files are reported
+/// as missing.
+async fn own_file(state: &AppState, user_name: &str, blob_id: i64) -> Result<(), ErrorResponse> {
+    let info = state.store.file_info(blob_id).await.map_err(store_error)?;
+    if info.user_name != user_name {
+        return Err(store_error(StoreError::NotFound(format!(
+            "Unknown file {}",
+            blob_id
+        ))));
+    }
+    Ok(())
+}
+
+/// Lists the signed-in user's files without their content.
+pub async fn files_handler(
+    State(state): State<AppState>,
+    CurrentUser(user_name): CurrentUser,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let files = state
+        .store
+        .list_files(&user_name)
+        .await
+        .map_err(store_error)?;
+    Ok((StatusCode::OK, Json(files)))
+}
+
+pub async fn create_file_handler(
+    State(state): State<AppState>,
+    CurrentUser(user_name): CurrentUser,
+    Json(body): Json<NewFile>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let info = state
+        .store
+        .create_file(&user_name, &body.name, body.content.as_bytes())
+        .await
+        .map_err(store_error)?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+pub async fn file_handler(
+    State(state): State<AppState>,
+    CurrentUser(user_name): CurrentUser,
+    Path(blob_id): Path<i64>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    own_file(&state, &user_name, blob_id).await?;
+    let (info, content) = state.store.get_file(blob_id).await.map_err(store_error)?;
+    Ok((
+        StatusCode::OK,
+        Json(FileResponse {
+            info,
+            content: String::from_utf8_lossy(&content).into_owned(),
+        }),
+    ))
+}
+
+/// Renames a file and/or replaces its content.
+pub async fn update_file_handler(
+    State(state): State<AppState>,
+    CurrentUser(user_name): CurrentUser,
+    Path(blob_id): Path<i64>,
+    Json(body): Json<FileUpdate>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    own_file(&state, &user_name, blob_id).await?;
+    let info = state
+        .store
+        .update_file(
+            blob_id,
+            body.name.as_deref(),
+            body.content.as_deref().map(str::as_bytes),
+        )
+        .await
+        .map_err(store_error)?;
+    Ok((StatusCode::OK, Json(info)))
+}
+
+pub async fn delete_file_handler(
+    State(state): State<AppState>,
+    CurrentUser(user_name): CurrentUser,
+    Path(blob_id): Path<i64>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    own_file(&state, &user_name, blob_id).await?;
+    state
+        .store
+        .delete_file(blob_id)
+        .await
+        .map_err(store_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NewCompletion {
+    pub level_id: u32,
+}
+
+/// Lists the levels the signed-in user has completed.
+pub async fn progress_handler(
+    State(state): State<AppState>,
+    CurrentUser(user_name): CurrentUser,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let progress = state
+        .store
+        .list_progress(&user_name)
+        .await
+        .map_err(store_error)?;
+    Ok((StatusCode::OK, Json(progress)))
+}
+
+pub async fn complete_level_handler(
+    State(state): State<AppState>,
+    CurrentUser(user_name): CurrentUser,
+    Json(body): Json<NewCompletion>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if find_level(body.level_id).is_none() {
+        return Err(store_error(StoreError::NotFound(format!(
+            "Unknown level {}",
+            body.level_id
+        ))));
+    }
+    let completion = state
+        .store
+        .complete_level(&user_name, body.level_id)
+        .await
+        .map_err(store_error)?;
+    Ok((StatusCode::CREATED, Json(completion)))
+}
+
+pub async fn remove_progress_handler(
+    State(state): State<AppState>,
+    CurrentUser(user_name): CurrentUser,
+    Path(level_id): Path<u32>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    state
+        .store
+        .remove_progress(&user_name, level_id)
+        .await
+        .map_err(store_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header::AUTHORIZATION, Method};
+    use serde_json::{json, Value};
+
+    use super::{
+        super::testing::{send, state_with},
+        *,
+    };
+    use crate::limits::Limits;
+
+    async fn register(state: &AppState, user_name: &str) -> String {
+        let (status, _, body) = send(
+            state,
+            Method::POST,
+            "/api/auth/register",
+            &[],
+            json!({"userName": user_name, "password": "correct horse"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        body["token"].as_str().unwrap().to_string()
+    }
+
+    async fn get_as(state: &AppState, token: &str, uri: &str) -> (StatusCode, Value) {
+        let bearer = format!("Bearer {}", token);
+        let (status, _, body) = send(
+            state,
+            Method::GET,
+            uri,
+            &[(AUTHORIZATION.as_str(), &bearer)],
+            Value::Null,
+        )
+        .await;
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn test_register_and_login() {
+        let state = state_with(Limits::default()).await;
+        let (status, headers, body) = send(
+            &state,
+            Method::POST,
+            "/api/auth/register",
+            &[],
+            json!({"userName": "alice", "password": "correct horse"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["user"]["userName"], "alice");
+        let token = body["token"].as_str().unwrap();
+        let cookie = headers.get(SET_COOKIE).unwrap().to_str().unwrap();
+        assert!(
+            cookie.starts_with(&format!("session={};", token)),
+            "{}",
+            cookie
+        );
+
+        let credentials = |password: &str| json!({"userName": "alice", "password": password});
+        let (status, _, _) = send(
+            &state,
+            Method::POST,
+            "/api/auth/register",
+            &[],
+            credentials("another horse"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        for (user_name, password) in [("alice", "wrong horse"), ("nobody", "correct horse")] {
+            let (status, _, _) = send(
+                &state,
+                Method::POST,
+                "/api/auth/login",
+                &[],
+                json!({"userName": user_name, "password": password}),
+            )
+            .await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", user_name);
+        }
+        let (status, _, body) = send(
+            &state,
+            Method::POST,
+            "/api/auth/login",
+            &[],
+            credentials("correct horse"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["user"]["userName"], "alice");
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_read_from_the_header_or_the_cookie() {
+        let state = state_with(Limits::default()).await;
+        let alice = register(&state, "alice").await;
+        let bob = register(&state, "bob").await;
+
+        let (status, _, _) = send(&state, Method::GET, "/api/auth/me", &[], Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = get_as(&state, &alice, "/api/auth/me").await;
+        assert_eq!(
+            (status, &body["userName"]),
+            (StatusCode::OK, &json!("alice"))
+        );
+
+        let cookie = format!("theme=dark; session={}", bob);
+        let (status, _, body) = send(
+            &state,
+            Method::GET,
+            "/api/auth/me",
+            &[("cookie", &cookie)],
+            Value::Null,
+        )
+        .await;
+        assert_eq!((status, &body["userName"]), (StatusCode::OK, &json!("bob")));
+
+        // The header wins over the cookie.
+        let bearer = format!("Bearer {}", alice);
+        let (_, _, body) = send(
+            &state,
+            Method::GET,
+            "/api/auth/me",
+            &[("cookie", &cookie), (AUTHORIZATION.as_str(), &bearer)],
+            Value::Null,
+        )
+        .await;
+        assert_eq!(body["userName"], "alice");
+        let (status, _) = get_as(&state, "not.a-token", "/api/auth/me").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_files_and_progress_are_private() {
+        let state = state_with(Limits::default()).await;
+        let alice = register(&state, "alice").await;
+        let bob = register(&state, "bob").await;
+        let as_alice = format!("Bearer {}", alice);
+        let as_bob = format!("Bearer {}", bob);
+
+        let (status, _, file) = send(
+            &state,
+            Method::POST,
+            "/api/files",
+            &[(AUTHORIZATION.as_str(), &as_alice)],
+            json!({"name": "bell.qasm", "content": "OPENQASM 2.0;"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let uri = format!("/api/files/{}", file["blobId"]);
+        let (status, body) = get_as(&state, &alice, &uri).await;
+        assert_eq!(
+            (status, &body["content"]),
+            (StatusCode::OK, &json!("OPENQASM 2.0;"))
+        );
+
+        for (method, body) in [
+            (Method::GET, Value::Null),
+            (Method::PUT, json!({"name": "mine.qasm"})),
+            (Method::DELETE, Value::Null),
+        ] {
+            let (status, _, _) = send(
+                &state,
+                method.clone(),
+                &uri,
+                &[(AUTHORIZATION.as_str(), &as_bob)],
+                body,
+            )
+            .await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", method);
+        }
+        let (_, files) = get_as(&state, &bob, "/api/files").await;
+        assert_eq!(files, json!([]));
+        let (_, files) = get_as(&state, &alice, "/api/files").await;
+        assert_eq!(files[0]["name"], "bell.qasm");
+
+        let (status, _, _) = send(
+            &state,
+            Method::POST,
+            "/api/progress",
+            &[(AUTHORIZATION.as_str(), &as_alice)],
+            json!({"levelId": 3}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, progress) = get_as(&state, &bob, "/api/progress").await;
+        assert_eq!(progress, json!([]));
+        let (status, _, _) = send(
+            &state,
+            Method::DELETE,
+            "/api/progress/3",
+            &[(AUTHORIZATION.as_str(), &as_bob)],
+            Value::Null,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, progress) = get_as(&state, &alice, "/api/progress").await;
+        assert_eq!(progress[0]["levelId"], 3);
+    }
+}
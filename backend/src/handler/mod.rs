@@ -0,0 +1,607 @@
+mod accounts;
+mod sessions;
+mod share;
+mod stream;
+
+pub use accounts::{
+    complete_level_handler, create_file_handler, delete_file_handler, delete_user_handler,
+    file_handler, files_handler, login_handler, logout_handler, me_handler, progress_handler,
+    register_handler, remove_progress_handler, update_file_handler, user_handler, users_handler,
+};
+pub use sessions::{
+    breakpoints_handler, create_session_handler, delete_session_handler, rewind_session_handler,
+    run_session_handler, session_handler, step_session_handler,
+};
+pub use share::{create_share_handler, share_handler};
+pub use stream::stream_handler;
+
+use axum::{
+    extract::{
+        rejection::{JsonRejection, StringRejection},
+        Path, Query, State,
+    },
+    http::{header::CONTENT_TYPE, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::task::JoinError;
+
+use crate::{
+    auth::{Auth, CurrentUser},
+    circuit::Circuit,
+    decompose::{decompose_circuit_cancellable, Basis},
+    diagram::{circuit_latex, circuit_svg, draw_circuit, DiagramOptions, LatexOptions},
+    equivalence::{check_equivalence_cancellable, EquivalenceOptions},
+    interpreter::{simulate_circuit_cancellable, SimulationOptions, SimulationResult},
+    levels::{find_level, grade_circuit, levels},
+    limits::{LimitError, Limits},
+    logging::timed,
+    metrics::circuit_metrics,
+    models::ProgramNode,
+    observable::ExpectationMode,
+    optimize::optimize_circuit,
+    qasm::{export_qasm_limited, parse_qasm, QasmVersion},
+    routing::{route_circuit_cancellable, Topology},
+    session::SessionStore,
+    store::Store,
+    telemetry::{ErrorKind, Telemetry},
+    unitary::{circuit_unitary_cancellable, unitary_result},
+};
+
+/// Shared by the handlers that need more than their request.
+#[derive(Clone, Debug)]
+pub struct AppState {
+    pub store: Store,
+    pub auth: Auth,
+    pub sessions: Arc<SessionStore>,
+    pub limits: Limits,
+    pub telemetry: Arc<Telemetry>,
+}
+
+fn limit_exceeded(status: StatusCode, error: LimitError) -> (StatusCode, Json<serde_json::Value>) {
+    (status, Json(serde_json::to_value(error).unwrap()))
+}
+
+/// Turns a body that could not be read into a JSON error, with the size
+/// limit spelled out when the body was too large.
+fn rejected_body(
+    state: &AppState,
+    status: StatusCode,
+    message: String,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if status == StatusCode::PAYLOAD_TOO_LARGE {
+        let error = LimitError::body(state.limits.max_body_bytes);
+        state
+            .telemetry
+            .record_errors(ErrorKind::Limit(error.limit), 1);
+        return limit_exceeded(status, error);
+    }
+    state.telemetry.record_errors(ErrorKind::Parse, 1);
+    (status, Json(serde_json::json!({ "error": message })))
+}
+
+/// Runs `work` on the blocking pool, handing it a flag that is set once
+/// `timeout` passes so that lowering and simulation stop soon after the
+/// caller has given up on them.
+async fn run_blocking<T: Send + 'static>(
+    timeout: Duration,
+    work: impl FnOnce(&AtomicBool) -> Result<T, LimitError> + Send + 'static,
+) -> Result<Result<T, LimitError>, JoinError> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let worker = tokio::task::spawn_blocking({
+        let cancelled = cancelled.clone();
+        let span = tracing::Span::current();
+        move || {
+            let _entered = span.enter();
+            work(&cancelled)
+        }
+    });
+    match tokio::time::timeout(timeout, worker).await {
+        Ok(result) => result,
+        Err(_) => {
+            cancelled.store(true, Ordering::Relaxed);
+            Ok(Err(LimitError::timeout(timeout)))
+        }
+    }
+}
+
+/// Runs `work` on the blocking pool within the server's timeout, answering
+/// with the limit it went over if it fails.
+async fn within_timeout<T: Send + 'static>(
+    state: &AppState,
+    work: impl FnOnce(&AtomicBool) -> Result<T, LimitError> + Send + 'static,
+) -> Result<T, (StatusCode, Json<serde_json::Value>)> {
+    let error = match run_blocking(state.limits.timeout, work).await {
+        Ok(Ok(value)) => return Ok(value),
+        Ok(Err(error)) => error,
+        Err(error) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": error.to_string() })),
+            ))
+        }
+    };
+    tracing::warn!(limit = error.limit, "{}", error.error);
+    state
+        .telemetry
+        .record_errors(ErrorKind::Limit(error.limit), 1);
+    let status = if error.limit == "timeoutMs" {
+        StatusCode::REQUEST_TIMEOUT
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+    Err(limit_exceeded(status, error))
+}
+
+/// Lowers a program that is drawn or analysed rather than simulated and
+/// runs `work` on its circuit, both within the limits. Work that takes
+/// more than linear time stops once the flag it is given is set.
+async fn with_circuit<T: Send + 'static>(
+    state: &AppState,
+    program: ProgramNode,
+    work: impl FnOnce(Circuit, &AtomicBool) -> T + Send + 'static,
+) -> Result<T, (StatusCode, Json<serde_json::Value>)> {
+    let limits = state.limits.clone();
+    within_timeout(state, move |cancelled| {
+        let circuit = timed("lower", || limits.lower_unsimulated(&program, cancelled))?;
+        Ok(work(circuit, cancelled))
+    })
+    .await
+}
+
+/// Simulates a program within the server's limits. The circuit is lowered
+/// and run on the blocking pool, and cancelled once the timeout passes.
+async fn simulate_within_limits(
+    state: &AppState,
+    program: ProgramNode,
+    options: SimulationOptions,
+) -> Result<SimulationResult, (StatusCode, Json<serde_json::Value>)> {
+    if let Err(error) = state.limits.check_options(&options) {
+        state
+            .telemetry
+            .record_errors(ErrorKind::Limit(error.limit), 1);
+        return Err(limit_exceeded(StatusCode::UNPROCESSABLE_ENTITY, error));
+    }
+    let limits = state.limits.clone();
+    let telemetry = state.telemetry.clone();
+    within_timeout(state, move |cancelled| {
+        let circuit = timed("lower", || limits.lower(&program, &options, cancelled))?;
+        tracing::info!(
+            qubits = circuit.qubits.len(),
+            instructions = circuit.instructions.len(),
+            backend = %options.backend,
+            "simulating"
+        );
+        let start = Instant::now();
+        let result = timed("simulate", || {
+            simulate_circuit_cancellable(&circuit, options, cancelled)
+        })
+        .ok_or_else(|| LimitError::timeout(limits.timeout))?;
+        let shots = (options.expectation == ExpectationMode::Shots).then_some(options.shots);
+        telemetry.record_simulation(
+            options.backend,
+            circuit.qubits.len(),
+            shots,
+            start.elapsed(),
+        );
+        telemetry.record_program_errors(&circuit);
+        Ok(result)
+    })
+    .await
+}
+
+/// Reports the server's load in the Prometheus text format.
+pub async fn prometheus_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        state.telemetry.render(state.sessions.len()),
+    )
+}
+
+pub async fn simulation_handler(
+    State(state): State<AppState>,
+    Query(options): Query<SimulationOptions>,
+    body: Result<Json<ProgramNode>, JsonRejection>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let Json(body) =
+        body.map_err(|rejection| rejected_body(&state, rejection.status(), rejection.body_text()))?;
+    let result = simulate_within_limits(&state, body, options).await?;
+    Ok((StatusCode::OK, Json(result)))
+}
+
+/// Returns the lowered circuit of a program as JSON.
+pub async fn circuit_handler(
+    State(state): State<AppState>,
+    Json(body): Json<ProgramNode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let circuit = with_circuit(&state, body, |circuit, _| circuit).await?;
+    Ok((StatusCode::OK, Json(circuit)))
+}
+
+/// Returns the depth, gate counts and other size figures of a program's
+/// circuit.
+pub async fn metrics_handler(
+    State(state): State<AppState>,
+    Json(body): Json<ProgramNode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let metrics = with_circuit(&state, body, |circuit, _| circuit_metrics(&circuit)).await?;
+    Ok((StatusCode::OK, Json(metrics)))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EquivalenceRequest {
+    pub reference: ProgramNode,
+    pub candidate: ProgramNode,
+    #[serde(flatten)]
+    pub options: EquivalenceOptions,
+}
+
+/// Returns whether two programs apply the same unitary up to global phase,
+/// with an input state telling them apart when they do not. Both are held
+/// to the dense backend's limits, as they are compared by simulating them.
+pub async fn equivalence_handler(
+    State(state): State<AppState>,
+    Json(body): Json<EquivalenceRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let limits = state.limits.clone();
+    let result = within_timeout(&state, move |cancelled| {
+        let dense = SimulationOptions::default();
+        let (reference, candidate) = timed("lower", || {
+            Ok::<_, LimitError>((
+                limits.lower(&body.reference, &dense, cancelled)?,
+                limits.lower(&body.candidate, &dense, cancelled)?,
+            ))
+        })?;
+        Ok(check_equivalence_cancellable(
+            &reference,
+            &candidate,
+            body.options,
+            cancelled,
+        ))
+    })
+    .await?;
+    match result {
+        Ok(result) => Ok((StatusCode::OK, Json(result))),
+        Err(error) => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": error })),
+        )),
+    }
+}
+
+/// Lists the levels without their solutions.
+pub async fn levels_handler() -> impl IntoResponse {
+    Json(levels())
+}
+
+fn unknown_level(id: u32) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "error": format!("Unknown level {}", id) })),
+    )
+}
+
+pub async fn level_handler(
+    Path(id): Path<u32>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let level = find_level(id).ok_or_else(|| unknown_level(id))?;
+    Ok((StatusCode::OK, Json(level)))
+}
+
+/// Grades a program against a level and returns the verdict with hints.
+/// Passing records the level as completed for a signed-in user.
+pub async fn level_submit_handler(
+    State(state): State<AppState>,
+    user: Option<CurrentUser>,
+    Path(id): Path<u32>,
+    Json(body): Json<ProgramNode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let level = find_level(id).ok_or_else(|| unknown_level(id))?;
+    let limits = state.limits.clone();
+    let telemetry = state.telemetry.clone();
+    let verdict = within_timeout(&state, move |cancelled| {
+        let options = SimulationOptions::default();
+        let circuit = timed("lower", || limits.lower(&body, &options, cancelled))?;
+        telemetry.record_program_errors(&circuit);
+        Ok(timed("grade", || grade_circuit(level, &circuit, cancelled)))
+    })
+    .await?;
+    if let (true, Some(CurrentUser(user_name))) = (verdict.passed, user) {
+        if let Err(error) = state.store.complete_level(&user_name, id).await {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "error": error.to_string() })),
+            ));
+        }
+    }
+    Ok((StatusCode::OK, Json(verdict)))
+}
+
+/// Returns the peephole-optimised circuit of a program with its gate
+/// counts before and after.
+pub async fn optimize_handler(
+    State(state): State<AppState>,
+    Json(body): Json<ProgramNode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let result = with_circuit(&state, body, |circuit, _| optimize_circuit(&circuit)).await?;
+    Ok((StatusCode::OK, Json(result)))
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct DecomposeOptions {
+    #[serde(default)]
+    pub basis: Basis,
+}
+
+/// Returns the circuit of a program rewritten into a native basis,
+/// `?basis=cx,rz,sx,x` (the default) or `?basis=cz,u3`.
+pub async fn decompose_handler(
+    State(state): State<AppState>,
+    Query(options): Query<DecomposeOptions>,
+    Json(body): Json<ProgramNode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let result = with_circuit(&state, body, move |circuit, cancelled| {
+        decompose_circuit_cancellable(&circuit, options.basis, cancelled)
+    })
+    .await?;
+    match result {
+        Ok(result) => Ok((StatusCode::OK, Json(result))),
+        Err(error) => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": error })),
+        )),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RouteRequest {
+    pub program: ProgramNode,
+    pub coupling: Topology,
+}
+
+/// Returns the circuit of a program routed onto a device's coupling map,
+/// with the SWAPs inserted and the initial and final layouts.
+pub async fn route_handler(
+    State(state): State<AppState>,
+    Json(body): Json<RouteRequest>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let coupling = body.coupling;
+    let result = with_circuit(&state, body.program, move |circuit, cancelled| {
+        coupling
+            .coupling_map()
+            .and_then(|coupling| route_circuit_cancellable(&circuit, &coupling, cancelled))
+    })
+    .await?;
+    match result {
+        Ok(result) => Ok((StatusCode::OK, Json(result))),
+        Err(error) => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": error })),
+        )),
+    }
+}
+
+/// Returns a text drawing of the lowered circuit, folded at `?width=`.
+pub async fn diagram_handler(
+    State(state): State<AppState>,
+    Query(options): Query<DiagramOptions>,
+    Json(body): Json<ProgramNode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let drawing = with_circuit(&state, body, move |circuit, _| {
+        draw_circuit(&circuit, options)
+    })
+    .await?;
+    Ok((StatusCode::OK, drawing))
+}
+
+const SVG: [(axum::http::HeaderName, &str); 1] = [(CONTENT_TYPE, "image/svg+xml")];
+
+/// Returns the circuit of a program as a standalone SVG image.
+pub async fn svg_handler(
+    State(state): State<AppState>,
+    Json(body): Json<ProgramNode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let svg = with_circuit(&state, body, |circuit, _| circuit_svg(&circuit)).await?;
+    Ok((StatusCode::OK, SVG, svg))
+}
+
+/// Returns the circuit of a program as LaTeX `quantikz` source.
+pub async fn latex_handler(
+    State(state): State<AppState>,
+    Query(options): Query<LatexOptions>,
+    Json(body): Json<ProgramNode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let latex = with_circuit(&state, body, move |circuit, _| {
+        circuit_latex(&circuit, options)
+    })
+    .await?;
+    Ok((StatusCode::OK, latex))
+}
+
+/// An OpenQASM 2.0 program passed in the query string of a `GET` request.
+#[derive(Deserialize, Debug)]
+pub struct QasmQuery {
+    pub qasm: String,
+    #[serde(default)]
+    pub fragment: bool,
+}
+
+fn parse_qasm_query(
+    query: &QasmQuery,
+) -> Result<ProgramNode, (StatusCode, Json<serde_json::Value>)> {
+    parse_qasm(&query.qasm).map_err(|error| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": error.to_string() })),
+        )
+    })
+}
+
+/// `GET` version of [`svg_handler`], so that images can link to a drawing.
+pub async fn svg_query_handler(
+    State(state): State<AppState>,
+    Query(query): Query<QasmQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let program = parse_qasm_query(&query)?;
+    let svg = with_circuit(&state, program, |circuit, _| circuit_svg(&circuit)).await?;
+    Ok((StatusCode::OK, SVG, svg))
+}
+
+/// `GET` version of [`latex_handler`].
+pub async fn latex_query_handler(
+    State(state): State<AppState>,
+    Query(query): Query<QasmQuery>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let program = parse_qasm_query(&query)?;
+    let options = LatexOptions {
+        fragment: query.fragment,
+    };
+    let latex = with_circuit(&state, program, move |circuit, _| {
+        circuit_latex(&circuit, options)
+    })
+    .await?;
+    Ok((StatusCode::OK, latex))
+}
+
+/// Simulates an OpenQASM 2.0 program sent as the plain text body.
+pub async fn qasm_handler(
+    State(state): State<AppState>,
+    Query(options): Query<SimulationOptions>,
+    body: Result<String, StringRejection>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let body =
+        body.map_err(|rejection| rejected_body(&state, rejection.status(), rejection.body_text()))?;
+    let program = timed("parse", || parse_qasm(&body)).map_err(|error| {
+        state.telemetry.record_errors(ErrorKind::Parse, 1);
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": error.to_string() })),
+        )
+    })?;
+    let result = simulate_within_limits(&state, program, options).await?;
+    Ok((StatusCode::OK, Json(result)))
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ExportOptions {
+    #[serde(default)]
+    pub version: QasmVersion,
+}
+
+/// Returns the program as OpenQASM text, version 2 unless `?version=3`,
+/// with loops unrolled up to the instruction limit.
+pub async fn qasm_export_handler(
+    State(state): State<AppState>,
+    Query(options): Query<ExportOptions>,
+    Json(body): Json<ProgramNode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let limits = state.limits.clone();
+    let result = within_timeout(&state, move |cancelled| {
+        timed("lower", || limits.lower_unsimulated(&body, cancelled))?;
+        Ok(export_qasm_limited(
+            &body,
+            options.version,
+            limits.max_instructions,
+        ))
+    })
+    .await?;
+    match result {
+        Ok(source) => Ok((StatusCode::OK, source)),
+        Err(error) => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": error })),
+        )),
+    }
+}
+
+pub async fn unitary_handler(
+    State(state): State<AppState>,
+    Json(body): Json<ProgramNode>,
+) -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    let limits = state.limits.clone();
+    let result = within_timeout(&state, move |cancelled| {
+        let dense = SimulationOptions::default();
+        let circuit = timed("lower", || limits.lower(&body, &dense, cancelled))?;
+        Ok(circuit_unitary_cancellable(&circuit, cancelled))
+    })
+    .await?;
+    match result {
+        Ok((qubits, gate)) => Ok((StatusCode::OK, Json(unitary_result(qubits, &gate)))),
+        Err(error) => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": error })),
+        )),
+    }
+}
+
+pub async fn up() -> Result<impl IntoResponse, (StatusCode, Json<serde_json::Value>)> {
+    Ok((StatusCode::OK, Json("The server is up!")))
+}
+
+/// Builds the state and sends requests through the router for handler
+/// tests.
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::sync::Arc;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{HeaderMap, Method, Request},
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        auth::Auth,
+        route::create_router,
+        session::{DEFAULT_MAX_SESSIONS, DEFAULT_SESSION_TTL},
+    };
+
+    pub async fn state_with(limits: Limits) -> AppState {
+        AppState {
+            store: Store::in_memory().await.unwrap(),
+            auth: Auth::random(60),
+            sessions: Arc::new(SessionStore::new(DEFAULT_MAX_SESSIONS, DEFAULT_SESSION_TTL)),
+            limits,
+            telemetry: Arc::new(Telemetry::default()),
+        }
+    }
+
+    /// Sends a request with a JSON body, or none for `Null`, and returns
+    /// the status, headers and JSON body of the response.
+    pub async fn send(
+        state: &AppState,
+        method: Method,
+        uri: &str,
+        headers: &[(&str, &str)],
+        body: serde_json::Value,
+    ) -> (StatusCode, HeaderMap, serde_json::Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let request = if body.is_null() {
+            request.body(Body::empty())
+        } else {
+            request
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+        };
+        let response = create_router(state.clone())
+            .oneshot(request.unwrap())
+            .await
+            .unwrap();
+        let (parts, body) = response.into_parts();
+        let bytes = to_bytes(body, usize::MAX).await.unwrap();
+        let json = serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null);
+        (parts.status, parts.headers, json)
+    }
+}
@@ -0,0 +1,200 @@
+//! Handlers for step-by-step simulation sessions.
+
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{limit_exceeded, within_timeout, AppState};
+use crate::{
+    circuit::Circuit,
+    interpreter::SimulationOptions,
+    logging::timed,
+    models::ProgramNode,
+    session::{Session, SessionState},
+    telemetry::ErrorKind,
+};
+
+type ErrorResponse = (StatusCode, Json<serde_json::Value>);
+
+fn error_response(status: StatusCode, error: impl ToString) -> ErrorResponse {
+    (
+        status,
+        Json(serde_json::json!({ "error": error.to_string() })),
+    )
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewSession {
+    pub program: ProgramNode,
+    #[serde(default)]
+    pub breakpoints: Vec<usize>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResponse {
+    pub id: String,
+    /// Seconds the session is kept without being used.
+    pub expires_in: u64,
+    #[serde(flatten)]
+    pub state: SessionState,
+    /// The lowered circuit, whose instruction indices are the steps.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit: Option<Circuit>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StepQuery {
+    #[serde(default = "one")]
+    pub count: usize,
+}
+
+fn one() -> usize {
+    1
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RewindQuery {
+    pub step: usize,
+}
+
+fn find_session(state: &AppState, id: &str) -> Result<Arc<Mutex<Session>>, ErrorResponse> {
+    state.sessions.get(id).ok_or_else(|| {
+        error_response(
+            StatusCode::NOT_FOUND,
+            format!("Unknown or expired session '{}'", id),
+        )
+    })
+}
+
+fn respond(state: &AppState, id: String, session: &Session) -> Json<SessionResponse> {
+    Json(SessionResponse {
+        id,
+        expires_in: state.sessions.ttl().as_secs(),
+        state: session.state(),
+        circuit: None,
+    })
+}
+
+/// Runs `work` on a session off the async runtime and responds with the
+/// session's state.
+async fn with_session(
+    state: AppState,
+    id: String,
+    work: impl FnOnce(&mut Session) -> Result<(), String> + Send + 'static,
+) -> Result<Json<SessionResponse>, ErrorResponse> {
+    let session = find_session(&state, &id)?;
+    tokio::task::spawn_blocking(move || {
+        let mut session = session.lock().unwrap();
+        work(&mut session)
+            .map_err(|error| error_response(StatusCode::UNPROCESSABLE_ENTITY, error))?;
+        Ok(respond(&state, id, &session))
+    })
+    .await
+    .map_err(|error| error_response(StatusCode::INTERNAL_SERVER_ERROR, error))?
+}
+
+/// Starts a session at the beginning of a program, which is lowered and
+/// set up on the blocking pool within the limits.
+pub async fn create_session_handler(
+    State(state): State<AppState>,
+    Query(options): Query<SimulationOptions>,
+    Json(body): Json<NewSession>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if let Err(error) = state.limits.check_options(&options) {
+        state
+            .telemetry
+            .record_errors(ErrorKind::Limit(error.limit), 1);
+        return Err(limit_exceeded(StatusCode::UNPROCESSABLE_ENTITY, error));
+    }
+    let limits = state.limits.clone();
+    let telemetry = state.telemetry.clone();
+    let session = within_timeout(&state, move |cancelled| {
+        let circuit = timed("lower", || limits.lower(&body.program, &options, cancelled))?;
+        telemetry.record_program_errors(&circuit);
+        Ok(Session::new(circuit, options).map(|mut session| {
+            session.set_breakpoints(body.breakpoints);
+            session
+        }))
+    })
+    .await?
+    .map_err(|error| error_response(StatusCode::UNPROCESSABLE_ENTITY, error))?;
+    let circuit = session.circuit().clone();
+    let Json(mut response) = respond(&state, String::new(), &session);
+    response.id = state.sessions.insert(session);
+    response.circuit = Some(circuit);
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn session_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    with_session(state, id, |_| Ok(())).await
+}
+
+/// Runs the next `?count=` instructions, one by default.
+pub async fn step_session_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<StepQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    with_session(state, id, move |session| {
+        session.step(query.count);
+        Ok(())
+    })
+    .await
+}
+
+/// Runs until the next breakpoint or the end of the program.
+pub async fn run_session_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    with_session(state, id, |session| {
+        session.run();
+        Ok(())
+    })
+    .await
+}
+
+/// Goes back to the state after `?step=` instructions.
+pub async fn rewind_session_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<RewindQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    with_session(state, id, move |session| session.rewind(query.step)).await
+}
+
+pub async fn breakpoints_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(breakpoints): Json<Vec<usize>>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    with_session(state, id, move |session| {
+        session.set_breakpoints(breakpoints);
+        Ok(())
+    })
+    .await
+}
+
+pub async fn delete_session_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if state.sessions.remove(&id) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(error_response(
+            StatusCode::NOT_FOUND,
+            format!("Unknown or expired session '{}'", id),
+        ))
+    }
+}
@@ -0,0 +1,224 @@
+//! Handlers for programs shared under short links.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{simulate_within_limits, AppState};
+use crate::{
+    interpreter::SimulationOptions,
+    logging::timed,
+    share::{share_id, SharedSource, MAX_SHARED_PROGRAM_BYTES},
+    store::{SharedRecord, StoreError},
+};
+
+type ErrorResponse = (StatusCode, Json<serde_json::Value>);
+
+fn error_response(status: StatusCode, error: impl ToString) -> ErrorResponse {
+    (
+        status,
+        Json(serde_json::json!({ "error": error.to_string() })),
+    )
+}
+
+fn store_error(error: StoreError) -> ErrorResponse {
+    match error {
+        StoreError::NotFound(_) => error_response(StatusCode::NOT_FOUND, error),
+        StoreError::Conflict(_) => error_response(StatusCode::CONFLICT, error),
+        _ => error_response(StatusCode::INTERNAL_SERVER_ERROR, error),
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedProgram {
+    pub id: String,
+    pub created_at: i64,
+    #[serde(flatten)]
+    pub source: SharedSource,
+    /// The simulation of the program, run once and then kept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ShareQuery {
+    #[serde(default)]
+    pub simulate: bool,
+}
+
+fn shared_program(record: SharedRecord) -> Result<SharedProgram, ErrorResponse> {
+    let source = SharedSource::from_stored(&record.format, &record.content)
+        .map_err(|error| error_response(StatusCode::INTERNAL_SERVER_ERROR, error))?;
+    Ok(SharedProgram {
+        id: record.share_id,
+        created_at: record.created_at,
+        source,
+        result: record
+            .result
+            .and_then(|result| serde_json::from_str(&result).ok()),
+    })
+}
+
+/// Stores `{"qasm": ...}` or `{"program": ...}` and returns its short ID.
+pub async fn create_share_handler(
+    State(state): State<AppState>,
+    Json(source): Json<SharedSource>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let content = source.content();
+    if content.len() > MAX_SHARED_PROGRAM_BYTES {
+        return Err(error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Shared programs are limited to {} bytes, this one has {}",
+                MAX_SHARED_PROGRAM_BYTES,
+                content.len()
+            ),
+        ));
+    }
+    source
+        .program()
+        .map_err(|error| error_response(StatusCode::UNPROCESSABLE_ENTITY, error))?;
+    let record = state
+        .store
+        .share_program(&share_id(&source), source.format(), &content)
+        .await
+        .map_err(store_error)?;
+    let mut shared = shared_program(record)?;
+    shared.result = None;
+    Ok((StatusCode::CREATED, Json(shared)))
+}
+
+/// Returns a shared program, with its simulation when `?simulate=true`.
+pub async fn share_handler(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<ShareQuery>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let record = state.store.shared_program(&id).await.map_err(store_error)?;
+    let mut shared = shared_program(record)?;
+    if !query.simulate {
+        shared.result = None;
+    } else if shared.result.is_none() {
+        let program = timed("parse", || shared.source.program())
+            .map_err(|error| error_response(StatusCode::UNPROCESSABLE_ENTITY, error))?;
+        let result = simulate_within_limits(&state, program, SimulationOptions::default()).await?;
+        let result = serde_json::to_value(result)
+            .map_err(|error| error_response(StatusCode::INTERNAL_SERVER_ERROR, error))?;
+        state
+            .store
+            .cache_share_result(&id, &result.to_string())
+            .await
+            .map_err(store_error)?;
+        shared.result = Some(result);
+    }
+    Ok((StatusCode::OK, Json(shared)))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+    use serde_json::{json, Value};
+
+    use super::{
+        super::testing::{send, state_with},
+        *,
+    };
+    use crate::limits::Limits;
+
+    const BELL: &str =
+        "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nh q[0];\ncx q[0], q[1];";
+
+    async fn simulate(
+        state: &AppState,
+        source: &SharedSource,
+    ) -> Result<serde_json::Value, StatusCode> {
+        let program = source.program().unwrap();
+        simulate_within_limits(state, program, SimulationOptions::default())
+            .await
+            .map(|result| serde_json::to_value(result).unwrap())
+            .map_err(|(status, _)| status)
+    }
+
+    #[tokio::test]
+    async fn test_shared_programs_are_simulated_within_the_limits() {
+        let bell = SharedSource::Qasm(BELL.to_string());
+        let tree = SharedSource::Program(serde_json::json!({
+            "type": "Program",
+            "statements": [{"type": "RegisterDeclaration", "identifier": "q", "size": 3}]
+        }));
+
+        let state = state_with(Limits::default()).await;
+        assert_eq!(
+            simulate(&state, &bell).await.unwrap()["metrics"]["gates"],
+            2
+        );
+        assert_eq!(
+            simulate(&state, &tree).await.unwrap()["metrics"]["numQubits"],
+            3
+        );
+
+        let narrow = state_with(Limits {
+            max_qubits: 2,
+            ..Limits::default()
+        })
+        .await;
+        assert_eq!(
+            simulate(&narrow, &tree).await,
+            Err(StatusCode::UNPROCESSABLE_ENTITY)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sharing_through_the_router() {
+        let state = state_with(Limits::default()).await;
+        let share = |qasm: String| {
+            let state = state.clone();
+            async move {
+                send(
+                    &state,
+                    Method::POST,
+                    "/api/share",
+                    &[],
+                    json!({ "qasm": qasm }),
+                )
+                .await
+            }
+        };
+
+        let (status, _, first) = share(BELL.to_string()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(first["qasm"], BELL);
+        let (status, _, again) = share(BELL.to_string()).await;
+        assert_eq!((status, &again), (StatusCode::CREATED, &first));
+
+        let uri = format!("/api/share/{}", first["id"].as_str().unwrap());
+        let (status, _, shared) = send(&state, Method::GET, &uri, &[], Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(shared.get("result").is_none());
+        let uri = format!("{}?simulate=true", uri);
+        let (status, _, simulated) = send(&state, Method::GET, &uri, &[], Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(simulated["result"]["metrics"]["gates"], 2);
+        let (_, _, cached) = send(&state, Method::GET, &uri, &[], Value::Null).await;
+        assert_eq!(cached, simulated);
+
+        let padding = " ".repeat(MAX_SHARED_PROGRAM_BYTES);
+        let (status, _, _) = share(format!("{}{}", BELL, padding)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let other = format!("{}\nx q[0];", BELL);
+        let id = share_id(&SharedSource::Qasm(other.clone()));
+        state
+            .store
+            .share_program(&id, "qasm", b"OPENQASM 2.0;")
+            .await
+            .unwrap();
+        let (status, _, _) = share(other).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,201 @@
+//! WebSocket endpoint that streams a simulation as it runs.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+use super::{run_blocking, AppState};
+use crate::{
+    circuit::Circuit,
+    interpreter::SimulationOptions,
+    limits::{LimitError, Limits},
+    logging::timed,
+    observable::ExpectationMode,
+    share::SharedSource,
+    stream::{stream_circuit, StreamEvent},
+    telemetry::ErrorKind,
+};
+
+/// Longest pause between steps a client can ask for.
+const MAX_DELAY_MS: u64 = 1000;
+
+/// Events that can wait to be sent before the simulation pauses.
+const EVENT_BUFFER: usize = 16;
+
+/// The first message of a stream: `{"qasm": ...}` or `{"program": ...}`,
+/// optionally with a pause after each step.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamRequest {
+    #[serde(flatten)]
+    pub source: SharedSource,
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    Cancel,
+}
+
+/// Upgrades to a WebSocket that takes a program and answers with an event
+/// per instruction, until the end, until the client cancels or until the
+/// stream has been open for the longest time allowed.
+pub async fn stream_handler(
+    State(state): State<AppState>,
+    upgrade: WebSocketUpgrade,
+    Query(options): Query<SimulationOptions>,
+) -> impl IntoResponse {
+    let span = tracing::Span::current();
+    let max_body_bytes = state.limits.max_body_bytes;
+    upgrade
+        .max_message_size(max_body_bytes)
+        .max_frame_size(max_body_bytes)
+        .on_upgrade(move |socket| stream_socket(socket, state, options).instrument(span))
+}
+
+async fn send_event(socket: &mut WebSocket, event: &StreamEvent) -> bool {
+    let text = serde_json::to_string(event).unwrap();
+    socket.send(Message::Text(text)).await.is_ok()
+}
+
+/// Reads the program, then parses and lowers it on the blocking pool
+/// within the timeout.
+async fn prepare(
+    text: &str,
+    limits: &Limits,
+    options: SimulationOptions,
+) -> Result<(Circuit, Duration), (Option<ErrorKind>, String)> {
+    let request = serde_json::from_str::<StreamRequest>(text)
+        .map_err(|error| (Some(ErrorKind::Parse), error.to_string()))?;
+    let limit = |error: LimitError| (Some(ErrorKind::Limit(error.limit)), error.error);
+    limits.check_options(&options).map_err(limit)?;
+    let delay = Duration::from_millis(request.delay_ms.min(MAX_DELAY_MS));
+    let source = request.source;
+    let lowering = limits.clone();
+    let prepared = run_blocking(limits.timeout, move |cancelled| {
+        // A source that does not parse is not over a limit, so it is
+        // passed on inside the result.
+        let program = match timed("parse", || source.program()) {
+            Ok(program) => program,
+            Err(error) => return Ok(Err(error)),
+        };
+        timed("lower", || lowering.lower(&program, &options, cancelled)).map(Ok)
+    })
+    .await;
+    match prepared {
+        Ok(Ok(Ok(circuit))) => Ok((circuit, delay)),
+        Ok(Ok(Err(error))) => Err((Some(ErrorKind::Parse), error)),
+        Ok(Err(error)) => Err(limit(error)),
+        Err(error) => Err((None, error.to_string())),
+    }
+}
+
+async fn stream_socket(mut socket: WebSocket, state: AppState, options: SimulationOptions) {
+    let limits = &state.limits;
+    let deadline = Instant::now() + limits.stream_timeout;
+    let text = match tokio::time::timeout_at(deadline.into(), socket.recv()).await {
+        Ok(Some(Ok(Message::Text(text)))) => text,
+        _ => return,
+    };
+    let (circuit, delay) = match prepare(&text, limits, options).await {
+        Ok((circuit, delay)) => {
+            state.telemetry.record_program_errors(&circuit);
+            (circuit, delay)
+        }
+        Err((kind, error)) => {
+            if let Some(kind) = kind {
+                state.telemetry.record_errors(kind, 1);
+            }
+            send_event(&mut socket, &StreamEvent::Error { error }).await;
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+    };
+
+    let (sender, mut events) = mpsc::channel(EVENT_BUFFER);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let flag = cancelled.clone();
+    let span = tracing::Span::current();
+    let telemetry = state.telemetry.clone();
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let start = Instant::now();
+        let mut finished = false;
+        timed("simulate", || {
+            stream_circuit(&circuit, options, |event| {
+                finished = matches!(event, StreamEvent::Done { .. });
+                sender.blocking_send(event).is_ok() && !flag.load(Ordering::Relaxed)
+            })
+        });
+        if finished {
+            let shots = (options.expectation == ExpectationMode::Shots).then_some(options.shots);
+            telemetry.record_simulation(
+                options.backend,
+                circuit.qubits.len(),
+                shots,
+                start.elapsed(),
+            );
+        }
+    });
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = tokio::time::sleep_until(deadline.into()) => {
+                cancelled.store(true, Ordering::Relaxed);
+                let error = LimitError::stream_timeout(limits.stream_timeout);
+                tracing::warn!(limit = error.limit, "{}", error.error);
+                state.telemetry.record_errors(ErrorKind::Limit(error.limit), 1);
+                send_event(&mut socket, &StreamEvent::Error { error: error.error }).await;
+                break;
+            }
+            event = events.recv() => {
+                let Some(event) = event else { break };
+                let step = matches!(event, StreamEvent::Step { .. });
+                if step && cancelled.load(Ordering::Relaxed) {
+                    continue;
+                }
+                if !send_event(&mut socket, &event).await {
+                    cancelled.store(true, Ordering::Relaxed);
+                    break;
+                }
+                if !step && !matches!(event, StreamEvent::Start { .. }) {
+                    break;
+                }
+                if step && !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    if let Ok(ClientMessage::Cancel) = serde_json::from_str(&text) {
+                        cancelled.store(true, Ordering::Relaxed);
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                    cancelled.store(true, Ordering::Relaxed);
+                    return;
+                }
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
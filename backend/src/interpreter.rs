@@ -1,152 +1,441 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    gate::{Gate, Hadamard, Identity, PauliX, PauliY, PauliZ},
-    models::{Expression, ProgramNode, StatementNode},
+    analysis::{self, AnalysisKind, StateAnalysis},
+    circuit::{Circuit, Instruction, Operation},
+    gate::{format_matrix, Gate, PauliX},
+    lower::lower_program,
+    metrics::{circuit_metrics, CircuitMetrics},
+    models::ProgramNode,
+    observable::ExpectationMode,
+    precision::Precision,
+    quantum_register::QuantumRegister,
     qubit::Qubit,
+    simulator::{BackendKind, Simulator},
+    sparse_register::SparseRegister,
 };
 
-pub fn interpret_program(program: ProgramNode) -> Vec<String> {
-    let mut results = vec![];
-    let mut variables: HashMap<String, Qubit> = HashMap::new();
-    let mut gates: HashMap<String, Box<dyn Gate>> = HashMap::new();
-    initialize_gate_map(&mut gates);
+pub const DEFAULT_SHOTS: usize = 1024;
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct SimulationOptions {
+    #[serde(default)]
+    pub precision: Precision,
+    #[serde(default)]
+    pub backend: BackendKind,
+    #[serde(default)]
+    pub expectation: ExpectationMode,
+    /// Measurements per Pauli term when `expectation` is `shots`.
+    #[serde(default = "default_shots")]
+    pub shots: usize,
+}
 
-    for statement in program.statements {
-        let result = interpret_statement(statement, &mut variables, &mut gates);
+fn default_shots() -> usize {
+    DEFAULT_SHOTS
+}
 
-        if let Some(result) = result {
-            results.push(result);
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        Self {
+            precision: Precision::default(),
+            backend: BackendKind::default(),
+            expectation: ExpectationMode::default(),
+            shots: DEFAULT_SHOTS,
         }
     }
+}
 
-    results
+/// Everything a simulation run reports back: the printed lines, the final
+/// classical registers, the structured form of every `print <analysis>`
+/// statement and the size of the circuit.
+#[derive(Serialize, Debug, Default)]
+pub struct SimulationResult {
+    pub output: Vec<String>,
+    /// Classical registers as bit strings, highest index first.
+    pub memory: BTreeMap<String, String>,
+    pub analysis: Vec<StateAnalysis>,
+    pub metrics: CircuitMetrics,
 }
 
-fn interpret_statement(
-    statement: StatementNode,
-    variables: &mut HashMap<String, Qubit>,
-    gates: &mut HashMap<String, Box<dyn Gate>>,
-) -> Option<String> {
-    match statement {
-        StatementNode::CreateStatement {
-            identifier,
-            complex_array,
-        } => {
-            if let std::collections::hash_map::Entry::Vacant(e) =
-                variables.entry(identifier.to_string())
-            {
-                if complex_array.values.len() != 2 {
-                    return Some(format!(
-                        "Invalid number of states for qubit {}: expected 2, got {}",
-                        identifier,
-                        complex_array.values.len()
-                    ));
-                }
+pub fn interpret_program(program: ProgramNode) -> Vec<String> {
+    interpret_program_with_options(program, SimulationOptions::default())
+}
 
-                let (real1, imag1) = evaluate_complex_expression(&complex_array.values[0]);
-                let (real2, imag2) = evaluate_complex_expression(&complex_array.values[1]);
+pub fn interpret_program_with_options(
+    program: ProgramNode,
+    options: SimulationOptions,
+) -> Vec<String> {
+    simulate_program(program, options).output
+}
 
-                let qubit = Qubit::new_from_amplitudes(real1, imag1, real2, imag2);
-                e.insert(qubit);
-                None
-            } else {
-                Some(format!("Identifier {} was already declared", identifier))
-            }
+pub fn simulate_program(program: ProgramNode, options: SimulationOptions) -> SimulationResult {
+    simulate_circuit(&lower_program(&program), options)
+}
+
+pub fn simulate_circuit(circuit: &Circuit, options: SimulationOptions) -> SimulationResult {
+    simulate_circuit_cancellable(circuit, options, &AtomicBool::new(false))
+        .expect("the simulation is never cancelled")
+}
+
+/// Simulates a circuit unless `cancelled` is set before it finishes, which is
+/// checked between instructions.
+pub fn simulate_circuit_cancellable(
+    circuit: &Circuit,
+    options: SimulationOptions,
+    cancelled: &AtomicBool,
+) -> Option<SimulationResult> {
+    let result = match (options.backend, options.precision) {
+        (BackendKind::Dense, Precision::Single) => {
+            run_circuit_cancellable::<QuantumRegister<f32>>(circuit, options, cancelled)?.1
+        }
+        (BackendKind::Dense, Precision::Double) => {
+            run_circuit_cancellable::<QuantumRegister<f64>>(circuit, options, cancelled)?.1
         }
+        (BackendKind::Sparse, Precision::Single) => {
+            run_circuit_cancellable::<SparseRegister<f32>>(circuit, options, cancelled)?.1
+        }
+        (BackendKind::Sparse, Precision::Double) => {
+            run_circuit_cancellable::<SparseRegister<f64>>(circuit, options, cancelled)?.1
+        }
+    };
+    Some(result)
+}
 
-        StatementNode::ApplyStatement {
-            identifier1,
-            identifier2,
-        } => {
-            if !variables.contains_key(&identifier1) {
-                return Some(format!("Cannot resolve symbol '{}'", identifier1));
-            }
+/// Executes a circuit on a fresh `S` and returns the final simulator along
+/// with the result.
+pub fn run_circuit<S: Simulator>(
+    circuit: &Circuit,
+    options: SimulationOptions,
+) -> (S, SimulationResult) {
+    run_circuit_cancellable(circuit, options, &AtomicBool::new(false))
+        .expect("the simulation is never cancelled")
+}
 
-            if !gates.contains_key(&identifier2) {
-                return Some(format!("Cannot resolve gate '{}'", identifier2));
-            }
+/// Executes a circuit on a fresh `S` unless `cancelled` is set before it
+/// finishes, which is checked between instructions.
+pub fn run_circuit_cancellable<S: Simulator>(
+    circuit: &Circuit,
+    options: SimulationOptions,
+    cancelled: &AtomicBool,
+) -> Option<(S, SimulationResult)> {
+    let mut execution = Execution::<S>::new(circuit);
+    while !cancelled.load(Ordering::Relaxed) {
+        if execution.step(circuit, options).is_none() {
+            return Some(execution.finish(circuit));
+        }
+    }
+    None
+}
 
-            let qubit = variables.get_mut(&identifier1).unwrap();
-            let gate = gates.get_mut(&identifier2).unwrap();
+/// A circuit part-way through its execution: the simulator, the classical
+/// bits and what has been printed so far.
+pub struct Execution<S> {
+    pub register: S,
+    pub clbits: Vec<u8>,
+    pub output: Vec<String>,
+    pub analysis: Vec<StateAnalysis>,
+    /// Index of the next instruction to run.
+    pub position: usize,
+    values: HashMap<String, f64>,
+    labels: Vec<String>,
+}
 
-            qubit.apply_gate(&**gate);
+/// The state of an [`Execution`] at one position, to go back to later.
+#[derive(Clone)]
+pub struct Snapshot<S> {
+    pub register: S,
+    pub clbits: Vec<u8>,
+    pub position: usize,
+    values: HashMap<String, f64>,
+    output: usize,
+    analysis: usize,
+}
 
-            None
+impl<S: Simulator> Execution<S> {
+    /// Starts a circuit with its qubits in their declared states. When the
+    /// simulator cannot hold them all, the reason is the only output and
+    /// no instruction runs.
+    pub fn new(circuit: &Circuit) -> Self {
+        let mut execution = Execution {
+            register: S::empty(),
+            clbits: vec![0; circuit.num_clbits()],
+            output: Vec::new(),
+            analysis: Vec::new(),
+            position: 0,
+            values: HashMap::new(),
+            labels: circuit.labels(),
+        };
+        for qubit in &circuit.qubits {
+            if let Err(message) = execution.register.add_qubit(qubit.state()) {
+                execution.output.push(message);
+                execution.position = circuit.instructions.len();
+                break;
+            }
         }
+        execution
+    }
 
-        StatementNode::MeasureStatement { identifier } => {
-            if !variables.contains_key(&identifier) {
-                Some(format!("Cannot resolve symbol '{}'", identifier))
-            } else {
-                Some(format!(
-                    "Result of measurement: {}",
-                    variables.get_mut(&identifier).unwrap().measure()
-                ))
+    pub fn is_finished(&self, circuit: &Circuit) -> bool {
+        self.position >= circuit.instructions.len()
+    }
+
+    /// Runs the next instruction, skipping it when its condition does not
+    /// hold, and returns whether it ran. Returns `None` once the circuit has
+    /// finished.
+    pub fn step(&mut self, circuit: &Circuit, options: SimulationOptions) -> Option<bool> {
+        let instruction = circuit.instructions.get(self.position)?;
+        self.position += 1;
+
+        if let Some(condition) = &instruction.condition {
+            let bits = circuit.classical_range(&condition.register).unwrap_or(0..0);
+            let value = self.clbits[bits]
+                .iter()
+                .enumerate()
+                .fold(0u64, |acc, (i, &bit)| acc | (u64::from(bit) << i));
+            if value != condition.value {
+                return Some(false);
             }
         }
 
-        StatementNode::DisplayStatement { identifier } => {
-            if variables.contains_key(&identifier) {
-                Some(format!(
-                    "{}: {:?}",
-                    identifier,
-                    variables.get_mut(&identifier).unwrap()
-                ))
-            } else if gates.contains_key(&identifier) {
-                Some(format!(
-                    "{}: {:?}",
-                    identifier,
-                    gates.get_mut(&identifier).unwrap()
-                ))
-            } else {
-                Some(format!("Cannot resolve symbol '{}'", identifier))
-            }
+        if let Some(line) = execute_instruction(
+            instruction,
+            &mut self.register,
+            &self.labels,
+            &mut self.clbits,
+            &mut self.values,
+            &mut self.analysis,
+            options,
+        ) {
+            self.output.push(line);
         }
+        Some(true)
+    }
+
+    /// Classical registers as bit strings, highest index first.
+    pub fn memory(&self, circuit: &Circuit) -> BTreeMap<String, String> {
+        memory(circuit, &self.clbits)
+    }
+}
+
+impl<S> Execution<S> {
+    /// Ends the execution with the final simulator and the result.
+    pub fn finish(self, circuit: &Circuit) -> (S, SimulationResult) {
+        let result = SimulationResult {
+            memory: memory(circuit, &self.clbits),
+            output: self.output,
+            analysis: self.analysis,
+            metrics: circuit_metrics(circuit),
+        };
+        (self.register, result)
+    }
+}
+
+impl<S: Simulator + Clone> Execution<S> {
+    pub fn snapshot(&self) -> Snapshot<S> {
+        Snapshot {
+            register: self.register.clone(),
+            clbits: self.clbits.clone(),
+            position: self.position,
+            values: self.values.clone(),
+            output: self.output.len(),
+            analysis: self.analysis.len(),
+        }
+    }
+
+    /// Goes back to a snapshot taken earlier in this execution, dropping
+    /// what was printed since.
+    pub fn restore(&mut self, snapshot: &Snapshot<S>) {
+        self.register = snapshot.register.clone();
+        self.clbits = snapshot.clbits.clone();
+        self.position = snapshot.position;
+        self.values = snapshot.values.clone();
+        self.output.truncate(snapshot.output);
+        self.analysis.truncate(snapshot.analysis);
     }
 }
 
-fn evaluate_complex_expression(expr: &Expression) -> (f64, f64) {
-    match expr {
-        Expression::RealNumber { value } => (*value, 0.0),
-        Expression::ImaginaryNumber { value } => (0.0, *value),
-        Expression::InfixExpression { op, left, right } => {
-            let (left_real, left_imag) = evaluate_complex_expression(left);
-            let (right_real, right_imag) = evaluate_complex_expression(right);
-
-            match op.as_str() {
-                "+" => (left_real + right_real, left_imag + right_imag),
-                "-" => (left_real - right_real, left_imag - right_imag),
-                "*" => (
-                    left_real * right_real - left_imag * right_imag,
-                    left_real * right_imag + left_imag * right_real,
-                ),
-                "/" => {
-                    let denominator = right_real * right_real + right_imag * right_imag;
-                    (
-                        (left_real * right_real + left_imag * right_imag) / denominator,
-                        (left_imag * right_real - left_real * right_imag) / denominator,
-                    )
+fn memory(circuit: &Circuit, clbits: &[u8]) -> BTreeMap<String, String> {
+    let mut memory = BTreeMap::new();
+    let mut offset = 0;
+    for classical in &circuit.classical {
+        let bits = &clbits[offset..offset + classical.size];
+        let value = bits.iter().rev().map(|bit| bit.to_string()).collect();
+        memory.insert(classical.name.clone(), value);
+        offset += classical.size;
+    }
+    memory
+}
+
+/// Runs one instruction and returns the line it prints, if any.
+fn execute_instruction<S: Simulator>(
+    instruction: &Instruction,
+    register: &mut S,
+    labels: &[String],
+    clbits: &mut [u8],
+    values: &mut HashMap<String, f64>,
+    analysis: &mut Vec<StateAnalysis>,
+    options: SimulationOptions,
+) -> Option<String> {
+    let qubits = &instruction.qubits;
+
+    match &instruction.operation {
+        Operation::Gate { .. } => {
+            let matrix = instruction.matrix()?;
+            register.apply_gate(&matrix, qubits);
+            None
+        }
+        Operation::Measure { echo } => {
+            let outcomes: Vec<u8> = qubits.iter().map(|&q| register.measure(q)).collect();
+            for (&bit, &outcome) in instruction.clbits.iter().zip(&outcomes) {
+                clbits[bit] = outcome;
+            }
+            echo.then(|| {
+                let outcomes: Vec<String> = outcomes.iter().map(u8::to_string).collect();
+                format!("Result of measurement: {}", outcomes.join(""))
+            })
+        }
+        Operation::Reset => {
+            let flip = PauliX::new().matrix_representation();
+            for &qubit in qubits {
+                if register.measure(qubit) == 1 {
+                    register.apply_gate(&flip, &[qubit]);
                 }
-                _ => (0.0, 0.0),
             }
+            None
         }
-        Expression::PrefixExpression { op, right } => {
-            let (real, imag) = evaluate_complex_expression(right);
-            match op.as_str() {
-                "-" => (-real, -imag),
-                _ => (real, imag),
+        Operation::Barrier => None,
+        Operation::Display { name, text } => Some(if let Some(&qubit) = qubits.first() {
+            format!(
+                "{}: {:?}",
+                name,
+                Qubit::new_from_vec(register.qubit_state(qubit))
+            )
+        } else if let Some(text) = text {
+            format!("{}: {}", name, text)
+        } else if !instruction.clbits.is_empty() {
+            let bits: String = instruction
+                .clbits
+                .iter()
+                .rev()
+                .map(|&bit| clbits[bit].to_string())
+                .collect();
+            format!("{}: {}", name, bits)
+        } else {
+            match values.get(name) {
+                Some(value) => format!("{}: {:.4}", name, value),
+                None => format!("Cannot resolve symbol '{}'", name),
             }
+        }),
+        Operation::Expect {
+            identifier,
+            label,
+            observable,
+        } => {
+            let value = match options.expectation {
+                ExpectationMode::Exact => register.expectation(observable),
+                ExpectationMode::Shots => {
+                    match register.sampled_expectation(observable, options.shots) {
+                        Ok(value) => value,
+                        Err(message) => return Some(message),
+                    }
+                }
+            };
+            values.insert(identifier.clone(), value);
+            Some(format!("{} = <{}> = {:.4}", identifier, label, value))
         }
+        Operation::Print { analysis: kind } => {
+            let names: Vec<String> = qubits.iter().map(|&q| labels[q].clone()).collect();
+            let (line, record) = analyse_state(register, *kind, qubits, names);
+            analysis.push(record);
+            Some(line)
+        }
+        Operation::Error { message, .. } => Some(message.clone()),
     }
 }
 
-fn initialize_gate_map(hashmap: &mut HashMap<String, Box<dyn Gate>>) {
-    hashmap.insert("identity".to_string(), Box::new(Identity::new()));
-    hashmap.insert("pauliX".to_string(), Box::new(PauliX::new()));
-    hashmap.insert("pauliY".to_string(), Box::new(PauliY::new()));
-    hashmap.insert("pauliZ".to_string(), Box::new(PauliZ::new()));
-    hashmap.insert("hadamard".to_string(), Box::new(Hadamard::new()));
+/// Evaluates a `print <analysis> targets;` statement, returning the printed
+/// line together with its structured form.
+fn analyse_state<S: Simulator>(
+    register: &S,
+    kind: AnalysisKind,
+    qubits: &[usize],
+    mut targets: Vec<String>,
+) -> (String, StateAnalysis) {
+    let names = targets.join(", ");
+    let result = match kind {
+        AnalysisKind::Probs => {
+            let probabilities: Vec<analysis::BasisProbability> =
+                analysis::probabilities(register, qubits)
+                    .into_iter()
+                    .map(|(state, probability)| analysis::BasisProbability {
+                        state: analysis::basis_label(state, qubits.len()),
+                        probability,
+                    })
+                    .collect();
+            let line = probabilities
+                .iter()
+                .map(|p| format!("|{}>: {:.4}", p.state, p.probability))
+                .collect::<Vec<_>>()
+                .join(", ");
+            (
+                format!("probs {}: {}", names, line),
+                StateAnalysis::Probs {
+                    qubits: targets,
+                    probabilities,
+                },
+            )
+        }
+        AnalysisKind::Bloch => {
+            let (x, y, z) = analysis::bloch_vector(register, qubits[0]);
+            (
+                format!("bloch {}: ({:.4}, {:.4}, {:.4})", names, x, y, z),
+                StateAnalysis::Bloch {
+                    qubit: targets.remove(0),
+                    x,
+                    y,
+                    z,
+                },
+            )
+        }
+        AnalysisKind::Density => {
+            let rho = analysis::reduced_density_matrix(register, qubits);
+            (
+                format!("density {}: {}", names, format_matrix(&rho)),
+                StateAnalysis::Density {
+                    qubits: targets,
+                    real: analysis::matrix_rows(&rho, |c| c.re),
+                    imag: analysis::matrix_rows(&rho, |c| c.im),
+                },
+            )
+        }
+        AnalysisKind::Entropy => {
+            let entropy = analysis::entanglement_entropy(register, qubits);
+            (
+                format!("entropy {}: {:.4}", names, entropy),
+                StateAnalysis::Entropy {
+                    qubits: targets,
+                    entropy,
+                },
+            )
+        }
+        AnalysisKind::Concurrence => {
+            let concurrence = analysis::concurrence(register, [qubits[0], qubits[1]]);
+            (
+                format!("concurrence {}: {:.4}", names, concurrence),
+                StateAnalysis::Concurrence {
+                    qubits: targets,
+                    concurrence,
+                },
+            )
+        }
+    };
+
+    result
 }
 
 #[cfg(test)]
@@ -465,4 +754,255 @@ mod tests {
             "Expected no errors when creating qubit with complex expressions"
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sparse_backend_matches_dense_output() {
+        let program = || ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![
+                StatementNode::CreateStatement {
+                    identifier: "q1".to_string(),
+                    complex_array: ComplexArrayNode {
+                        r#type: NodeType::ComplexArray,
+                        values: vec![create_real_number(1.0), create_real_number(0.0)],
+                    },
+                },
+                StatementNode::ApplyStatement {
+                    identifier1: "q1".to_string(),
+                    identifier2: "pauliX".to_string(),
+                },
+                StatementNode::DisplayStatement {
+                    identifier: "q1".to_string(),
+                },
+                StatementNode::MeasureStatement {
+                    identifier: "q1".to_string(),
+                },
+            ],
+        };
+        let sparse = SimulationOptions {
+            backend: BackendKind::Sparse,
+            ..Default::default()
+        };
+
+        let results = interpret_program_with_options(program(), sparse);
+
+        assert_eq!(results, interpret_program(program()));
+        assert_eq!(results[1], "Result of measurement: 1");
+    }
+
+    fn create_plus_qubit(identifier: &str) -> StatementNode {
+        StatementNode::CreateStatement {
+            identifier: identifier.to_string(),
+            complex_array: ComplexArrayNode {
+                r#type: NodeType::ComplexArray,
+                values: vec![create_real_number(1.0), create_real_number(1.0)],
+            },
+        }
+    }
+
+    #[test]
+    fn test_expect_declared_observable() {
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![
+                create_plus_qubit("q1"),
+                create_plus_qubit("q2"),
+                StatementNode::ApplyStatement {
+                    identifier1: "q2".to_string(),
+                    identifier2: "hadamard".to_string(),
+                },
+                StatementNode::ObservableDeclaration {
+                    identifier: "H".to_string(),
+                    observable: "0.5*X0Z1 - 0.3*Z0".to_string(),
+                },
+                StatementNode::ExpectStatement {
+                    observable: "H".to_string(),
+                    identifier: "e".to_string(),
+                },
+                StatementNode::DisplayStatement {
+                    identifier: "e".to_string(),
+                },
+            ],
+        };
+
+        let results = interpret_program(program);
+
+        assert_eq!(results, vec!["e = <H> = 0.5000", "e: 0.5000"]);
+    }
+
+    #[test]
+    fn test_expect_inline_observable_with_shots() {
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![
+                create_plus_qubit("q1"),
+                StatementNode::ExpectStatement {
+                    observable: "X0".to_string(),
+                    identifier: "e".to_string(),
+                },
+            ],
+        };
+        let options = SimulationOptions {
+            expectation: ExpectationMode::Shots,
+            shots: 10,
+            ..Default::default()
+        };
+
+        let results = interpret_program_with_options(program.clone(), options);
+        assert_eq!(results, vec!["e = <X0> = 1.0000"]);
+
+        let no_shots = SimulationOptions {
+            shots: 0,
+            ..options
+        };
+        let results = interpret_program_with_options(program, no_shots);
+        assert_eq!(
+            results,
+            vec!["Sampling an expectation value needs at least 1 shot"]
+        );
+    }
+
+    #[test]
+    fn test_expect_errors() {
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![
+                create_plus_qubit("q1"),
+                StatementNode::ObservableDeclaration {
+                    identifier: "H".to_string(),
+                    observable: "0.5*Q0".to_string(),
+                },
+                StatementNode::ExpectStatement {
+                    observable: "Z1".to_string(),
+                    identifier: "e".to_string(),
+                },
+                StatementNode::ExpectStatement {
+                    observable: "unknown".to_string(),
+                    identifier: "e".to_string(),
+                },
+            ],
+        };
+
+        let results = interpret_program(program);
+
+        assert_eq!(
+            results,
+            vec![
+                "Invalid observable H: Unexpected character 'Q' in observable",
+                "Observable Z1 acts on qubit 1 but only 1 qubits exist",
+                "Cannot resolve observable 'unknown'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_print_analysis_statements() {
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![
+                create_plus_qubit("q1"),
+                StatementNode::CreateStatement {
+                    identifier: "q2".to_string(),
+                    complex_array: ComplexArrayNode {
+                        r#type: NodeType::ComplexArray,
+                        values: vec![create_real_number(0.0), create_real_number(1.0)],
+                    },
+                },
+                StatementNode::PrintStatement {
+                    analysis: AnalysisKind::Probs,
+                    targets: vec![],
+                },
+                StatementNode::PrintStatement {
+                    analysis: AnalysisKind::Bloch,
+                    targets: vec!["q1".into()],
+                },
+                StatementNode::PrintStatement {
+                    analysis: AnalysisKind::Density,
+                    targets: vec!["q2".into()],
+                },
+                StatementNode::PrintStatement {
+                    analysis: AnalysisKind::Entropy,
+                    targets: vec!["q1".into()],
+                },
+                StatementNode::PrintStatement {
+                    analysis: AnalysisKind::Concurrence,
+                    targets: vec!["q1".into(), "q2".into()],
+                },
+            ],
+        };
+
+        let result = simulate_program(program, SimulationOptions::default());
+
+        assert_eq!(
+            result.output,
+            vec![
+                "probs q1, q2: |01>: 0.5000, |11>: 0.5000",
+                "bloch q1: (1.0000, 0.0000, 0.0000)",
+                "density q2: [[0.00+0.00i, 0.00+0.00i], [0.00+0.00i, 1.00+0.00i]]",
+                "entropy q1: 0.0000",
+                "concurrence q1, q2: 0.0000",
+            ]
+        );
+        assert_eq!(result.analysis.len(), 5);
+        match &result.analysis[1] {
+            StateAnalysis::Bloch { qubit, x, .. } => {
+                assert_eq!(qubit, "q1");
+                assert!((x - 1.0).abs() < 1e-12);
+            }
+            other => panic!("Expected a Bloch vector, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_print_analysis_errors() {
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![
+                create_plus_qubit("q1"),
+                StatementNode::PrintStatement {
+                    analysis: AnalysisKind::Bloch,
+                    targets: vec![],
+                },
+                StatementNode::PrintStatement {
+                    analysis: AnalysisKind::Concurrence,
+                    targets: vec!["q1".into(), "q1".into()],
+                },
+                StatementNode::PrintStatement {
+                    analysis: AnalysisKind::Probs,
+                    targets: vec!["q9".into()],
+                },
+            ],
+        };
+
+        let result = simulate_program(program, SimulationOptions::default());
+
+        assert_eq!(
+            result.output,
+            vec![
+                "print bloch expects 1 qubit(s), got 0",
+                "Qubit 'q1' is listed twice",
+                "Cannot resolve symbol 'q9'",
+            ]
+        );
+        assert!(result.analysis.is_empty());
+    }
+
+    #[test]
+    fn test_cancelled_simulation() {
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![create_plus_qubit("q1")],
+        };
+        let circuit = lower_program(&program);
+
+        let cancelled = AtomicBool::new(true);
+        let result =
+            simulate_circuit_cancellable(&circuit, SimulationOptions::default(), &cancelled);
+        assert!(result.is_none());
+
+        cancelled.store(false, Ordering::Relaxed);
+        let result =
+            simulate_circuit_cancellable(&circuit, SimulationOptions::default(), &cancelled);
+        assert_eq!(result.unwrap().metrics.num_qubits, 1);
+    }
+}
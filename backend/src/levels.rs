@@ -0,0 +1,488 @@
+//! Challenges attached to the lessons in `frontend/src/data/lessons.json`.
+//!
+//! A level shares its `id` with the lesson it practises and asks for a
+//! circuit reaching a goal: a final state, the unitary of a reference
+//! program, or a distribution of measurement outcomes. Submissions may be
+//! limited to a gate set and a gate count or depth. Grading returns a
+//! fidelity with the goal and hints instead of the expected answer.
+
+use std::{
+    collections::BTreeMap,
+    sync::{atomic::AtomicBool, OnceLock},
+};
+
+use nalgebra::{Complex, DVector};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    circuit::{Circuit, Operation},
+    equivalence::{check_equivalence_cancellable, EquivalenceOptions},
+    gate::Gate,
+    interpreter::{run_circuit_cancellable, simulate_circuit_cancellable, SimulationOptions},
+    limits::CANCELLED,
+    lower::lower_program,
+    metrics::circuit_metrics,
+    models::ProgramNode,
+    qasm::parse_qasm,
+    quantum_register::QuantumRegister,
+    simulator::Simulator,
+    unitary::{circuit_unitary_cancellable, MAX_UNITARY_QUBITS},
+};
+
+const DEFAULT_SHOTS: usize = 512;
+/// Fidelity needed to pass a state or unitary goal.
+const EXACT_THRESHOLD: f64 = 0.99;
+/// Fidelity needed to pass a distribution goal, which is sampled when the
+/// program measures.
+const SAMPLED_THRESHOLD: f64 = 0.95;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Level {
+    /// The id of the lesson the level belongs to.
+    pub id: u32,
+    pub title: String,
+    pub description: String,
+    pub qubits: usize,
+    pub goal: Goal,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_gates: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_gates: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    /// Fidelity needed to pass, overriding the goal's default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+    /// Shown, in order, after failed attempts.
+    #[serde(default, skip_serializing)]
+    pub hints: Vec<String>,
+}
+
+/// Basis states are written with the last qubit first, like the classical
+/// registers in simulation results.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Goal {
+    /// The final state, `[re, im]` per basis state, up to a global phase.
+    State {
+        amplitudes: BTreeMap<String, [f64; 2]>,
+    },
+    /// The unitary of an OpenQASM 2.0 program body (after the header and
+    /// `include "qelib1.inc";`), up to a global phase. The reference is the
+    /// answer, so it is never sent to clients.
+    Unitary {
+        #[serde(skip_serializing)]
+        reference: String,
+    },
+    /// Probabilities of the measured classical registers, or of the final
+    /// state when the program declares no register.
+    Distribution {
+        probabilities: BTreeMap<String, f64>,
+        #[serde(default = "default_shots", skip_serializing)]
+        shots: usize,
+    },
+}
+
+fn default_shots() -> usize {
+    DEFAULT_SHOTS
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Verdict {
+    pub level: u32,
+    pub passed: bool,
+    /// How close the program comes to the goal, from 0 to 1.
+    pub fidelity: f64,
+    pub gates: usize,
+    pub depth: usize,
+    pub hints: Vec<String>,
+}
+
+/// The levels shipped with the backend.
+pub fn levels() -> &'static [Level] {
+    static LEVELS: OnceLock<Vec<Level>> = OnceLock::new();
+    LEVELS.get_or_init(|| {
+        load_levels(include_str!("levels.json")).expect("levels.json holds valid levels")
+    })
+}
+
+pub fn find_level(id: u32) -> Option<&'static Level> {
+    levels().iter().find(|level| level.id == id)
+}
+
+/// Parses and checks level definitions.
+pub fn load_levels(source: &str) -> Result<Vec<Level>, String> {
+    let levels: Vec<Level> =
+        serde_json::from_str(source).map_err(|e| format!("Invalid levels: {}", e))?;
+    for level in &levels {
+        let invalid = |message: String| format!("Level {}: {}", level.id, message);
+        let check_keys = |keys: Vec<&String>| {
+            for key in keys {
+                if key.len() != level.qubits || !key.chars().all(|c| c == '0' || c == '1') {
+                    return Err(invalid(format!("'{}' is not a basis state", key)));
+                }
+            }
+            Ok(())
+        };
+        match &level.goal {
+            Goal::State { amplitudes } => check_keys(amplitudes.keys().collect())?,
+            Goal::Distribution { probabilities, .. } => {
+                // Registers may be wider than the qubits, so only check the
+                // total.
+                let total: f64 = probabilities.values().sum();
+                if (total - 1.0).abs() > 1e-6 {
+                    return Err(invalid(format!("probabilities add up to {}", total)));
+                }
+            }
+            Goal::Unitary { reference } => {
+                let circuit = reference_circuit(reference).map_err(invalid)?;
+                if circuit.qubits.len() != level.qubits {
+                    return Err(invalid(format!(
+                        "the reference has {} qubits",
+                        circuit.qubits.len()
+                    )));
+                }
+            }
+        }
+    }
+    Ok(levels)
+}
+
+fn reference_circuit(reference: &str) -> Result<Circuit, String> {
+    let source = format!("OPENQASM 2.0;\ninclude \"qelib1.inc\";\n{}", reference);
+    let program = parse_qasm(&source).map_err(|e| format!("invalid reference: {}", e))?;
+    let circuit = lower_program(&program);
+    for instruction in &circuit.instructions {
+        if let Operation::Error { message, .. } = &instruction.operation {
+            return Err(format!("invalid reference: {}", message));
+        }
+    }
+    Ok(circuit)
+}
+
+/// Runs a submission against a level.
+pub fn grade(level: &Level, program: &ProgramNode) -> Verdict {
+    grade_circuit(level, &lower_program(program), &AtomicBool::new(false))
+}
+
+/// Runs the lowered circuit of a submission against a level, giving up
+/// once `cancelled` is set.
+pub fn grade_circuit(level: &Level, circuit: &Circuit, cancelled: &AtomicBool) -> Verdict {
+    let metrics = circuit_metrics(circuit);
+    let mut verdict = Verdict {
+        level: level.id,
+        passed: false,
+        fidelity: 0.0,
+        gates: metrics.gates,
+        depth: metrics.depth,
+        hints: vec![],
+    };
+
+    let threshold = level.threshold.unwrap_or(match level.goal {
+        Goal::Distribution { .. } => SAMPLED_THRESHOLD,
+        _ => EXACT_THRESHOLD,
+    });
+    let mut problems = check_rules(level, circuit);
+    if problems.is_empty() {
+        match fidelity(level, circuit, cancelled) {
+            Ok((fidelity, problem)) => {
+                verdict.fidelity = fidelity;
+                if fidelity < threshold {
+                    problems.extend(problem);
+                }
+            }
+            Err(error) => problems.push(error),
+        }
+    }
+    if let Some(max) = level.max_gates.filter(|max| metrics.gates > *max) {
+        problems.push(format!(
+            "Use at most {} gates; your circuit has {}.",
+            max, metrics.gates
+        ));
+    }
+    if let Some(max) = level.max_depth.filter(|max| metrics.depth > *max) {
+        problems.push(format!(
+            "Keep the depth at most {}; your circuit has depth {}.",
+            max, metrics.depth
+        ));
+    }
+
+    verdict.passed = problems.is_empty() && verdict.fidelity >= threshold;
+    if !verdict.passed {
+        verdict.hints = problems;
+        verdict.hints.extend(level.hints.iter().cloned());
+    }
+    verdict
+}
+
+/// Problems with the shape of the circuit, which stop it being graded.
+fn check_rules(level: &Level, circuit: &Circuit) -> Vec<String> {
+    let mut problems = vec![];
+    for instruction in &circuit.instructions {
+        match &instruction.operation {
+            Operation::Error { message, .. } => problems.push(message.clone()),
+            Operation::Gate { name, .. } => {
+                if let Some(allowed) = &level.allowed_gates {
+                    let problem = format!(
+                        "Gate '{}' is not allowed here; use {}.",
+                        name,
+                        allowed.join(", ")
+                    );
+                    if !allowed.contains(name) && !problems.contains(&problem) {
+                        problems.push(problem);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if circuit.qubits.len() != level.qubits {
+        problems.push(format!(
+            "This level needs {} qubits; your circuit has {}.",
+            level.qubits,
+            circuit.qubits.len()
+        ));
+    }
+    if circuit
+        .qubits
+        .iter()
+        .any(|qubit| qubit.initial != [[1.0, 0.0], [0.0, 0.0]])
+    {
+        problems.push("Start every qubit in |0> and reach the goal with gates.".to_string());
+    }
+    problems
+}
+
+/// The fidelity with the goal, and what is wrong if it falls short.
+fn fidelity(
+    level: &Level,
+    circuit: &Circuit,
+    cancelled: &AtomicBool,
+) -> Result<(f64, Option<String>), String> {
+    match &level.goal {
+        Goal::State { amplitudes } => {
+            let size = 1 << level.qubits;
+            let mut target = DVector::zeros(size);
+            for (key, [re, im]) in amplitudes {
+                target[usize::from_str_radix(key, 2).unwrap()] = Complex::new(*re, *im);
+            }
+            let state = final_state(circuit, cancelled)?;
+            let fidelity = (target.dotc(&state).norm() / target.norm()).powi(2);
+            Ok((fidelity, None))
+        }
+        Goal::Unitary { reference } => {
+            let reference = reference_circuit(reference)?;
+            let options = EquivalenceOptions {
+                ignore_ancillas: true,
+            };
+            let result = check_equivalence_cancellable(&reference, circuit, options, cancelled)?;
+            let fidelity = if result.equivalent {
+                1.0
+            } else {
+                process_fidelity(&reference, circuit, cancelled).unwrap_or(0.0)
+            };
+            let problem = result.counter_example.map(|example| {
+                let input = example.label.unwrap_or("a random input".to_string());
+                format!("{}: try the input {}.", example.reason, input)
+            });
+            Ok((fidelity, problem))
+        }
+        Goal::Distribution {
+            probabilities,
+            shots,
+        } => {
+            let measured = distribution(circuit, *shots, cancelled)?;
+            let overlap: f64 = probabilities
+                .iter()
+                .map(|(key, p)| (p * measured.get(key).copied().unwrap_or(0.0)).sqrt())
+                .sum();
+            let format = |distribution: &BTreeMap<String, f64>| {
+                let entries: Vec<String> = distribution
+                    .iter()
+                    .filter(|(_, p)| **p > 0.0)
+                    .map(|(key, p)| format!("{}: {:.2}", key, p))
+                    .collect();
+                entries.join(", ")
+            };
+            let problem = format!(
+                "Measured {}; expected {}.",
+                format(&measured),
+                format(probabilities)
+            );
+            Ok((overlap.powi(2), Some(problem)))
+        }
+    }
+}
+
+fn final_state(circuit: &Circuit, cancelled: &AtomicBool) -> Result<DVector<Complex<f64>>, String> {
+    let (register, _) = run_circuit_cancellable::<QuantumRegister<f64>>(
+        circuit,
+        SimulationOptions::default(),
+        cancelled,
+    )
+    .ok_or(CANCELLED)?;
+    let mut state = DVector::zeros(1 << register.num_qubits());
+    for (index, amplitude) in register.amplitudes() {
+        state[index] = amplitude;
+    }
+    Ok(state)
+}
+
+/// `|Tr(U†V)|² / d²` for circuits of the same, small width.
+fn process_fidelity(reference: &Circuit, circuit: &Circuit, cancelled: &AtomicBool) -> Option<f64> {
+    let width = reference.qubits.len();
+    if circuit.qubits.len() != width || width > MAX_UNITARY_QUBITS {
+        return None;
+    }
+    let u = circuit_unitary_cancellable(reference, cancelled).ok()?.1;
+    let v = circuit_unitary_cancellable(circuit, cancelled).ok()?.1;
+    let (u, v) = (u.matrix_representation(), v.matrix_representation());
+    let trace = (u.adjoint() * v).trace();
+    Some(trace.norm_sqr() / (1 << (2 * width)) as f64)
+}
+
+/// Outcome frequencies of the classical registers, joined with spaces in
+/// declaration order, over `shots` runs. Without registers, the exact
+/// probabilities of the final state.
+fn distribution(
+    circuit: &Circuit,
+    shots: usize,
+    cancelled: &AtomicBool,
+) -> Result<BTreeMap<String, f64>, String> {
+    let mut counts = BTreeMap::new();
+    if circuit.classical.is_empty() {
+        let state = final_state(circuit, cancelled)?;
+        for (index, amplitude) in state.iter().enumerate() {
+            if amplitude.norm_sqr() > 1e-12 {
+                let key = format!("{:0width$b}", index, width = circuit.qubits.len());
+                counts.insert(key, amplitude.norm_sqr());
+            }
+        }
+        return Ok(counts);
+    }
+
+    for _ in 0..shots {
+        let memory = simulate_circuit_cancellable(circuit, SimulationOptions::default(), cancelled)
+            .ok_or(CANCELLED)?
+            .memory;
+        let key: Vec<&str> = circuit
+            .classical
+            .iter()
+            .map(|register| memory[&register.name].as_str())
+            .collect();
+        *counts.entry(key.join(" ")).or_insert(0.0) += 1.0 / shots as f64;
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submit(id: u32, body: &str) -> Verdict {
+        let source = format!("OPENQASM 2.0;\ninclude \"qelib1.inc\";\n{}", body);
+        grade(find_level(id).unwrap(), &parse_qasm(&source).unwrap())
+    }
+
+    #[test]
+    fn test_every_level_has_a_solution() {
+        let solutions = [
+            (3, "qreg q[1];\nx q[0];"),
+            (4, "qreg q[1];\nh q[0];"),
+            (5, "qreg q[1];\nx q[0];\nh q[0];"),
+            (6, "qreg q[2];\nx q[0];\nh q[1];"),
+            (7, "qreg q[2];\nh q[0];\ncx q[0], q[1];"),
+            (
+                8,
+                "qreg q[2];\ncreg c[2];\nh q[0];\nmeasure q[0] -> c[0];\nif(c==1) x q[1];\n\
+                 measure q[1] -> c[1];",
+            ),
+            (9, "qreg q[1];\nh q[0];\nx q[0];\nh q[0];"),
+            (
+                12,
+                "qreg q[2];\nh q[0];\nh q[1];\ncx q[0], q[1];\nh q[0];\nh q[1];",
+            ),
+            (
+                13,
+                "qreg q[2];\nh q[0];\nh q[1];\ncz q[0], q[1];\nh q[0];\nh q[1];\nx q[0];\n\
+                 x q[1];\ncz q[0], q[1];\nx q[0];\nx q[1];\nh q[0];\nh q[1];",
+            ),
+        ];
+        assert_eq!(levels().len(), solutions.len());
+        for (id, solution) in solutions {
+            let verdict = submit(id, solution);
+            assert!(verdict.passed, "level {}: {:?}", id, verdict);
+            assert!(verdict.hints.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_wrong_state() {
+        let verdict = submit(4, "qreg q[1];\nx q[0];");
+        assert!(!verdict.passed);
+        assert!(verdict.hints[0].contains("'pauliX' is not allowed"));
+
+        let verdict = submit(5, "qreg q[1];\nh q[0];");
+        assert!(!verdict.passed);
+        assert!(verdict.fidelity.abs() < 1e-9);
+        assert_eq!(verdict.hints, find_level(5).unwrap().hints);
+    }
+
+    #[test]
+    fn test_wrong_unitary_gives_a_counter_example() {
+        let verdict = submit(9, "qreg q[1];\nh q[0];\nh q[0];");
+        assert!(!verdict.passed);
+        assert!(verdict.fidelity.abs() < 1e-9);
+        assert!(verdict.hints[0].contains("|+>"), "{:?}", verdict.hints);
+    }
+
+    #[test]
+    fn test_limits() {
+        let verdict = submit(7, "qreg q[2];\nh q[0];\ncx q[0], q[1];\nh q[1];\nh q[1];");
+        assert!(!verdict.passed);
+        assert_eq!((verdict.gates, verdict.fidelity.round()), (4, 1.0));
+        assert!(verdict.hints[0].contains("at most 2 gates"));
+
+        let verdict = submit(7, "qreg q[3];\nh q[0];\ncx q[0], q[1];");
+        assert!(verdict.hints[0].contains("needs 2 qubits"));
+    }
+
+    #[test]
+    fn test_sampled_distribution() {
+        let verdict = submit(
+            8,
+            "qreg q[2];\ncreg c[2];\nh q[0];\nmeasure q[0] -> c[0];\nmeasure q[1] -> c[1];",
+        );
+        assert!(!verdict.passed);
+        assert!(verdict.fidelity < 0.95);
+        assert!(verdict.hints[0].starts_with("Measured 00: "));
+    }
+
+    #[test]
+    fn test_cancelled_grading_fails() {
+        let source = "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[1];\nh q[0];";
+        let circuit = lower_program(&parse_qasm(source).unwrap());
+        let verdict = grade_circuit(find_level(4).unwrap(), &circuit, &AtomicBool::new(true));
+        assert!(!verdict.passed);
+        assert!(verdict.hints.contains(&CANCELLED.to_string()));
+    }
+
+    #[test]
+    fn test_invalid_levels_are_rejected() {
+        let level = |goal: &str| {
+            format!(
+                r#"[{{"id": 1, "title": "", "description": "", "qubits": 1, "goal": {}}}]"#,
+                goal
+            )
+        };
+        assert!(load_levels(&level(r#"{"type": "state", "amplitudes": {"01": [1, 0]}}"#)).is_err());
+        assert!(load_levels(&level(
+            r#"{"type": "distribution", "probabilities": {"1": 0.5}}"#
+        ))
+        .is_err());
+        assert!(load_levels(&level(r#"{"type": "unitary", "reference": "h q[0];"}"#)).is_err());
+        assert!(load_levels(&level(r#"{"type": "unitary", "reference": "qreg q[1];"}"#)).is_ok());
+    }
+}
@@ -0,0 +1,33 @@
+pub mod analysis;
+pub mod auth;
+pub mod circuit;
+pub mod cli;
+pub mod config;
+pub mod decompose;
+pub mod diagram;
+pub mod equivalence;
+pub mod gate;
+pub mod handler;
+pub mod interpreter;
+pub mod levels;
+pub mod limits;
+pub mod logging;
+pub mod lower;
+pub mod metrics;
+pub mod models;
+pub mod observable;
+pub mod optimize;
+pub mod precision;
+pub mod qasm;
+pub mod quantum_register;
+pub mod qubit;
+pub mod route;
+pub mod routing;
+pub mod session;
+pub mod share;
+pub mod simulator;
+pub mod sparse_register;
+pub mod store;
+pub mod stream;
+pub mod telemetry;
+pub mod unitary;
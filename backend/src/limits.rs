@@ -0,0 +1,455 @@
+//! Bounds on the work a single simulation request can ask for.
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    circuit::Circuit,
+    interpreter::SimulationOptions,
+    lower::{lower_program_limited, LoweringStopped},
+    models::ProgramNode,
+    observable::ExpectationMode,
+    simulator::BackendKind,
+    sparse_register::MAX_SPARSE_QUBITS,
+};
+
+/// Widest register the dense backend simulates, 256 MiB of amplitudes in
+/// double precision.
+pub const DEFAULT_MAX_QUBITS: usize = 24;
+pub const DEFAULT_MAX_SPARSE_QUBITS: usize = 63;
+pub const DEFAULT_MAX_INSTRUCTIONS: usize = 100_000;
+pub const DEFAULT_MAX_CLBITS: usize = 100_000;
+pub const DEFAULT_MAX_SHOTS: usize = 100_000;
+pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_STREAM_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Widest dense register that can be configured, 512 GiB of amplitudes.
+const MAX_DENSE_QUBITS: usize = 35;
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Most qubits of a circuit run on the dense backend.
+    pub max_qubits: usize,
+    /// Most qubits of a circuit run on the sparse backend.
+    pub max_sparse_qubits: usize,
+    /// Most instructions of a circuit, after loops and gate definitions are
+    /// expanded.
+    pub max_instructions: usize,
+    /// Most classical bits of a circuit.
+    pub max_clbits: usize,
+    pub max_shots: usize,
+    pub max_body_bytes: usize,
+    /// Wall-clock time a simulation may take before it is cancelled.
+    #[serde(rename = "timeout_ms", deserialize_with = "milliseconds")]
+    pub timeout: Duration,
+    /// Wall-clock time a WebSocket stream may stay open, pauses between
+    /// steps included.
+    #[serde(rename = "stream_timeout_ms", deserialize_with = "milliseconds")]
+    pub stream_timeout: Duration,
+    /// Backends a request may choose.
+    pub backends: Vec<BackendKind>,
+}
+
+fn milliseconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_qubits: DEFAULT_MAX_QUBITS,
+            max_sparse_qubits: DEFAULT_MAX_SPARSE_QUBITS,
+            max_instructions: DEFAULT_MAX_INSTRUCTIONS,
+            max_clbits: DEFAULT_MAX_CLBITS,
+            max_shots: DEFAULT_MAX_SHOTS,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            timeout: DEFAULT_TIMEOUT,
+            stream_timeout: DEFAULT_STREAM_TIMEOUT,
+            backends: vec![BackendKind::Dense, BackendKind::Sparse],
+        }
+    }
+}
+
+/// A request that went over one of the [`Limits`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LimitError {
+    pub error: String,
+    /// `qubits`, `clbits`, `instructions`, `shots`, `bodyBytes`, `timeoutMs`,
+    /// `streamTimeoutMs` or `backend`.
+    pub limit: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<u64>,
+    /// What the request asked for, when it is known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<u64>,
+}
+
+impl LimitError {
+    fn new(limit: &'static str, what: &str, max: usize, actual: usize) -> LimitError {
+        LimitError {
+            error: format!(
+                "The {} asks for {} {}, more than the {} allowed",
+                what, actual, limit, max
+            ),
+            limit,
+            max: Some(max as u64),
+            actual: Some(actual as u64),
+        }
+    }
+
+    pub fn body(max: usize) -> LimitError {
+        LimitError {
+            error: format!("The request body is larger than {} bytes", max),
+            limit: "bodyBytes",
+            max: Some(max as u64),
+            actual: None,
+        }
+    }
+
+    pub fn timeout(timeout: Duration) -> LimitError {
+        LimitError {
+            error: format!(
+                "The request did not finish within {} ms",
+                timeout.as_millis()
+            ),
+            limit: "timeoutMs",
+            max: Some(timeout.as_millis() as u64),
+            actual: None,
+        }
+    }
+
+    pub fn stream_timeout(timeout: Duration) -> LimitError {
+        LimitError {
+            error: format!("The stream was closed after {} ms", timeout.as_millis()),
+            limit: "streamTimeoutMs",
+            max: Some(timeout.as_millis() as u64),
+            actual: None,
+        }
+    }
+}
+
+/// What work that was cancelled after the timeout fails with; nobody waits
+/// for it by then.
+pub const CANCELLED: &str = "Cancelled after the timeout";
+
+/// Fails once `cancelled` is set, for work that runs within the timeout and
+/// checks it between steps.
+pub fn check_cancelled(cancelled: &AtomicBool) -> Result<(), String> {
+    if cancelled.load(Ordering::Relaxed) {
+        Err(CANCELLED.to_string())
+    } else {
+        Ok(())
+    }
+}
+
+impl Limits {
+    /// Checks that the limits leave something to simulate with.
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = [
+            ("max_qubits", self.max_qubits),
+            ("max_sparse_qubits", self.max_sparse_qubits),
+            ("max_instructions", self.max_instructions),
+            ("max_clbits", self.max_clbits),
+            ("max_shots", self.max_shots),
+            ("max_body_bytes", self.max_body_bytes),
+            ("timeout_ms", self.timeout.as_millis() as usize),
+            (
+                "stream_timeout_ms",
+                self.stream_timeout.as_millis() as usize,
+            ),
+        ];
+        if let Some((name, _)) = positive.iter().find(|(_, value)| *value == 0) {
+            return Err(format!("limits.{} must be greater than 0", name));
+        }
+        if self.max_sparse_qubits > MAX_SPARSE_QUBITS {
+            return Err(format!(
+                "limits.max_sparse_qubits can be at most {}",
+                MAX_SPARSE_QUBITS
+            ));
+        }
+        if self.max_qubits > MAX_DENSE_QUBITS {
+            return Err(format!(
+                "limits.max_qubits can be at most {}",
+                MAX_DENSE_QUBITS
+            ));
+        }
+        if self.backends.is_empty() {
+            return Err("limits.backends must enable at least one backend".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn check_options(&self, options: &SimulationOptions) -> Result<(), LimitError> {
+        if !self.backends.contains(&options.backend) {
+            return Err(LimitError {
+                error: format!(
+                    "The {} backend is not enabled on this server",
+                    options.backend
+                ),
+                limit: "backend",
+                max: None,
+                actual: None,
+            });
+        }
+        if options.expectation == ExpectationMode::Shots && options.shots == 0 {
+            return Err(LimitError {
+                error: "Sampling expectation values needs at least 1 shot".to_string(),
+                limit: "shots",
+                max: Some(self.max_shots as u64),
+                actual: Some(0),
+            });
+        }
+        if options.expectation == ExpectationMode::Shots && options.shots > self.max_shots {
+            return Err(LimitError::new(
+                "shots",
+                "simulation",
+                self.max_shots,
+                options.shots,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Most qubits of a circuit run on `backend`.
+    pub fn qubits_for(&self, backend: BackendKind) -> usize {
+        match backend {
+            BackendKind::Dense => self.max_qubits,
+            BackendKind::Sparse => self.max_sparse_qubits,
+        }
+    }
+
+    /// Lowers a program to simulate, refusing circuits with too many
+    /// instructions or classical bits, or too many qubits for the chosen
+    /// backend. Lowering
+    /// stops as soon as the program goes over, or once `cancelled` is set.
+    pub fn lower(
+        &self,
+        program: &ProgramNode,
+        options: &SimulationOptions,
+        cancelled: &AtomicBool,
+    ) -> Result<Circuit, LimitError> {
+        self.lower_within(program, self.qubits_for(options.backend), cancelled)
+    }
+
+    /// Lowers a program that is only drawn or analysed, which may be as
+    /// wide as the widest backend allows.
+    pub fn lower_unsimulated(
+        &self,
+        program: &ProgramNode,
+        cancelled: &AtomicBool,
+    ) -> Result<Circuit, LimitError> {
+        let max_qubits = self.max_qubits.max(self.max_sparse_qubits);
+        self.lower_within(program, max_qubits, cancelled)
+    }
+
+    fn lower_within(
+        &self,
+        program: &ProgramNode,
+        max_qubits: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<Circuit, LimitError> {
+        lower_program_limited(
+            program,
+            self.max_instructions,
+            max_qubits,
+            self.max_clbits,
+            cancelled,
+        )
+        .map_err(|stopped| match stopped {
+            LoweringStopped::Instructions(count) => {
+                LimitError::new("instructions", "program", self.max_instructions, count)
+            }
+            LoweringStopped::Qubits(count) => {
+                LimitError::new("qubits", "program", max_qubits, count)
+            }
+            LoweringStopped::Clbits(count) => {
+                LimitError::new("clbits", "program", self.max_clbits, count)
+            }
+            LoweringStopped::Cancelled => LimitError::timeout(self.timeout),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qasm::parse_qasm;
+
+    fn program(source: &str) -> ProgramNode {
+        parse_qasm(&format!(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\n{}",
+            source
+        ))
+        .unwrap()
+    }
+
+    fn running() -> AtomicBool {
+        AtomicBool::new(false)
+    }
+
+    #[test]
+    fn test_qubits_depend_on_the_backend() {
+        let limits = Limits {
+            max_qubits: 4,
+            max_sparse_qubits: 8,
+            ..Default::default()
+        };
+        let wide = program("qreg q[6];\nh q[0];");
+
+        let error = limits
+            .lower(&wide, &SimulationOptions::default(), &running())
+            .unwrap_err();
+        assert_eq!(
+            (error.limit, error.max, error.actual),
+            ("qubits", Some(4), Some(6))
+        );
+
+        let sparse = SimulationOptions {
+            backend: BackendKind::Sparse,
+            ..Default::default()
+        };
+        assert_eq!(
+            limits
+                .lower(&wide, &sparse, &running())
+                .unwrap()
+                .qubits
+                .len(),
+            6
+        );
+    }
+
+    #[test]
+    fn test_instructions_and_shots() {
+        let limits = Limits {
+            max_instructions: 3,
+            max_shots: 10,
+            ..Default::default()
+        };
+        let options = SimulationOptions::default();
+        assert!(limits
+            .lower(
+                &program("qreg q[1];\nx q[0];\nx q[0];"),
+                &options,
+                &running()
+            )
+            .is_ok());
+        let error = limits
+            .lower(&program("qreg q[2];\nh q;\nh q;"), &options, &running())
+            .unwrap_err();
+        assert_eq!((error.limit, error.actual), ("instructions", Some(4)));
+
+        let shots = SimulationOptions {
+            expectation: ExpectationMode::Shots,
+            shots: 11,
+            ..Default::default()
+        };
+        assert_eq!(limits.check_options(&shots).unwrap_err().limit, "shots");
+        let no_shots = SimulationOptions { shots: 0, ..shots };
+        assert_eq!(limits.check_options(&no_shots).unwrap_err().actual, Some(0));
+        assert!(limits.check_options(&options).is_ok());
+    }
+
+    #[test]
+    fn test_lowering_stops_as_soon_as_it_goes_over() {
+        let limits = Limits {
+            max_instructions: 1000,
+            ..Default::default()
+        };
+        let options = SimulationOptions::default();
+
+        let wide = program("qreg q[20000000];");
+        let error = limits.lower(&wide, &options, &running()).unwrap_err();
+        assert_eq!((error.limit, error.actual), ("qubits", Some(20_000_000)));
+
+        // Each gate doubles the one before, to 2^30 instructions in total.
+        let mut source = "gate g0 a { h a; h a; }\n".to_string();
+        for level in 1..30 {
+            source += &format!(
+                "gate g{} a {{ g{} a; g{} a; }}\n",
+                level,
+                level - 1,
+                level - 1
+            );
+        }
+        let nested = program(&(source + "qreg q[1];\ng29 q[0];"));
+        let error = limits.lower(&nested, &options, &running()).unwrap_err();
+        assert_eq!((error.limit, error.actual), ("instructions", Some(1001)));
+
+        let error = limits
+            .lower(&nested, &options, &AtomicBool::new(true))
+            .unwrap_err();
+        assert_eq!(error.limit, "timeoutMs");
+    }
+
+    #[test]
+    fn test_backends_and_validation() {
+        let limits = Limits {
+            backends: vec![BackendKind::Sparse],
+            ..Default::default()
+        };
+        let error = limits
+            .check_options(&SimulationOptions::default())
+            .unwrap_err();
+        assert_eq!(error.limit, "backend");
+        assert!(limits.validate().is_ok());
+
+        let invalid = [
+            Limits {
+                backends: vec![],
+                ..Default::default()
+            },
+            Limits {
+                max_shots: 0,
+                ..Default::default()
+            },
+            Limits {
+                timeout: Duration::ZERO,
+                ..Default::default()
+            },
+            Limits {
+                stream_timeout: Duration::ZERO,
+                ..Default::default()
+            },
+            Limits {
+                max_sparse_qubits: 64,
+                ..Default::default()
+            },
+        ];
+        for limits in invalid {
+            assert!(limits.validate().is_err(), "{:?}", limits);
+        }
+    }
+
+    #[test]
+    fn test_classical_bits() {
+        let limits = Limits {
+            max_clbits: 8,
+            ..Default::default()
+        };
+        let options = SimulationOptions::default();
+        assert!(limits
+            .lower(&program("creg c[4];\ncreg d[4];"), &options, &running())
+            .is_ok());
+        let error = limits
+            .lower(
+                &program("creg c[4];\ncreg d[100000000000];"),
+                &options,
+                &running(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            (error.limit, error.max, error.actual),
+            ("clbits", Some(8), Some(100_000_000_004))
+        );
+        let error = limits
+            .lower(&program("creg c[8];\ncreg d[1];"), &options, &running())
+            .unwrap_err();
+        assert_eq!(error.actual, Some(9));
+    }
+}
@@ -0,0 +1,74 @@
+//! Structured logs: a span per request carrying its ID, and spans around the
+//! phases of a simulation with how long each one took.
+
+use std::{io::IsTerminal, time::Instant};
+
+use axum::{extract::Request, http::HeaderName};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::{Level, Span};
+use tracing_subscriber::EnvFilter;
+
+/// Header carrying the ID of a request, kept when the client sends one.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Prints logs to stderr at `level` and above. `RUST_LOG`, when set, takes
+/// precedence, e.g. `RUST_LOG=info,quantum_simulator=debug`.
+pub fn init(level: &str) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .init();
+}
+
+/// Gives each request an ID, unless it has one, and returns it in the
+/// response.
+pub fn request_id_layers() -> (SetRequestIdLayer<MakeRequestUuid>, PropagateRequestIdLayer) {
+    (
+        SetRequestIdLayer::new(REQUEST_ID_HEADER, MakeRequestUuid),
+        PropagateRequestIdLayer::new(REQUEST_ID_HEADER),
+    )
+}
+
+/// Opens a span per request and logs its status and latency.
+pub fn trace_layer() -> TraceLayer<
+    tower_http::classify::SharedClassifier<tower_http::classify::ServerErrorsAsFailures>,
+    fn(&Request) -> Span,
+> {
+    TraceLayer::new_for_http()
+        .make_span_with(request_span as fn(&Request) -> Span)
+        .on_response(DefaultOnResponse::new().level(Level::INFO))
+}
+
+fn request_span(request: &Request) -> Span {
+    let id = request
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default();
+    tracing::info_span!(
+        "request",
+        id,
+        method = %request.method(),
+        path = request.uri().path(),
+    )
+}
+
+/// Runs one phase of handling a request, such as `parse`, `lower` or
+/// `simulate`, in its own span and logs how long it took.
+pub fn timed<T>(phase: &'static str, work: impl FnOnce() -> T) -> T {
+    let span = tracing::info_span!("phase", phase);
+    let _entered = span.enter();
+    let start = Instant::now();
+    let result = work();
+    tracing::info!(
+        elapsed_ms = start.elapsed().as_secs_f64() * 1000.0,
+        "{} finished",
+        phase
+    );
+    result
+}
@@ -0,0 +1,1393 @@
+//! Lowering of the program AST into a [`Circuit`].
+//!
+//! Every statement is checked here; a statement that fails becomes an
+//! [`Operation::Error`] instruction so its message shows up in the output
+//! at the point where the statement was, as the rest of the program still
+//! runs.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    rc::Rc,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use nalgebra::{Complex, DMatrix};
+
+use crate::{
+    analysis::{AnalysisKind, MAX_DENSITY_QUBITS},
+    circuit::{
+        Circuit, ClassicalRegister, Condition, GateMatrix, Instruction, Operation,
+        ProgramErrorKind as Kind, QubitInfo,
+    },
+    gate::{format_matrix, is_unitary, parameter_count, standard_gate},
+    models::{Expression, ProgramNode, StatementNode, Target},
+    observable::Observable,
+    qubit::Qubit,
+};
+
+/// Deepest nesting of composite gate calls, which also stops a gate from
+/// expanding into itself forever.
+pub const MAX_GATE_DEPTH: usize = 64;
+
+/// Most iterations of a `repeat` statement.
+pub const MAX_REPEAT_COUNT: usize = 1_000_000;
+
+/// Largest entry of `U^† U - I` accepted for a user-defined matrix gate.
+const UNITARY_TOLERANCE: f64 = 1e-9;
+
+/// A gate declared by the program itself.
+enum UserGate {
+    Matrix(DMatrix<Complex<f64>>),
+    Composite {
+        parameters: Vec<String>,
+        qubits: Vec<String>,
+        statements: Vec<StatementNode>,
+    },
+}
+
+pub fn lower_program(program: &ProgramNode) -> Circuit {
+    let mut lowering = Lowering::default();
+    lowering.program(program);
+    lowering.circuit
+}
+
+/// Why a statement could not be lowered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramError {
+    pub kind: Kind,
+    pub message: String,
+}
+
+fn error(kind: Kind, message: impl Into<String>) -> ProgramError {
+    ProgramError {
+        kind,
+        message: message.into(),
+    }
+}
+
+/// Why [`lower_program_limited`] gave up on a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoweringStopped {
+    /// The circuit would have at least this many instructions.
+    Instructions(usize),
+    /// The program declares at least this many qubits.
+    Qubits(usize),
+    /// The program declares at least this many classical bits.
+    Clbits(usize),
+    Cancelled,
+}
+
+/// Lowers a program unless it has more than `max_instructions`
+/// instructions, `max_qubits` qubits or `max_clbits` classical bits, or
+/// until `cancelled` is set.
+/// Every declaration and gate expansion is checked as it is lowered, and a
+/// loop as soon as its first iteration tells how large it is, so an
+/// oversized program is refused without being expanded.
+pub fn lower_program_limited(
+    program: &ProgramNode,
+    max_instructions: usize,
+    max_qubits: usize,
+    max_clbits: usize,
+    cancelled: &AtomicBool,
+) -> Result<Circuit, LoweringStopped> {
+    let mut lowering = Lowering {
+        budget: Some(Budget {
+            max_instructions,
+            max_qubits,
+            max_clbits,
+            cancelled,
+        }),
+        ..Default::default()
+    };
+    lowering.program(program);
+    match lowering.stopped {
+        Some(stopped) => Err(stopped),
+        None if lowering.circuit.instructions.len() > max_instructions => Err(
+            LoweringStopped::Instructions(lowering.circuit.instructions.len()),
+        ),
+        None => Ok(lowering.circuit),
+    }
+}
+
+struct Budget<'a> {
+    max_instructions: usize,
+    max_qubits: usize,
+    max_clbits: usize,
+    cancelled: &'a AtomicBool,
+}
+
+/// Everything a program can refer to by name while it is lowered.
+#[derive(Default)]
+struct Lowering<'a> {
+    circuit: Circuit,
+    variables: HashMap<String, usize>,
+    registers: HashMap<String, Vec<usize>>,
+    classical: HashMap<String, Range<usize>>,
+    definitions: HashMap<String, Rc<UserGate>>,
+    observables: HashMap<String, Observable>,
+    /// Results of `expect` statements, known only once the program runs.
+    values: HashSet<String>,
+    budget: Option<Budget<'a>>,
+    /// Set once the program went over the budget, which ends lowering.
+    stopped: Option<LoweringStopped>,
+}
+
+impl Lowering<'_> {
+    fn program(&mut self, program: &ProgramNode) {
+        for statement in &program.statements {
+            let mut instructions = vec![];
+            match self.statement(statement, None, &mut instructions) {
+                Ok(()) => self.circuit.instructions.extend(instructions),
+                Err(ProgramError { kind, message }) => self
+                    .circuit
+                    .instructions
+                    .push(Instruction::error(kind, message)),
+            }
+            if self.stopped.is_some() {
+                return;
+            }
+        }
+    }
+
+    /// Stops lowering when the circuit would grow to `instructions`
+    /// instructions or `qubits` qubits past the budget, or when it was
+    /// cancelled.
+    fn check_budget(&mut self, instructions: usize, qubits: usize) -> Result<(), ProgramError> {
+        let Some(budget) = &self.budget else {
+            return Ok(());
+        };
+        let stopped = if budget.cancelled.load(Ordering::Relaxed) {
+            LoweringStopped::Cancelled
+        } else if instructions > budget.max_instructions {
+            LoweringStopped::Instructions(instructions)
+        } else if qubits > budget.max_qubits {
+            LoweringStopped::Qubits(qubits)
+        } else {
+            return Ok(());
+        };
+        self.stopped = Some(stopped);
+        Err(error(
+            Kind::Other,
+            format!("Lowering stopped: {:?}", stopped),
+        ))
+    }
+
+    /// Stops lowering when the circuit would grow to `clbits` classical
+    /// bits past the budget.
+    fn check_clbits(&mut self, clbits: usize) -> Result<(), ProgramError> {
+        let Some(budget) = &self.budget else {
+            return Ok(());
+        };
+        if clbits <= budget.max_clbits {
+            return Ok(());
+        }
+        let stopped = LoweringStopped::Clbits(clbits);
+        self.stopped = Some(stopped);
+        Err(error(
+            Kind::Other,
+            format!("Lowering stopped: {:?}", stopped),
+        ))
+    }
+
+    /// Instructions lowered so far, including those of the statement in
+    /// progress.
+    fn instruction_count(&self, instructions: &[Instruction]) -> usize {
+        self.circuit.instructions.len() + instructions.len()
+    }
+
+    fn is_declared(&self, identifier: &str) -> bool {
+        self.variables.contains_key(identifier)
+            || self.registers.contains_key(identifier)
+            || self.classical.contains_key(identifier)
+            || self.observables.contains_key(identifier)
+    }
+
+    fn check_undeclared(&self, identifier: &str) -> Result<(), ProgramError> {
+        if self.is_declared(identifier) {
+            Err(error(
+                Kind::Redeclared,
+                format!("Identifier {} was already declared", identifier),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn add_qubit(&mut self, info: QubitInfo) -> usize {
+        self.circuit.qubits.push(info);
+        self.circuit.qubits.len() - 1
+    }
+
+    fn add_classical(&mut self, identifier: &str, size: usize) -> Result<(), ProgramError> {
+        let offset = self.circuit.num_clbits();
+        self.check_clbits(offset.saturating_add(size))?;
+        self.circuit.classical.push(ClassicalRegister {
+            name: identifier.to_string(),
+            size,
+        });
+        self.classical
+            .insert(identifier.to_string(), offset..offset + size);
+        Ok(())
+    }
+
+    /// Qubit positions a quantum operand refers to.
+    fn resolve_qubits(&self, target: &Target) -> Result<Vec<usize>, ProgramError> {
+        let identifier = &target.identifier;
+        match (self.registers.get(identifier), target.index) {
+            (Some(qubits), None) => Ok(qubits.clone()),
+            (Some(qubits), Some(index)) => qubits.get(index).map(|&q| vec![q]).ok_or_else(|| {
+                error(
+                    Kind::BadIndex,
+                    format!(
+                        "Index {} is out of range for register '{}' of size {}",
+                        index,
+                        identifier,
+                        qubits.len()
+                    ),
+                )
+            }),
+            (None, None) => self
+                .variables
+                .get(identifier)
+                .map(|&q| vec![q])
+                .ok_or_else(|| unknown_symbol(identifier)),
+            (None, Some(_)) if self.variables.contains_key(identifier) => Err(error(
+                Kind::UnknownIdentifier,
+                format!("'{}' is a qubit, not a register", identifier),
+            )),
+            (None, Some(_)) => Err(unknown_symbol(identifier)),
+        }
+    }
+
+    /// Clbit positions a classical operand refers to.
+    fn resolve_bits(&self, target: &Target) -> Result<Vec<usize>, ProgramError> {
+        let bits = self
+            .classical
+            .get(&target.identifier)
+            .ok_or_else(|| unknown_register(&target.identifier))?;
+        match target.index {
+            None => Ok(bits.clone().collect()),
+            Some(index) if index < bits.len() => Ok(vec![bits.start + index]),
+            Some(index) => Err(error(
+                Kind::BadIndex,
+                format!(
+                    "Index {} is out of range for register '{}' of size {}",
+                    index,
+                    target.identifier,
+                    bits.len()
+                ),
+            )),
+        }
+    }
+
+    /// Lowers one statement into `instructions`. `condition` is the
+    /// enclosing `if`, if any.
+    fn statement(
+        &mut self,
+        statement: &StatementNode,
+        condition: Option<&Condition>,
+        instructions: &mut Vec<Instruction>,
+    ) -> Result<(), ProgramError> {
+        let start = instructions.len();
+
+        match statement {
+            StatementNode::CreateStatement {
+                identifier,
+                complex_array,
+            } => {
+                self.reject_conditional(condition)?;
+                self.check_undeclared(identifier)?;
+
+                if complex_array.values.len() != 2 {
+                    return Err(error(
+                        Kind::WrongArguments,
+                        format!(
+                            "Invalid number of states for qubit {}: expected 2, got {}",
+                            identifier,
+                            complex_array.values.len()
+                        ),
+                    ));
+                }
+
+                self.check_budget(
+                    self.instruction_count(instructions),
+                    self.circuit.qubits.len() + 1,
+                )?;
+                let (real1, imag1) = evaluate_complex_expression(&complex_array.values[0]);
+                let (real2, imag2) = evaluate_complex_expression(&complex_array.values[1]);
+                let state = Qubit::new_from_amplitudes(real1, imag1, real2, imag2).state();
+
+                let index = self.add_qubit(QubitInfo {
+                    label: identifier.clone(),
+                    initial: [[state[0].re, state[0].im], [state[1].re, state[1].im]],
+                });
+                self.variables.insert(identifier.clone(), index);
+            }
+
+            StatementNode::ApplyStatement {
+                identifier1,
+                identifier2,
+            } => {
+                let qubits = self.resolve_qubits(&Target::new(identifier1, None))?;
+                self.gate_call(identifier2, &[], vec![qubits], instructions)?;
+            }
+
+            StatementNode::MeasureStatement { identifier } => {
+                let Some(&qubit) = self.variables.get(identifier) else {
+                    return Err(unknown_symbol(identifier));
+                };
+                instructions.push(Instruction::new(
+                    Operation::Measure { echo: true },
+                    vec![qubit],
+                ));
+            }
+
+            StatementNode::DisplayStatement { identifier } => {
+                instructions.push(self.display(identifier)?);
+            }
+
+            StatementNode::ObservableDeclaration {
+                identifier,
+                observable,
+            } => {
+                self.reject_conditional(condition)?;
+                self.check_undeclared(identifier)?;
+
+                let observable = observable.parse::<Observable>().map_err(|message| {
+                    error(
+                        Kind::BadExpression,
+                        format!("Invalid observable {}: {}", identifier, message),
+                    )
+                })?;
+                self.observables.insert(identifier.clone(), observable);
+            }
+
+            StatementNode::ExpectStatement {
+                observable,
+                identifier,
+            } => {
+                self.check_undeclared(identifier)?;
+
+                let parsed = match self.observables.get(observable) {
+                    Some(parsed) => parsed.clone(),
+                    None => observable.parse::<Observable>().map_err(|_| {
+                        error(
+                            Kind::UnknownIdentifier,
+                            format!("Cannot resolve observable '{}'", observable),
+                        )
+                    })?,
+                };
+
+                let num_qubits = self.circuit.qubits.len();
+                if let Some(qubit) = parsed.max_qubit() {
+                    if qubit >= num_qubits {
+                        return Err(error(
+                            Kind::BadIndex,
+                            format!(
+                                "Observable {} acts on qubit {} but only {} qubits exist",
+                                observable, qubit, num_qubits
+                            ),
+                        ));
+                    }
+                }
+
+                self.values.insert(identifier.clone());
+                instructions.push(Instruction::new(
+                    Operation::Expect {
+                        identifier: identifier.clone(),
+                        label: observable.clone(),
+                        observable: parsed,
+                    },
+                    vec![],
+                ));
+            }
+
+            StatementNode::PrintStatement { analysis, targets } => {
+                let qubits = self.analysis_targets(*analysis, targets)?;
+                instructions.push(Instruction::new(
+                    Operation::Print {
+                        analysis: *analysis,
+                    },
+                    qubits,
+                ));
+            }
+
+            StatementNode::RegisterDeclaration { identifier, size } => {
+                self.reject_conditional(condition)?;
+                self.check_undeclared(identifier)?;
+                if *size == 0 {
+                    return Err(error(
+                        Kind::WrongArguments,
+                        format!("Register {} must hold at least one qubit", identifier),
+                    ));
+                }
+                self.check_budget(
+                    self.instruction_count(instructions),
+                    self.circuit.qubits.len().saturating_add(*size),
+                )?;
+
+                let qubits = (0..*size)
+                    .map(|i| self.add_qubit(QubitInfo::zero(format!("{}[{}]", identifier, i))))
+                    .collect();
+                self.registers.insert(identifier.clone(), qubits);
+            }
+
+            StatementNode::ClassicalDeclaration { identifier, size } => {
+                self.reject_conditional(condition)?;
+                self.check_undeclared(identifier)?;
+                if *size == 0 {
+                    return Err(error(
+                        Kind::WrongArguments,
+                        format!("Register {} must hold at least one bit", identifier),
+                    ));
+                }
+                self.add_classical(identifier, *size)?;
+            }
+
+            StatementNode::GateApplication {
+                gate,
+                parameters,
+                targets,
+            } => {
+                if targets.is_empty() {
+                    return Err(error(
+                        Kind::WrongArguments,
+                        format!("Gate {} is applied to no qubits", gate),
+                    ));
+                }
+                let parameters = parameters
+                    .iter()
+                    .map(|parameter| evaluate_real_expression(parameter, &HashMap::new()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let operands = targets
+                    .iter()
+                    .map(|target| self.resolve_qubits(target))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.gate_call(gate, &parameters, operands, instructions)?;
+            }
+
+            StatementNode::GateDefinition {
+                identifier,
+                parameters,
+                qubits,
+                statements,
+            } => {
+                self.reject_conditional(condition)?;
+                self.check_gate_name(identifier)?;
+                check_gate_body(identifier, parameters, qubits, statements)?;
+                self.definitions.insert(
+                    identifier.clone(),
+                    Rc::new(UserGate::Composite {
+                        parameters: parameters.clone(),
+                        qubits: qubits.clone(),
+                        statements: statements.clone(),
+                    }),
+                );
+            }
+
+            StatementNode::MatrixGateDefinition { identifier, matrix } => {
+                self.reject_conditional(condition)?;
+                self.check_gate_name(identifier)?;
+                let matrix = matrix_gate(identifier, matrix)?;
+                self.definitions
+                    .insert(identifier.clone(), Rc::new(UserGate::Matrix(matrix)));
+            }
+
+            StatementNode::MeasureAssignment {
+                source,
+                destination,
+            } => {
+                let qubits = self.resolve_qubits(source)?;
+                if destination.index.is_none() && !self.is_declared(&destination.identifier) {
+                    self.reject_conditional(condition)?;
+                    self.add_classical(&destination.identifier, qubits.len())?;
+                }
+                let bits = self.resolve_bits(destination)?;
+                if bits.len() != qubits.len() {
+                    return Err(error(
+                        Kind::WrongArguments,
+                        format!(
+                            "Cannot measure {} qubit(s) into {} bit(s)",
+                            qubits.len(),
+                            bits.len()
+                        ),
+                    ));
+                }
+
+                let mut instruction = Instruction::new(Operation::Measure { echo: false }, qubits);
+                instruction.clbits = bits;
+                instructions.push(instruction);
+            }
+
+            StatementNode::IfStatement {
+                identifier,
+                value,
+                statements,
+            } => {
+                if condition.is_some() {
+                    return Err(error(
+                        Kind::Unsupported,
+                        "Nested if statements are not supported",
+                    ));
+                }
+                if !self.classical.contains_key(identifier) {
+                    return Err(unknown_register(identifier));
+                }
+                let condition = Condition {
+                    register: identifier.clone(),
+                    value: *value,
+                };
+                for statement in statements {
+                    self.statement(statement, Some(&condition), instructions)?;
+                }
+            }
+
+            StatementNode::RepeatStatement { count, statements } => {
+                if *count > MAX_REPEAT_COUNT {
+                    return Err(error(
+                        Kind::WrongArguments,
+                        format!(
+                            "repeat runs at most {} times, got {}",
+                            MAX_REPEAT_COUNT, count
+                        ),
+                    ));
+                }
+                if statements.is_empty() {
+                    return Ok(());
+                }
+                for iteration in 0..*count {
+                    let before = instructions.len();
+                    for statement in statements {
+                        self.statement(statement, condition, instructions)?;
+                    }
+                    // Every iteration lowers to as many instructions as this
+                    // one, so the rest of the loop is counted before it is
+                    // expanded.
+                    let body = instructions.len() - before;
+                    let remaining = body.saturating_mul(count - 1 - iteration);
+                    self.check_budget(
+                        self.instruction_count(instructions)
+                            .saturating_add(remaining),
+                        self.circuit.qubits.len(),
+                    )?;
+                    // A body of declarations only lowers to nothing; the
+                    // second iteration is enough to report them as repeated.
+                    if body == 0 && iteration == 1 {
+                        break;
+                    }
+                }
+            }
+
+            StatementNode::ResetStatement { target } => {
+                let qubits = self.resolve_qubits(target)?;
+                instructions.push(Instruction::new(Operation::Reset, qubits));
+            }
+
+            StatementNode::BarrierStatement { targets } => {
+                let mut qubits = vec![];
+                for target in targets {
+                    qubits.extend(self.resolve_qubits(target)?);
+                }
+                if targets.is_empty() {
+                    qubits = (0..self.circuit.qubits.len()).collect();
+                }
+                instructions.push(Instruction::new(Operation::Barrier, qubits));
+            }
+        }
+
+        if let Some(condition) = condition {
+            for instruction in &mut instructions[start..] {
+                instruction
+                    .condition
+                    .get_or_insert_with(|| condition.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn reject_conditional(&self, condition: Option<&Condition>) -> Result<(), ProgramError> {
+        match condition {
+            Some(_) => Err(error(
+                Kind::Unsupported,
+                "Declarations are not allowed inside if statements",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn display(&self, identifier: &str) -> Result<Instruction, ProgramError> {
+        let display = |text: Option<String>| Operation::Display {
+            name: identifier.to_string(),
+            text,
+        };
+
+        if let Some(&qubit) = self.variables.get(identifier) {
+            return Ok(Instruction::new(display(None), vec![qubit]));
+        }
+        if let Some(bits) = self.classical.get(identifier) {
+            let mut instruction = Instruction::new(display(None), vec![]);
+            instruction.clbits = bits.clone().collect();
+            return Ok(instruction);
+        }
+        if self.values.contains(identifier) {
+            return Ok(Instruction::new(display(None), vec![]));
+        }
+
+        let text = if let Some(gate) = self.definitions.get(identifier) {
+            match gate.as_ref() {
+                UserGate::Matrix(matrix) => format_matrix(matrix),
+                UserGate::Composite { qubits, .. } => {
+                    format!("composite gate on {} qubit(s)", qubits.len())
+                }
+            }
+        } else if parameter_count(identifier) == Some(0) {
+            format!("{:?}", standard_gate(identifier, &[]).unwrap())
+        } else if let Some(observable) = self.observables.get(identifier) {
+            observable.to_string()
+        } else {
+            return Err(unknown_symbol(identifier));
+        };
+        Ok(Instruction::new(display(Some(text)), vec![]))
+    }
+
+    /// Qubits of a `print <analysis> targets;` statement; no targets means
+    /// every qubit declared so far.
+    fn analysis_targets(
+        &self,
+        kind: AnalysisKind,
+        targets: &[Target],
+    ) -> Result<Vec<usize>, ProgramError> {
+        let mut qubits = vec![];
+        if targets.is_empty() && kind != AnalysisKind::Bloch && kind != AnalysisKind::Concurrence {
+            qubits = (0..self.circuit.qubits.len()).collect();
+        }
+        for target in targets {
+            for index in self.resolve_qubits(target)? {
+                if qubits.contains(&index) {
+                    return Err(error(
+                        Kind::Redeclared,
+                        format!(
+                            "Qubit '{}' is listed twice",
+                            self.circuit.qubits[index].label
+                        ),
+                    ));
+                }
+                qubits.push(index);
+            }
+        }
+
+        let expected = match kind {
+            AnalysisKind::Bloch => Some(1),
+            AnalysisKind::Concurrence => Some(2),
+            _ => None,
+        };
+        if let Some(expected) = expected {
+            if qubits.len() != expected {
+                return Err(error(
+                    Kind::WrongArguments,
+                    format!(
+                        "print {:?} expects {} qubit(s), got {}",
+                        kind,
+                        expected,
+                        qubits.len()
+                    )
+                    .to_lowercase(),
+                ));
+            }
+        }
+        let dense = kind == AnalysisKind::Density || kind == AnalysisKind::Entropy;
+        if dense && qubits.len() > MAX_DENSITY_QUBITS {
+            return Err(error(
+                Kind::WrongArguments,
+                format!(
+                    "print {:?} acts on at most {} qubits, got {}",
+                    kind,
+                    MAX_DENSITY_QUBITS,
+                    qubits.len()
+                )
+                .to_lowercase(),
+            ));
+        }
+        Ok(qubits)
+    }
+
+    fn check_gate_name(&self, identifier: &str) -> Result<(), ProgramError> {
+        if self.definitions.contains_key(identifier) {
+            Err(error(
+                Kind::Redeclared,
+                format!("Gate {} was already defined", identifier),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Applies a gate to its operands. An operand is either one qubit or a
+    /// register; registers must have equal sizes and the gate is applied
+    /// once per index, with single qubits reused in every application.
+    fn gate_call(
+        &mut self,
+        gate: &str,
+        parameters: &[f64],
+        operands: Vec<Vec<usize>>,
+        instructions: &mut Vec<Instruction>,
+    ) -> Result<(), ProgramError> {
+        let width = operands.iter().map(Vec::len).max().unwrap_or(0);
+        if operands
+            .iter()
+            .any(|qubits| qubits.len() != 1 && qubits.len() != width)
+        {
+            return Err(error(
+                Kind::WrongArguments,
+                format!("Registers passed to {} have different sizes", gate),
+            ));
+        }
+
+        for i in 0..width {
+            let qubits: Vec<usize> = operands
+                .iter()
+                .map(|qubits| {
+                    if qubits.len() == 1 {
+                        qubits[0]
+                    } else {
+                        qubits[i]
+                    }
+                })
+                .collect();
+            if (1..qubits.len()).any(|j| qubits[..j].contains(&qubits[j])) {
+                return Err(error(
+                    Kind::WrongArguments,
+                    format!("Gate {} is applied to the same qubit twice", gate),
+                ));
+            }
+            self.expand_gate(gate, parameters, &qubits, 0, instructions)?;
+        }
+        Ok(())
+    }
+
+    fn expand_gate(
+        &mut self,
+        gate: &str,
+        parameters: &[f64],
+        qubits: &[usize],
+        depth: usize,
+        instructions: &mut Vec<Instruction>,
+    ) -> Result<(), ProgramError> {
+        // Checked before every expansion, not only every instruction, so
+        // that composite gates expanding into nothing but each other stop
+        // once cancelled.
+        self.check_budget(
+            self.instruction_count(instructions),
+            self.circuit.qubits.len(),
+        )?;
+        let definition = self.definitions.get(gate).cloned();
+        let instruction = match definition.as_deref() {
+            Some(UserGate::Matrix(matrix)) => {
+                check_parameter_count(gate, 0, parameters.len())?;
+                check_qubit_count(gate, matrix.nrows().trailing_zeros() as usize, qubits.len())?;
+                Instruction::new(
+                    Operation::Gate {
+                        name: gate.to_string(),
+                        parameters: vec![],
+                        matrix: Some(GateMatrix::from_matrix(matrix)),
+                    },
+                    qubits.to_vec(),
+                )
+            }
+            Some(UserGate::Composite {
+                parameters: names,
+                qubits: arguments,
+                statements,
+            }) => {
+                if depth >= MAX_GATE_DEPTH {
+                    return Err(error(
+                        Kind::BadGateDefinition,
+                        format!(
+                            "Gate {} is nested more than {} levels deep",
+                            gate, MAX_GATE_DEPTH
+                        ),
+                    ));
+                }
+                check_parameter_count(gate, names.len(), parameters.len())?;
+                check_qubit_count(gate, arguments.len(), qubits.len())?;
+
+                let scope: HashMap<String, f64> = names
+                    .iter()
+                    .cloned()
+                    .zip(parameters.iter().copied())
+                    .collect();
+                for statement in statements {
+                    if let StatementNode::GateApplication {
+                        gate: inner,
+                        parameters,
+                        targets,
+                    } = statement
+                    {
+                        let values = parameters
+                            .iter()
+                            .map(|parameter| evaluate_real_expression(parameter, &scope))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        let operands: Vec<usize> = targets
+                            .iter()
+                            .map(|target| {
+                                let position = arguments
+                                    .iter()
+                                    .position(|argument| *argument == target.identifier)
+                                    .unwrap();
+                                qubits[position]
+                            })
+                            .collect();
+                        self.expand_gate(inner, &values, &operands, depth + 1, instructions)?;
+                    }
+                }
+                return Ok(());
+            }
+            None => match parameter_count(gate) {
+                Some(expected) => {
+                    check_parameter_count(gate, expected, parameters.len())?;
+                    check_qubit_count(
+                        gate,
+                        standard_gate(gate, parameters).unwrap().num_qubits(),
+                        qubits.len(),
+                    )?;
+                    Instruction::gate(gate, parameters.to_vec(), qubits.to_vec())
+                }
+                None => {
+                    return Err(error(
+                        Kind::UnknownGate,
+                        format!("Cannot resolve gate '{}'", gate),
+                    ))
+                }
+            },
+        };
+
+        self.check_budget(
+            self.instruction_count(instructions) + 1,
+            self.circuit.qubits.len(),
+        )?;
+        instructions.push(instruction);
+        Ok(())
+    }
+}
+
+fn unknown_symbol(identifier: &str) -> ProgramError {
+    error(
+        Kind::UnknownIdentifier,
+        format!("Cannot resolve symbol '{}'", identifier),
+    )
+}
+
+fn unknown_register(identifier: &str) -> ProgramError {
+    error(
+        Kind::UnknownIdentifier,
+        format!("Cannot resolve classical register '{}'", identifier),
+    )
+}
+
+/// Composite gates may only apply gates to their own qubit arguments.
+fn check_gate_body(
+    identifier: &str,
+    parameters: &[String],
+    qubits: &[String],
+    statements: &[StatementNode],
+) -> Result<(), ProgramError> {
+    if qubits.is_empty() {
+        return Err(error(
+            Kind::BadGateDefinition,
+            format!("Gate {} must act on at least one qubit", identifier),
+        ));
+    }
+    for (i, name) in parameters.iter().chain(qubits).enumerate() {
+        if parameters
+            .iter()
+            .chain(qubits)
+            .skip(i + 1)
+            .any(|other| other == name)
+        {
+            return Err(error(
+                Kind::Redeclared,
+                format!(
+                    "Argument '{}' of gate {} is declared twice",
+                    name, identifier
+                ),
+            ));
+        }
+    }
+
+    for statement in statements {
+        let targets = match statement {
+            StatementNode::GateApplication { targets, .. } => targets,
+            StatementNode::BarrierStatement { targets } => targets,
+            _ => {
+                return Err(error(
+                    Kind::BadGateDefinition,
+                    format!(
+                        "Gate {} may only contain gate applications and barriers",
+                        identifier
+                    ),
+                ))
+            }
+        };
+        for target in targets {
+            if target.index.is_some() || !qubits.contains(&target.identifier) {
+                return Err(error(
+                    Kind::BadGateDefinition,
+                    format!(
+                        "Gate {} refers to '{}', which is not one of its qubit arguments",
+                        identifier, target
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn matrix_gate(
+    identifier: &str,
+    rows: &[Vec<Expression>],
+) -> Result<DMatrix<Complex<f64>>, ProgramError> {
+    let size = rows.len();
+    if size < 2 || !size.is_power_of_two() || rows.iter().any(|row| row.len() != size) {
+        return Err(error(
+            Kind::BadGateDefinition,
+            format!(
+                "Matrix of gate {} must be square with a power-of-two size",
+                identifier
+            ),
+        ));
+    }
+
+    let matrix = DMatrix::from_fn(size, size, |row, col| {
+        let (re, im) = evaluate_complex_expression(&rows[row][col]);
+        Complex::new(re, im)
+    });
+    if !is_unitary(&matrix, UNITARY_TOLERANCE) {
+        return Err(error(
+            Kind::BadGateDefinition,
+            format!("Matrix of gate {} is not unitary", identifier),
+        ));
+    }
+    Ok(matrix)
+}
+
+fn check_parameter_count(gate: &str, expected: usize, actual: usize) -> Result<(), ProgramError> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(error(
+            Kind::WrongArguments,
+            format!(
+                "Gate {} expects {} parameter(s), got {}",
+                gate, expected, actual
+            ),
+        ))
+    }
+}
+
+fn check_qubit_count(gate: &str, expected: usize, actual: usize) -> Result<(), ProgramError> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(error(
+            Kind::WrongArguments,
+            format!(
+                "Gate {} acts on {} qubit(s), got {}",
+                gate, expected, actual
+            ),
+        ))
+    }
+}
+
+pub fn evaluate_complex_expression(expr: &Expression) -> (f64, f64) {
+    match expr {
+        Expression::RealNumber { value } => (*value, 0.0),
+        Expression::ImaginaryNumber { value } => (0.0, *value),
+        Expression::InfixExpression { op, left, right } => {
+            let (left_real, left_imag) = evaluate_complex_expression(left);
+            let (right_real, right_imag) = evaluate_complex_expression(right);
+
+            match op.as_str() {
+                "+" => (left_real + right_real, left_imag + right_imag),
+                "-" => (left_real - right_real, left_imag - right_imag),
+                "*" => (
+                    left_real * right_real - left_imag * right_imag,
+                    left_real * right_imag + left_imag * right_real,
+                ),
+                "/" => {
+                    let denominator = right_real * right_real + right_imag * right_imag;
+                    (
+                        (left_real * right_real + left_imag * right_imag) / denominator,
+                        (left_imag * right_real - left_real * right_imag) / denominator,
+                    )
+                }
+                _ => (0.0, 0.0),
+            }
+        }
+        Expression::PrefixExpression { op, right } => {
+            let (real, imag) = evaluate_complex_expression(right);
+            match op.as_str() {
+                "-" => (-real, -imag),
+                _ => (real, imag),
+            }
+        }
+        Expression::Identifier { .. } | Expression::FunctionCall { .. } => (
+            evaluate_real_expression(expr, &HashMap::new()).unwrap_or(0.0),
+            0.0,
+        ),
+    }
+}
+
+/// Evaluates a gate parameter; `scope` holds the parameters of the
+/// enclosing gate definition.
+pub fn evaluate_real_expression(
+    expr: &Expression,
+    scope: &HashMap<String, f64>,
+) -> Result<f64, ProgramError> {
+    match expr {
+        Expression::RealNumber { value } => Ok(*value),
+        Expression::ImaginaryNumber { .. } => Err(error(
+            Kind::BadExpression,
+            "Gate parameters must be real numbers",
+        )),
+        Expression::Identifier { value } => match scope.get(value) {
+            Some(value) => Ok(*value),
+            None if value == "pi" => Ok(std::f64::consts::PI),
+            None => Err(error(
+                Kind::UnknownIdentifier,
+                format!("Cannot resolve parameter '{}'", value),
+            )),
+        },
+        Expression::InfixExpression { op, left, right } => {
+            let left = evaluate_real_expression(left, scope)?;
+            let right = evaluate_real_expression(right, scope)?;
+            match op.as_str() {
+                "+" => Ok(left + right),
+                "-" => Ok(left - right),
+                "*" => Ok(left * right),
+                "/" => Ok(left / right),
+                "^" => Ok(left.powf(right)),
+                _ => Err(error(
+                    Kind::BadExpression,
+                    format!("Unknown operator '{}'", op),
+                )),
+            }
+        }
+        Expression::PrefixExpression { op, right } => {
+            let value = evaluate_real_expression(right, scope)?;
+            match op.as_str() {
+                "-" => Ok(-value),
+                _ => Ok(value),
+            }
+        }
+        Expression::FunctionCall { function, argument } => {
+            let value = evaluate_real_expression(argument, scope)?;
+            match function.as_str() {
+                "sin" => Ok(value.sin()),
+                "cos" => Ok(value.cos()),
+                "tan" => Ok(value.tan()),
+                "exp" => Ok(value.exp()),
+                "ln" => Ok(value.ln()),
+                "sqrt" => Ok(value.sqrt()),
+                _ => Err(error(
+                    Kind::BadExpression,
+                    format!("Unknown function '{}'", function),
+                )),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interpreter::{simulate_circuit, SimulationOptions},
+        models::NodeType,
+        qasm::parse_qasm,
+    };
+
+    #[test]
+    fn test_lowering_expands_gates_and_conditions() {
+        let program = parse_qasm(
+            r#"OPENQASM 2.0;
+include "qelib1.inc";
+gate bell a, b { h a; cx a, b; }
+qreg q[2];
+creg c[2];
+bell q[0], q[1];
+measure q -> c;
+if (c == 3) rz(pi / 2) q;
+"#,
+        )
+        .unwrap();
+
+        let circuit = lower_program(&program);
+
+        assert_eq!(circuit.labels(), vec!["q[0]", "q[1]"]);
+        assert_eq!(circuit.classical_range("c"), Some(0..2));
+        let ops: Vec<(&str, &[usize])> = circuit
+            .instructions
+            .iter()
+            .map(|instruction| match &instruction.operation {
+                Operation::Gate { name, .. } => (name.as_str(), instruction.qubits.as_slice()),
+                Operation::Measure { .. } => ("measure", instruction.qubits.as_slice()),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                ("hadamard", &[0][..]),
+                ("cnot", &[0, 1][..]),
+                ("measure", &[0, 1][..]),
+                ("rz", &[0][..]),
+                ("rz", &[1][..]),
+            ]
+        );
+        assert_eq!(circuit.instructions[2].clbits, vec![0, 1]);
+        assert_eq!(
+            circuit.instructions[4].condition,
+            Some(Condition {
+                register: "c".to_string(),
+                value: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors_are_lowered_in_place() {
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![
+                StatementNode::RegisterDeclaration {
+                    identifier: "q".to_string(),
+                    size: 1,
+                },
+                StatementNode::GateApplication {
+                    gate: "rx".to_string(),
+                    parameters: vec![],
+                    targets: vec!["q".into()],
+                },
+                StatementNode::DisplayStatement {
+                    identifier: "hadamard".to_string(),
+                },
+            ],
+        };
+
+        let circuit = lower_program(&program);
+
+        assert_eq!(
+            circuit.instructions[0].operation,
+            Operation::Error {
+                kind: Kind::WrongArguments,
+                message: "Gate rx expects 1 parameter(s), got 0".to_string()
+            }
+        );
+        assert!(matches!(
+            &circuit.instructions[1].operation,
+            Operation::Display { text: Some(text), .. } if text.starts_with("[[0.71+0.00i")
+        ));
+    }
+
+    #[test]
+    fn test_circuit_json_round_trip() {
+        let program: ProgramNode = serde_json::from_value(serde_json::json!({
+            "type": "Program",
+            "statements": [
+                {"type": "CreateStatement", "identifier": "a", "complexArray": {
+                    "type": "ComplexArray",
+                    "values": [{"type": "RealNumber", "value": 0.0}, {"type": "RealNumber", "value": 1.0}]
+                }},
+                {"type": "MatrixGateDefinition", "identifier": "flip", "matrix": [
+                    [{"type": "RealNumber", "value": 0.0}, {"type": "RealNumber", "value": 1.0}],
+                    [{"type": "RealNumber", "value": 1.0}, {"type": "RealNumber", "value": 0.0}]
+                ]},
+                {"type": "ApplyStatement", "identifier1": "a", "identifier2": "flip"},
+                {"type": "ObservableDeclaration", "identifier": "H", "observable": "Z0"},
+                {"type": "ExpectStatement", "observable": "H", "identifier": "e"},
+                {"type": "MeasureAssignment", "source": "a", "destination": "m"}
+            ]
+        }))
+        .unwrap();
+        let circuit = lower_program(&program);
+
+        let json = serde_json::to_value(&circuit).unwrap();
+        let restored: Circuit = serde_json::from_value(json.clone()).unwrap();
+
+        assert_eq!(restored, circuit);
+        assert_eq!(json["instructions"][0]["op"], "gate");
+        assert_eq!(json["instructions"][1]["observable"], "1*Z0");
+        let result = simulate_circuit(&restored, SimulationOptions::default());
+        assert_eq!(result.output, vec!["e = <H> = 1.0000"]);
+        assert_eq!(result.memory["m"], "0");
+    }
+
+    #[test]
+    fn test_limited_lowering_stops_unrolling() {
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![
+                StatementNode::RegisterDeclaration {
+                    identifier: "q".to_string(),
+                    size: 1,
+                },
+                StatementNode::RepeatStatement {
+                    count: usize::MAX,
+                    statements: vec![StatementNode::GateApplication {
+                        gate: "hadamard".to_string(),
+                        parameters: vec![],
+                        targets: vec!["q".into()],
+                    }],
+                },
+            ],
+        };
+
+        let error = lower_program(&program).instructions.pop().unwrap();
+        assert!(matches!(error.operation, Operation::Error { .. }));
+
+        let mut short = program.clone();
+        let StatementNode::RepeatStatement { count, .. } = &mut short.statements[1] else {
+            unreachable!();
+        };
+        *count = MAX_REPEAT_COUNT;
+        assert_eq!(
+            lower_program_limited(&short, 10, 1, 10, &AtomicBool::new(false)),
+            Err(LoweringStopped::Instructions(MAX_REPEAT_COUNT))
+        );
+
+        let StatementNode::RepeatStatement { count, .. } = &mut short.statements[1] else {
+            unreachable!();
+        };
+        *count = 10;
+        assert_eq!(
+            lower_program_limited(&short, 10, 1, 10, &AtomicBool::new(false))
+                .unwrap()
+                .instructions
+                .len(),
+            10
+        );
+    }
+
+    #[test]
+    fn test_loops_without_instructions_stop_early() {
+        let repeat = |statements| StatementNode::RepeatStatement {
+            count: MAX_REPEAT_COUNT,
+            statements,
+        };
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![
+                repeat(vec![]),
+                repeat(vec![StatementNode::ClassicalDeclaration {
+                    identifier: "c".to_string(),
+                    size: 1,
+                }]),
+            ],
+        };
+
+        let circuit = lower_program(&program);
+        assert_eq!(
+            circuit.instructions[0].operation,
+            Operation::Error {
+                kind: Kind::Redeclared,
+                message: "Identifier c was already declared".to_string()
+            }
+        );
+        assert_eq!(circuit.instructions.len(), 1);
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let create = |identifier: &str| {
+            serde_json::json!({"type": "CreateStatement", "identifier": identifier, "complexArray": {
+                "type": "ComplexArray",
+                "values": [{"type": "RealNumber", "value": 1.0}, {"type": "RealNumber", "value": 0.0}]
+            }})
+        };
+        let apply = |identifier: &str, gate: &str| serde_json::json!({"type": "ApplyStatement", "identifier1": identifier, "identifier2": gate});
+        let cases = [
+            (vec![create("a"), apply("a", "nope")], Kind::UnknownGate),
+            (
+                vec![create("a"), apply("b", "hadamard")],
+                Kind::UnknownIdentifier,
+            ),
+            (vec![create("a"), create("a")], Kind::Redeclared),
+        ];
+        for (statements, kind) in cases {
+            let program: ProgramNode = serde_json::from_value(serde_json::json!({
+                "type": "Program",
+                "statements": statements
+            }))
+            .unwrap();
+            let errors: Vec<_> = lower_program(&program)
+                .instructions
+                .into_iter()
+                .filter_map(|instruction| match instruction.operation {
+                    Operation::Error { kind, message } => Some((kind, message)),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(errors.len(), 1, "{:?}", errors);
+            assert_eq!(errors[0].0, kind, "{}", errors[0].1);
+        }
+
+        let program = parse_qasm(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[1];\ncreg c[2];\nmeasure q -> c;",
+        )
+        .unwrap();
+        let circuit = lower_program(&program);
+        assert!(matches!(
+            circuit.instructions[0].operation,
+            Operation::Error {
+                kind: Kind::WrongArguments,
+                ..
+            }
+        ));
+        assert_eq!(Kind::BadGateDefinition.to_string(), "bad_gate_definition");
+    }
+
+    #[test]
+    fn test_density_targets_are_bounded() {
+        let print = |analysis: AnalysisKind, targets: Vec<Target>| StatementNode::PrintStatement {
+            analysis,
+            targets,
+        };
+        let lower = |statements: Vec<StatementNode>| {
+            let mut all = vec![StatementNode::RegisterDeclaration {
+                identifier: "q".to_string(),
+                size: 16,
+            }];
+            all.extend(statements);
+            lower_program(&ProgramNode {
+                r#type: NodeType::Program,
+                statements: all,
+            })
+        };
+
+        let circuit = lower(vec![
+            print(AnalysisKind::Density, vec!["q".into()]),
+            print(AnalysisKind::Entropy, vec![]),
+        ]);
+        assert_eq!(circuit.instructions.len(), 2);
+        for instruction in &circuit.instructions {
+            match &instruction.operation {
+                Operation::Error { kind, message } => {
+                    assert_eq!(*kind, Kind::WrongArguments);
+                    assert!(message.contains("at most 10 qubits, got 16"), "{}", message)
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        let circuit = lower(vec![
+            print(AnalysisKind::Density, vec![Target::new("q", Some(0))]),
+            print(AnalysisKind::Probs, vec!["q".into()]),
+        ]);
+        assert!(circuit
+            .instructions
+            .iter()
+            .all(|instruction| matches!(instruction.operation, Operation::Print { .. })));
+    }
+}
@@ -1,25 +1,120 @@
-use axum::http::{header::ACCEPT, header::CONTENT_TYPE, Method};
-use route::create_router;
-use tower_http::cors::{Any, CorsLayer};
-
-mod gate;
-mod handler;
-mod interpreter;
-mod models;
-mod qubit;
-mod route;
-mod quantum_register;
+use axum::http::{header::ACCEPT, header::AUTHORIZATION, header::CONTENT_TYPE, Method};
+use clap::Parser;
+use quantum_simulator::auth::{Auth, DEFAULT_TOKEN_LIFETIME};
+use quantum_simulator::cli::{self, Cli, Command};
+use quantum_simulator::config::Config;
+use quantum_simulator::diagram::{DiagramOptions, LatexOptions};
+use quantum_simulator::equivalence::EquivalenceOptions;
+use quantum_simulator::handler::AppState;
+use quantum_simulator::logging;
+use quantum_simulator::route::create_router;
+use quantum_simulator::session::SessionStore;
+use quantum_simulator::store::Store;
+use quantum_simulator::telemetry::Telemetry;
+use std::path::Path;
+use std::sync::Arc;
+use tower_http::cors::{AllowOrigin, CorsLayer};
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Some(Command::Run {
+            file,
+            qasm,
+            simulation,
+            json,
+        }) => cli::run_file(&file, qasm, simulation.options(), json),
+        Some(Command::Unitary { file, qasm, json }) => cli::print_unitary(&file, qasm, json),
+        Some(Command::Circuit { file, qasm }) => cli::print_circuit(&file, qasm),
+        Some(Command::Metrics { file, qasm, json }) => cli::print_metrics(&file, qasm, json),
+        Some(Command::Equivalent {
+            reference,
+            candidate,
+            qasm,
+            ignore_ancillas,
+            json,
+        }) => {
+            let options = EquivalenceOptions { ignore_ancillas };
+            cli::print_equivalence(&reference, &candidate, qasm, options, json)
+        }
+        Some(Command::Optimize { file, qasm, json }) => cli::print_optimized(&file, qasm, json),
+        Some(Command::Decompose {
+            file,
+            qasm,
+            basis,
+            json,
+        }) => cli::print_decomposed(&file, qasm, basis, json),
+        Some(Command::Route {
+            file,
+            qasm,
+            coupling,
+            json,
+        }) => cli::print_routed(&file, qasm, &coupling, json),
+        Some(Command::Draw {
+            file,
+            qasm,
+            format,
+            width,
+            ascii,
+            fragment,
+        }) => {
+            let options = DiagramOptions { width, ascii };
+            let latex = LatexOptions { fragment };
+            cli::print_diagram(&file, qasm, format, options, latex)
+        }
+        Some(Command::Export {
+            file,
+            qasm,
+            version,
+        }) => cli::print_qasm(&file, qasm, version),
+        Some(Command::Serve) | None => serve(cli.config.as_deref()).await,
+    };
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+async fn serve(config: Option<&Path>) -> Result<(), String> {
+    let config =
+        Config::load(config).map_err(|error| format!("Invalid configuration: {}", error))?;
+    logging::init(&config.log_level);
+    let origins = match config.allowed_origins() {
+        Some(origins) => AllowOrigin::list(origins),
+        None => AllowOrigin::any(),
+    };
     let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::POST, Method::GET])
-        .allow_headers([CONTENT_TYPE, ACCEPT]);
+        .allow_origin(origins)
+        .allow_methods([Method::POST, Method::GET, Method::PUT, Method::DELETE])
+        .allow_headers([CONTENT_TYPE, ACCEPT, AUTHORIZATION]);
 
-    let app = create_router().layer(cors);
+    let store = Store::connect(&config.database_url)
+        .await
+        .map_err(|error| format!("Could not open the database: {}", error))?;
+    let auth = match &config.auth_secret {
+        Some(secret) => Auth::new(secret.as_bytes(), DEFAULT_TOKEN_LIFETIME),
+        None => {
+            tracing::warn!("auth_secret is not set, sessions end when the server stops");
+            Auth::random(DEFAULT_TOKEN_LIFETIME)
+        }
+    };
+    let sessions = Arc::new(SessionStore::new(config.max_sessions, config.session_ttl));
+    let app = create_router(AppState {
+        store,
+        auth,
+        sessions,
+        limits: config.limits.clone(),
+        telemetry: Arc::new(Telemetry::default()),
+    })
+    .layer(cors);
 
-    println!("Server started on localhost:8000");
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let address = config.address();
+    let listener = tokio::net::TcpListener::bind(&address)
+        .await
+        .map_err(|error| format!("Could not listen on {}: {}", address, error))?;
+    tracing::info!(%address, "Server started");
+    axum::serve(listener, app)
+        .await
+        .map_err(|error| error.to_string())
 }
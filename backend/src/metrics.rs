@@ -0,0 +1,220 @@
+//! Size and cost figures of a lowered circuit.
+//!
+//! Depth counts layers of gates, measurements and resets, where two
+//! instructions are in different layers when they share a qubit or a
+//! classical bit (a condition reads every bit of its register). Barriers and
+//! instructions that only inspect the state are not counted.
+
+use std::{collections::BTreeMap, f64::consts::FRAC_PI_4};
+
+use serde::Serialize;
+
+use crate::circuit::{Circuit, Operation};
+
+const TOLERANCE: f64 = 1e-9;
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitMetrics {
+    pub num_qubits: usize,
+    pub num_clbits: usize,
+    pub depth: usize,
+    /// Layer of the last instruction on each qubit, in register order.
+    pub qubit_depths: Vec<usize>,
+    /// Layer of the last instruction on each classical bit.
+    pub clbit_depths: Vec<usize>,
+    pub gates: usize,
+    pub gate_counts: BTreeMap<String, usize>,
+    pub two_qubit_gates: usize,
+    /// Whether every gate is a Clifford or a T gate, so that the T figures
+    /// describe the whole cost of the circuit.
+    pub clifford_t: bool,
+    /// T gates and their inverses, including phase and z rotations by odd
+    /// multiples of π/4.
+    pub t_count: usize,
+    /// Layers containing a T gate along the longest path.
+    pub t_depth: usize,
+    /// Indices into the circuit's instructions of a longest chain of
+    /// dependent instructions, in order.
+    pub critical_path: Vec<usize>,
+}
+
+pub fn circuit_metrics(circuit: &Circuit) -> CircuitMetrics {
+    let num_qubits = circuit.qubits.len();
+    let num_clbits = circuit.num_clbits();
+    let mut metrics = CircuitMetrics {
+        num_qubits,
+        num_clbits,
+        clifford_t: true,
+        ..Default::default()
+    };
+
+    // Per wire: the layer reached, the instruction that reached it and the
+    // T-depth so far. Qubits come first, then classical bits.
+    let mut levels = vec![0; num_qubits + num_clbits];
+    let mut last: Vec<Option<usize>> = vec![None; levels.len()];
+    let mut t_levels = vec![0; levels.len()];
+    let mut parents: Vec<Option<usize>> = vec![None; circuit.instructions.len()];
+    let mut deepest: Option<(usize, usize)> = None;
+
+    for (index, instruction) in circuit.instructions.iter().enumerate() {
+        let mut is_t = false;
+        match &instruction.operation {
+            Operation::Gate {
+                name, parameters, ..
+            } => {
+                metrics.gates += 1;
+                *metrics.gate_counts.entry(name.clone()).or_default() += 1;
+                if instruction.qubits.len() == 2 {
+                    metrics.two_qubit_gates += 1;
+                }
+                match clifford_t_kind(name, parameters) {
+                    Some(GateKind::T) => {
+                        metrics.t_count += 1;
+                        is_t = true;
+                    }
+                    Some(GateKind::Clifford) => {}
+                    None => metrics.clifford_t = false,
+                }
+            }
+            Operation::Measure { .. } | Operation::Reset => {}
+            _ => continue,
+        }
+
+        let mut wires = instruction.qubits.clone();
+        wires.extend(instruction.clbits.iter().map(|bit| num_qubits + bit));
+        if let Some(range) = instruction
+            .condition
+            .as_ref()
+            .and_then(|condition| circuit.classical_range(&condition.register))
+        {
+            wires.extend(range.map(|bit| num_qubits + bit));
+        }
+
+        let start = wires.iter().map(|&w| levels[w]).max().unwrap_or(0);
+        parents[index] = wires
+            .iter()
+            .filter(|&&w| levels[w] == start)
+            .find_map(|&w| last[w]);
+        let t_level = wires.iter().map(|&w| t_levels[w]).max().unwrap_or(0) + usize::from(is_t);
+        for &wire in &wires {
+            levels[wire] = start + 1;
+            last[wire] = Some(index);
+            t_levels[wire] = t_level;
+        }
+        if deepest.is_none_or(|(depth, _)| start + 1 > depth) {
+            deepest = Some((start + 1, index));
+        }
+    }
+
+    if let Some((depth, index)) = deepest {
+        metrics.depth = depth;
+        let mut path = vec![index];
+        while let Some(parent) = parents[*path.last().unwrap()] {
+            path.push(parent);
+        }
+        path.reverse();
+        metrics.critical_path = path;
+    }
+    metrics.t_depth = t_levels.iter().copied().max().unwrap_or(0);
+    metrics.clbit_depths = levels.split_off(num_qubits);
+    metrics.qubit_depths = levels;
+    metrics
+}
+
+enum GateKind {
+    Clifford,
+    T,
+}
+
+/// Whether a gate belongs to the Clifford+T gate set, and which part.
+fn clifford_t_kind(name: &str, parameters: &[f64]) -> Option<GateKind> {
+    match name {
+        "identity" | "pauliX" | "pauliY" | "pauliZ" | "hadamard" | "s" | "sDagger" | "sqrtX"
+        | "sqrtXDagger" | "cnot" | "cy" | "cz" | "swap" => Some(GateKind::Clifford),
+        "t" | "tDagger" => Some(GateKind::T),
+        "phase" | "rz" => {
+            let eighths = parameters[0] / FRAC_PI_4;
+            if (eighths - eighths.round()).abs() > TOLERANCE {
+                None
+            } else if eighths.round() as i64 % 2 == 0 {
+                Some(GateKind::Clifford)
+            } else {
+                Some(GateKind::T)
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lower::lower_program, qasm::parse_qasm};
+
+    fn metrics_of(source: &str) -> CircuitMetrics {
+        let program = parse_qasm(&format!(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\n{}",
+            source
+        ))
+        .unwrap();
+        circuit_metrics(&lower_program(&program))
+    }
+
+    #[test]
+    fn test_bell_circuit() {
+        let metrics = metrics_of(
+            "qreg q[3];\ncreg c[2];\nh q[0];\ncx q[0], q[1];\nbarrier q;\n\
+             measure q[0] -> c[0];\nmeasure q[1] -> c[1];",
+        );
+        assert_eq!((metrics.num_qubits, metrics.num_clbits), (3, 2));
+        assert_eq!(metrics.depth, 3);
+        assert_eq!(metrics.qubit_depths, [3, 3, 0]);
+        assert_eq!(metrics.clbit_depths, [3, 3]);
+        assert_eq!(metrics.gates, 2);
+        assert_eq!(metrics.two_qubit_gates, 1);
+        assert_eq!(
+            metrics.gate_counts,
+            BTreeMap::from([("cnot".to_string(), 1), ("hadamard".to_string(), 1)])
+        );
+        assert_eq!(metrics.critical_path, [0, 1, 3]);
+        assert!(metrics.clifford_t);
+        assert_eq!(metrics.t_count, 0);
+    }
+
+    #[test]
+    fn test_t_count_and_depth() {
+        let metrics = metrics_of(
+            "qreg q[3];\nt q[0];\nt q[1];\ncx q[0], q[1];\ntdg q[1];\nu1(pi/4) q[2];\n\
+             rz(pi/2) q[2];\ns q[0];",
+        );
+        assert!(metrics.clifford_t);
+        assert_eq!(metrics.t_count, 4);
+        assert_eq!(metrics.t_depth, 2);
+        assert_eq!(metrics.depth, 3);
+        assert_eq!(metrics.critical_path, [0, 2, 3]);
+
+        let metrics = metrics_of("qreg q[1];\nt q[0];\nrx(0.3) q[0];");
+        assert!(!metrics.clifford_t);
+        assert_eq!(metrics.t_count, 1);
+    }
+
+    #[test]
+    fn test_conditions_read_their_register() {
+        let metrics = metrics_of(
+            "qreg q[2];\ncreg c[1];\nh q[0];\nh q[0];\nmeasure q[0] -> c[0];\n\
+             if(c==1) x q[1];",
+        );
+        assert_eq!(metrics.depth, 4);
+        assert_eq!(metrics.qubit_depths, [3, 4]);
+        assert_eq!(metrics.critical_path, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_empty_circuit() {
+        let metrics = metrics_of("qreg q[2];");
+        assert_eq!(metrics.depth, 0);
+        assert!(metrics.critical_path.is_empty());
+        assert_eq!(metrics.qubit_depths, [0, 0]);
+    }
+}
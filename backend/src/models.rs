@@ -1,6 +1,10 @@
-use serde::Deserialize;
+use std::fmt;
 
-#[derive(Deserialize, Debug)]
+use serde::{Deserialize, Serialize};
+
+use crate::analysis::AnalysisKind;
+
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
 pub enum NodeType {
     Program,
@@ -8,22 +12,37 @@ pub enum NodeType {
     ApplyStatement,
     MeasureStatement,
     DisplayStatement,
+    ObservableDeclaration,
+    ExpectStatement,
+    PrintStatement,
+    RegisterDeclaration,
+    ClassicalDeclaration,
+    GateApplication,
+    GateDefinition,
+    MatrixGateDefinition,
+    MeasureAssignment,
+    IfStatement,
+    RepeatStatement,
+    ResetStatement,
+    BarrierStatement,
     ComplexArray,
     RealNumber,
     ImaginaryNumber,
     Number,
     InfixExpression,
     PrefixExpression,
+    Identifier,
+    FunctionCall,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ProgramNode {
     pub r#type: NodeType,
     pub statements: Vec<StatementNode>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "PascalCase")]
 pub enum StatementNode {
     CreateStatement {
@@ -41,16 +60,138 @@ pub enum StatementNode {
     DisplayStatement {
         identifier: String,
     },
+    ObservableDeclaration {
+        identifier: String,
+        observable: String,
+    },
+    ExpectStatement {
+        observable: String,
+        identifier: String,
+    },
+    PrintStatement {
+        analysis: AnalysisKind,
+        #[serde(default)]
+        targets: Vec<Target>,
+    },
+    /// `n` fresh qubits in `|0>`, addressed as `identifier[i]`.
+    RegisterDeclaration {
+        identifier: String,
+        size: usize,
+    },
+    /// `n` classical bits initialised to 0.
+    ClassicalDeclaration {
+        identifier: String,
+        size: usize,
+    },
+    /// Applies a built-in or user-defined gate. A whole register as a target
+    /// broadcasts the gate over its qubits.
+    GateApplication {
+        gate: String,
+        #[serde(default)]
+        parameters: Vec<Expression>,
+        targets: Vec<Target>,
+    },
+    /// A composite gate whose body may only contain gate applications and
+    /// barriers on its formal qubit arguments.
+    GateDefinition {
+        identifier: String,
+        #[serde(default)]
+        parameters: Vec<String>,
+        qubits: Vec<String>,
+        statements: Vec<StatementNode>,
+    },
+    MatrixGateDefinition {
+        identifier: String,
+        matrix: Vec<Vec<Expression>>,
+    },
+    /// Measures `source` into the classical bits of `destination`.
+    MeasureAssignment {
+        source: Target,
+        destination: Target,
+    },
+    /// Runs `statements` when the classical register holds `value`, read
+    /// with bit 0 as the least significant bit.
+    IfStatement {
+        identifier: String,
+        value: u64,
+        statements: Vec<StatementNode>,
+    },
+    RepeatStatement {
+        count: usize,
+        statements: Vec<StatementNode>,
+    },
+    ResetStatement {
+        target: Target,
+    },
+    BarrierStatement {
+        #[serde(default)]
+        targets: Vec<Target>,
+    },
+}
+
+/// A qubit or classical bit operand: `q` names a whole register or a single
+/// qubit, `q[2]` one element of a register. Deserialised from that string
+/// form.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Target {
+    pub identifier: String,
+    pub index: Option<usize>,
+}
+
+impl Target {
+    pub fn new(identifier: &str, index: Option<usize>) -> Self {
+        Self {
+            identifier: identifier.to_string(),
+            index,
+        }
+    }
+}
+
+impl From<&str> for Target {
+    fn from(identifier: &str) -> Self {
+        Target::new(identifier, None)
+    }
+}
+
+impl TryFrom<String> for Target {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let Some(open) = value.find('[') else {
+            return Ok(Target::new(&value, None));
+        };
+        let index = value[open + 1..]
+            .strip_suffix(']')
+            .and_then(|index| index.trim().parse().ok())
+            .ok_or_else(|| format!("Invalid target '{}'", value))?;
+        Ok(Target::new(value[..open].trim(), Some(index)))
+    }
 }
 
-#[derive(Deserialize, Debug)]
+impl From<Target> for String {
+    fn from(target: Target) -> Self {
+        target.to_string()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "{}[{}]", self.identifier, index),
+            None => write!(f, "{}", self.identifier),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ComplexArrayNode {
     pub r#type: NodeType,
     pub values: Vec<Expression>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "PascalCase")]
 pub enum Expression {
     RealNumber {
@@ -68,4 +209,32 @@ pub enum Expression {
         op: String,
         right: Box<Expression>,
     },
-}
\ No newline at end of file
+    /// `pi` or a parameter of the enclosing gate definition.
+    Identifier {
+        value: String,
+    },
+    /// One of `sin`, `cos`, `tan`, `exp`, `ln` and `sqrt`.
+    FunctionCall {
+        function: String,
+        argument: Box<Expression>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_parsing() {
+        assert_eq!(
+            Target::try_from("q".to_string()),
+            Ok(Target::new("q", None))
+        );
+        assert_eq!(
+            Target::try_from("q[3]".to_string()),
+            Ok(Target::new("q", Some(3)))
+        );
+        assert!(Target::try_from("q[x]".to_string()).is_err());
+        assert_eq!(Target::new("q", Some(3)).to_string(), "q[3]");
+    }
+}
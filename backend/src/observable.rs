@@ -0,0 +1,407 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use nalgebra::Complex;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pauli {
+    I,
+    X,
+    Y,
+    Z,
+}
+
+/// A tensor product of Pauli operators with a real weight, e.g. `0.5*Z0Z1`.
+///
+/// Qubit indices refer to positions in the register, i.e. the order in which
+/// qubits were created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PauliTerm {
+    pub coefficient: f64,
+    pub operators: Vec<(usize, Pauli)>,
+}
+
+/// Hermitian observable given as a weighted sum of Pauli strings.
+/// Serialised in its text form, e.g. `"0.5*Z0Z1 - 0.3*X2"`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(into = "String", try_from = "String")]
+pub struct Observable {
+    pub terms: Vec<PauliTerm>,
+}
+
+/// How `expect` statements are evaluated.
+///
+/// `Exact` reads the expectation value straight from the state vector,
+/// `Shots` estimates every Pauli term from a finite number of simulated
+/// measurements, the way it would be done on hardware.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpectationMode {
+    #[default]
+    Exact,
+    Shots,
+}
+
+impl FromStr for ExpectationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(ExpectationMode::Exact),
+            "shots" => Ok(ExpectationMode::Shots),
+            _ => Err(format!(
+                "Unknown expectation mode '{}': expected 'exact' or 'shots'",
+                s
+            )),
+        }
+    }
+}
+
+impl PauliTerm {
+    /// Bit masks of the qubits the term flips (X, Y) and the qubits that
+    /// contribute a sign (Y, Z).
+    fn masks(&self) -> (usize, usize, u32) {
+        let mut flip = 0;
+        let mut sign = 0;
+        let mut y_count = 0;
+        for &(qubit, pauli) in &self.operators {
+            match pauli {
+                Pauli::I => {}
+                Pauli::X => flip |= 1 << qubit,
+                Pauli::Y => {
+                    flip |= 1 << qubit;
+                    sign |= 1 << qubit;
+                    y_count += 1;
+                }
+                Pauli::Z => sign |= 1 << qubit,
+            }
+        }
+        (flip, sign, y_count)
+    }
+
+    /// Expectation value of the bare Pauli string (without the coefficient)
+    /// computed from an amplitude lookup, without building its matrix.
+    ///
+    /// `P|i> = i^{#Y} (-1)^{|i & sign|} |i ^ flip>`, so
+    /// `<psi|P|psi> = sum_i conj(psi[i ^ flip]) * phase(i) * psi[i]`.
+    pub fn expectation<I, F>(&self, populated: I, amplitude: F) -> f64
+    where
+        I: IntoIterator<Item = (usize, Complex<f64>)>,
+        F: Fn(usize) -> Complex<f64>,
+    {
+        let (flip, sign, y_count) = self.masks();
+        let y_phase = Complex::new(0.0, 1.0).powu(y_count);
+
+        let value: Complex<f64> = populated
+            .into_iter()
+            .map(|(i, psi_i)| {
+                let parity = if (i & sign).count_ones() % 2 == 0 {
+                    1.0
+                } else {
+                    -1.0
+                };
+                amplitude(i ^ flip).conj() * y_phase * parity * psi_i
+            })
+            .sum();
+        value.re
+    }
+
+    pub fn max_qubit(&self) -> Option<usize> {
+        self.operators.iter().map(|(qubit, _)| *qubit).max()
+    }
+}
+
+impl Observable {
+    pub fn max_qubit(&self) -> Option<usize> {
+        self.terms.iter().filter_map(PauliTerm::max_qubit).max()
+    }
+
+    /// Combines exact per-term expectation values into `sum_k c_k <P_k>`.
+    pub fn exact(&self, term_expectation: impl Fn(&PauliTerm) -> f64) -> f64 {
+        self.terms
+            .iter()
+            .map(|term| term.coefficient * term_expectation(term))
+            .sum()
+    }
+
+    /// Estimates the expectation value by measuring every term `shots` times.
+    ///
+    /// A measurement of a Pauli string returns `+1` with probability
+    /// `(1 + <P>) / 2`, so sampling from that distribution is equivalent to
+    /// rotating into the term's eigenbasis and measuring the state. Without
+    /// any shots there is nothing to estimate from, which is an error.
+    pub fn sampled(
+        &self,
+        shots: usize,
+        term_expectation: impl Fn(&PauliTerm) -> f64,
+    ) -> Result<f64, String> {
+        if shots == 0 {
+            return Err("Sampling an expectation value needs at least 1 shot".to_string());
+        }
+        let mut rng = rand::thread_rng();
+        let value = self
+            .terms
+            .iter()
+            .map(|term| {
+                let probability_plus = ((1.0 + term_expectation(term)) / 2.0).clamp(0.0, 1.0);
+                let plus = (0..shots)
+                    .filter(|_| rng.gen_range(0.0_f64..1.0) < probability_plus)
+                    .count();
+                let mean = (2.0 * plus as f64 - shots as f64) / shots as f64;
+                term.coefficient * mean
+            })
+            .sum();
+        Ok(value)
+    }
+}
+
+impl FromStr for Observable {
+    type Err = String;
+
+    /// Parses sums such as `0.5*Z0Z1 - 0.3*X2 + Y0` or `-Z1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+        if chars.is_empty() {
+            return Err("Observable is empty".to_string());
+        }
+
+        let mut terms = vec![];
+        let mut pos = 0;
+        while pos < chars.len() {
+            let mut sign = 1.0;
+            if chars[pos] == '+' || chars[pos] == '-' {
+                if chars[pos] == '-' {
+                    sign = -1.0;
+                }
+                pos += 1;
+            } else if !terms.is_empty() {
+                return Err(format!("Expected '+' or '-' at position {}", pos));
+            }
+
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_ascii_digit() || chars[pos] == '.') {
+                pos += 1;
+            }
+            let coefficient = if start == pos {
+                1.0
+            } else {
+                let number: String = chars[start..pos].iter().collect();
+                let value = number
+                    .parse::<f64>()
+                    .map_err(|_| format!("Invalid coefficient '{}'", number))?;
+                if pos < chars.len() && chars[pos] == '*' {
+                    pos += 1;
+                }
+                value
+            };
+
+            let mut operators: Vec<(usize, Pauli)> = vec![];
+            while pos < chars.len() && chars[pos] != '+' && chars[pos] != '-' {
+                let pauli = match chars[pos] {
+                    'I' => Pauli::I,
+                    'X' => Pauli::X,
+                    'Y' => Pauli::Y,
+                    'Z' => Pauli::Z,
+                    '*' => {
+                        pos += 1;
+                        continue;
+                    }
+                    c => return Err(format!("Unexpected character '{}' in observable", c)),
+                };
+                pos += 1;
+
+                let start = pos;
+                while pos < chars.len() && chars[pos].is_ascii_digit() {
+                    pos += 1;
+                }
+                if start == pos {
+                    return Err(format!("Missing qubit index after '{:?}'", pauli));
+                }
+                let qubit: usize = chars[start..pos]
+                    .iter()
+                    .collect::<String>()
+                    .parse()
+                    .map_err(|_| "Qubit index out of range".to_string())?;
+                if operators.iter().any(|(q, _)| *q == qubit) {
+                    return Err(format!("Qubit {} appears twice in one Pauli string", qubit));
+                }
+                operators.push((qubit, pauli));
+            }
+
+            if operators.is_empty() && start == pos {
+                return Err("Expected a coefficient or a Pauli string".to_string());
+            }
+            operators.retain(|(_, pauli)| *pauli != Pauli::I);
+            terms.push(PauliTerm {
+                coefficient: sign * coefficient,
+                operators,
+            });
+        }
+
+        Ok(Observable { terms })
+    }
+}
+
+impl TryFrom<String> for Observable {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Observable> for String {
+    fn from(observable: Observable) -> Self {
+        observable.to_string()
+    }
+}
+
+impl Display for Observable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, term) in self.terms.iter().enumerate() {
+            let coefficient = if i == 0 {
+                format!("{}", term.coefficient)
+            } else if term.coefficient < 0.0 {
+                format!(" - {}", -term.coefficient)
+            } else {
+                format!(" + {}", term.coefficient)
+            };
+            write!(f, "{}", coefficient)?;
+            if term.operators.is_empty() {
+                write!(f, "*I")?;
+            } else {
+                write!(f, "*")?;
+            }
+            for (qubit, pauli) in &term.operators {
+                write!(f, "{:?}{}", pauli, qubit)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gate::{CNot, Gate, Hadamard, PauliX};
+    use crate::quantum_register::QuantumRegister;
+    use crate::qubit::Qubit;
+    use crate::simulator::Simulator;
+    use crate::sparse_register::SparseRegister;
+
+    fn bell_pair<S: Simulator>() -> S {
+        let mut register = S::empty();
+        register.add_qubit(Qubit::basis0().state()).unwrap();
+        register.add_qubit(Qubit::basis0().state()).unwrap();
+        register.apply_gate(&Hadamard::new().matrix_representation(), &[0]);
+        register.apply_gate(&CNot::new().matrix_representation(), &[0, 1]);
+        register
+    }
+
+    #[test]
+    fn test_parse_observable() {
+        let observable: Observable = "0.5*Z0Z1 - 0.3*X2".parse().unwrap();
+        assert_eq!(
+            observable,
+            Observable {
+                terms: vec![
+                    PauliTerm {
+                        coefficient: 0.5,
+                        operators: vec![(0, Pauli::Z), (1, Pauli::Z)],
+                    },
+                    PauliTerm {
+                        coefficient: -0.3,
+                        operators: vec![(2, Pauli::X)],
+                    },
+                ],
+            }
+        );
+        assert_eq!(observable.max_qubit(), Some(2));
+    }
+
+    #[test]
+    fn test_parse_implicit_coefficient_and_identity() {
+        let observable: Observable = "-Y1 + 2 * I0 + X0 * X1".parse().unwrap();
+        assert_eq!(observable.terms.len(), 3);
+        assert_eq!(observable.terms[0].coefficient, -1.0);
+        assert!(observable.terms[1].operators.is_empty());
+        assert_eq!(
+            observable.terms[2].operators,
+            vec![(0, Pauli::X), (1, Pauli::X)]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!("".parse::<Observable>().is_err());
+        assert!("0.5*Q0".parse::<Observable>().is_err());
+        assert!("Z".parse::<Observable>().is_err());
+        assert!("Z0Z0".parse::<Observable>().is_err());
+        assert!("Z0 X1".parse::<Observable>().is_ok());
+    }
+
+    #[test]
+    fn test_bell_state_correlations() {
+        let register: QuantumRegister = bell_pair();
+        let value = |s: &str| register.expectation(&s.parse().unwrap());
+
+        assert!((value("Z0Z1") - 1.0).abs() < 1e-12);
+        assert!((value("X0X1") - 1.0).abs() < 1e-12);
+        assert!((value("Y0Y1") + 1.0).abs() < 1e-12);
+        assert!(value("Z0").abs() < 1e-12);
+        assert!((value("0.5*Z0Z1 - 0.3*X0X1 + 2") - 2.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_sparse_and_dense_agree() {
+        let mut dense: QuantumRegister = bell_pair();
+        let mut sparse: SparseRegister = bell_pair();
+        dense.apply_gate(&PauliX::new().matrix_representation(), &[1]);
+        sparse.apply_gate(&PauliX::new().matrix_representation(), &[1]);
+
+        let observable: Observable = "0.7*Z0Z1 + 0.2*Y0X1 - 1.5*X0Y1".parse().unwrap();
+        let difference = dense.expectation(&observable) - sparse.expectation(&observable);
+        assert!(difference.abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_single_qubit_y_eigenstate() {
+        let mut register = QuantumRegister::<f64>::new(1);
+        register.apply_gate(&Hadamard::new().matrix_representation(), &[0]);
+        let s_gate = nalgebra::DMatrix::from_row_slice(
+            2,
+            2,
+            &[
+                Complex::new(1.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 0.0),
+                Complex::new(0.0, 1.0),
+            ],
+        );
+        register.apply_gate(&s_gate, &[0]);
+
+        let value = register.expectation(&"Y0".parse().unwrap());
+        assert!((value - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_sampled_estimate_converges() {
+        let register: QuantumRegister = bell_pair();
+        let observable: Observable = "Z0Z1 + 0.5*X0".parse().unwrap();
+        let term_value = |term: &PauliTerm| {
+            register.expectation(&Observable {
+                terms: vec![PauliTerm {
+                    coefficient: 1.0,
+                    operators: term.operators.clone(),
+                }],
+            })
+        };
+
+        let estimate = observable.sampled(20000, term_value).unwrap();
+        assert!((estimate - 1.0).abs() < 0.05, "estimate {}", estimate);
+        assert_eq!(observable.sampled(100, |_| 1.0), Ok(1.5));
+        assert!(observable.sampled(0, |_| 1.0).is_err());
+    }
+}
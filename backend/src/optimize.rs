@@ -0,0 +1,317 @@
+//! Peephole optimisation of lowered circuits.
+//!
+//! Repeats three rewrites until none applies:
+//! - gates equal to the identity up to a global phase are removed;
+//! - a gate followed by another on the same qubits whose product is the
+//!   identity cancels with it;
+//! - consecutive single-qubit gates on a wire are fused into one `u` gate.
+//!
+//! When looking for the next gate on its qubits, a gate may move past gates
+//! it commutes with, e.g. a diagonal gate past the control of a `cnot`, so
+//! `t; cnot; tDagger` on the control cancels. Classically controlled gates,
+//! measurements, resets and barriers are never moved across, and
+//! instructions that inspect the state block every qubit.
+
+use nalgebra::{Complex, DMatrix};
+use serde::Serialize;
+
+use crate::{
+    circuit::{Circuit, Instruction, Operation},
+    gate::u_angles,
+};
+
+const TOLERANCE: f64 = 1e-9;
+/// Widest pair of gates checked for commutation.
+const MAX_COMMUTATION_QUBITS: usize = 4;
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OptimizationResult {
+    pub circuit: Circuit,
+    pub gates_before: usize,
+    pub gates_after: usize,
+}
+
+pub fn optimize_circuit(circuit: &Circuit) -> OptimizationResult {
+    let num_qubits = circuit.qubits.len();
+    let mut slots: Vec<Option<Instruction>> =
+        circuit.instructions.iter().cloned().map(Some).collect();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in 0..slots.len() {
+            let Some(matrix) = slots[i].as_ref().and_then(movable_matrix) else {
+                continue;
+            };
+            if is_identity(&matrix) {
+                slots[i] = None;
+                changed = true;
+                continue;
+            }
+            let Some(j) = partner(&slots, i, num_qubits) else {
+                continue;
+            };
+
+            let first = slots[i].as_ref().unwrap();
+            let second = slots[j].as_ref().unwrap();
+            let qubits = first.qubits.clone();
+            let product = embed(&second.matrix().unwrap(), &second.qubits, &qubits) * &matrix;
+            if is_identity(&product) {
+                slots[i] = None;
+                slots[j] = None;
+                changed = true;
+            } else if qubits.len() == 1 {
+                let (theta, phi, lambda, _) = u_angles(&product);
+                slots[i] = None;
+                slots[j] = Some(Instruction::gate("u", vec![theta, phi, lambda], qubits));
+                changed = true;
+            }
+        }
+    }
+
+    let optimized = Circuit {
+        instructions: slots.into_iter().flatten().collect(),
+        ..circuit.clone()
+    };
+    OptimizationResult {
+        gates_before: count_gates(circuit),
+        gates_after: count_gates(&optimized),
+        circuit: optimized,
+    }
+}
+
+pub fn count_gates(circuit: &Circuit) -> usize {
+    circuit
+        .instructions
+        .iter()
+        .filter(|instruction| matches!(instruction.operation, Operation::Gate { .. }))
+        .count()
+}
+
+/// The matrix of an unconditional gate, the only instructions that move.
+fn movable_matrix(instruction: &Instruction) -> Option<DMatrix<Complex<f64>>> {
+    if instruction.condition.is_some() {
+        return None;
+    }
+    instruction.matrix()
+}
+
+/// The next gate acting on exactly the qubits of gate `i` that it can be
+/// combined with, skipping gates that commute with it.
+fn partner(slots: &[Option<Instruction>], i: usize, num_qubits: usize) -> Option<usize> {
+    let first = slots[i].as_ref()?;
+    let matrix = first.matrix()?;
+    let mut qubits = first.qubits.clone();
+    qubits.sort_unstable();
+
+    for (j, slot) in slots.iter().enumerate().skip(i + 1) {
+        let Some(other) = slot else {
+            continue;
+        };
+        if !touches(other, &first.qubits, num_qubits) {
+            continue;
+        }
+        let other_matrix = movable_matrix(other)?;
+        let mut other_qubits = other.qubits.clone();
+        other_qubits.sort_unstable();
+        if other_qubits == qubits {
+            return Some(j);
+        }
+        if !commutes((&matrix, &first.qubits), (&other_matrix, &other.qubits)) {
+            return None;
+        }
+    }
+    None
+}
+
+/// Whether an instruction acts on any of `qubits`. Instructions that read
+/// the state act on all qubits.
+fn touches(instruction: &Instruction, qubits: &[usize], num_qubits: usize) -> bool {
+    match instruction.operation {
+        Operation::Gate { .. }
+        | Operation::Measure { .. }
+        | Operation::Reset
+        | Operation::Barrier => instruction.qubits.iter().any(|q| qubits.contains(q)),
+        _ => num_qubits > 0,
+    }
+}
+
+fn commutes(
+    (a, a_qubits): (&DMatrix<Complex<f64>>, &[usize]),
+    (b, b_qubits): (&DMatrix<Complex<f64>>, &[usize]),
+) -> bool {
+    let mut order = a_qubits.to_vec();
+    order.extend(b_qubits.iter().filter(|q| !a_qubits.contains(q)));
+    if order.len() > MAX_COMMUTATION_QUBITS {
+        return false;
+    }
+    let a = embed(a, a_qubits, &order);
+    let b = embed(b, b_qubits, &order);
+    approx_equal(&(&a * &b), &(&b * &a))
+}
+
+/// Extends a gate on `qubits` to the qubits in `order`, the first of them
+/// being the most significant bit like in gate matrices.
+fn embed(
+    matrix: &DMatrix<Complex<f64>>,
+    qubits: &[usize],
+    order: &[usize],
+) -> DMatrix<Complex<f64>> {
+    let width = order.len();
+    let bit = |qubit: usize| width - 1 - order.iter().position(|q| *q == qubit).unwrap();
+    let gate_index = |index: usize| {
+        qubits
+            .iter()
+            .fold(0, |acc, &qubit| (acc << 1) | ((index >> bit(qubit)) & 1))
+    };
+    let others = order
+        .iter()
+        .filter(|q| !qubits.contains(q))
+        .fold(0, |mask, &qubit| mask | (1 << bit(qubit)));
+
+    DMatrix::from_fn(1 << width, 1 << width, |row, col| {
+        if row & others != col & others {
+            Complex::new(0.0, 0.0)
+        } else {
+            matrix[(gate_index(row), gate_index(col))]
+        }
+    })
+}
+
+/// Whether a unitary is the identity up to a global phase.
+fn is_identity(matrix: &DMatrix<Complex<f64>>) -> bool {
+    let phase = matrix[(0, 0)];
+    (phase.norm() - 1.0).abs() < TOLERANCE
+        && approx_equal(
+            matrix,
+            &(DMatrix::identity(matrix.nrows(), matrix.ncols()) * phase),
+        )
+}
+
+fn approx_equal(a: &DMatrix<Complex<f64>>, b: &DMatrix<Complex<f64>>) -> bool {
+    a.iter()
+        .zip(b.iter())
+        .all(|(x, y)| (x - y).norm() < TOLERANCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{circuit::Condition, gate::Gate, lower::lower_program, qasm::parse_qasm, unitary};
+
+    fn lower_qasm(source: &str) -> Circuit {
+        let program = parse_qasm(&format!(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\n{}",
+            source
+        ))
+        .unwrap();
+        lower_program(&program)
+    }
+
+    fn gate_names(circuit: &Circuit) -> Vec<&str> {
+        circuit
+            .instructions
+            .iter()
+            .filter_map(|instruction| match &instruction.operation {
+                Operation::Gate { name, .. } => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Checks that the optimised circuit has the same unitary up to a
+    /// global phase and returns it.
+    fn optimize_equivalent(source: &str) -> OptimizationResult {
+        let circuit = lower_qasm(source);
+        let result = optimize_circuit(&circuit);
+        let before = unitary::circuit_unitary(&circuit).unwrap().1;
+        let after = unitary::circuit_unitary(&result.circuit).unwrap().1;
+        let (before, after) = (
+            before.matrix_representation(),
+            after.matrix_representation(),
+        );
+        let (row, col) = before
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.norm().total_cmp(&b.1.norm()))
+            .map(|(index, _)| (index % before.nrows(), index / before.nrows()))
+            .unwrap();
+        let phase = after[(row, col)] / before[(row, col)];
+        assert!(approx_equal(&(before * phase), &after), "{}", source);
+        assert_eq!(result.gates_after, count_gates(&result.circuit));
+        result
+    }
+
+    #[test]
+    fn test_inverse_pairs_cancel() {
+        let result = optimize_equivalent("qreg q[2];\nh q[0];\nh q[0];\nx q[1];\nx q[1];");
+        assert_eq!((result.gates_before, result.gates_after), (4, 0));
+
+        let result = optimize_equivalent("qreg q[2];\ncx q[0], q[1];\ncx q[0], q[1];\nh q[1];");
+        assert_eq!(gate_names(&result.circuit), ["hadamard"]);
+
+        let result = optimize_equivalent("qreg q[2];\ncz q[0], q[1];\ncz q[1], q[0];");
+        assert_eq!(result.gates_after, 0);
+    }
+
+    #[test]
+    fn test_nested_runs_collapse() {
+        let result = optimize_equivalent("qreg q[1];\nh q[0];\nx q[0];\nx q[0];\nh q[0];");
+        assert_eq!(result.gates_after, 0);
+    }
+
+    #[test]
+    fn test_single_qubit_gates_fuse() {
+        let result =
+            optimize_equivalent("qreg q[2];\nh q[0];\nt q[0];\nrz(0.3) q[0];\ncx q[0], q[1];");
+        assert_eq!(gate_names(&result.circuit), ["u", "cnot"]);
+        assert_eq!((result.gates_before, result.gates_after), (4, 2));
+    }
+
+    #[test]
+    fn test_identities_are_removed() {
+        let result = optimize_equivalent("qreg q[2];\nid q[0];\nrz(0) q[1];\nrx(2*pi) q[0];");
+        assert_eq!(result.gates_after, 0);
+    }
+
+    #[test]
+    fn test_diagonal_gates_commute_through_controls() {
+        let result = optimize_equivalent("qreg q[2];\nt q[0];\ncx q[0], q[1];\ntdg q[0];");
+        assert_eq!(gate_names(&result.circuit), ["cnot"]);
+
+        let result = optimize_equivalent("qreg q[2];\ns q[1];\ncz q[0], q[1];\nsdg q[1];");
+        assert_eq!(gate_names(&result.circuit), ["cz"]);
+
+        // The target of a cnot does not commute with a diagonal gate.
+        let result = optimize_equivalent("qreg q[2];\nt q[1];\ncx q[0], q[1];\ntdg q[1];");
+        assert_eq!(result.gates_after, 3);
+    }
+
+    #[test]
+    fn test_mixed_circuit_is_equivalent() {
+        let result = optimize_equivalent(
+            "qreg q[3];\nh q[0];\ncx q[0], q[1];\nt q[1];\nh q[2];\nh q[2];\ns q[0];\n\
+             cx q[1], q[2];\nsdg q[0];\nccx q[0], q[1], q[2];\nrz(pi/3) q[2];\nrz(-pi/3) q[2];\n\
+             ccx q[0], q[1], q[2];\nswap q[0], q[2];\nu3(0.1,0.2,0.3) q[1];\nry(0.4) q[1];",
+        );
+        assert!(result.gates_after < result.gates_before);
+    }
+
+    #[test]
+    fn test_measurements_and_conditions_block() {
+        let circuit = lower_qasm(
+            "qreg q[1];\ncreg c[1];\nh q[0];\nmeasure q[0] -> c[0];\nh q[0];\n\
+             if(c==1) x q[0];\nx q[0];",
+        );
+        let result = optimize_circuit(&circuit);
+        assert_eq!(result.gates_after, 4);
+        assert_eq!(
+            result.circuit.instructions[3].condition,
+            Some(Condition {
+                register: "c".to_string(),
+                value: 1
+            })
+        );
+    }
+}
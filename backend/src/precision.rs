@@ -0,0 +1,67 @@
+use nalgebra::{Complex, DMatrix, RealField};
+use serde::Deserialize;
+
+/// Floating point type the state vector is stored in.
+///
+/// Gates are always defined in `f64` and converted into the register's
+/// precision when they are applied.
+pub trait Float: RealField + Copy {
+    fn from_double(value: f64) -> Self;
+    fn to_double(self) -> f64;
+}
+
+impl Float for f32 {
+    fn from_double(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_double(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Float for f64 {
+    fn from_double(value: f64) -> Self {
+        value
+    }
+
+    fn to_double(self) -> f64 {
+        self
+    }
+}
+
+/// Precision requested for a simulation run.
+///
+/// `Single` halves the memory needed for the state vector (8 bytes per
+/// amplitude instead of 16) at the cost of roughly 7 significant digits.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    #[serde(rename = "f32", alias = "single")]
+    Single,
+    #[default]
+    #[serde(rename = "f64", alias = "double")]
+    Double,
+}
+
+impl std::str::FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "f32" | "single" => Ok(Precision::Single),
+            "f64" | "double" => Ok(Precision::Double),
+            _ => Err(format!(
+                "Unknown precision '{}': expected 'f32' or 'f64'",
+                s
+            )),
+        }
+    }
+}
+
+pub fn cast_complex<T: Float>(value: Complex<f64>) -> Complex<T> {
+    Complex::new(T::from_double(value.re), T::from_double(value.im))
+}
+
+pub fn cast_matrix<T: Float>(matrix: &DMatrix<Complex<f64>>) -> DMatrix<Complex<T>> {
+    matrix.map(cast_complex)
+}
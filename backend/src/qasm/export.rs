@@ -0,0 +1,765 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    str::FromStr,
+};
+
+use nalgebra::{Complex, DMatrix};
+use serde::Deserialize;
+
+use crate::{
+    gate::{parameter_count, u_angles},
+    lower::evaluate_complex_expression,
+    models::{Expression, ProgramNode, StatementNode, Target},
+    qubit::Qubit,
+};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QasmVersion {
+    #[default]
+    #[serde(rename = "2", alias = "2.0")]
+    V2,
+    #[serde(rename = "3", alias = "3.0")]
+    V3,
+}
+
+impl FromStr for QasmVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "2" | "2.0" => Ok(QasmVersion::V2),
+            "3" | "3.0" => Ok(QasmVersion::V3),
+            _ => Err(format!("Unknown OpenQASM version '{}': expected 2 or 3", s)),
+        }
+    }
+}
+
+/// Built-in gates and their names in `qelib1.inc` and `stdgates.inc`.
+const GATE_NAMES: &[(&str, &str, &str)] = &[
+    ("identity", "id", "id"),
+    ("pauliX", "x", "x"),
+    ("pauliY", "y", "y"),
+    ("pauliZ", "z", "z"),
+    ("hadamard", "h", "h"),
+    ("s", "s", "s"),
+    ("sDagger", "sdg", "sdg"),
+    ("t", "t", "t"),
+    ("tDagger", "tdg", "tdg"),
+    ("sqrtX", "sx", "sx"),
+    ("sqrtXDagger", "sxdg", "sxdg"),
+    ("phase", "u1", "p"),
+    ("rx", "rx", "rx"),
+    ("ry", "ry", "ry"),
+    ("rz", "rz", "rz"),
+    ("u", "u3", "U"),
+    ("cnot", "cx", "cx"),
+    ("cy", "cy", "cy"),
+    ("cz", "cz", "cz"),
+    ("ch", "ch", "ch"),
+    ("swap", "swap", "swap"),
+    ("controlledPhase", "cu1", "cp"),
+    ("crx", "crx", "crx"),
+    ("cry", "cry", "cry"),
+    ("crz", "crz", "crz"),
+    ("cu", "cu3", "cu"),
+    ("toffoli", "ccx", "ccx"),
+    ("fredkin", "cswap", "cswap"),
+    ("rxx", "rxx", "rxx"),
+    ("rzz", "rzz", "rzz"),
+];
+
+/// Gates `stdgates.inc` lacks, defined exactly in terms of the ones it has.
+const QASM3_HELPERS: &[(&str, &str)] = &[
+    ("sxdg", "gate sxdg a { h a; sdg a; h a; }"),
+    (
+        "rxx",
+        "gate rxx(theta) a, b { h a; h b; cx a, b; rz(theta) b; cx a, b; h a; h b; }",
+    ),
+    (
+        "rzz",
+        "gate rzz(theta) a, b { cx a, b; rz(theta) b; cx a, b; }",
+    ),
+];
+
+/// Names user gates may not take because the header already defines them.
+const RESERVED: &[&str] = &[
+    "U", "CX", "u0", "u2", "cu1", "cu3", "u1", "u3", "p", "cp", "cu", "id", "x", "y", "z", "h",
+    "s", "sdg", "t", "tdg", "sx", "sxdg", "rx", "ry", "rz", "cx", "cy", "cz", "ch", "swap", "ccx",
+    "cswap", "crx", "cry", "crz", "rxx", "rzz", "pi", "gate", "measure", "reset", "barrier", "if",
+    "qreg", "creg", "qubit", "bit", "opaque", "include",
+];
+
+/// Writes a program as OpenQASM. Composite gates become `gate` definitions,
+/// single-qubit matrix gates are decomposed into `U`, wider matrix gates are
+/// declared `opaque`, `repeat` loops are unrolled and qubits created in a
+/// state other than `|0>` are prepared with a `U` gate. Statements that only
+/// inspect the state have no OpenQASM counterpart and become comments.
+pub fn export_qasm(program: &ProgramNode, version: QasmVersion) -> Result<String, String> {
+    export_qasm_limited(program, version, usize::MAX)
+}
+
+/// Like [`export_qasm`], but refuses programs whose statements, once loops
+/// are unrolled, take more than `max_lines` lines.
+pub fn export_qasm_limited(
+    program: &ProgramNode,
+    version: QasmVersion,
+    max_lines: usize,
+) -> Result<String, String> {
+    let mut exporter = Exporter {
+        version,
+        max_lines,
+        lines: vec![],
+        helpers: BTreeSet::new(),
+        gate_names: HashMap::new(),
+        declared: HashSet::new(),
+        widths: HashMap::new(),
+    };
+    for statement in &program.statements {
+        exporter.statement(statement, None, 0)?;
+        exporter.check_length()?;
+    }
+
+    let mut output = match version {
+        QasmVersion::V2 => vec![
+            "OPENQASM 2.0;".to_string(),
+            "include \"qelib1.inc\";".to_string(),
+        ],
+        QasmVersion::V3 => vec![
+            "OPENQASM 3.0;".to_string(),
+            "include \"stdgates.inc\";".to_string(),
+        ],
+    };
+    for (name, definition) in QASM3_HELPERS {
+        if exporter.helpers.contains(name) {
+            output.push(definition.to_string());
+        }
+    }
+    output.extend(exporter.lines);
+    Ok(output.join("\n") + "\n")
+}
+
+struct Exporter {
+    version: QasmVersion,
+    max_lines: usize,
+    lines: Vec<String>,
+    /// Helper definitions the OpenQASM 3 output needs.
+    helpers: BTreeSet<&'static str>,
+    /// OpenQASM names of user-defined gates.
+    gate_names: HashMap<String, String>,
+    /// Every quantum and classical register declared so far.
+    declared: HashSet<String>,
+    /// Sizes of the quantum registers.
+    widths: HashMap<String, usize>,
+}
+
+impl Exporter {
+    /// Emits one statement. `condition` is the enclosing `if`, which
+    /// OpenQASM 2 has to repeat on every guarded operation.
+    fn statement(
+        &mut self,
+        statement: &StatementNode,
+        condition: Option<(&str, u64)>,
+        depth: usize,
+    ) -> Result<(), String> {
+        let indent = "    ".repeat(depth);
+        let guard = match (self.version, condition) {
+            (QasmVersion::V2, Some((register, value))) => format!("if({}=={}) ", register, value),
+            _ => String::new(),
+        };
+        let nested = condition.is_some() || depth > 0;
+
+        let line = match statement {
+            StatementNode::CreateStatement {
+                identifier,
+                complex_array,
+            } => {
+                self.reject_nested(nested, "create")?;
+                if complex_array.values.len() != 2 {
+                    return Err(format!(
+                        "Invalid number of states for qubit {}: expected 2, got {}",
+                        identifier,
+                        complex_array.values.len()
+                    ));
+                }
+                let (r1, i1) = evaluate_complex_expression(&complex_array.values[0]);
+                let (r2, i2) = evaluate_complex_expression(&complex_array.values[1]);
+                let state = Qubit::new_from_amplitudes(r1, i1, r2, i2).state();
+
+                self.declare_qubits(identifier, None)?;
+                if state[1].norm() > 1e-12 {
+                    let theta = 2.0 * state[1].norm().atan2(state[0].norm());
+                    let phi = state[1].arg() - state[0].arg();
+                    self.lines.push(format!(
+                        "U({}, {}, 0) {};",
+                        format_number(theta),
+                        format_number(phi),
+                        identifier
+                    ));
+                }
+                return Ok(());
+            }
+            StatementNode::RegisterDeclaration { identifier, size } => {
+                self.reject_nested(nested, "register declarations")?;
+                return self.declare_qubits(identifier, Some(*size));
+            }
+            StatementNode::ClassicalDeclaration { identifier, size } => {
+                self.reject_nested(nested, "register declarations")?;
+                return self.declare_bits(identifier, *size);
+            }
+            StatementNode::ApplyStatement {
+                identifier1,
+                identifier2,
+            } => format!("{} {};", self.gate_name(identifier2)?, identifier1),
+            StatementNode::GateApplication {
+                gate,
+                parameters,
+                targets,
+            } => self.gate_call(gate, parameters, targets)?,
+            StatementNode::GateDefinition {
+                identifier,
+                parameters,
+                qubits,
+                statements,
+            } => {
+                self.reject_nested(nested, "gate definitions")?;
+                let name = self.user_gate_name(identifier);
+                let mut body = vec![];
+                for statement in statements {
+                    body.push(match statement {
+                        StatementNode::GateApplication {
+                            gate,
+                            parameters,
+                            targets,
+                        } => self.gate_call(gate, parameters, targets)?,
+                        StatementNode::BarrierStatement { targets } => {
+                            format!("barrier {};", join_targets(targets))
+                        }
+                        _ => {
+                            return Err(format!(
+                                "Gate {} may only contain gate applications and barriers",
+                                identifier
+                            ))
+                        }
+                    });
+                }
+                let signature = if parameters.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}({})", name, parameters.join(", "))
+                };
+                self.lines.push(format!(
+                    "gate {} {} {{ {} }}",
+                    signature,
+                    qubits.join(", "),
+                    body.join(" ")
+                ));
+                self.gate_names.insert(identifier.clone(), name);
+                return Ok(());
+            }
+            StatementNode::MatrixGateDefinition { identifier, matrix } => {
+                self.reject_nested(nested, "gate definitions")?;
+                self.matrix_gate(identifier, matrix)?;
+                return Ok(());
+            }
+            StatementNode::MeasureStatement { identifier } => {
+                let register = format!("{}_result", identifier);
+                if !self.declared.contains(&register) {
+                    self.reject_nested(nested, "measure statements")?;
+                    self.declare_bits(&register, 1)?;
+                }
+                self.measure(
+                    &Target::from(identifier.as_str()),
+                    &format!("{}[0]", register),
+                )
+            }
+            StatementNode::MeasureAssignment {
+                source,
+                destination,
+            } => {
+                if condition.is_some_and(|(register, _)| register == destination.identifier)
+                    && self.version == QasmVersion::V2
+                {
+                    return Err(format!(
+                        "Cannot export: a measurement inside `if ({} == ...)` writes {}",
+                        destination.identifier, destination.identifier
+                    ));
+                }
+                if destination.index.is_none() && !self.declared.contains(&destination.identifier) {
+                    self.reject_nested(nested, "implicit register declarations")?;
+                    let size = self.width_of(source);
+                    self.declare_bits(&destination.identifier, size)?;
+                }
+                self.measure(source, &destination.to_string())
+            }
+            StatementNode::ResetStatement { target } => format!("reset {};", target),
+            StatementNode::BarrierStatement { targets } if targets.is_empty() => {
+                return Ok(());
+            }
+            StatementNode::BarrierStatement { targets } => {
+                format!("barrier {};", join_targets(targets))
+            }
+            StatementNode::IfStatement {
+                identifier,
+                value,
+                statements,
+            } => {
+                match self.version {
+                    QasmVersion::V2 => {
+                        if condition.is_some() {
+                            return Err("OpenQASM 2 cannot nest if statements".to_string());
+                        }
+                        for statement in statements {
+                            self.statement(statement, Some((identifier, *value)), depth)?;
+                        }
+                    }
+                    QasmVersion::V3 => {
+                        self.lines
+                            .push(format!("{}if ({} == {}) {{", indent, identifier, value));
+                        for statement in statements {
+                            self.statement(statement, Some((identifier, *value)), depth + 1)?;
+                        }
+                        self.lines.push(format!("{}}}", indent));
+                    }
+                }
+                return Ok(());
+            }
+            StatementNode::RepeatStatement { count, statements } => {
+                for _ in 0..*count {
+                    let before = self.lines.len();
+                    for statement in statements {
+                        self.statement(statement, condition, depth)?;
+                    }
+                    // A body that writes nothing writes nothing every time.
+                    if self.lines.len() == before {
+                        break;
+                    }
+                    self.check_length()?;
+                }
+                return Ok(());
+            }
+            StatementNode::DisplayStatement { identifier } => {
+                format!("// display {}", identifier)
+            }
+            StatementNode::ObservableDeclaration {
+                identifier,
+                observable,
+            } => format!("// observable {} = {}", identifier, observable),
+            StatementNode::ExpectStatement {
+                observable,
+                identifier,
+            } => format!("// expect {} => {}", observable, identifier),
+            StatementNode::PrintStatement { analysis, targets } => format!(
+                "// print {} {}",
+                format!("{:?}", analysis).to_lowercase(),
+                join_targets(targets)
+            )
+            .trim_end()
+            .to_string(),
+        };
+
+        let guard = if line.starts_with("//") { "" } else { &guard };
+        self.lines.push(format!("{}{}{}", indent, guard, line));
+        Ok(())
+    }
+
+    fn check_length(&self) -> Result<(), String> {
+        if self.lines.len() > self.max_lines {
+            return Err(format!(
+                "The exported program is longer than {} lines",
+                self.max_lines
+            ));
+        }
+        Ok(())
+    }
+
+    fn reject_nested(&self, nested: bool, what: &str) -> Result<(), String> {
+        if nested {
+            Err(format!("Cannot export {} inside if or repeat blocks", what))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn declare_qubits(&mut self, identifier: &str, size: Option<usize>) -> Result<(), String> {
+        if !self.declared.insert(identifier.to_string()) {
+            return Err(format!("Identifier {} was already declared", identifier));
+        }
+        self.widths
+            .insert(identifier.to_string(), size.unwrap_or(1));
+        self.lines.push(match (self.version, size) {
+            (QasmVersion::V2, size) => format!("qreg {}[{}];", identifier, size.unwrap_or(1)),
+            (QasmVersion::V3, None) => format!("qubit {};", identifier),
+            (QasmVersion::V3, Some(size)) => format!("qubit[{}] {};", size, identifier),
+        });
+        Ok(())
+    }
+
+    fn declare_bits(&mut self, identifier: &str, size: usize) -> Result<(), String> {
+        if !self.declared.insert(identifier.to_string()) {
+            return Err(format!("Identifier {} was already declared", identifier));
+        }
+        self.lines.push(match self.version {
+            QasmVersion::V2 => format!("creg {}[{}];", identifier, size),
+            QasmVersion::V3 => format!("bit[{}] {};", size, identifier),
+        });
+        Ok(())
+    }
+
+    /// Number of qubits a quantum operand spans.
+    fn width_of(&self, target: &Target) -> usize {
+        match target.index {
+            Some(_) => 1,
+            None => self.widths.get(&target.identifier).copied().unwrap_or(1),
+        }
+    }
+
+    fn measure(&self, source: &Target, destination: &str) -> String {
+        match self.version {
+            QasmVersion::V2 => format!("measure {} -> {};", source, destination),
+            QasmVersion::V3 => format!("{} = measure {};", destination, source),
+        }
+    }
+
+    fn gate_call(
+        &mut self,
+        gate: &str,
+        parameters: &[Expression],
+        targets: &[Target],
+    ) -> Result<String, String> {
+        let mut parameters: Vec<String> = parameters
+            .iter()
+            .map(|parameter| format_expression(parameter, self.version))
+            .collect();
+        if self.version == QasmVersion::V3 && gate == "cu" && !self.gate_names.contains_key(gate) {
+            // stdgates.inc adds a fourth, global phase parameter to cu.
+            parameters.push("0".to_string());
+        }
+
+        let name = self.gate_name(gate)?;
+        Ok(if parameters.is_empty() {
+            format!("{} {};", name, join_targets(targets))
+        } else {
+            format!(
+                "{}({}) {};",
+                name,
+                parameters.join(", "),
+                join_targets(targets)
+            )
+        })
+    }
+
+    fn gate_name(&mut self, gate: &str) -> Result<String, String> {
+        if let Some(name) = self.gate_names.get(gate) {
+            return Ok(name.clone());
+        }
+        if parameter_count(gate).is_none() {
+            return Err(format!("Cannot resolve gate '{}'", gate));
+        }
+
+        let (_, qasm2, qasm3) = GATE_NAMES
+            .iter()
+            .find(|(name, _, _)| *name == gate)
+            .unwrap();
+        Ok(match self.version {
+            QasmVersion::V2 => qasm2.to_string(),
+            QasmVersion::V3 => {
+                if let Some((helper, _)) = QASM3_HELPERS.iter().find(|(name, _)| name == qasm3) {
+                    self.helpers.insert(helper);
+                }
+                qasm3.to_string()
+            }
+        })
+    }
+
+    fn user_gate_name(&self, identifier: &str) -> String {
+        let mut name = identifier.to_string();
+        while RESERVED.contains(&name.as_str())
+            || self.gate_names.values().any(|taken| *taken == name)
+        {
+            name.push('_');
+        }
+        name
+    }
+
+    fn matrix_gate(&mut self, identifier: &str, rows: &[Vec<Expression>]) -> Result<(), String> {
+        let size = rows.len();
+        if size < 2 || !size.is_power_of_two() || rows.iter().any(|row| row.len() != size) {
+            return Err(format!(
+                "Matrix of gate {} must be square with a power-of-two size",
+                identifier
+            ));
+        }
+        let name = self.user_gate_name(identifier);
+        let width = size.trailing_zeros() as usize;
+        let qubits: Vec<String> = (0..width).map(|i| format!("q{}", i)).collect();
+
+        if width == 1 {
+            let matrix = DMatrix::from_fn(2, 2, |row, col| {
+                let (re, im) = evaluate_complex_expression(&rows[row][col]);
+                Complex::new(re, im)
+            });
+            let (theta, phi, lambda, _) = u_angles(&matrix);
+            self.lines.push(format!(
+                "gate {} q0 {{ U({}, {}, {}) q0; }}",
+                name,
+                format_number(theta),
+                format_number(phi),
+                format_number(lambda)
+            ));
+        } else {
+            self.lines.push(format!(
+                "// {} is a {}-qubit matrix gate without a decomposition",
+                name, width
+            ));
+            self.lines
+                .push(format!("opaque {} {};", name, qubits.join(", ")));
+        }
+        self.gate_names.insert(identifier.to_string(), name);
+        Ok(())
+    }
+}
+
+fn join_targets(targets: &[Target]) -> String {
+    targets
+        .iter()
+        .map(Target::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Shortest text that parses back to the same `f64`.
+fn format_number(value: f64) -> String {
+    let value = if value == 0.0 { 0.0 } else { value };
+    format!("{}", value)
+}
+
+fn format_expression(expression: &Expression, version: QasmVersion) -> String {
+    match expression {
+        Expression::RealNumber { value } => format_number(*value),
+        Expression::ImaginaryNumber { value } => format!("{}im", format_number(*value)),
+        Expression::Identifier { value } => value.clone(),
+        Expression::InfixExpression { op, left, right } => {
+            let op = match (op.as_str(), version) {
+                ("^", QasmVersion::V3) => "**",
+                (op, _) => op,
+            };
+            format!(
+                "({} {} {})",
+                format_expression(left, version),
+                op,
+                format_expression(right, version)
+            )
+        }
+        Expression::PrefixExpression { op, right } => {
+            format!("{}{}", op, format_expression(right, version))
+        }
+        Expression::FunctionCall { function, argument } => {
+            format!("{}({})", function, format_expression(argument, version))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gate::Gate,
+        interpreter::{run_circuit, SimulationOptions},
+        lower::lower_program,
+        models::{ComplexArrayNode, NodeType},
+        qasm::parse_qasm,
+        quantum_register::QuantumRegister,
+        unitary::program_unitary,
+    };
+
+    fn assert_same_unitary(left: &ProgramNode, right: &ProgramNode) {
+        let (_, left) = program_unitary(left).unwrap();
+        let (_, right) = program_unitary(right).unwrap();
+        let (left, right) = (left.matrix_representation(), right.matrix_representation());
+        // Equal up to a global phase: align on the largest entry of `left`.
+        let (index, _) = left
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.norm().partial_cmp(&b.1.norm()).unwrap())
+            .unwrap();
+        let phase = left[index] / right[index];
+        for (l, r) in left.iter().zip(right.iter()) {
+            assert!((l - r * phase).norm() < 1e-9, "{} != {}", l, r * phase);
+        }
+    }
+
+    fn assert_same_state(left: ProgramNode, right: ProgramNode) {
+        let options = SimulationOptions::default();
+        let (left, _) = run_circuit::<QuantumRegister>(&lower_program(&left), options);
+        let (right, _) = run_circuit::<QuantumRegister>(&lower_program(&right), options);
+        let overlap = left.state().dotc(right.state());
+        assert!((overlap.norm() - 1.0).abs() < 1e-9, "overlap {}", overlap);
+    }
+
+    const LIBRARY_CIRCUIT: &str = r#"OPENQASM 2.0;
+include "qelib1.inc";
+gate entangle(theta) a, b { h a; crz(theta / 2) a, b; cx a, b; }
+qreg q[3];
+entangle(pi) q[0], q[1];
+u3(0.1, 0.2, 0.3) q[2];
+sxdg q[0];
+rzz(0.4) q[1], q[2];
+ccx q[0], q[1], q[2];
+cu3(0.5, 0.6, 0.7) q[2], q[0];
+cswap q[2], q[0], q[1];
+"#;
+
+    #[test]
+    fn test_qasm2_round_trip() {
+        let program = parse_qasm(LIBRARY_CIRCUIT).unwrap();
+
+        let exported = export_qasm(&program, QasmVersion::V2).unwrap();
+        let reimported = parse_qasm(&exported).unwrap();
+
+        assert_same_unitary(&program, &reimported);
+        assert_eq!(exported, export_qasm(&reimported, QasmVersion::V2).unwrap());
+    }
+
+    #[test]
+    fn test_qasm2_export_text() {
+        let source = r#"OPENQASM 2.0;
+include "qelib1.inc";
+qreg q[2];
+creg c[2];
+h q[0];
+cx q[0], q[1];
+measure q -> c;
+if(c==3) x q[0];
+"#;
+
+        let exported = export_qasm(&parse_qasm(source).unwrap(), QasmVersion::V2).unwrap();
+
+        assert_eq!(exported, source);
+    }
+
+    #[test]
+    fn test_qasm3_export_text() {
+        let source = r#"OPENQASM 2.0;
+include "qelib1.inc";
+qreg q[2];
+creg c[1];
+u1(pi ^ 2) q[0];
+rxx(0.5) q[0], q[1];
+measure q[0] -> c[0];
+if(c==1) cu3(1, 2, 3) q[0], q[1];
+"#;
+
+        let exported = export_qasm(&parse_qasm(source).unwrap(), QasmVersion::V3).unwrap();
+
+        assert_eq!(
+            exported,
+            [
+                "OPENQASM 3.0;",
+                "include \"stdgates.inc\";",
+                "gate rxx(theta) a, b { h a; h b; cx a, b; rz(theta) b; cx a, b; h a; h b; }",
+                "qubit[2] q;",
+                "bit[1] c;",
+                "p((pi ** 2)) q[0];",
+                "rxx(0.5) q[0], q[1];",
+                "c[0] = measure q[0];",
+                "if (c == 1) {",
+                "    cu(1, 2, 3, 0) q[0], q[1];",
+                "}",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_native_program_export() {
+        let h = std::f64::consts::FRAC_1_SQRT_2;
+        let program: ProgramNode = serde_json::from_value(serde_json::json!({
+            "type": "Program",
+            "statements": [
+                {"type": "CreateStatement", "identifier": "a", "complexArray": {
+                    "type": "ComplexArray",
+                    "values": [{"type": "RealNumber", "value": 1.0}, {"type": "RealNumber", "value": 1.0}]
+                }},
+                {"type": "CreateStatement", "identifier": "b", "complexArray": {
+                    "type": "ComplexArray",
+                    "values": [{"type": "RealNumber", "value": 1.0}, {"type": "RealNumber", "value": 0.0}]
+                }},
+                {"type": "MatrixGateDefinition", "identifier": "h", "matrix": [
+                    [{"type": "RealNumber", "value": h}, {"type": "RealNumber", "value": h}],
+                    [{"type": "RealNumber", "value": h}, {"type": "RealNumber", "value": -h}]
+                ]},
+                {"type": "RepeatStatement", "count": 2, "statements": [
+                    {"type": "ApplyStatement", "identifier1": "a", "identifier2": "h"},
+                    {"type": "GateApplication", "gate": "cnot", "targets": ["a", "b"]}
+                ]},
+                {"type": "PrintStatement", "analysis": "probs"}
+            ]
+        }))
+        .unwrap();
+
+        let exported = export_qasm(&program, QasmVersion::V2).unwrap();
+        let reimported = parse_qasm(&exported).unwrap();
+
+        assert!(
+            exported.contains("gate h_ q0 { U(1.5707963267948966, 0, -3.141592653589793) q0; }")
+        );
+        assert!(exported.contains("qreg a[1];\nU(1.5707963267948966, 0, 0) a;"));
+        assert_eq!(exported.matches("h_ a;").count(), 2);
+        assert!(exported.ends_with("// print probs\n"));
+        assert_same_state(program, reimported);
+    }
+
+    #[test]
+    fn test_wide_matrix_gates_are_opaque() {
+        let mut program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![],
+        };
+        let one = || Expression::RealNumber { value: 1.0 };
+        let zero = || Expression::RealNumber { value: 0.0 };
+        program
+            .statements
+            .push(StatementNode::MatrixGateDefinition {
+                identifier: "swapish".to_string(),
+                matrix: (0..4)
+                    .map(|row| {
+                        (0..4)
+                            .map(|col| if row == col { one() } else { zero() })
+                            .collect()
+                    })
+                    .collect(),
+            });
+        program.statements.push(StatementNode::CreateStatement {
+            identifier: "q".to_string(),
+            complex_array: ComplexArrayNode {
+                r#type: NodeType::ComplexArray,
+                values: vec![one(), zero()],
+            },
+        });
+
+        let exported = export_qasm(&program, QasmVersion::V3).unwrap();
+
+        assert!(exported.contains("opaque swapish q0, q1;"));
+        assert!(exported.contains("qubit q;"));
+    }
+
+    #[test]
+    fn test_long_loops_are_refused() {
+        let mut program = parse_qasm("OPENQASM 2.0;\nqreg q[1];").unwrap();
+        program.statements.push(StatementNode::RepeatStatement {
+            count: usize::MAX,
+            statements: vec![],
+        });
+        assert!(export_qasm_limited(&program, QasmVersion::V2, 10).is_ok());
+
+        program.statements.push(StatementNode::RepeatStatement {
+            count: usize::MAX,
+            statements: vec![StatementNode::ResetStatement {
+                target: "q[0]".into(),
+            }],
+        });
+        let error = export_qasm_limited(&program, QasmVersion::V2, 10).unwrap_err();
+        assert!(error.contains("longer than 10 lines"), "{}", error);
+    }
+}
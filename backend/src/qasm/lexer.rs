@@ -0,0 +1,138 @@
+use super::QasmError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Identifier(String),
+    /// Integer or real literal, kept as written.
+    Number(String),
+    Str(String),
+    Symbol(&'static str),
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub line: usize,
+    pub column: usize,
+}
+
+const SYMBOLS: [&str; 15] = [
+    "->", "==", ";", ",", "(", ")", "[", "]", "{", "}", "+", "-", "*", "/", "^",
+];
+
+pub fn tokenize(source: &str) -> Result<Vec<Token>, QasmError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = vec![];
+    let (mut i, mut line, mut column) = (0, 1, 1);
+
+    let advance = |i: &mut usize, line: &mut usize, column: &mut usize, count: usize| {
+        for _ in 0..count {
+            if chars[*i] == '\n' {
+                *line += 1;
+                *column = 1;
+            } else {
+                *column += 1;
+            }
+            *i += 1;
+        }
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+        let (start_line, start_column) = (line, column);
+
+        if c.is_whitespace() {
+            advance(&mut i, &mut line, &mut column, 1);
+        } else if rest == "//" {
+            while i < chars.len() && chars[i] != '\n' {
+                advance(&mut i, &mut line, &mut column, 1);
+            }
+        } else if rest == "/*" {
+            advance(&mut i, &mut line, &mut column, 2);
+            loop {
+                if i + 1 >= chars.len() {
+                    return Err(QasmError::new(
+                        start_line,
+                        start_column,
+                        "Unterminated comment",
+                    ));
+                }
+                if chars[i] == '*' && chars[i + 1] == '/' {
+                    advance(&mut i, &mut line, &mut column, 2);
+                    break;
+                }
+                advance(&mut i, &mut line, &mut column, 1);
+            }
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let length = chars[i..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                .count();
+            let word = chars[i..i + length].iter().collect();
+            tokens.push(Token {
+                kind: TokenKind::Identifier(word),
+                line,
+                column,
+            });
+            advance(&mut i, &mut line, &mut column, length);
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let mut length = chars[i..]
+                .iter()
+                .take_while(|c| c.is_ascii_digit() || **c == '.')
+                .count();
+            if matches!(chars.get(i + length), Some('e' | 'E')) {
+                let sign = usize::from(matches!(chars.get(i + length + 1), Some('+' | '-')));
+                let digits = chars[(i + length + 1 + sign).min(chars.len())..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .count();
+                if digits > 0 {
+                    length += 1 + sign + digits;
+                }
+            }
+            let number = chars[i..i + length].iter().collect();
+            tokens.push(Token {
+                kind: TokenKind::Number(number),
+                line,
+                column,
+            });
+            advance(&mut i, &mut line, &mut column, length);
+        } else if c == '"' {
+            let length = chars[i + 1..].iter().take_while(|c| **c != '"').count();
+            if i + 1 + length >= chars.len() {
+                return Err(QasmError::new(line, column, "Unterminated string"));
+            }
+            let text = chars[i + 1..i + 1 + length].iter().collect();
+            tokens.push(Token {
+                kind: TokenKind::Str(text),
+                line,
+                column,
+            });
+            advance(&mut i, &mut line, &mut column, length + 2);
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token {
+                kind: TokenKind::Symbol(symbol),
+                line,
+                column,
+            });
+            advance(&mut i, &mut line, &mut column, symbol.len());
+        } else {
+            return Err(QasmError::new(
+                line,
+                column,
+                &format!("Unexpected character '{}'", c),
+            ));
+        }
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        line,
+        column,
+    });
+    Ok(tokens)
+}
@@ -0,0 +1,198 @@
+//! Import of OpenQASM 2.0 programs and export to OpenQASM 2.0 and 3.
+//!
+//! Programs are lowered into the same [`ProgramNode`] the frontend sends, so
+//! they run through the regular interpreter. Gates from `qelib1.inc` are
+//! mapped onto the simulator's built-in gates; `U` and `CX` are always
+//! available.
+
+mod export;
+mod lexer;
+mod parser;
+
+pub use export::{export_qasm, export_qasm_limited, QasmVersion};
+
+use std::fmt;
+
+use crate::models::ProgramNode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QasmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl QasmError {
+    fn new(line: usize, column: usize, message: &str) -> Self {
+        Self {
+            line,
+            column,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for QasmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for QasmError {}
+
+pub fn parse_qasm(source: &str) -> Result<ProgramNode, QasmError> {
+    let tokens = lexer::tokenize(source)?;
+    parser::Parser::new(tokens).parse_program()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interpreter::{simulate_program, SimulationOptions},
+        models::StatementNode,
+        unitary::program_unitary,
+    };
+    use nalgebra::Complex;
+
+    fn run(source: &str) -> crate::interpreter::SimulationResult {
+        simulate_program(parse_qasm(source).unwrap(), SimulationOptions::default())
+    }
+
+    #[test]
+    fn test_bell_state_measurements_agree() {
+        let source = r#"
+            OPENQASM 2.0;
+            include "qelib1.inc";
+            qreg q[2];
+            creg c[2];
+            h q[0];
+            cx q[0], q[1];
+            measure q -> c;
+        "#;
+
+        for _ in 0..10 {
+            let result = run(source);
+            assert!(result.output.is_empty(), "{:?}", result.output);
+            let bits = &result.memory["c"];
+            assert!(bits == "00" || bits == "11", "unexpected outcome {}", bits);
+        }
+    }
+
+    #[test]
+    fn test_classically_controlled_correction() {
+        let source = r#"
+            OPENQASM 2.0;
+            include "qelib1.inc";
+            qreg q[2];
+            creg c[1];
+            creg d[1];
+            x q[0];
+            measure q[0] -> c[0];
+            if (c == 1) x q[1];
+            reset q[0];
+            barrier q;
+            measure q[1] -> d[0];
+        "#;
+
+        let result = run(source);
+
+        assert_eq!(result.memory["c"], "1");
+        assert_eq!(result.memory["d"], "1");
+        let program = parse_qasm(source).unwrap();
+        assert!(matches!(
+            program.statements[5],
+            StatementNode::IfStatement { value: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_gate_definitions_and_parameters() {
+        let source = r#"
+            OPENQASM 2.0;
+            include "qelib1.inc";
+            // A flip built from rotations: rx(pi) up to a global phase.
+            gate flip(theta) a { u3(theta, -pi/2, pi/2) a; }
+            gate pair a, b { flip(pi) a; CX a, b; }
+            qreg q[2];
+            creg c[2];
+            pair q[0], q[1];
+            measure q -> c;
+        "#;
+
+        assert_eq!(run(source).memory["c"], "11");
+    }
+
+    #[test]
+    fn test_register_broadcast() {
+        let source = r#"
+            OPENQASM 2.0;
+            include "qelib1.inc";
+            qreg a[3];
+            qreg b[3];
+            creg c[3];
+            x a;
+            cx a, b;
+            measure b -> c;
+        "#;
+
+        assert_eq!(run(source).memory["c"], "111");
+    }
+
+    #[test]
+    fn test_u2_and_unitary_of_imported_program() {
+        let source = r#"
+            OPENQASM 2.0;
+            include "qelib1.inc";
+            qreg q[1];
+            u2(0, pi) q[0];
+        "#;
+
+        let (qubits, gate) = program_unitary(&parse_qasm(source).unwrap()).unwrap();
+        let matrix = crate::gate::Gate::matrix_representation(&gate);
+
+        assert_eq!(qubits, vec!["q[0]"]);
+        let h = std::f64::consts::FRAC_1_SQRT_2;
+        assert!((matrix[(1, 1)] - Complex::new(-h, 0.0)).norm() < 1e-12);
+        assert!((matrix[(0, 1)] - Complex::new(h, 0.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |source: &str| parse_qasm(source).unwrap_err().to_string();
+
+        assert_eq!(
+            error("OPENQASM 3.0;"),
+            "line 1, column 10: Unsupported OpenQASM version 3.0; only 2.0 can be imported"
+        );
+        assert_eq!(
+            error("OPENQASM 2.0;\nqreg q[1];\nh q[0];"),
+            "line 3, column 6: Unknown gate 'h'; did you forget include \"qelib1.inc\";?"
+        );
+        assert_eq!(
+            error("OPENQASM 2.0;\nqreg q[1]\nU(0,0,0) q[0];"),
+            "line 3, column 1: Expected ';'"
+        );
+        assert_eq!(
+            error("OPENQASM 2.0;\ninclude \"other.inc\";"),
+            "line 2, column 9: Cannot include 'other.inc': only qelib1.inc is available"
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors_are_reported() {
+        let result = run("OPENQASM 2.0;\nqreg q[1];\nCX q[0], q[0];\nU(0,0,0) q[3];");
+
+        assert_eq!(
+            result.output,
+            vec![
+                "Gate cnot is applied to the same qubit twice",
+                "Index 3 is out of range for register 'q' of size 1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::HashSet;
+
+use crate::models::{Expression, NodeType, ProgramNode, StatementNode, Target};
+
+use super::{
+    lexer::{Token, TokenKind},
+    QasmError,
+};
+
+/// `qelib1.inc` gates and the built-in gate each one maps to. `u2` and `u0`
+/// are rewritten separately since their parameters change.
+const QELIB1_GATES: &[(&str, &str)] = &[
+    ("id", "identity"),
+    ("x", "pauliX"),
+    ("y", "pauliY"),
+    ("z", "pauliZ"),
+    ("h", "hadamard"),
+    ("s", "s"),
+    ("sdg", "sDagger"),
+    ("t", "t"),
+    ("tdg", "tDagger"),
+    ("sx", "sqrtX"),
+    ("sxdg", "sqrtXDagger"),
+    ("rx", "rx"),
+    ("ry", "ry"),
+    ("rz", "rz"),
+    ("u1", "phase"),
+    ("p", "phase"),
+    ("u3", "u"),
+    ("u", "u"),
+    ("cx", "cnot"),
+    ("cy", "cy"),
+    ("cz", "cz"),
+    ("ch", "ch"),
+    ("swap", "swap"),
+    ("ccx", "toffoli"),
+    ("cswap", "fredkin"),
+    ("crx", "crx"),
+    ("cry", "cry"),
+    ("crz", "crz"),
+    ("cu1", "controlledPhase"),
+    ("cp", "controlledPhase"),
+    ("cu3", "cu"),
+    ("rxx", "rxx"),
+    ("rzz", "rzz"),
+];
+
+const FUNCTIONS: [&str; 6] = ["sin", "cos", "tan", "exp", "ln", "sqrt"];
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Whether `qelib1.inc` was included.
+    standard_library: bool,
+    user_gates: HashSet<String>,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens,
+            position: 0,
+            standard_library: false,
+            user_gates: HashSet::new(),
+        }
+    }
+
+    pub fn parse_program(&mut self) -> Result<ProgramNode, QasmError> {
+        self.expect_keyword("OPENQASM")?;
+        let version = self.expect_number()?;
+        if version != "2.0" && version != "2" {
+            return Err(self.error_at_previous(&format!(
+                "Unsupported OpenQASM version {}; only 2.0 can be imported",
+                version
+            )));
+        }
+        self.expect_symbol(";")?;
+
+        let mut statements = vec![];
+        while self.peek().kind != TokenKind::Eof {
+            if let Some(statement) = self.parse_statement()? {
+                statements.push(statement);
+            }
+        }
+
+        Ok(ProgramNode {
+            r#type: NodeType::Program,
+            statements,
+        })
+    }
+
+    fn parse_statement(&mut self) -> Result<Option<StatementNode>, QasmError> {
+        let keyword = match &self.peek().kind {
+            TokenKind::Identifier(word) => word.clone(),
+            _ => return Err(self.error("Expected a statement")),
+        };
+
+        let statement = match keyword.as_str() {
+            "include" => {
+                self.advance();
+                let file = match self.advance().kind {
+                    TokenKind::Str(file) => file,
+                    _ => return Err(self.error_at_previous("Expected a file name")),
+                };
+                if file != "qelib1.inc" {
+                    return Err(self.error_at_previous(&format!(
+                        "Cannot include '{}': only qelib1.inc is available",
+                        file
+                    )));
+                }
+                self.expect_symbol(";")?;
+                self.standard_library = true;
+                return Ok(None);
+            }
+            "qreg" | "creg" => {
+                self.advance();
+                let identifier = self.expect_identifier()?;
+                self.expect_symbol("[")?;
+                let size = self.expect_integer()?;
+                self.expect_symbol("]")?;
+                self.expect_symbol(";")?;
+                if keyword == "qreg" {
+                    StatementNode::RegisterDeclaration { identifier, size }
+                } else {
+                    StatementNode::ClassicalDeclaration { identifier, size }
+                }
+            }
+            "gate" => self.parse_gate_definition()?,
+            "opaque" => {
+                return Err(self.error("Opaque gates have no definition and cannot be simulated"))
+            }
+            "if" => {
+                self.advance();
+                self.expect_symbol("(")?;
+                let identifier = self.expect_identifier()?;
+                self.expect_symbol("==")?;
+                let value = self.expect_integer()? as u64;
+                self.expect_symbol(")")?;
+                let statement = self.parse_quantum_operation()?;
+                StatementNode::IfStatement {
+                    identifier,
+                    value,
+                    statements: vec![statement],
+                }
+            }
+            _ => self.parse_quantum_operation()?,
+        };
+        Ok(Some(statement))
+    }
+
+    /// Gate calls, `measure`, `reset` and `barrier`: everything an `if`
+    /// may guard.
+    fn parse_quantum_operation(&mut self) -> Result<StatementNode, QasmError> {
+        let keyword = self.expect_identifier()?;
+        let statement = match keyword.as_str() {
+            "measure" => {
+                let source = self.parse_target()?;
+                self.expect_symbol("->")?;
+                let destination = self.parse_target()?;
+                StatementNode::MeasureAssignment {
+                    source,
+                    destination,
+                }
+            }
+            "reset" => StatementNode::ResetStatement {
+                target: self.parse_target()?,
+            },
+            "barrier" => StatementNode::BarrierStatement {
+                targets: self.parse_targets()?,
+            },
+            _ => self.parse_gate_call(keyword)?,
+        };
+        self.expect_symbol(";")?;
+        Ok(statement)
+    }
+
+    /// The call itself, without the closing `;`. The gate name has already
+    /// been consumed.
+    fn parse_gate_call(&mut self, name: String) -> Result<StatementNode, QasmError> {
+        let mut parameters = vec![];
+        if self.accept_symbol("(") && !self.accept_symbol(")") {
+            loop {
+                parameters.push(self.parse_expression()?);
+                if self.accept_symbol(")") {
+                    break;
+                }
+                self.expect_symbol(",")?;
+            }
+        }
+        let targets = self.parse_targets()?;
+
+        let gate = match self.resolve_gate(&name) {
+            Some(gate) => gate,
+            None if !self.standard_library && QELIB1_GATES.iter().any(|(n, _)| *n == name) => {
+                return Err(self.error_at_previous(&format!(
+                    "Unknown gate '{}'; did you forget include \"qelib1.inc\";?",
+                    name
+                )))
+            }
+            None => return Err(self.error_at_previous(&format!("Unknown gate '{}'", name))),
+        };
+
+        match (self.is_user_gate(&name), name.as_str()) {
+            (false, "u2") if self.standard_library => {
+                let half_pi = Expression::InfixExpression {
+                    op: "/".to_string(),
+                    left: Box::new(Expression::Identifier {
+                        value: "pi".to_string(),
+                    }),
+                    right: Box::new(Expression::RealNumber { value: 2.0 }),
+                };
+                parameters.insert(0, half_pi);
+            }
+            // `u0(gamma)` idles for gamma time steps, which is the identity here.
+            (false, "u0") if self.standard_library => parameters.clear(),
+            _ => {}
+        }
+
+        Ok(StatementNode::GateApplication {
+            gate,
+            parameters,
+            targets,
+        })
+    }
+
+    fn is_user_gate(&self, name: &str) -> bool {
+        self.user_gates.contains(name)
+    }
+
+    /// The simulator's name for a gate; user definitions shadow the library.
+    fn resolve_gate(&self, name: &str) -> Option<String> {
+        if self.is_user_gate(name) {
+            return Some(name.to_string());
+        }
+        match name {
+            "U" => return Some("u".to_string()),
+            "CX" => return Some("cnot".to_string()),
+            _ => {}
+        }
+        if !self.standard_library {
+            return None;
+        }
+        match name {
+            "u2" => Some("u".to_string()),
+            "u0" => Some("identity".to_string()),
+            _ => QELIB1_GATES
+                .iter()
+                .find(|(qasm, _)| *qasm == name)
+                .map(|(_, gate)| gate.to_string()),
+        }
+    }
+
+    fn parse_gate_definition(&mut self) -> Result<StatementNode, QasmError> {
+        self.expect_keyword("gate")?;
+        let identifier = self.expect_identifier()?;
+
+        let mut parameters = vec![];
+        if self.accept_symbol("(") && !self.accept_symbol(")") {
+            loop {
+                parameters.push(self.expect_identifier()?);
+                if self.accept_symbol(")") {
+                    break;
+                }
+                self.expect_symbol(",")?;
+            }
+        }
+
+        let mut qubits = vec![self.expect_identifier()?];
+        while self.accept_symbol(",") {
+            qubits.push(self.expect_identifier()?);
+        }
+
+        self.expect_symbol("{")?;
+        let mut statements = vec![];
+        while !self.accept_symbol("}") {
+            let name = self.expect_identifier()?;
+            let statement = if name == "barrier" {
+                StatementNode::BarrierStatement {
+                    targets: self.parse_targets()?,
+                }
+            } else {
+                self.parse_gate_call(name)?
+            };
+            self.expect_symbol(";")?;
+            statements.push(statement);
+        }
+
+        self.user_gates.insert(identifier.clone());
+        Ok(StatementNode::GateDefinition {
+            identifier,
+            parameters,
+            qubits,
+            statements,
+        })
+    }
+
+    fn parse_targets(&mut self) -> Result<Vec<Target>, QasmError> {
+        let mut targets = vec![self.parse_target()?];
+        while self.accept_symbol(",") {
+            targets.push(self.parse_target()?);
+        }
+        Ok(targets)
+    }
+
+    fn parse_target(&mut self) -> Result<Target, QasmError> {
+        let identifier = self.expect_identifier()?;
+        let mut index = None;
+        if self.accept_symbol("[") {
+            index = Some(self.expect_integer()?);
+            self.expect_symbol("]")?;
+        }
+        Ok(Target { identifier, index })
+    }
+
+    fn parse_expression(&mut self) -> Result<Expression, QasmError> {
+        let mut left = self.parse_term()?;
+        while let Some(op) = self.accept_any(&["+", "-"]) {
+            let right = self.parse_term()?;
+            left = infix(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_term(&mut self) -> Result<Expression, QasmError> {
+        let mut left = self.parse_unary()?;
+        while let Some(op) = self.accept_any(&["*", "/"]) {
+            let right = self.parse_unary()?;
+            left = infix(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, QasmError> {
+        if let Some(op) = self.accept_any(&["-", "+"]) {
+            let right = self.parse_unary()?;
+            return Ok(Expression::PrefixExpression {
+                op: op.to_string(),
+                right: Box::new(right),
+            });
+        }
+        self.parse_power()
+    }
+
+    fn parse_power(&mut self) -> Result<Expression, QasmError> {
+        let base = self.parse_primary()?;
+        if self.accept_symbol("^") {
+            let exponent = self.parse_unary()?;
+            return Ok(infix("^", base, exponent));
+        }
+        Ok(base)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, QasmError> {
+        let token = self.advance();
+        match token.kind {
+            TokenKind::Number(number) => number
+                .parse()
+                .map(|value| Expression::RealNumber { value })
+                .map_err(|_| self.error_at_previous(&format!("Invalid number {}", number))),
+            TokenKind::Identifier(name) if FUNCTIONS.contains(&name.as_str()) => {
+                self.expect_symbol("(")?;
+                let argument = self.parse_expression()?;
+                self.expect_symbol(")")?;
+                Ok(Expression::FunctionCall {
+                    function: name,
+                    argument: Box::new(argument),
+                })
+            }
+            TokenKind::Identifier(name) => Ok(Expression::Identifier { value: name }),
+            TokenKind::Symbol("(") => {
+                let expression = self.parse_expression()?;
+                self.expect_symbol(")")?;
+                Ok(expression)
+            }
+            _ => Err(self.error_at_previous("Expected an expression")),
+        }
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.position]
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.position].clone();
+        if token.kind != TokenKind::Eof {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn accept_symbol(&mut self, symbol: &str) -> bool {
+        self.accept_any(&[symbol]).is_some()
+    }
+
+    fn accept_any(&mut self, symbols: &[&str]) -> Option<&'static str> {
+        match self.peek().kind {
+            TokenKind::Symbol(found) if symbols.contains(&found) => {
+                self.advance();
+                Some(found)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), QasmError> {
+        if self.accept_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", symbol)))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), QasmError> {
+        match &self.peek().kind {
+            TokenKind::Identifier(word) if word == keyword => {
+                self.advance();
+                Ok(())
+            }
+            _ => Err(self.error(&format!("Expected '{}'", keyword))),
+        }
+    }
+
+    fn expect_identifier(&mut self) -> Result<String, QasmError> {
+        match self.peek().kind.clone() {
+            TokenKind::Identifier(word) => {
+                self.advance();
+                Ok(word)
+            }
+            _ => Err(self.error("Expected an identifier")),
+        }
+    }
+
+    fn expect_number(&mut self) -> Result<String, QasmError> {
+        match self.peek().kind.clone() {
+            TokenKind::Number(number) => {
+                self.advance();
+                Ok(number)
+            }
+            _ => Err(self.error("Expected a number")),
+        }
+    }
+
+    fn expect_integer(&mut self) -> Result<usize, QasmError> {
+        let number = self.expect_number()?;
+        number
+            .parse()
+            .map_err(|_| self.error_at_previous(&format!("Expected an integer, got {}", number)))
+    }
+
+    fn error(&self, message: &str) -> QasmError {
+        let token = self.peek();
+        QasmError::new(token.line, token.column, message)
+    }
+
+    fn error_at_previous(&self, message: &str) -> QasmError {
+        let token = &self.tokens[self.position.saturating_sub(1)];
+        QasmError::new(token.line, token.column, message)
+    }
+}
+
+fn infix(op: &str, left: Expression, right: Expression) -> Expression {
+    Expression::InfixExpression {
+        op: op.to_string(),
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
@@ -1,73 +1,369 @@
-use nalgebra::{Complex, DMatrix, DVector, Matrix2};
+use nalgebra::{Complex, DMatrix, DVector, Vector2};
 use rand::Rng;
+
+use crate::observable::PauliTerm;
+use crate::precision::{cast_complex, cast_matrix, Float};
 use crate::qubit::Measurement;
+use crate::simulator::Simulator;
 
-pub struct QuantumRegister {
-    state: DVector<Complex<f64>>
+/// State vector of `n` qubits stored as `2^n` complex amplitudes.
+///
+/// Qubit `i` corresponds to bit `i` of the basis state index, so qubit 0 is
+/// the least significant bit.
+#[derive(Clone)]
+pub struct QuantumRegister<T: Float = f64> {
+    state: DVector<Complex<T>>,
 }
 
-fn tensor_product(a: &DMatrix<Complex<f64>>, b: &DMatrix<Complex<f64>>) -> DMatrix<Complex<f64>>{
-    let mut result = DMatrix::zeros(a.nrows() * b.nrows(), a.ncols() * b.ncols());
+impl<T: Float> QuantumRegister<T> {
+    pub fn new(num_qubits: usize) -> Self {
+        let mut state = DVector::zeros(1 << num_qubits);
+        state[0] = Complex::new(T::one(), T::zero());
+        QuantumRegister { state }
+    }
 
-    for i in 0..a.nrows(){
-        for j in 0..a.ncols()  {
-            for k in 0..b.nrows() {
-                for l in 0..b.ncols(){
-                    result[(i * b.nrows() + k, j * b.ncols() + l)] = a[(i, j)] * b[(k, l)];
-                }
-            }
-        }
+    /// Wraps an existing state vector, which must have a power of two length.
+    pub fn from_state(state: DVector<Complex<T>>) -> Self {
+        assert!(
+            state.len().is_power_of_two(),
+            "state vector length must be a power of two"
+        );
+        QuantumRegister { state }
+    }
+
+    pub fn state(&self) -> &DVector<Complex<T>> {
+        &self.state
     }
-    result
 }
 
-impl QuantumRegister{
-    pub fn new(num_qubits: usize) -> Self{
-        let mut state = DVector::zeros(1 << num_qubits);
-        state[0] = Complex::new(1.0, 0.0);
-        QuantumRegister {state}
+impl<T: Float> Simulator for QuantumRegister<T> {
+    fn empty() -> Self {
+        Self::new(0)
+    }
+
+    fn num_qubits(&self) -> usize {
+        self.state.len().trailing_zeros() as usize
     }
 
-    pub fn apply_gate(&mut self, gate_matrix: &DMatrix<Complex<f64>>, targets: &[usize]){
-        let num_qubits = self.state.len().trailing_zeros() as usize;
-        let mut full_matrix = DMatrix::identity(1 << num_qubits, 1 << num_qubits);
+    /// Appends a qubit in the given (normalised) state as the new most
+    /// significant qubit and returns its index.
+    fn add_qubit(&mut self, amplitudes: Vector2<Complex<f64>>) -> Result<usize, String> {
+        let index = self.num_qubits();
+        let len = self.state.len();
+        let a0 = cast_complex::<T>(amplitudes[0]);
+        let a1 = cast_complex::<T>(amplitudes[1]);
+
+        let mut state = DVector::zeros(len * 2);
+        for i in 0..len {
+            state[i] = self.state[i] * a0;
+            state[i + len] = self.state[i] * a1;
+        }
+        self.state = state;
+        Ok(index)
+    }
+
+    /// Applies a `2^k x 2^k` gate matrix to `k` target qubits.
+    ///
+    /// `targets[0]` is the most significant qubit of the gate matrix, so a
+    /// `CNot` applied to `[control, target]` uses the textbook matrix.
+    fn apply_gate(&mut self, gate_matrix: &DMatrix<Complex<f64>>, targets: &[usize]) {
+        let k = targets.len();
+        assert_eq!(
+            gate_matrix.nrows(),
+            1 << k,
+            "gate matrix does not match the number of targets"
+        );
+
+        let matrix = cast_matrix::<T>(gate_matrix);
+        let dim = 1 << k;
+        let target_mask = targets.iter().fold(0, |mask, &t| mask | (1 << t));
+
+        // Offset of every sub-index of the gate within the full state index.
+        let offsets: Vec<usize> = (0..dim)
+            .map(|sub| {
+                targets
+                    .iter()
+                    .enumerate()
+                    .filter(|(bit, _)| (sub >> (k - 1 - bit)) & 1 == 1)
+                    .fold(0, |offset, (_, &t)| offset | (1 << t))
+            })
+            .collect();
+
+        let mut amplitudes = vec![Complex::new(T::zero(), T::zero()); dim];
+        for base in 0..self.state.len() {
+            if base & target_mask != 0 {
+                continue;
+            }
+
+            for (sub, amplitude) in amplitudes.iter_mut().enumerate() {
+                *amplitude = self.state[base | offsets[sub]];
+            }
 
-        for &target in targets {
-            let mut gate_on_target = DMatrix::identity(1, 1);
-            for i in 0..num_qubits {
-                if i == target {
-                    gate_on_target = tensor_product(&gate_on_target, gate_matrix);
-                } else {
-                    gate_on_target = tensor_product(&gate_on_target, &Matrix2::identity());
+            for (row, &offset) in offsets.iter().enumerate() {
+                let mut sum = Complex::new(T::zero(), T::zero());
+                for (col, &amplitude) in amplitudes.iter().enumerate() {
+                    sum += matrix[(row, col)] * amplitude;
                 }
+                self.state[base | offset] = sum;
             }
-            full_matrix = full_matrix * gate_on_target;
         }
+    }
 
-        self.state *= full_matrix;
+    fn probability_of_one(&self, qubit_index: usize) -> f64 {
+        self.state
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| (i >> qubit_index) & 1 == 1)
+            .map(|(_, x)| x.norm_sqr().to_double())
+            .sum()
     }
 
-    pub fn measure(&mut self, qubit_index: usize) -> Measurement {
+    /// Taken from the slice of the state vector with the most weight.
+    fn qubit_state(&self, qubit_index: usize) -> Vector2<Complex<f64>> {
+        let bit = 1 << qubit_index;
+        let base = (0..self.state.len())
+            .filter(|i| i & bit == 0)
+            .max_by(|&a, &b| {
+                let weight_a =
+                    (self.state[a].norm_sqr() + self.state[a | bit].norm_sqr()).to_double();
+                let weight_b =
+                    (self.state[b].norm_sqr() + self.state[b | bit].norm_sqr()).to_double();
+                weight_a.total_cmp(&weight_b)
+            })
+            .unwrap_or(0);
+
+        let to_double = |c: Complex<T>| Complex::new(c.re.to_double(), c.im.to_double());
+        Vector2::new(
+            to_double(self.state[base]),
+            to_double(self.state[base | bit]),
+        )
+        .normalize()
+    }
+
+    fn measure(&mut self, qubit_index: usize) -> Measurement {
         let mut rng = rand::thread_rng();
         let random_num = rng.gen_range(0.0_f64..1.0);
-        let prob_0 = self.state.iter().step_by(1 << qubit_index).map(|x| x.norm_sqr()).sum();
+        let prob_0 = 1.0 - self.probability_of_one(qubit_index);
+        let outcome = if random_num < prob_0 { 0 } else { 1 };
 
-        if random_num < prob_0 {
-            for i in 0..self.state.len() {
-                if (i >> qubit_index) & 1 == 1 {
-                    self.state[i] = Complex::new(0.0, 0.0);
-                }
+        for i in 0..self.state.len() {
+            if (i >> qubit_index) & 1 != outcome {
+                self.state[i] = Complex::new(T::zero(), T::zero());
             }
-            self.state.normalize_mut();
-            0
-        } else {
-            for i in 0..self.state.len() {
-                if (i >> qubit_index) & 1 == 0 {
-                    self.state[i] = Complex::new(0.0, 0.0);
-                }
+        }
+        self.state.normalize_mut();
+        outcome as Measurement
+    }
+
+    fn amplitudes(&self) -> Vec<(usize, Complex<f64>)> {
+        self.state
+            .iter()
+            .enumerate()
+            .filter(|(_, a)| a.norm_sqr() > T::zero())
+            .map(|(i, a)| (i, Complex::new(a.re.to_double(), a.im.to_double())))
+            .collect()
+    }
+
+    fn pauli_expectation(&self, term: &PauliTerm) -> f64 {
+        let to_f64 = |c: Complex<T>| Complex::new(c.re.to_double(), c.im.to_double());
+        term.expectation(
+            self.state.iter().enumerate().map(|(i, &a)| (i, to_f64(a))),
+            |i| to_f64(self.state[i]),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gate::{CNot, Gate, Hadamard, PauliX, PauliY, PauliZ};
+    use std::f64::consts::PI;
+
+    /// Largest per-amplitude deviation accepted between an `f32` and an
+    /// `f64` run of the same circuit. `f32` carries ~7 significant digits
+    /// and every gate adds rounding error of order `1e-7`, so circuits of a
+    /// few hundred gates stay well below this bound.
+    const F32_TOLERANCE: f64 = 1e-5;
+
+    type Op = (DMatrix<Complex<f64>>, Vec<usize>);
+
+    fn controlled_phase(theta: f64) -> DMatrix<Complex<f64>> {
+        let mut matrix = DMatrix::identity(4, 4);
+        matrix[(3, 3)] = Complex::from_polar(1.0, theta);
+        matrix
+    }
+
+    fn rotation_y(theta: f64) -> DMatrix<Complex<f64>> {
+        let (s, c) = (theta / 2.0).sin_cos();
+        DMatrix::from_row_slice(
+            2,
+            2,
+            &[
+                Complex::new(c, 0.0),
+                Complex::new(-s, 0.0),
+                Complex::new(s, 0.0),
+                Complex::new(c, 0.0),
+            ],
+        )
+    }
+
+    fn ghz(n: usize) -> Vec<Op> {
+        let mut ops = vec![(Hadamard::new().matrix_representation(), vec![0])];
+        for i in 1..n {
+            ops.push((CNot::new().matrix_representation(), vec![i - 1, i]));
+        }
+        ops
+    }
+
+    fn qft(n: usize) -> Vec<Op> {
+        let mut ops = vec![];
+        // Non-trivial input state so the transform does not act on |0...0>.
+        for i in (0..n).step_by(2) {
+            ops.push((PauliX::new().matrix_representation(), vec![i]));
+        }
+        for i in (0..n).rev() {
+            ops.push((Hadamard::new().matrix_representation(), vec![i]));
+            for j in (0..i).rev() {
+                let theta = PI / (1 << (i - j)) as f64;
+                ops.push((controlled_phase(theta), vec![j, i]));
             }
-            self.state.normalize_mut();
-            1
         }
+        ops
     }
-}
\ No newline at end of file
+
+    fn layered(n: usize, depth: usize) -> Vec<Op> {
+        let mut ops = vec![];
+        for layer in 0..depth {
+            for i in 0..n {
+                let theta = 0.37 * (layer * n + i + 1) as f64;
+                ops.push((rotation_y(theta), vec![i]));
+                let gate = match (layer + i) % 3 {
+                    0 => PauliY::new().matrix_representation(),
+                    1 => PauliZ::new().matrix_representation(),
+                    _ => Hadamard::new().matrix_representation(),
+                };
+                ops.push((gate, vec![i]));
+            }
+            for i in (layer % 2..n - 1).step_by(2) {
+                ops.push((CNot::new().matrix_representation(), vec![i, i + 1]));
+            }
+        }
+        ops
+    }
+
+    fn run<T: Float>(n: usize, ops: &[Op]) -> QuantumRegister<T> {
+        let mut register = QuantumRegister::<T>::new(n);
+        for (matrix, targets) in ops {
+            register.apply_gate(matrix, targets);
+        }
+        register
+    }
+
+    fn max_deviation(single: &QuantumRegister<f32>, double: &QuantumRegister<f64>) -> f64 {
+        single
+            .state()
+            .iter()
+            .zip(double.state().iter())
+            .map(|(a, b)| (Complex::new(a.re as f64, a.im as f64) - b).norm())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn test_register_initial_state() {
+        let register = QuantumRegister::<f64>::new(3);
+        assert_eq!(register.num_qubits(), 3);
+        assert_eq!(register.state()[0], Complex::new(1.0, 0.0));
+        assert!(register.state().iter().skip(1).all(|x| x.norm() == 0.0));
+    }
+
+    #[test]
+    fn test_apply_gate_targets_single_qubit() {
+        let mut register = QuantumRegister::<f64>::new(3);
+        register.apply_gate(&PauliX::new().matrix_representation(), &[1]);
+        assert_eq!(register.state()[0b010], Complex::new(1.0, 0.0));
+        assert_eq!(register.probability_of_one(1), 1.0);
+        assert_eq!(register.probability_of_one(0), 0.0);
+    }
+
+    #[test]
+    fn test_cnot_control_is_first_target() {
+        let mut register = QuantumRegister::<f64>::new(2);
+        register.apply_gate(&PauliX::new().matrix_representation(), &[1]);
+        register.apply_gate(&CNot::new().matrix_representation(), &[1, 0]);
+        assert_eq!(register.state()[0b11], Complex::new(1.0, 0.0));
+
+        let mut register = QuantumRegister::<f64>::new(2);
+        register.apply_gate(&PauliX::new().matrix_representation(), &[1]);
+        register.apply_gate(&CNot::new().matrix_representation(), &[0, 1]);
+        assert_eq!(register.state()[0b10], Complex::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn test_add_qubit_tensor_product() {
+        let mut register = QuantumRegister::<f64>::new(0);
+        register
+            .add_qubit(Vector2::new(Complex::new(0.0, 0.0), Complex::new(1.0, 0.0)))
+            .unwrap();
+        let index = register
+            .add_qubit(Vector2::new(Complex::new(1.0, 0.0), Complex::new(0.0, 0.0)))
+            .unwrap();
+
+        assert_eq!(index, 1);
+        assert_eq!(register.num_qubits(), 2);
+        assert_eq!(register.state()[0b01], Complex::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn test_measure_entangled_qubits_agree() {
+        for _ in 0..100 {
+            let mut register = run::<f64>(3, &ghz(3));
+            let first = register.measure(0);
+            assert_eq!(register.measure(1), first);
+            assert_eq!(register.measure(2), first);
+        }
+    }
+
+    #[test]
+    fn test_qubit_state_of_product_state() {
+        let mut register = QuantumRegister::<f64>::new(2);
+        register.apply_gate(&PauliX::new().matrix_representation(), &[0]);
+        register.apply_gate(&Hadamard::new().matrix_representation(), &[1]);
+
+        let state = register.qubit_state(0);
+        assert!((state[0].norm() - 0.0).abs() < 1e-12);
+        assert!((state[1].norm() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_f32_matches_f64_ghz() {
+        let ops = ghz(12);
+        let deviation = max_deviation(&run::<f32>(12, &ops), &run::<f64>(12, &ops));
+        assert!(deviation < F32_TOLERANCE, "deviation {}", deviation);
+    }
+
+    #[test]
+    fn test_f32_matches_f64_qft() {
+        let ops = qft(8);
+        let deviation = max_deviation(&run::<f32>(8, &ops), &run::<f64>(8, &ops));
+        assert!(deviation < F32_TOLERANCE, "deviation {}", deviation);
+    }
+
+    #[test]
+    fn test_f32_matches_f64_layered_circuit() {
+        let ops = layered(10, 20);
+        let deviation = max_deviation(&run::<f32>(10, &ops), &run::<f64>(10, &ops));
+        assert!(deviation < F32_TOLERANCE, "deviation {}", deviation);
+    }
+
+    #[test]
+    fn test_f32_measurement_probabilities_match_f64() {
+        let ops = layered(6, 8);
+        let single = run::<f32>(6, &ops);
+        let double = run::<f64>(6, &ops);
+        for qubit in 0..6 {
+            let difference =
+                (single.probability_of_one(qubit) - double.probability_of_one(qubit)).abs();
+            assert!(difference < F32_TOLERANCE);
+        }
+    }
+}
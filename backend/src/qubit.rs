@@ -1,4 +1,4 @@
-use nalgebra::{Complex, ComplexField, Vector2};
+use nalgebra::{Complex, ComplexField, Matrix2, Vector2};
 use rand::prelude::*;
 use std::fmt::{Debug, Formatter};
 
@@ -42,7 +42,11 @@ impl Qubit {
     }
 
     pub fn apply_gate(&mut self, gate: &dyn Gate) {
-        self.state = gate.matrix_representation() * self.state;
+        let matrix: Matrix2<Complex<f64>> = gate
+            .matrix_representation()
+            .fixed_view::<2, 2>(0, 0)
+            .into_owned();
+        self.state = matrix * self.state;
     }
 
     pub fn measure(&mut self) -> Measurement {
@@ -60,6 +64,12 @@ impl Qubit {
     }
 }
 
+impl Default for Qubit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Debug for Qubit {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let number1 = if self.state.x.imaginary() >= 0.0 {
@@ -1,9 +1,90 @@
-use axum::{routing::get, routing::post, Router};
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware::from_fn_with_state,
+    routing::{delete, get, post, put},
+    Router,
+};
 
-use crate::handler::{simulation_handler, up};
+use crate::{
+    auth::resolve_user,
+    handler::{
+        breakpoints_handler, circuit_handler, complete_level_handler, create_file_handler,
+        create_session_handler, create_share_handler, decompose_handler, delete_file_handler,
+        delete_session_handler, delete_user_handler, diagram_handler, equivalence_handler,
+        file_handler, files_handler, latex_handler, latex_query_handler, level_handler,
+        level_submit_handler, levels_handler, login_handler, logout_handler, me_handler,
+        metrics_handler, optimize_handler, progress_handler, prometheus_handler,
+        qasm_export_handler, qasm_handler, register_handler, remove_progress_handler,
+        rewind_session_handler, route_handler, run_session_handler, session_handler, share_handler,
+        simulation_handler, step_session_handler, stream_handler, svg_handler, svg_query_handler,
+        unitary_handler, up, update_file_handler, user_handler, users_handler, AppState,
+    },
+    logging::{request_id_layers, trace_layer},
+    telemetry::count_requests,
+};
 
-pub fn create_router() -> Router {
+pub fn create_router(state: AppState) -> Router {
+    let (set_request_id, propagate_request_id) = request_id_layers();
     Router::new()
         .route("/api/", post(simulation_handler))
         .route("/api/", get(up))
+        .route("/api/unitary", post(unitary_handler))
+        .route("/api/circuit", post(circuit_handler))
+        .route("/api/diagram", post(diagram_handler))
+        .route("/api/equivalence", post(equivalence_handler))
+        .route("/api/levels", get(levels_handler))
+        .route("/api/levels/:id", get(level_handler))
+        .route("/api/levels/:id/submit", post(level_submit_handler))
+        .route("/api/metrics", post(metrics_handler))
+        .route("/api/optimize", post(optimize_handler))
+        .route("/api/decompose", post(decompose_handler))
+        .route("/api/route", post(route_handler))
+        .route("/api/qasm", post(qasm_handler))
+        .route("/api/export/qasm", post(qasm_export_handler))
+        .route("/api/export/svg", post(svg_handler).get(svg_query_handler))
+        .route(
+            "/api/export/latex",
+            post(latex_handler).get(latex_query_handler),
+        )
+        .route("/api/auth/register", post(register_handler))
+        .route("/api/auth/login", post(login_handler))
+        .route("/api/auth/logout", post(logout_handler))
+        .route("/api/auth/me", get(me_handler))
+        .route("/api/users", get(users_handler))
+        .route(
+            "/api/users/:name",
+            get(user_handler).delete(delete_user_handler),
+        )
+        .route("/api/files", get(files_handler).post(create_file_handler))
+        .route(
+            "/api/files/:id",
+            get(file_handler)
+                .put(update_file_handler)
+                .delete(delete_file_handler),
+        )
+        .route(
+            "/api/progress",
+            get(progress_handler).post(complete_level_handler),
+        )
+        .route("/api/progress/:level", delete(remove_progress_handler))
+        .route("/api/stream", get(stream_handler))
+        .route("/api/sessions", post(create_session_handler))
+        .route(
+            "/api/sessions/:id",
+            get(session_handler).delete(delete_session_handler),
+        )
+        .route("/api/sessions/:id/step", post(step_session_handler))
+        .route("/api/sessions/:id/run", post(run_session_handler))
+        .route("/api/sessions/:id/rewind", post(rewind_session_handler))
+        .route("/api/sessions/:id/breakpoints", put(breakpoints_handler))
+        .route("/api/share", post(create_share_handler))
+        .route("/api/share/:id", get(share_handler))
+        .route("/metrics", get(prometheus_handler))
+        .layer(from_fn_with_state(state.clone(), resolve_user))
+        .layer(from_fn_with_state(state.clone(), count_requests))
+        .layer(DefaultBodyLimit::max(state.limits.max_body_bytes))
+        .layer(propagate_request_id)
+        .layer(trace_layer())
+        .layer(set_request_id)
+        .with_state(state)
 }
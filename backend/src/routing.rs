@@ -0,0 +1,764 @@
+//! Routing circuits onto the coupling graph of a device.
+//!
+//! Real devices only apply two-qubit gates between connected qubits. Routing
+//! places every logical qubit on a physical one and inserts SWAPs whenever a
+//! gate's qubits are not neighbours, following the SABRE heuristic: among
+//! the swaps touching the gates that are ready to run, pick the one that
+//! brings those gates, and the next few after them, closest together. The
+//! initial layout comes from routing the circuit forwards and backwards a
+//! few times, starting each pass from where the previous one ended.
+
+use std::{collections::VecDeque, str::FromStr, sync::atomic::AtomicBool};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    circuit::{Circuit, Instruction, Operation, QubitInfo},
+    limits::check_cancelled,
+    optimize::count_gates,
+};
+
+/// Forward and backward passes used to improve the initial layout.
+const LAYOUT_ITERATIONS: usize = 3;
+/// Two-qubit gates beyond the front layer that the heuristic looks at.
+const EXTENDED_SET_SIZE: usize = 20;
+const EXTENDED_SET_WEIGHT: f64 = 0.5;
+/// Penalty added to recently swapped qubits, so that swaps spread out.
+const DECAY_DELTA: f64 = 0.001;
+const DECAY_RESET: usize = 5;
+
+/// A device topology, written in JSON as e.g. `{"kind": "grid", "rows": 2,
+/// "columns": 3}` or `{"kind": "custom", "edges": [[0, 1], [1, 2]]}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Topology {
+    /// Qubits in a line, `0 - 1 - ... - n-1`.
+    Linear { qubits: usize },
+    /// A line whose ends are connected.
+    Ring { qubits: usize },
+    /// Qubit `r * columns + c` is connected to its four neighbours.
+    Grid { rows: usize, columns: usize },
+    /// `rows + 1` chains of `4 * columns + 1` qubits, neighbouring chains
+    /// joined by bridge qubits every four positions, at alternating offsets.
+    /// `rows = columns = 1` is a single heavy hexagon of 12 qubits.
+    HeavyHex { rows: usize, columns: usize },
+    /// Undirected edges between qubits `0..=max index`.
+    Custom { edges: Vec<[usize; 2]> },
+}
+
+impl FromStr for Topology {
+    type Err = String;
+
+    /// Parses `linear:5`, `ring:5`, `grid:2x3` or `heavy-hex:1x2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Unknown coupling map '{}': expected linear:N, ring:N, grid:RxC or heavy-hex:RxC",
+                s
+            )
+        };
+        let (kind, size) = s.split_once(':').ok_or_else(invalid)?;
+        let number = |text: &str| text.trim().parse::<usize>().map_err(|_| invalid());
+        let dimensions = || -> Result<(usize, usize), String> {
+            let (rows, columns) = size.split_once('x').ok_or_else(invalid)?;
+            Ok((number(rows)?, number(columns)?))
+        };
+        match kind.trim().to_lowercase().as_str() {
+            "linear" | "line" => Ok(Topology::Linear {
+                qubits: number(size)?,
+            }),
+            "ring" => Ok(Topology::Ring {
+                qubits: number(size)?,
+            }),
+            "grid" => {
+                let (rows, columns) = dimensions()?;
+                Ok(Topology::Grid { rows, columns })
+            }
+            "heavy-hex" | "heavyhex" => {
+                let (rows, columns) = dimensions()?;
+                Ok(Topology::HeavyHex { rows, columns })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Topology {
+    pub fn coupling_map(&self) -> Result<CouplingMap, String> {
+        let mut edges = vec![];
+        let num_qubits = match *self {
+            Topology::Linear { qubits } => {
+                edges.extend((1..qubits).map(|q| (q - 1, q)));
+                qubits
+            }
+            Topology::Ring { qubits } => {
+                edges.extend((1..qubits).map(|q| (q - 1, q)));
+                if qubits > 2 {
+                    edges.push((qubits - 1, 0));
+                }
+                qubits
+            }
+            Topology::Grid { rows, columns } => {
+                for r in 0..rows {
+                    for c in 0..columns {
+                        let q = r * columns + c;
+                        if c + 1 < columns {
+                            edges.push((q, q + 1));
+                        }
+                        if r + 1 < rows {
+                            edges.push((q, q + columns));
+                        }
+                    }
+                }
+                rows * columns
+            }
+            Topology::HeavyHex { rows, columns } => {
+                let length = 4 * columns + 1;
+                let chains = if columns == 0 { 0 } else { rows + 1 };
+                for chain in 0..chains {
+                    let start = chain * length;
+                    edges.extend((start + 1..start + length).map(|q| (q - 1, q)));
+                }
+                let mut bridge = chains * length;
+                for chain in 0..chains.saturating_sub(1) {
+                    let offset = if chain % 2 == 0 { 0 } else { 2 };
+                    for c in (offset..length).step_by(4) {
+                        edges.push((chain * length + c, bridge));
+                        edges.push((bridge, (chain + 1) * length + c));
+                        bridge += 1;
+                    }
+                }
+                bridge
+            }
+            Topology::Custom { edges: ref custom } => {
+                edges.extend(custom.iter().map(|[a, b]| (*a, *b)));
+                edges.iter().map(|&(a, b)| a.max(b) + 1).max().unwrap_or(0)
+            }
+        };
+        CouplingMap::new(num_qubits, &edges)
+    }
+}
+
+/// Undirected connectivity of a device with the distances between its
+/// qubits.
+#[derive(Debug, Clone)]
+pub struct CouplingMap {
+    neighbours: Vec<Vec<usize>>,
+    distances: Vec<Vec<usize>>,
+}
+
+impl CouplingMap {
+    pub fn new(num_qubits: usize, edges: &[(usize, usize)]) -> Result<Self, String> {
+        if num_qubits == 0 {
+            return Err("Coupling map has no qubits".to_string());
+        }
+        let mut neighbours = vec![vec![]; num_qubits];
+        for &(a, b) in edges {
+            if a == b || a >= num_qubits || b >= num_qubits {
+                return Err(format!("Invalid coupling edge [{}, {}]", a, b));
+            }
+            if !neighbours[a].contains(&b) {
+                neighbours[a].push(b);
+                neighbours[b].push(a);
+            }
+        }
+        for list in &mut neighbours {
+            list.sort_unstable();
+        }
+
+        let distances: Vec<Vec<usize>> = (0..num_qubits)
+            .map(|source| breadth_first_distances(&neighbours, source))
+            .collect();
+        if distances[0].contains(&usize::MAX) {
+            return Err("Coupling map is not connected".to_string());
+        }
+        Ok(Self {
+            neighbours,
+            distances,
+        })
+    }
+
+    pub fn num_qubits(&self) -> usize {
+        self.neighbours.len()
+    }
+
+    pub fn connected(&self, a: usize, b: usize) -> bool {
+        self.neighbours[a].contains(&b)
+    }
+
+    pub fn distance(&self, a: usize, b: usize) -> usize {
+        self.distances[a][b]
+    }
+
+    /// Each edge once, as `(lower, higher)`.
+    pub fn edges(&self) -> Vec<(usize, usize)> {
+        self.neighbours
+            .iter()
+            .enumerate()
+            .flat_map(|(a, list)| list.iter().filter(move |&&b| a < b).map(move |&b| (a, b)))
+            .collect()
+    }
+}
+
+fn breadth_first_distances(neighbours: &[Vec<usize>], source: usize) -> Vec<usize> {
+    let mut distances = vec![usize::MAX; neighbours.len()];
+    distances[source] = 0;
+    let mut queue = VecDeque::from([source]);
+    while let Some(qubit) = queue.pop_front() {
+        for &next in &neighbours[qubit] {
+            if distances[next] == usize::MAX {
+                distances[next] = distances[qubit] + 1;
+                queue.push_back(next);
+            }
+        }
+    }
+    distances
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingResult {
+    /// The circuit on the device's qubits, labelled `p[i]`.
+    pub circuit: Circuit,
+    /// SWAP gates inserted, each costing three CNOTs on hardware.
+    pub swaps: usize,
+    /// Physical qubit of each logical qubit, in register order, before the
+    /// first instruction.
+    pub initial_layout: Vec<usize>,
+    /// Physical qubit of each logical qubit after the last instruction.
+    pub final_layout: Vec<usize>,
+    pub gates_before: usize,
+    pub gates_after: usize,
+}
+
+pub fn route_circuit(circuit: &Circuit, coupling: &CouplingMap) -> Result<RoutingResult, String> {
+    route_circuit_cancellable(circuit, coupling, &AtomicBool::new(false))
+}
+
+/// Routes a circuit unless `cancelled` is set first, which is checked
+/// between the steps of each pass.
+pub fn route_circuit_cancellable(
+    circuit: &Circuit,
+    coupling: &CouplingMap,
+    cancelled: &AtomicBool,
+) -> Result<RoutingResult, String> {
+    let num_logical = circuit.qubits.len();
+    let num_physical = coupling.num_qubits();
+    if num_logical > num_physical {
+        return Err(format!(
+            "The circuit uses {} qubits but the device only has {}",
+            num_logical, num_physical
+        ));
+    }
+    let nodes = dependency_graph(circuit)?;
+
+    // Layouts map virtual qubits to physical ones; the virtual qubits past
+    // the circuit's own are the idle physical qubits.
+    let trivial: Vec<usize> = (0..num_physical).collect();
+    let mut candidates = vec![trivial.clone()];
+    let reversed = reverse(&nodes);
+    let mut layout = trivial;
+    for _ in 0..LAYOUT_ITERATIONS {
+        layout = sabre(&nodes, coupling, layout, cancelled).layout;
+        layout = sabre(&reversed, coupling, layout, cancelled).layout;
+        candidates.push(layout.clone());
+    }
+    let (initial, pass) = candidates
+        .into_iter()
+        .map(|layout| (layout.clone(), sabre(&nodes, coupling, layout, cancelled)))
+        .min_by_key(|(_, pass)| pass.swaps)
+        .unwrap();
+    // A cancelled pass stops part-way through the circuit.
+    check_cancelled(cancelled)?;
+
+    let mut layout = initial.clone();
+    let mut instructions = vec![];
+    for step in &pass.steps {
+        match *step {
+            Step::Run(index) => {
+                instructions.push(place(&circuit.instructions[index], &layout));
+            }
+            Step::Swap(a, b) => {
+                instructions.push(Instruction::gate("swap", vec![], vec![a, b]));
+                swap_physical(&mut layout, a, b);
+            }
+        }
+    }
+
+    let mut qubits: Vec<QubitInfo> = (0..num_physical)
+        .map(|p| QubitInfo::zero(format!("p[{}]", p)))
+        .collect();
+    for (logical, info) in circuit.qubits.iter().enumerate() {
+        qubits[initial[logical]].initial = info.initial;
+    }
+    let routed = Circuit {
+        qubits,
+        classical: circuit.classical.clone(),
+        instructions,
+    };
+    Ok(RoutingResult {
+        swaps: pass.swaps,
+        initial_layout: initial[..num_logical].to_vec(),
+        final_layout: layout[..num_logical].to_vec(),
+        gates_before: count_gates(circuit),
+        gates_after: count_gates(&routed),
+        circuit: routed,
+    })
+}
+
+/// An instruction in the dependency graph.
+#[derive(Debug, Clone)]
+struct Node {
+    instruction: usize,
+    /// Logical qubits of a two-qubit gate, which must be neighbours to run.
+    pair: Option<(usize, usize)>,
+    successors: Vec<usize>,
+    predecessors: usize,
+}
+
+/// Orders instructions that share a qubit or classical bit. Instructions
+/// that print anything also share one output wire, and instructions that
+/// inspect the state touch every wire, so the output is unchanged.
+fn dependency_graph(circuit: &Circuit) -> Result<Vec<Node>, String> {
+    let num_qubits = circuit.qubits.len();
+    let num_clbits = circuit.num_clbits();
+    let output = num_qubits + num_clbits;
+    let mut last: Vec<Option<usize>> = vec![None; output + 1];
+    let mut nodes: Vec<Node> = vec![];
+
+    for (index, instruction) in circuit.instructions.iter().enumerate() {
+        let mut wires: Vec<usize> = match &instruction.operation {
+            Operation::Gate { name, .. } => {
+                if instruction.qubits.len() > 2 {
+                    return Err(format!(
+                        "Gate '{}' acts on {} qubits; decompose the circuit before routing it",
+                        name,
+                        instruction.qubits.len()
+                    ));
+                }
+                instruction.qubits.clone()
+            }
+            Operation::Measure { echo } => {
+                let mut wires = instruction.qubits.clone();
+                wires.extend(instruction.clbits.iter().map(|bit| num_qubits + bit));
+                if *echo {
+                    wires.push(output);
+                }
+                wires
+            }
+            Operation::Reset | Operation::Barrier => instruction.qubits.clone(),
+            Operation::Display { .. }
+            | Operation::Expect { .. }
+            | Operation::Print { .. }
+            | Operation::Error { .. } => (0..=output).collect(),
+        };
+        if let Some(range) = instruction
+            .condition
+            .as_ref()
+            .and_then(|condition| circuit.classical_range(&condition.register))
+        {
+            wires.extend(range.map(|bit| num_qubits + bit));
+        }
+        wires.sort_unstable();
+        wires.dedup();
+
+        let pair = match (&instruction.operation, instruction.qubits.as_slice()) {
+            (Operation::Gate { .. }, &[a, b]) => Some((a, b)),
+            _ => None,
+        };
+        let mut node = Node {
+            instruction: index,
+            pair,
+            successors: vec![],
+            predecessors: 0,
+        };
+        let mut predecessors: Vec<usize> = wires.iter().filter_map(|&w| last[w]).collect();
+        predecessors.sort_unstable();
+        predecessors.dedup();
+        let position = nodes.len();
+        for &predecessor in &predecessors {
+            nodes[predecessor].successors.push(position);
+        }
+        node.predecessors = predecessors.len();
+        for &wire in &wires {
+            last[wire] = Some(position);
+        }
+        nodes.push(node);
+    }
+    Ok(nodes)
+}
+
+/// The same graph with every dependency pointing the other way.
+fn reverse(nodes: &[Node]) -> Vec<Node> {
+    let count = nodes.len();
+    let mut reversed: Vec<Node> = nodes
+        .iter()
+        .rev()
+        .map(|node| Node {
+            successors: vec![],
+            predecessors: node.successors.len(),
+            ..node.clone()
+        })
+        .collect();
+    for (index, node) in nodes.iter().enumerate() {
+        for &successor in &node.successors {
+            reversed[count - 1 - successor]
+                .successors
+                .push(count - 1 - index);
+        }
+    }
+    reversed
+}
+
+enum Step {
+    Run(usize),
+    Swap(usize, usize),
+}
+
+struct Pass {
+    steps: Vec<Step>,
+    swaps: usize,
+    /// Layout after the last instruction.
+    layout: Vec<usize>,
+}
+
+/// Runs the instructions in dependency order, swapping qubits whenever no
+/// ready gate has its qubits next to each other.
+fn sabre(
+    nodes: &[Node],
+    coupling: &CouplingMap,
+    mut layout: Vec<usize>,
+    cancelled: &AtomicBool,
+) -> Pass {
+    let mut predecessors: Vec<usize> = nodes.iter().map(|node| node.predecessors).collect();
+    let mut front: Vec<usize> = (0..nodes.len()).filter(|&i| predecessors[i] == 0).collect();
+    let mut steps = vec![];
+    let mut swaps = 0;
+    let mut decay = vec![1.0; coupling.num_qubits()];
+    let mut swaps_since_progress = 0;
+
+    while check_cancelled(cancelled).is_ok() {
+        let mut progressed = false;
+        let mut i = 0;
+        while i < front.len() {
+            let node = &nodes[front[i]];
+            let ready = node
+                .pair
+                .is_none_or(|(a, b)| coupling.connected(layout[a], layout[b]));
+            if !ready {
+                i += 1;
+                continue;
+            }
+            steps.push(Step::Run(node.instruction));
+            front.remove(i);
+            for &successor in &node.successors {
+                predecessors[successor] -= 1;
+                if predecessors[successor] == 0 {
+                    front.push(successor);
+                }
+            }
+            progressed = true;
+        }
+        if front.is_empty() {
+            break;
+        }
+        if progressed {
+            decay.fill(1.0);
+            swaps_since_progress = 0;
+        }
+
+        let (a, b) = if swaps_since_progress > coupling.num_qubits() {
+            // The heuristic is going round in circles: move the first
+            // blocked gate's qubits together along a shortest path.
+            let (first, second) = nodes[front[0]].pair.unwrap();
+            let (from, to) = (layout[first], layout[second]);
+            let step = coupling.neighbours[from]
+                .iter()
+                .copied()
+                .find(|&next| coupling.distance(next, to) < coupling.distance(from, to))
+                .unwrap();
+            (from, step)
+        } else {
+            best_swap(nodes, &front, coupling, &layout, &decay)
+        };
+        steps.push(Step::Swap(a, b));
+        swap_physical(&mut layout, a, b);
+        swaps += 1;
+        swaps_since_progress += 1;
+        decay[a] += DECAY_DELTA;
+        decay[b] += DECAY_DELTA;
+        if swaps % DECAY_RESET == 0 {
+            decay.fill(1.0);
+        }
+    }
+    Pass {
+        steps,
+        swaps,
+        layout,
+    }
+}
+
+/// The swap next to a blocked gate with the lowest SABRE score.
+fn best_swap(
+    nodes: &[Node],
+    front: &[usize],
+    coupling: &CouplingMap,
+    layout: &[usize],
+    decay: &[f64],
+) -> (usize, usize) {
+    let front_pairs: Vec<(usize, usize)> = front.iter().filter_map(|&i| nodes[i].pair).collect();
+    let extended = extended_set(nodes, front);
+
+    let mut candidates = vec![];
+    for &(a, b) in &front_pairs {
+        for physical in [layout[a], layout[b]] {
+            for &neighbour in &coupling.neighbours[physical] {
+                candidates.push((physical.min(neighbour), physical.max(neighbour)));
+            }
+        }
+    }
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let mean_distance = |pairs: &[(usize, usize)], layout: &[usize]| {
+        if pairs.is_empty() {
+            return 0.0;
+        }
+        let total: usize = pairs
+            .iter()
+            .map(|&(a, b)| coupling.distance(layout[a], layout[b]))
+            .sum();
+        total as f64 / pairs.len() as f64
+    };
+    let mut trial = layout.to_vec();
+    candidates
+        .into_iter()
+        .map(|(a, b)| {
+            swap_physical(&mut trial, a, b);
+            let score = decay[a].max(decay[b])
+                * (mean_distance(&front_pairs, &trial)
+                    + EXTENDED_SET_WEIGHT * mean_distance(&extended, &trial));
+            swap_physical(&mut trial, a, b);
+            ((a, b), score)
+        })
+        .min_by(|x, y| x.1.total_cmp(&y.1))
+        .map(|(swap, _)| swap)
+        .unwrap()
+}
+
+/// Qubit pairs of the first two-qubit gates after the front layer.
+fn extended_set(nodes: &[Node], front: &[usize]) -> Vec<(usize, usize)> {
+    let mut pairs = vec![];
+    let mut visited = vec![false; nodes.len()];
+    let mut queue: VecDeque<usize> = front.iter().copied().collect();
+    while let Some(index) = queue.pop_front() {
+        for &successor in &nodes[index].successors {
+            if visited[successor] {
+                continue;
+            }
+            visited[successor] = true;
+            if let Some(pair) = nodes[successor].pair {
+                pairs.push(pair);
+                if pairs.len() == EXTENDED_SET_SIZE {
+                    return pairs;
+                }
+            }
+            queue.push_back(successor);
+        }
+    }
+    pairs
+}
+
+/// Exchanges the virtual qubits held by two physical qubits.
+fn swap_physical(layout: &mut [usize], a: usize, b: usize) {
+    for physical in layout.iter_mut() {
+        if *physical == a {
+            *physical = b;
+        } else if *physical == b {
+            *physical = a;
+        }
+    }
+}
+
+/// An instruction with its qubits moved to their physical positions.
+fn place(instruction: &Instruction, layout: &[usize]) -> Instruction {
+    let mut placed = instruction.clone();
+    for qubit in &mut placed.qubits {
+        *qubit = layout[*qubit];
+    }
+    if let Operation::Expect { observable, .. } = &mut placed.operation {
+        for term in &mut observable.terms {
+            for (qubit, _) in &mut term.operators {
+                if let Some(&physical) = layout.get(*qubit) {
+                    *qubit = physical;
+                }
+            }
+        }
+    }
+    placed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gate::Gate, interpreter::simulate_program, lower::lower_program, models::ProgramNode,
+        qasm::parse_qasm, unitary,
+    };
+    use nalgebra::DMatrix;
+
+    fn lower_qasm(source: &str) -> Circuit {
+        let program = parse_qasm(&format!(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\n{}",
+            source
+        ))
+        .unwrap();
+        lower_program(&program)
+    }
+
+    fn coupling(spec: &str) -> CouplingMap {
+        spec.parse::<Topology>().unwrap().coupling_map().unwrap()
+    }
+
+    /// Routes a measurement-free circuit and checks that every two-qubit
+    /// gate is on an edge and that the unitary matches once the layouts
+    /// are accounted for, when the device is small enough to build it.
+    fn assert_routes(source: &str, coupling: &CouplingMap) -> RoutingResult {
+        let circuit = lower_qasm(source);
+        let result = route_circuit(&circuit, coupling).unwrap();
+        for instruction in &result.circuit.instructions {
+            if let [a, b] = instruction.qubits[..] {
+                assert!(coupling.connected(a, b), "{:?}", instruction);
+            }
+        }
+        assert_eq!(
+            result.gates_after,
+            result.gates_before + result.swaps,
+            "{}",
+            source
+        );
+        if coupling.num_qubits() > 10 {
+            return result;
+        }
+
+        let expected = unitary::circuit_unitary(&circuit)
+            .unwrap()
+            .1
+            .matrix_representation();
+        let routed = unitary::circuit_unitary(&result.circuit)
+            .unwrap()
+            .1
+            .matrix_representation();
+        let (n, m) = (circuit.qubits.len(), coupling.num_qubits());
+        // Basis index of the device for a logical basis index, both with
+        // the first qubit as the most significant bit.
+        let physical = |index: usize, layout: &[usize]| {
+            (0..n)
+                .filter(|logical| index >> (n - 1 - logical) & 1 == 1)
+                .fold(0, |acc, logical| acc | 1 << (m - 1 - layout[logical]))
+        };
+        let actual = DMatrix::from_fn(1 << n, 1 << n, |row, col| {
+            routed[(
+                physical(row, &result.final_layout),
+                physical(col, &result.initial_layout),
+            )]
+        });
+        assert!(
+            unitary::equal_up_to_phase(&expected, &actual, 1e-9),
+            "{}",
+            source
+        );
+        result
+    }
+
+    #[test]
+    fn test_topologies() {
+        let edges = |spec: &str| coupling(spec).edges().len();
+        assert_eq!(coupling("linear:5").num_qubits(), 5);
+        assert_eq!(edges("linear:5"), 4);
+        assert_eq!(edges("ring:5"), 5);
+        assert_eq!(edges("grid:2x3"), 7);
+        assert_eq!(coupling("heavy-hex:1x1").num_qubits(), 12);
+        assert_eq!(edges("heavy-hex:1x1"), 12);
+
+        let heavy_hex = coupling("heavy-hex:2x2");
+        assert_eq!(heavy_hex.num_qubits(), 3 * 9 + 3 + 2);
+        assert!((0..heavy_hex.num_qubits()).all(|q| heavy_hex.neighbours[q].len() <= 3));
+
+        let custom: Topology =
+            serde_json::from_str(r#"{"kind": "custom", "edges": [[0, 2], [2, 1]]}"#).unwrap();
+        let custom = custom.coupling_map().unwrap();
+        assert_eq!(custom.distance(0, 1), 2);
+
+        assert!("torus:3".parse::<Topology>().is_err());
+        assert!(Topology::Custom {
+            edges: vec![[0, 1], [2, 3]]
+        }
+        .coupling_map()
+        .is_err());
+    }
+
+    #[test]
+    fn test_connected_gates_need_no_swaps() {
+        let result = assert_routes(
+            "qreg q[3];\nh q[0];\ncx q[0], q[1];\ncx q[1], q[2];",
+            &coupling("linear:3"),
+        );
+        assert_eq!(result.swaps, 0);
+    }
+
+    #[test]
+    fn test_star_interactions_need_swaps_on_a_line() {
+        let result = assert_routes(
+            "qreg q[4];\nh q[0];\ncx q[0], q[1];\ncx q[0], q[2];\ncx q[0], q[3];\n\
+             cx q[1], q[3];\ncz q[2], q[0];",
+            &coupling("linear:4"),
+        );
+        assert!(result.swaps > 0);
+    }
+
+    #[test]
+    fn test_cancelled_routing_stops() {
+        let circuit = lower_qasm("qreg q[4];\ncx q[0], q[3];");
+        let cancelled = AtomicBool::new(true);
+        assert!(route_circuit_cancellable(&circuit, &coupling("linear:4"), &cancelled).is_err());
+    }
+
+    #[test]
+    fn test_routing_on_every_topology() {
+        let source = "qreg q[5];\nh q[0];\ncx q[0], q[4];\ncx q[1], q[3];\nrzz(0.3) q[4], q[2];\n\
+                      swap q[0], q[3];\ncu1(pi/4) q[2], q[1];\ncx q[3], q[0];\nt q[4];\ncx q[4], q[1];";
+        for spec in ["linear:5", "ring:5", "grid:2x3", "heavy-hex:1x1"] {
+            assert_routes(source, &coupling(spec));
+        }
+        let custom = Topology::Custom {
+            edges: vec![[0, 1], [1, 2], [1, 3], [3, 4], [4, 5]],
+        };
+        assert_routes(source, &custom.coupling_map().unwrap());
+    }
+
+    #[test]
+    fn test_measurements_follow_the_layout() {
+        let source = "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[3];\ncreg c[3];\n\
+                      x q[0];\ncx q[0], q[2];\ncx q[2], q[1];\ncx q[0], q[1];\nmeasure q -> c;";
+        let circuit = lower_program(&parse_qasm(source).unwrap());
+        let result = route_circuit(&circuit, &coupling("linear:4")).unwrap();
+        assert_eq!(result.circuit.qubits.len(), 4);
+
+        let program: ProgramNode = parse_qasm(source).unwrap();
+        let expected = simulate_program(program, Default::default()).memory;
+        let routed = crate::interpreter::simulate_circuit(&result.circuit, Default::default());
+        assert_eq!(routed.memory, expected);
+    }
+
+    #[test]
+    fn test_errors() {
+        let circuit = lower_qasm("qreg q[3];\nccx q[0], q[1], q[2];");
+        assert!(route_circuit(&circuit, &coupling("linear:3"))
+            .unwrap_err()
+            .contains("decompose"));
+
+        let circuit = lower_qasm("qreg q[4];\nh q[0];");
+        assert!(route_circuit(&circuit, &coupling("linear:3")).is_err());
+    }
+}
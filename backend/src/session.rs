@@ -0,0 +1,483 @@
+//! Simulation sessions that run a circuit one instruction at a time, for a
+//! debugger-like view of the state.
+//!
+//! A session keeps a snapshot after every step so that it can rewind to
+//! them. Snapshots of a session share a budget of amplitudes; beyond it the
+//! oldest are dropped, except the initial state.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::Rng;
+use serde::Serialize;
+
+use crate::{
+    analysis::StateAnalysis,
+    circuit::{Circuit, Instruction},
+    interpreter::{Execution, SimulationOptions, Snapshot},
+    precision::Precision,
+    quantum_register::QuantumRegister,
+    simulator::{BackendKind, Simulator},
+    sparse_register::SparseRegister,
+};
+
+pub const MAX_SESSION_QUBITS: usize = 12;
+pub const DEFAULT_MAX_SESSIONS: usize = 32;
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Amplitudes kept across the snapshots of one session, 8 MiB.
+const SNAPSHOT_BUDGET: usize = 1 << 19;
+
+pub struct Session {
+    circuit: Circuit,
+    run: Box<dyn Stepping>,
+    /// Steps before which `run` stops.
+    breakpoints: BTreeSet<usize>,
+}
+
+/// An execution on one backend and precision along with its snapshots.
+struct Run<S> {
+    execution: Execution<S>,
+    options: SimulationOptions,
+    /// Snapshots by the number of steps taken.
+    snapshots: BTreeMap<usize, Snapshot<S>>,
+}
+
+/// What a session needs from a [`Run`], whatever its simulator.
+trait Stepping: Send {
+    fn position(&self) -> usize;
+    fn advance(&mut self, circuit: &Circuit) -> bool;
+    fn rewind(&mut self, step: usize) -> Result<(), String>;
+    fn earliest_rewind(&self) -> usize;
+    fn state(&self) -> Vec<[f64; 2]>;
+    fn memory(&self, circuit: &Circuit) -> BTreeMap<String, String>;
+    fn output(&self) -> &[String];
+    fn analysis(&self) -> &[StateAnalysis];
+    /// Snapshots kept, for tests.
+    #[cfg(test)]
+    fn snapshots(&self) -> usize;
+}
+
+impl<S: Simulator + Clone + Send + 'static> Run<S> {
+    fn start(circuit: &Circuit, options: SimulationOptions) -> Box<dyn Stepping> {
+        let execution = Execution::<S>::new(circuit);
+        let snapshots = BTreeMap::from([(0, execution.snapshot())]);
+        Box::new(Run {
+            execution,
+            options,
+            snapshots,
+        })
+    }
+}
+
+impl<S: Simulator + Clone + Send> Stepping for Run<S> {
+    fn position(&self) -> usize {
+        self.execution.position
+    }
+
+    fn advance(&mut self, circuit: &Circuit) -> bool {
+        if self.execution.step(circuit, self.options).is_none() {
+            return false;
+        }
+        self.snapshots
+            .insert(self.execution.position, self.execution.snapshot());
+        let amplitudes = match self.options.backend {
+            BackendKind::Dense => 1 << self.execution.register.num_qubits(),
+            BackendKind::Sparse => self.execution.register.amplitudes().len(),
+        };
+        // Classical bits take a byte each, an amplitude sixteen.
+        let size = amplitudes + self.execution.clbits.len().div_ceil(16);
+        while self.snapshots.len() > 2 && self.snapshots.len() * size > SNAPSHOT_BUDGET {
+            let oldest = self.earliest_rewind();
+            self.snapshots.remove(&oldest);
+        }
+        true
+    }
+
+    fn rewind(&mut self, step: usize) -> Result<(), String> {
+        if step > self.execution.position {
+            return Err(format!(
+                "Cannot rewind to step {}, the session is at step {}",
+                step, self.execution.position
+            ));
+        }
+        let snapshot = self.snapshots.get(&step).ok_or_else(|| {
+            format!(
+                "Step {} is no longer kept, rewind to 0 or to step {} or later",
+                step,
+                self.earliest_rewind()
+            )
+        })?;
+        self.execution.restore(snapshot);
+        self.snapshots.split_off(&(step + 1));
+        Ok(())
+    }
+
+    fn earliest_rewind(&self) -> usize {
+        self.snapshots
+            .keys()
+            .copied()
+            .find(|&step| step > 0)
+            .unwrap_or(0)
+    }
+
+    fn state(&self) -> Vec<[f64; 2]> {
+        let register = &self.execution.register;
+        let mut state = vec![[0.0, 0.0]; 1 << register.num_qubits()];
+        for (index, amplitude) in register.amplitudes() {
+            state[index] = [amplitude.re, amplitude.im];
+        }
+        state
+    }
+
+    fn memory(&self, circuit: &Circuit) -> BTreeMap<String, String> {
+        self.execution.memory(circuit)
+    }
+
+    fn output(&self) -> &[String] {
+        &self.execution.output
+    }
+
+    fn analysis(&self) -> &[StateAnalysis] {
+        &self.execution.analysis
+    }
+
+    #[cfg(test)]
+    fn snapshots(&self) -> usize {
+        self.snapshots.len()
+    }
+}
+
+/// What a session shows after each request.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionState {
+    /// Instructions run so far, which is also the index of the next one.
+    pub step: usize,
+    pub steps: usize,
+    pub finished: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<Instruction>,
+    /// Amplitudes as `[re, im]`, with qubit 0 as the least significant bit
+    /// of the index.
+    pub state: Vec<[f64; 2]>,
+    /// Classical registers as bit strings, highest index first.
+    pub memory: BTreeMap<String, String>,
+    pub output: Vec<String>,
+    pub analysis: Vec<StateAnalysis>,
+    pub breakpoints: Vec<usize>,
+    /// The earliest step after the start that can still be rewound to.
+    pub earliest_rewind: usize,
+}
+
+impl Session {
+    /// Starts a session on the backend and at the precision of `options`.
+    pub fn new(circuit: Circuit, options: SimulationOptions) -> Result<Session, String> {
+        if circuit.qubits.len() > MAX_SESSION_QUBITS {
+            return Err(format!(
+                "Sessions are limited to {} qubits, the program has {}",
+                MAX_SESSION_QUBITS,
+                circuit.qubits.len()
+            ));
+        }
+        let run = match (options.backend, options.precision) {
+            (BackendKind::Dense, Precision::Single) => {
+                Run::<QuantumRegister<f32>>::start(&circuit, options)
+            }
+            (BackendKind::Dense, Precision::Double) => {
+                Run::<QuantumRegister<f64>>::start(&circuit, options)
+            }
+            (BackendKind::Sparse, Precision::Single) => {
+                Run::<SparseRegister<f32>>::start(&circuit, options)
+            }
+            (BackendKind::Sparse, Precision::Double) => {
+                Run::<SparseRegister<f64>>::start(&circuit, options)
+            }
+        };
+        Ok(Session {
+            circuit,
+            run,
+            breakpoints: BTreeSet::new(),
+        })
+    }
+
+    pub fn circuit(&self) -> &Circuit {
+        &self.circuit
+    }
+
+    /// Runs up to `count` instructions and returns how many ran.
+    pub fn step(&mut self, count: usize) -> usize {
+        let mut taken = 0;
+        while taken < count && self.run.advance(&self.circuit) {
+            taken += 1;
+        }
+        taken
+    }
+
+    /// Runs until the next breakpoint or the end, always taking at least
+    /// one step so that a session paused on a breakpoint moves on.
+    pub fn run(&mut self) -> usize {
+        let mut taken = 0;
+        while self.run.advance(&self.circuit) {
+            taken += 1;
+            if self.breakpoints.contains(&self.run.position()) {
+                break;
+            }
+        }
+        taken
+    }
+
+    /// Goes back to the state after `step` instructions. Stepping on from
+    /// there measures again, so outcomes may differ from the first time.
+    pub fn rewind(&mut self, step: usize) -> Result<(), String> {
+        self.run.rewind(step)
+    }
+
+    pub fn set_breakpoints(&mut self, breakpoints: impl IntoIterator<Item = usize>) {
+        self.breakpoints = breakpoints.into_iter().collect();
+    }
+
+    pub fn state(&self) -> SessionState {
+        let position = self.run.position();
+        SessionState {
+            step: position,
+            steps: self.circuit.instructions.len(),
+            finished: position >= self.circuit.instructions.len(),
+            next: self.circuit.instructions.get(position).cloned(),
+            state: self.run.state(),
+            memory: self.run.memory(&self.circuit),
+            output: self.run.output().to_vec(),
+            analysis: self.run.analysis().to_vec(),
+            breakpoints: self.breakpoints.iter().copied().collect(),
+            earliest_rewind: self.run.earliest_rewind(),
+        }
+    }
+}
+
+struct Entry {
+    session: Arc<Mutex<Session>>,
+    last_used: Instant,
+}
+
+/// Sessions by ID. Sessions expire when unused for the time to live, and
+/// the least recently used one makes way when the store is full.
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Entry>>,
+    max_sessions: usize,
+    ttl: Duration,
+}
+
+impl std::fmt::Debug for SessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionStore")
+            .field("max_sessions", &self.max_sessions)
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionStore {
+    pub fn new(max_sessions: usize, ttl: Duration) -> SessionStore {
+        SessionStore {
+            sessions: Mutex::new(HashMap::new()),
+            max_sessions,
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Stores a session and returns its new ID.
+    pub fn insert(&self, session: Session) -> String {
+        let id = URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 16]>());
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        sessions.retain(|_, entry| now.duration_since(entry.last_used) < self.ttl);
+        while sessions.len() >= self.max_sessions.max(1) {
+            let oldest = sessions
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(id, _)| id.clone())
+                .unwrap();
+            sessions.remove(&oldest);
+        }
+        sessions.insert(
+            id.clone(),
+            Entry {
+                session: Arc::new(Mutex::new(session)),
+                last_used: now,
+            },
+        );
+        id
+    }
+
+    /// Looks up a session that has not expired and marks it as used.
+    pub fn get(&self, id: &str) -> Option<Arc<Mutex<Session>>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = Instant::now();
+        let entry = sessions.get_mut(id)?;
+        if now.duration_since(entry.last_used) >= self.ttl {
+            sessions.remove(id);
+            return None;
+        }
+        entry.last_used = now;
+        Some(entry.session.clone())
+    }
+
+    pub fn remove(&self, id: &str) -> bool {
+        self.sessions.lock().unwrap().remove(id).is_some()
+    }
+
+    /// Sessions that have not expired.
+    pub fn len(&self) -> usize {
+        let now = Instant::now();
+        self.sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| now.duration_since(entry.last_used) < self.ttl)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lower::lower_program, qasm::parse_qasm};
+
+    fn session(source: &str) -> Session {
+        let program = parse_qasm(&format!(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\n{}",
+            source
+        ))
+        .unwrap();
+        Session::new(lower_program(&program), SimulationOptions::default()).unwrap()
+    }
+
+    fn close(state: &[[f64; 2]], expected: &[f64]) -> bool {
+        state.len() == expected.len()
+            && state.iter().zip(expected).all(|(amplitude, &re)| {
+                (amplitude[0] - re).abs() < 1e-9 && amplitude[1].abs() < 1e-9
+            })
+    }
+
+    #[test]
+    fn test_steps_and_rewind() {
+        let mut session = session("qreg q[2];\nh q[0];\ncx q[0], q[1];\nx q[1];");
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        assert!(close(&session.state().state, &[1.0, 0.0, 0.0, 0.0]));
+        assert_eq!(
+            session.state().next.unwrap(),
+            session.circuit().instructions[0]
+        );
+
+        assert_eq!(session.step(1), 1);
+        assert!(close(&session.state().state, &[half, half, 0.0, 0.0]));
+        assert_eq!(session.step(5), 2);
+        let state = session.state();
+        assert!(state.finished);
+        assert_eq!((state.step, state.steps), (3, 3));
+        assert!(close(&state.state, &[0.0, half, half, 0.0]));
+
+        session.rewind(2).unwrap();
+        assert!(close(&session.state().state, &[half, 0.0, 0.0, half]));
+        assert!(session.rewind(3).is_err());
+        session.rewind(0).unwrap();
+        assert!(close(&session.state().state, &[1.0, 0.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut session = session("qreg q[1];\nx q[0];\nx q[0];\nx q[0];\nx q[0];");
+        session.set_breakpoints([1, 3]);
+        assert_eq!(session.run(), 1);
+        assert_eq!(session.run(), 2);
+        assert_eq!(session.state().step, 3);
+        assert_eq!(session.run(), 1);
+        assert!(session.state().finished);
+        assert_eq!(session.run(), 0);
+    }
+
+    #[test]
+    fn test_rewinding_drops_output_and_memory() {
+        let mut session = session("qreg q[1];\ncreg c[1];\nx q[0];\nmeasure q[0] -> c[0];");
+        session.step(2);
+        assert_eq!(session.state().memory["c"], "1");
+        session.rewind(1).unwrap();
+        assert_eq!(session.state().memory["c"], "0");
+    }
+
+    #[test]
+    fn test_snapshot_budget() {
+        let gates = "h q[0];\n".repeat(200);
+        let mut session = session(&format!("qreg q[{}];\n{}", MAX_SESSION_QUBITS, gates));
+        session.step(200);
+        let earliest = session.state().earliest_rewind;
+        assert!(earliest > 1);
+        assert!(session.rewind(earliest - 1).is_err());
+        session.rewind(earliest).unwrap();
+        session.rewind(0).unwrap();
+        assert!(session.run.snapshots() * (1 << MAX_SESSION_QUBITS) <= SNAPSHOT_BUDGET);
+    }
+
+    #[test]
+    fn test_backends_and_precisions() {
+        let program = parse_qasm(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nh q[0];\ncx q[0], q[1];",
+        )
+        .unwrap();
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        for backend in [BackendKind::Dense, BackendKind::Sparse] {
+            for precision in [Precision::Single, Precision::Double] {
+                let options = SimulationOptions {
+                    backend,
+                    precision,
+                    ..SimulationOptions::default()
+                };
+                let mut session = Session::new(lower_program(&program), options).unwrap();
+                session.step(2);
+                let state = session.state().state;
+                assert_eq!(state.len(), 4);
+                for (amplitude, expected) in state.iter().zip([half, 0.0, 0.0, half]) {
+                    assert!((amplitude[0] - expected).abs() < 1e-6 && amplitude[1].abs() < 1e-6);
+                }
+                session.rewind(1).unwrap();
+                assert!((session.state().state[1][0] - half).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_qubit_limit() {
+        let program = parse_qasm("OPENQASM 2.0;\nqreg q[13];").unwrap();
+        assert!(Session::new(lower_program(&program), SimulationOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_store_evicts_and_expires() {
+        let store = SessionStore::new(2, Duration::from_secs(60));
+        let first = store.insert(session("qreg q[1];"));
+        let second = store.insert(session("qreg q[1];"));
+        store.get(&first).unwrap();
+        let third = store.insert(session("qreg q[1];"));
+        assert!(store.get(&second).is_none());
+        assert!(store.get(&first).is_some() && store.get(&third).is_some());
+        assert!(store.remove(&first));
+        assert_eq!(store.len(), 1);
+
+        let expired = SessionStore::new(2, Duration::ZERO);
+        let id = expired.insert(session("qreg q[1];"));
+        assert!(expired.get(&id).is_none());
+        assert!(expired.is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+//! Programs shared under short links.
+//!
+//! A shared program is stored once under an ID derived from its content, so
+//! sharing the same program twice gives the same link.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{models::ProgramNode, qasm::parse_qasm};
+
+/// Largest program, in bytes of OpenQASM source or program JSON, that can be
+/// shared.
+pub const MAX_SHARED_PROGRAM_BYTES: usize = 64 * 1024;
+
+/// Bytes of the SHA-256 digest kept in an ID, which is 11 characters long.
+const ID_BYTES: usize = 8;
+
+/// A program as it is shared: OpenQASM source or a program tree.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SharedSource {
+    Qasm(String),
+    Program(serde_json::Value),
+}
+
+impl SharedSource {
+    /// Name of the format, as stored next to the content.
+    pub fn format(&self) -> &'static str {
+        match self {
+            SharedSource::Qasm(_) => "qasm",
+            SharedSource::Program(_) => "program",
+        }
+    }
+
+    /// The stored bytes. Program trees are written with sorted keys, so the
+    /// same tree always gives the same ID.
+    pub fn content(&self) -> Vec<u8> {
+        match self {
+            SharedSource::Qasm(source) => source.as_bytes().to_vec(),
+            SharedSource::Program(program) => serde_json::to_vec(program).unwrap(),
+        }
+    }
+
+    pub fn from_stored(format: &str, content: &[u8]) -> Result<SharedSource, String> {
+        match format {
+            "qasm" => String::from_utf8(content.to_vec())
+                .map(SharedSource::Qasm)
+                .map_err(|error| error.to_string()),
+            "program" => serde_json::from_slice(content)
+                .map(SharedSource::Program)
+                .map_err(|error| error.to_string()),
+            _ => Err(format!("Unknown shared program format '{}'", format)),
+        }
+    }
+
+    /// Parses the program, so that only programs that run can be shared.
+    pub fn program(&self) -> Result<ProgramNode, String> {
+        match self {
+            SharedSource::Qasm(source) => parse_qasm(source).map_err(|error| error.to_string()),
+            SharedSource::Program(program) => {
+                ProgramNode::deserialize(program).map_err(|error| error.to_string())
+            }
+        }
+    }
+}
+
+/// The short ID of a program: the start of the SHA-256 digest of its format
+/// and content, in base64url.
+pub fn share_id(source: &SharedSource) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(source.format().as_bytes());
+    hasher.update([0]);
+    hasher.update(source.content());
+    URL_SAFE_NO_PAD.encode(&hasher.finalize()[..ID_BYTES])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BELL: &str =
+        "OPENQASM 2.0;\ninclude \"qelib1.inc\";\nqreg q[2];\nh q[0];\ncx q[0], q[1];";
+
+    #[test]
+    fn test_ids_follow_content() {
+        let bell = SharedSource::Qasm(BELL.to_string());
+        let id = share_id(&bell);
+        assert_eq!(id.len(), 11);
+        assert_eq!(id, share_id(&SharedSource::Qasm(BELL.to_string())));
+        assert_ne!(id, share_id(&SharedSource::Qasm(format!("{}\n", BELL))));
+
+        let first: serde_json::Value = serde_json::from_str(r#"{"a": 1, "b": 2}"#).unwrap();
+        let second: serde_json::Value = serde_json::from_str(r#"{"b": 2, "a": 1}"#).unwrap();
+        assert_eq!(
+            share_id(&SharedSource::Program(first)),
+            share_id(&SharedSource::Program(second))
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let tree = SharedSource::Program(serde_json::json!({
+            "type": "Program",
+            "statements": [{"type": "RegisterDeclaration", "identifier": "q", "size": 3}]
+        }));
+        let stored = SharedSource::from_stored(tree.format(), &tree.content()).unwrap();
+        assert_eq!(stored, tree);
+        assert_eq!(stored.program().unwrap().statements.len(), 1);
+
+        assert!(SharedSource::Qasm("qreg".to_string()).program().is_err());
+        assert!(SharedSource::from_stored("png", b"").is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use nalgebra::{Complex, DMatrix, Vector2};
+use serde::Deserialize;
+
+use crate::observable::{Observable, PauliTerm};
+use crate::qubit::Measurement;
+
+/// Operations the interpreter needs from a state representation.
+///
+/// Qubit `i` always corresponds to bit `i` of the basis state index and gate
+/// matrices use `targets[0]` as their most significant qubit.
+pub trait Simulator {
+    /// A register without any qubits.
+    fn empty() -> Self
+    where
+        Self: Sized;
+
+    fn num_qubits(&self) -> usize;
+
+    /// Appends a qubit in the given (normalised) state and returns its
+    /// index, or an error when the register cannot grow any wider.
+    fn add_qubit(&mut self, amplitudes: Vector2<Complex<f64>>) -> Result<usize, String>;
+
+    fn apply_gate(&mut self, gate_matrix: &DMatrix<Complex<f64>>, targets: &[usize]);
+
+    fn probability_of_one(&self, qubit_index: usize) -> f64;
+
+    /// Amplitudes of a single qubit, exact up to global phase as long as the
+    /// qubit is not entangled with the rest of the register.
+    fn qubit_state(&self, qubit_index: usize) -> Vector2<Complex<f64>>;
+
+    fn measure(&mut self, qubit_index: usize) -> Measurement;
+
+    /// All basis states with a nonzero amplitude, in no particular order.
+    fn amplitudes(&self) -> Vec<(usize, Complex<f64>)>;
+
+    /// Expectation value of a single Pauli string, ignoring its coefficient.
+    fn pauli_expectation(&self, term: &PauliTerm) -> f64;
+
+    /// Exact `<psi|O|psi>` computed term by term from the amplitudes.
+    fn expectation(&self, observable: &Observable) -> f64 {
+        observable.exact(|term| self.pauli_expectation(term))
+    }
+
+    /// Estimate of `<psi|O|psi>` from `shots` measurements per Pauli term.
+    fn sampled_expectation(&self, observable: &Observable, shots: usize) -> Result<f64, String> {
+        observable.sampled(shots, |term| self.pauli_expectation(term))
+    }
+}
+
+/// Which state representation a simulation runs on.
+///
+/// `Dense` stores all `2^n` amplitudes, `Sparse` only the nonzero ones and
+/// is the better choice for classical-reversible circuits such as adders.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Dense,
+    Sparse,
+}
+
+impl std::str::FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dense" => Ok(BackendKind::Dense),
+            "sparse" => Ok(BackendKind::Sparse),
+            _ => Err(format!(
+                "Unknown backend '{}': expected 'dense' or 'sparse'",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendKind::Dense => write!(f, "dense"),
+            BackendKind::Sparse => write!(f, "sparse"),
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+
+use nalgebra::{Complex, DMatrix, Vector2};
+use rand::Rng;
+
+use crate::observable::PauliTerm;
+use crate::precision::{cast_complex, cast_matrix, Float};
+use crate::qubit::Measurement;
+use crate::simulator::Simulator;
+
+/// Amplitudes with a magnitude below this are dropped after every operation.
+pub const DEFAULT_PRUNING_EPSILON: f64 = 1e-12;
+
+/// Largest register the sparse backend can address with a `usize` index.
+pub const MAX_SPARSE_QUBITS: usize = usize::BITS as usize - 1;
+
+/// State vector that only stores the nonzero amplitudes.
+///
+/// Memory grows with the number of populated basis states instead of `2^n`,
+/// which makes wide classical-reversible circuits (adders, permutation
+/// oracles) feasible. Uses the same qubit ordering as `QuantumRegister`.
+#[derive(Clone)]
+pub struct SparseRegister<T: Float = f64> {
+    num_qubits: usize,
+    amplitudes: HashMap<usize, Complex<T>>,
+    epsilon: f64,
+}
+
+impl<T: Float> SparseRegister<T> {
+    pub fn new(num_qubits: usize) -> Result<Self, String> {
+        Self::with_epsilon(num_qubits, DEFAULT_PRUNING_EPSILON)
+    }
+
+    pub fn with_epsilon(num_qubits: usize, epsilon: f64) -> Result<Self, String> {
+        if num_qubits > MAX_SPARSE_QUBITS {
+            return Err(too_wide(num_qubits));
+        }
+
+        let mut amplitudes = HashMap::new();
+        amplitudes.insert(0, Complex::new(T::one(), T::zero()));
+        Ok(Self {
+            num_qubits,
+            amplitudes,
+            epsilon,
+        })
+    }
+
+    /// Number of basis states with a nonzero amplitude.
+    pub fn nonzero_count(&self) -> usize {
+        self.amplitudes.len()
+    }
+
+    pub fn amplitude(&self, basis_state: usize) -> Complex<T> {
+        self.amplitudes
+            .get(&basis_state)
+            .copied()
+            .unwrap_or_else(|| Complex::new(T::zero(), T::zero()))
+    }
+
+    fn is_negligible(&self, amplitude: &Complex<T>) -> bool {
+        amplitude.norm_sqr().to_double() < self.epsilon * self.epsilon
+    }
+
+    fn normalize(&mut self) {
+        let norm = self
+            .amplitudes
+            .values()
+            .map(|a| a.norm_sqr())
+            .fold(T::zero(), |sum, x| sum + x)
+            .sqrt();
+        for amplitude in self.amplitudes.values_mut() {
+            *amplitude /= Complex::new(norm, T::zero());
+        }
+    }
+}
+
+fn too_wide(num_qubits: usize) -> String {
+    format!(
+        "The sparse backend supports at most {} qubits, the circuit has {}",
+        MAX_SPARSE_QUBITS, num_qubits
+    )
+}
+
+impl<T: Float> Simulator for SparseRegister<T> {
+    fn empty() -> Self {
+        Self::new(0).expect("an empty register is never too wide")
+    }
+
+    fn num_qubits(&self) -> usize {
+        self.num_qubits
+    }
+
+    fn add_qubit(&mut self, amplitudes: Vector2<Complex<f64>>) -> Result<usize, String> {
+        let index = self.num_qubits;
+        if index >= MAX_SPARSE_QUBITS {
+            return Err(too_wide(index + 1));
+        }
+
+        let a0 = cast_complex::<T>(amplitudes[0]);
+        let a1 = cast_complex::<T>(amplitudes[1]);
+        let mut state = HashMap::with_capacity(self.amplitudes.len() * 2);
+        for (&basis_state, &amplitude) in &self.amplitudes {
+            for (bit, factor) in [(0, a0), (1, a1)] {
+                let value = amplitude * factor;
+                if !self.is_negligible(&value) {
+                    state.insert(basis_state | (bit << index), value);
+                }
+            }
+        }
+
+        self.amplitudes = state;
+        self.num_qubits += 1;
+        Ok(index)
+    }
+
+    fn apply_gate(&mut self, gate_matrix: &DMatrix<Complex<f64>>, targets: &[usize]) {
+        let k = targets.len();
+        assert_eq!(
+            gate_matrix.nrows(),
+            1 << k,
+            "gate matrix does not match the number of targets"
+        );
+
+        let matrix = cast_matrix::<T>(gate_matrix);
+        let dim = 1 << k;
+        let target_mask = targets.iter().fold(0, |mask, &t| mask | (1 << t));
+        let offsets: Vec<usize> = (0..dim)
+            .map(|sub| {
+                targets
+                    .iter()
+                    .enumerate()
+                    .filter(|(bit, _)| (sub >> (k - 1 - bit)) & 1 == 1)
+                    .fold(0, |offset, (_, &t)| offset | (1 << t))
+            })
+            .collect();
+        let sub_index = |basis_state: usize| {
+            targets
+                .iter()
+                .fold(0, |sub, &t| (sub << 1) | ((basis_state >> t) & 1))
+        };
+
+        // Group the populated amplitudes by the untouched bits so every
+        // group is one small 2^k vector the gate acts on.
+        let mut groups: HashMap<usize, Vec<(usize, Complex<T>)>> = HashMap::new();
+        for (&basis_state, &amplitude) in &self.amplitudes {
+            groups
+                .entry(basis_state & !target_mask)
+                .or_default()
+                .push((sub_index(basis_state), amplitude));
+        }
+
+        let zero = Complex::new(T::zero(), T::zero());
+        let mut state = HashMap::with_capacity(self.amplitudes.len());
+        for (base, inputs) in groups {
+            for (row, &offset) in offsets.iter().enumerate() {
+                let mut sum = zero;
+                for &(col, amplitude) in &inputs {
+                    let entry = matrix[(row, col)];
+                    if entry != zero {
+                        sum += entry * amplitude;
+                    }
+                }
+                if !self.is_negligible(&sum) {
+                    state.insert(base | offset, sum);
+                }
+            }
+        }
+
+        self.amplitudes = state;
+    }
+
+    fn probability_of_one(&self, qubit_index: usize) -> f64 {
+        self.amplitudes
+            .iter()
+            .filter(|(basis_state, _)| (*basis_state >> qubit_index) & 1 == 1)
+            .map(|(_, a)| a.norm_sqr().to_double())
+            .sum()
+    }
+
+    fn qubit_state(&self, qubit_index: usize) -> Vector2<Complex<f64>> {
+        let bit = 1 << qubit_index;
+        let base = self
+            .amplitudes
+            .keys()
+            .map(|basis_state| basis_state & !bit)
+            .max_by(|&a, &b| {
+                let weight = |base: usize| {
+                    (self.amplitude(base).norm_sqr() + self.amplitude(base | bit).norm_sqr())
+                        .to_double()
+                };
+                weight(a).total_cmp(&weight(b)).then(b.cmp(&a))
+            })
+            .unwrap_or(0);
+
+        let to_f64 = |c: Complex<T>| Complex::new(c.re.to_double(), c.im.to_double());
+        Vector2::new(
+            to_f64(self.amplitude(base)),
+            to_f64(self.amplitude(base | bit)),
+        )
+        .normalize()
+    }
+
+    fn measure(&mut self, qubit_index: usize) -> Measurement {
+        let mut rng = rand::thread_rng();
+        let random_num = rng.gen_range(0.0_f64..1.0);
+        let prob_0 = 1.0 - self.probability_of_one(qubit_index);
+        let outcome = if random_num < prob_0 { 0 } else { 1 };
+
+        self.amplitudes
+            .retain(|basis_state, _| (basis_state >> qubit_index) & 1 == outcome);
+        self.normalize();
+        outcome as Measurement
+    }
+
+    fn amplitudes(&self) -> Vec<(usize, Complex<f64>)> {
+        self.amplitudes
+            .iter()
+            .map(|(&i, a)| (i, Complex::new(a.re.to_double(), a.im.to_double())))
+            .collect()
+    }
+
+    fn pauli_expectation(&self, term: &PauliTerm) -> f64 {
+        let to_f64 = |c: Complex<T>| Complex::new(c.re.to_double(), c.im.to_double());
+        term.expectation(self.amplitudes.iter().map(|(&i, &a)| (i, to_f64(a))), |i| {
+            to_f64(self.amplitude(i))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gate::{CNot, Gate, Hadamard, PauliX};
+    use crate::quantum_register::QuantumRegister;
+
+    fn toffoli() -> DMatrix<Complex<f64>> {
+        let mut matrix = DMatrix::identity(8, 8);
+        matrix[(6, 6)] = Complex::new(0.0, 0.0);
+        matrix[(7, 7)] = Complex::new(0.0, 0.0);
+        matrix[(6, 7)] = Complex::new(1.0, 0.0);
+        matrix[(7, 6)] = Complex::new(1.0, 0.0);
+        matrix
+    }
+
+    fn set_value(register: &mut SparseRegister, qubits: &[usize], value: usize) {
+        for (bit, &qubit) in qubits.iter().enumerate() {
+            if (value >> bit) & 1 == 1 {
+                register.apply_gate(&PauliX::new().matrix_representation(), &[qubit]);
+            }
+        }
+    }
+
+    /// Ripple-carry adder (Vedral, Barenco, Ekert): computes `b += a`,
+    /// leaving the carry-out in `carry[n]` and all other carries at zero.
+    fn add(register: &mut SparseRegister, a: &[usize], b: &[usize], carry: &[usize]) {
+        let cnot = CNot::new().matrix_representation();
+        let toffoli = toffoli();
+        let n = a.len();
+
+        for i in 0..n {
+            register.apply_gate(&toffoli, &[a[i], b[i], carry[i + 1]]);
+            register.apply_gate(&cnot, &[a[i], b[i]]);
+            register.apply_gate(&toffoli, &[carry[i], b[i], carry[i + 1]]);
+        }
+        register.apply_gate(&cnot, &[a[n - 1], b[n - 1]]);
+        for i in (0..n).rev() {
+            if i < n - 1 {
+                register.apply_gate(&toffoli, &[carry[i], b[i], carry[i + 1]]);
+                register.apply_gate(&cnot, &[a[i], b[i]]);
+                register.apply_gate(&toffoli, &[a[i], b[i], carry[i + 1]]);
+            }
+            register.apply_gate(&cnot, &[a[i], b[i]]);
+            register.apply_gate(&cnot, &[carry[i], b[i]]);
+        }
+    }
+
+    fn read_value(register: &SparseRegister, qubits: &[usize]) -> usize {
+        assert_eq!(register.nonzero_count(), 1);
+        let (&basis_state, _) = register.amplitudes.iter().next().unwrap();
+        qubits.iter().enumerate().fold(0, |value, (bit, &q)| {
+            value | (((basis_state >> q) & 1) << bit)
+        })
+    }
+
+    #[test]
+    fn test_sparse_matches_dense() {
+        let mut sparse = SparseRegister::<f64>::new(4).unwrap();
+        let mut dense = QuantumRegister::<f64>::new(4);
+        let hadamard = Hadamard::new().matrix_representation();
+        let cnot = CNot::new().matrix_representation();
+
+        for register in [&mut sparse as &mut dyn Simulator, &mut dense] {
+            register.apply_gate(&hadamard, &[0]);
+            register.apply_gate(&cnot, &[0, 2]);
+            register.apply_gate(&hadamard, &[3]);
+            register.apply_gate(&toffoli(), &[0, 3, 1]);
+        }
+
+        for (i, amplitude) in dense.state().iter().enumerate() {
+            assert!((sparse.amplitude(i) - amplitude).norm() < 1e-12);
+        }
+        assert_eq!(sparse.nonzero_count(), 4);
+    }
+
+    #[test]
+    fn test_interference_prunes_amplitudes() {
+        let mut register = SparseRegister::<f64>::new(1).unwrap();
+        let hadamard = Hadamard::new().matrix_representation();
+        register.apply_gate(&hadamard, &[0]);
+        assert_eq!(register.nonzero_count(), 2);
+        register.apply_gate(&hadamard, &[0]);
+        assert_eq!(register.nonzero_count(), 1);
+        assert!((register.amplitude(0).re - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_add_qubit_keeps_zero_amplitudes_out() {
+        let mut register = SparseRegister::<f64>::empty();
+        register
+            .add_qubit(Vector2::new(Complex::new(0.0, 0.0), Complex::new(1.0, 0.0)))
+            .unwrap();
+        register
+            .add_qubit(Vector2::new(Complex::new(1.0, 0.0), Complex::new(0.0, 0.0)))
+            .unwrap();
+        assert_eq!(register.num_qubits(), 2);
+        assert_eq!(register.nonzero_count(), 1);
+        assert_eq!(register.amplitude(0b01), Complex::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn test_too_many_qubits_is_an_error() {
+        assert!(SparseRegister::<f64>::new(MAX_SPARSE_QUBITS + 1).is_err());
+        let mut register = SparseRegister::<f64>::new(MAX_SPARSE_QUBITS).unwrap();
+        let error = register
+            .add_qubit(Vector2::new(Complex::new(1.0, 0.0), Complex::new(0.0, 0.0)))
+            .unwrap_err();
+        assert!(error.contains("at most 63 qubits"), "{}", error);
+        assert_eq!(register.num_qubits(), MAX_SPARSE_QUBITS);
+    }
+
+    #[test]
+    fn test_measure_collapses_ghz() {
+        let hadamard = Hadamard::new().matrix_representation();
+        let cnot = CNot::new().matrix_representation();
+        for _ in 0..50 {
+            let mut register = SparseRegister::<f64>::new(40).unwrap();
+            register.apply_gate(&hadamard, &[0]);
+            for i in 1..40 {
+                register.apply_gate(&cnot, &[i - 1, i]);
+            }
+            assert_eq!(register.nonzero_count(), 2);
+
+            let first = register.measure(0);
+            assert_eq!(register.nonzero_count(), 1);
+            assert_eq!(register.measure(39), first);
+        }
+    }
+
+    #[test]
+    fn test_sixty_qubit_adder() {
+        // 20-bit operands a and b plus 21 carry qubits: 61 qubits in total.
+        let bits = 20;
+        let a: Vec<usize> = (0..bits).collect();
+        let b: Vec<usize> = (bits..2 * bits).collect();
+        let carry: Vec<usize> = (2 * bits..3 * bits + 1).collect();
+        let mut register = SparseRegister::<f64>::new(3 * bits + 1).unwrap();
+
+        let (x, y) = (0b1011_0110_1110_0101_1011, 0b0111_1001_0101_1100_0110);
+        set_value(&mut register, &a, x);
+        set_value(&mut register, &b, y);
+        add(&mut register, &a, &b, &carry);
+
+        let sum = read_value(&register, &b) | (read_value(&register, &carry[bits..]) << bits);
+        assert_eq!(sum, x + y);
+        assert_eq!(read_value(&register, &a), x);
+        assert_eq!(read_value(&register, &carry[..bits]), 0);
+    }
+
+    #[test]
+    fn test_adder_in_superposition() {
+        let bits = 4;
+        let a: Vec<usize> = (0..bits).collect();
+        let b: Vec<usize> = (bits..2 * bits).collect();
+        let carry: Vec<usize> = (2 * bits..3 * bits + 1).collect();
+        let mut register = SparseRegister::<f64>::new(3 * bits + 1).unwrap();
+
+        for &qubit in &a {
+            register.apply_gate(&Hadamard::new().matrix_representation(), &[qubit]);
+        }
+        set_value(&mut register, &b, 5);
+        add(&mut register, &a, &b, &carry);
+
+        assert_eq!(register.nonzero_count(), 16);
+        for (&basis_state, amplitude) in &register.amplitudes {
+            let x = basis_state & 0b1111;
+            let sum = (basis_state >> bits) & 0b1111 | ((basis_state >> (3 * bits)) & 1) << bits;
+            assert_eq!(sum, x + 5);
+            assert!((amplitude.norm() - 0.25).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,731 @@
+//! Persistence of user accounts, their saved program files and the levels
+//! they have completed, following `db/database.puml`, and of shared programs.
+//!
+//! The same queries run against SQLite, for local development and tests, and
+//! Postgres, for deployment; the URL scheme picks the database. Levels
+//! themselves are defined in `levels.json`, so completed levels are stored by
+//! id only.
+
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::Rng;
+use serde::Serialize;
+use sqlx::{
+    any::{install_default_drivers, AnyPoolOptions},
+    AnyPool,
+};
+
+pub const DEFAULT_DATABASE_URL: &str = "sqlite://quantum_simulator.db?mode=rwc";
+
+const MAX_USER_NAME_LENGTH: usize = 32;
+
+/// Schema changes in the order they are applied. `{id}` and `{blob}` stand
+/// for the column types that differ between the databases.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE users (
+        user_name TEXT PRIMARY KEY,
+        created_at BIGINT NOT NULL
+    )",
+    "CREATE TABLE files (
+        blob_id {id},
+        user_name TEXT NOT NULL REFERENCES users(user_name) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        content {blob} NOT NULL,
+        created_at BIGINT NOT NULL,
+        updated_at BIGINT NOT NULL
+    )",
+    "CREATE INDEX files_user_name ON files(user_name)",
+    "CREATE TABLE completed_levels (
+        user_name TEXT NOT NULL REFERENCES users(user_name) ON DELETE CASCADE,
+        level_id BIGINT NOT NULL,
+        completed_at BIGINT NOT NULL,
+        PRIMARY KEY (user_name, level_id)
+    )",
+    "ALTER TABLE users ADD COLUMN password_hash TEXT NOT NULL DEFAULT ''",
+    "CREATE TABLE shared_programs (
+        share_id TEXT PRIMARY KEY,
+        format TEXT NOT NULL,
+        content {blob} NOT NULL,
+        created_at BIGINT NOT NULL,
+        result TEXT
+    )",
+    "ALTER TABLE users ADD COLUMN token_nonce TEXT NOT NULL DEFAULT ''",
+];
+
+#[derive(Debug)]
+pub enum StoreError {
+    NotFound(String),
+    Conflict(String),
+    Invalid(String),
+    Database(sqlx::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::NotFound(message)
+            | StoreError::Conflict(message)
+            | StoreError::Invalid(message) => write!(f, "{}", message),
+            StoreError::Database(error) => write!(f, "Database error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(error: sqlx::Error) -> Self {
+        StoreError::Database(error)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub user_name: String,
+    /// Seconds since the Unix epoch, as are the other timestamps.
+    pub created_at: i64,
+}
+
+/// A saved program without its content.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FileInfo {
+    pub blob_id: i64,
+    pub user_name: String,
+    pub name: String,
+    /// Length of the content in bytes.
+    pub size: i64,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Completion {
+    pub level_id: u32,
+    pub completed_at: i64,
+}
+
+/// A shared program as stored, with the JSON of its simulation once it has
+/// been run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedRecord {
+    pub share_id: String,
+    pub format: String,
+    pub content: Vec<u8>,
+    pub created_at: i64,
+    pub result: Option<String>,
+}
+
+type SharedRow = (String, String, Vec<u8>, i64, Option<String>);
+
+impl From<SharedRow> for SharedRecord {
+    fn from((share_id, format, content, created_at, result): SharedRow) -> Self {
+        SharedRecord {
+            share_id,
+            format,
+            content,
+            created_at,
+            result,
+        }
+    }
+}
+
+type FileRow = (i64, String, String, i64, i64, i64);
+
+const FILE_COLUMNS: &str =
+    "blob_id, user_name, name, CAST(LENGTH(content) AS BIGINT), created_at, updated_at";
+
+impl From<FileRow> for FileInfo {
+    fn from((blob_id, user_name, name, size, created_at, updated_at): FileRow) -> Self {
+        FileInfo {
+            blob_id,
+            user_name,
+            name,
+            size,
+            created_at,
+            updated_at,
+        }
+    }
+}
+
+/// A pool of connections to the database, cheap to clone.
+#[derive(Clone, Debug)]
+pub struct Store {
+    pool: AnyPool,
+}
+
+impl Store {
+    /// Connects to a `sqlite:` or `postgres:` URL and brings its schema up to
+    /// date.
+    pub async fn connect(url: &str) -> Result<Store, StoreError> {
+        install_default_drivers();
+        let postgres = url.starts_with("postgres:") || url.starts_with("postgresql:");
+        if !postgres && !url.starts_with("sqlite:") {
+            return Err(StoreError::Invalid(format!(
+                "Unsupported database URL '{}', expected sqlite: or postgres:",
+                url
+            )));
+        }
+        // Every connection to an in-memory SQLite database opens a new one.
+        let max_connections = if url.contains(":memory:") { 1 } else { 8 };
+        let pool = AnyPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(url)
+            .await?;
+        let store = Store { pool };
+        store.migrate(postgres).await?;
+        Ok(store)
+    }
+
+    /// A private in-memory SQLite database.
+    pub async fn in_memory() -> Result<Store, StoreError> {
+        Store::connect("sqlite::memory:").await
+    }
+
+    async fn migrate(&self, postgres: bool) -> Result<(), StoreError> {
+        let (id, blob) = if postgres {
+            ("BIGSERIAL PRIMARY KEY", "BYTEA")
+        } else {
+            ("INTEGER PRIMARY KEY AUTOINCREMENT", "BLOB")
+        };
+        sqlx::query("CREATE TABLE IF NOT EXISTS schema_migrations (version BIGINT PRIMARY KEY)")
+            .execute(&self.pool)
+            .await?;
+        let (applied,): (i64,) =
+            sqlx::query_as("SELECT CAST(COUNT(*) AS BIGINT) FROM schema_migrations")
+                .fetch_one(&self.pool)
+                .await?;
+        for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+            let mut transaction = self.pool.begin().await?;
+            sqlx::query(&migration.replace("{id}", id).replace("{blob}", blob))
+                .execute(&mut *transaction)
+                .await?;
+            sqlx::query("INSERT INTO schema_migrations (version) VALUES ($1)")
+                .bind(version as i64 + 1)
+                .execute(&mut *transaction)
+                .await?;
+            transaction.commit().await?;
+        }
+        Ok(())
+    }
+
+    /// Creates a user who signs in with a password of the given hash.
+    pub async fn create_user(
+        &self,
+        user_name: &str,
+        password_hash: &str,
+    ) -> Result<User, StoreError> {
+        validate_user_name(user_name)?;
+        let user = User {
+            user_name: user_name.to_string(),
+            created_at: now(),
+        };
+        let nonce = URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 16]>());
+        let inserted = sqlx::query(
+            "INSERT INTO users (user_name, created_at, password_hash, token_nonce) \
+             VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
+        )
+        .bind(&user.user_name)
+        .bind(user.created_at)
+        .bind(password_hash)
+        .bind(nonce)
+        .execute(&self.pool)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            return Err(StoreError::Conflict(format!(
+                "User '{}' already exists",
+                user_name
+            )));
+        }
+        Ok(user)
+    }
+
+    pub async fn list_users(&self) -> Result<Vec<User>, StoreError> {
+        let rows: Vec<(String, i64)> =
+            sqlx::query_as("SELECT user_name, created_at FROM users ORDER BY user_name")
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(user_name, created_at)| User {
+                user_name,
+                created_at,
+            })
+            .collect())
+    }
+
+    pub async fn get_user(&self, user_name: &str) -> Result<User, StoreError> {
+        let row: Option<(String, i64)> =
+            sqlx::query_as("SELECT user_name, created_at FROM users WHERE user_name = $1")
+                .bind(user_name)
+                .fetch_optional(&self.pool)
+                .await?;
+        row.map(|(user_name, created_at)| User {
+            user_name,
+            created_at,
+        })
+        .ok_or_else(|| unknown_user(user_name))
+    }
+
+    /// The password hash of a user, empty for users created before
+    /// passwords were required, who cannot sign in.
+    pub async fn password_hash(&self, user_name: &str) -> Result<String, StoreError> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT password_hash FROM users WHERE user_name = $1")
+                .bind(user_name)
+                .fetch_optional(&self.pool)
+                .await?;
+        row.map(|(hash,)| hash)
+            .ok_or_else(|| unknown_user(user_name))
+    }
+
+    /// A random value drawn when the user was created. Session tokens carry
+    /// it, so deleting the user revokes them even if the name is taken again.
+    pub async fn token_nonce(&self, user_name: &str) -> Result<String, StoreError> {
+        let row: Option<(String,)> =
+            sqlx::query_as("SELECT token_nonce FROM users WHERE user_name = $1")
+                .bind(user_name)
+                .fetch_optional(&self.pool)
+                .await?;
+        row.map(|(nonce,)| nonce)
+            .ok_or_else(|| unknown_user(user_name))
+    }
+
+    /// Deletes a user together with their files and progress.
+    pub async fn delete_user(&self, user_name: &str) -> Result<(), StoreError> {
+        let deleted = sqlx::query("DELETE FROM users WHERE user_name = $1")
+            .bind(user_name)
+            .execute(&self.pool)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(unknown_user(user_name));
+        }
+        Ok(())
+    }
+
+    pub async fn create_file(
+        &self,
+        user_name: &str,
+        name: &str,
+        content: &[u8],
+    ) -> Result<FileInfo, StoreError> {
+        self.get_user(user_name).await?;
+        validate_file_name(name)?;
+        let created_at = now();
+        let (blob_id,): (i64,) = sqlx::query_as(
+            "INSERT INTO files (user_name, name, content, created_at, updated_at) \
+             VALUES ($1, $2, $3, $4, $4) RETURNING blob_id",
+        )
+        .bind(user_name)
+        .bind(name)
+        .bind(content.to_vec())
+        .bind(created_at)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(FileInfo {
+            blob_id,
+            user_name: user_name.to_string(),
+            name: name.to_string(),
+            size: content.len() as i64,
+            created_at,
+            updated_at: created_at,
+        })
+    }
+
+    /// The files of a user, most recently updated first.
+    pub async fn list_files(&self, user_name: &str) -> Result<Vec<FileInfo>, StoreError> {
+        self.get_user(user_name).await?;
+        let rows: Vec<FileRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM files WHERE user_name = $1 ORDER BY updated_at DESC, blob_id DESC",
+            FILE_COLUMNS
+        ))
+        .bind(user_name)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(FileInfo::from).collect())
+    }
+
+    pub async fn file_info(&self, blob_id: i64) -> Result<FileInfo, StoreError> {
+        let row: Option<FileRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM files WHERE blob_id = $1",
+            FILE_COLUMNS
+        ))
+        .bind(blob_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(FileInfo::from).ok_or_else(|| unknown_file(blob_id))
+    }
+
+    pub async fn get_file(&self, blob_id: i64) -> Result<(FileInfo, Vec<u8>), StoreError> {
+        let info = self.file_info(blob_id).await?;
+        let (content,): (Vec<u8>,) = sqlx::query_as("SELECT content FROM files WHERE blob_id = $1")
+            .bind(blob_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok((info, content))
+    }
+
+    /// Renames a file and/or replaces its content.
+    pub async fn update_file(
+        &self,
+        blob_id: i64,
+        name: Option<&str>,
+        content: Option<&[u8]>,
+    ) -> Result<FileInfo, StoreError> {
+        if let Some(name) = name {
+            validate_file_name(name)?;
+        }
+        let updated = sqlx::query(
+            "UPDATE files SET name = COALESCE($1, name), content = COALESCE($2, content), \
+             updated_at = $3 WHERE blob_id = $4",
+        )
+        .bind(name.map(str::to_string))
+        .bind(content.map(<[u8]>::to_vec))
+        .bind(now())
+        .bind(blob_id)
+        .execute(&self.pool)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(unknown_file(blob_id));
+        }
+        self.file_info(blob_id).await
+    }
+
+    pub async fn delete_file(&self, blob_id: i64) -> Result<(), StoreError> {
+        let deleted = sqlx::query("DELETE FROM files WHERE blob_id = $1")
+            .bind(blob_id)
+            .execute(&self.pool)
+            .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(unknown_file(blob_id));
+        }
+        Ok(())
+    }
+
+    /// Records that a user completed a level. Completing it again keeps the
+    /// first completion time.
+    pub async fn complete_level(
+        &self,
+        user_name: &str,
+        level_id: u32,
+    ) -> Result<Completion, StoreError> {
+        self.get_user(user_name).await?;
+        sqlx::query(
+            "INSERT INTO completed_levels (user_name, level_id, completed_at) \
+             VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+        )
+        .bind(user_name)
+        .bind(i64::from(level_id))
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+        let (completed_at,): (i64,) = sqlx::query_as(
+            "SELECT completed_at FROM completed_levels WHERE user_name = $1 AND level_id = $2",
+        )
+        .bind(user_name)
+        .bind(i64::from(level_id))
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(Completion {
+            level_id,
+            completed_at,
+        })
+    }
+
+    pub async fn list_progress(&self, user_name: &str) -> Result<Vec<Completion>, StoreError> {
+        self.get_user(user_name).await?;
+        let rows: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT level_id, completed_at FROM completed_levels WHERE user_name = $1 \
+             ORDER BY level_id",
+        )
+        .bind(user_name)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(level_id, completed_at)| Completion {
+                level_id: level_id as u32,
+                completed_at,
+            })
+            .collect())
+    }
+
+    pub async fn remove_progress(&self, user_name: &str, level_id: u32) -> Result<(), StoreError> {
+        let deleted =
+            sqlx::query("DELETE FROM completed_levels WHERE user_name = $1 AND level_id = $2")
+                .bind(user_name)
+                .bind(i64::from(level_id))
+                .execute(&self.pool)
+                .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(StoreError::NotFound(format!(
+                "User '{}' has not completed level {}",
+                user_name, level_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Stores a shared program under its ID, keeping the existing one when
+    /// the same program was shared before. A different program under the
+    /// same ID is a conflict rather than a link to the wrong program.
+    pub async fn share_program(
+        &self,
+        share_id: &str,
+        format: &str,
+        content: &[u8],
+    ) -> Result<SharedRecord, StoreError> {
+        sqlx::query(
+            "INSERT INTO shared_programs (share_id, format, content, created_at) \
+             VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
+        )
+        .bind(share_id)
+        .bind(format)
+        .bind(content.to_vec())
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+        let shared = self.shared_program(share_id).await?;
+        if shared.format != format || shared.content != content {
+            return Err(StoreError::Conflict(format!(
+                "Shared program '{}' already holds a different program",
+                share_id
+            )));
+        }
+        Ok(shared)
+    }
+
+    pub async fn shared_program(&self, share_id: &str) -> Result<SharedRecord, StoreError> {
+        let row: Option<SharedRow> = sqlx::query_as(
+            "SELECT share_id, format, content, created_at, result FROM shared_programs \
+             WHERE share_id = $1",
+        )
+        .bind(share_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        row.map(SharedRecord::from)
+            .ok_or_else(|| StoreError::NotFound(format!("Unknown shared program '{}'", share_id)))
+    }
+
+    /// Keeps the simulation result of a shared program, unless one is
+    /// already kept.
+    pub async fn cache_share_result(&self, share_id: &str, result: &str) -> Result<(), StoreError> {
+        sqlx::query(
+            "UPDATE shared_programs SET result = $1 WHERE share_id = $2 AND result IS NULL",
+        )
+        .bind(result)
+        .bind(share_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+fn unknown_user(user_name: &str) -> StoreError {
+    StoreError::NotFound(format!("Unknown user '{}'", user_name))
+}
+
+fn unknown_file(blob_id: i64) -> StoreError {
+    StoreError::NotFound(format!("Unknown file {}", blob_id))
+}
+
+fn validate_user_name(user_name: &str) -> Result<(), StoreError> {
+    let valid = !user_name.is_empty()
+        && user_name.len() <= MAX_USER_NAME_LENGTH
+        && user_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(StoreError::Invalid(format!(
+            "User names are 1 to {} letters, digits, '_', '-' or '.'",
+            MAX_USER_NAME_LENGTH
+        )))
+    }
+}
+
+fn validate_file_name(name: &str) -> Result<(), StoreError> {
+    if name.trim().is_empty() {
+        Err(StoreError::Invalid(
+            "File names cannot be empty".to_string(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_users() {
+        let store = Store::in_memory().await.unwrap();
+        store.create_user("bob", "hash").await.unwrap();
+        store.create_user("alice", "hash").await.unwrap();
+        assert!(matches!(
+            store.create_user("alice", "hash").await,
+            Err(StoreError::Conflict(_))
+        ));
+        assert!(matches!(
+            store.create_user("no spaces", "hash").await,
+            Err(StoreError::Invalid(_))
+        ));
+        let names: Vec<_> = store
+            .list_users()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|user| user.user_name)
+            .collect();
+        assert_eq!(names, ["alice", "bob"]);
+        assert_eq!(store.get_user("bob").await.unwrap().user_name, "bob");
+        assert_eq!(store.password_hash("bob").await.unwrap(), "hash");
+        let nonce = store.token_nonce("bob").await.unwrap();
+        assert_ne!(nonce, store.token_nonce("alice").await.unwrap());
+
+        store.delete_user("bob").await.unwrap();
+        assert!(matches!(
+            store.get_user("bob").await,
+            Err(StoreError::NotFound(_))
+        ));
+        assert!(matches!(
+            store.delete_user("bob").await,
+            Err(StoreError::NotFound(_))
+        ));
+        store.create_user("bob", "hash").await.unwrap();
+        assert_ne!(store.token_nonce("bob").await.unwrap(), nonce);
+    }
+
+    #[tokio::test]
+    async fn test_files() {
+        let store = Store::in_memory().await.unwrap();
+        store.create_user("alice", "hash").await.unwrap();
+        let bell = b"qreg q[2];\nh q[0];\ncx q[0], q[1];";
+        let info = store.create_file("alice", "bell.qasm", bell).await.unwrap();
+        assert_eq!(info.size, bell.len() as i64);
+        let other = store
+            .create_file("alice", "empty.json", b"{}")
+            .await
+            .unwrap();
+        assert_ne!(info.blob_id, other.blob_id);
+        assert!(matches!(
+            store.create_file("carol", "x", b"").await,
+            Err(StoreError::NotFound(_))
+        ));
+
+        let (stored, content) = store.get_file(info.blob_id).await.unwrap();
+        assert_eq!(stored, info);
+        assert_eq!(content, bell);
+
+        let renamed = store
+            .update_file(info.blob_id, Some("entangle.qasm"), None)
+            .await
+            .unwrap();
+        assert_eq!(renamed.name, "entangle.qasm");
+        assert_eq!(renamed.size, info.size);
+        let rewritten = store
+            .update_file(info.blob_id, None, Some(b"qreg q[1];"))
+            .await
+            .unwrap();
+        assert_eq!(rewritten.name, "entangle.qasm");
+        assert_eq!(store.get_file(info.blob_id).await.unwrap().1, b"qreg q[1];");
+        assert_eq!(store.list_files("alice").await.unwrap().len(), 2);
+
+        store.delete_file(other.blob_id).await.unwrap();
+        assert!(matches!(
+            store.get_file(other.blob_id).await,
+            Err(StoreError::NotFound(_))
+        ));
+        store.delete_user("alice").await.unwrap();
+        assert!(matches!(
+            store.file_info(info.blob_id).await,
+            Err(StoreError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_progress() {
+        let store = Store::in_memory().await.unwrap();
+        store.create_user("alice", "hash").await.unwrap();
+        let first = store.complete_level("alice", 7).await.unwrap();
+        assert_eq!(store.complete_level("alice", 7).await.unwrap(), first);
+        store.complete_level("alice", 3).await.unwrap();
+        let levels: Vec<_> = store
+            .list_progress("alice")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|completion| completion.level_id)
+            .collect();
+        assert_eq!(levels, [3, 7]);
+
+        store.remove_progress("alice", 3).await.unwrap();
+        assert!(matches!(
+            store.remove_progress("alice", 3).await,
+            Err(StoreError::NotFound(_))
+        ));
+        assert!(matches!(
+            store.complete_level("bob", 3).await,
+            Err(StoreError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_shared_programs() {
+        let store = Store::in_memory().await.unwrap();
+        let shared = store
+            .share_program("abc", "qasm", b"qreg q[1];")
+            .await
+            .unwrap();
+        assert_eq!(shared.content, b"qreg q[1];");
+        assert_eq!(shared.result, None);
+        assert_eq!(
+            store
+                .share_program("abc", "qasm", b"qreg q[1];")
+                .await
+                .unwrap(),
+            shared
+        );
+        assert!(matches!(
+            store.share_program("abc", "qasm", b"other").await,
+            Err(StoreError::Conflict(_))
+        ));
+        assert!(matches!(
+            store.share_program("abc", "program", b"qreg q[1];").await,
+            Err(StoreError::Conflict(_))
+        ));
+
+        store.cache_share_result("abc", "{}").await.unwrap();
+        store.cache_share_result("abc", "[]").await.unwrap();
+        assert_eq!(
+            store.shared_program("abc").await.unwrap().result.as_deref(),
+            Some("{}")
+        );
+        assert!(matches!(
+            store.shared_program("xyz").await,
+            Err(StoreError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unknown_databases() {
+        assert!(matches!(
+            Store::connect("mysql://localhost/db").await,
+            Err(StoreError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,212 @@
+//! Simulation that reports each instruction as it runs, for animating a
+//! circuit while it executes.
+
+use serde::Serialize;
+
+use crate::{
+    circuit::{Circuit, Instruction, Operation},
+    interpreter::{Execution, SimulationOptions, SimulationResult},
+    precision::Precision,
+    quantum_register::QuantumRegister,
+    simulator::{BackendKind, Simulator},
+    sparse_register::SparseRegister,
+};
+
+/// Widest register whose state vector is sent with every step.
+pub const MAX_STREAMED_STATE_QUBITS: usize = 10;
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum StreamEvent {
+    #[serde(rename_all = "camelCase")]
+    Start {
+        steps: usize,
+        qubits: Vec<String>,
+        num_clbits: usize,
+    },
+    Step {
+        index: usize,
+        instruction: Instruction,
+        /// False when the instruction's condition did not hold.
+        executed: bool,
+        /// Outcome of each measured qubit, in the instruction's order.
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        outcomes: Vec<u8>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output: Option<String>,
+        /// Amplitudes as `[re, im]` with qubit 0 as the least significant
+        /// bit, for registers of up to [`MAX_STREAMED_STATE_QUBITS`].
+        #[serde(skip_serializing_if = "Option::is_none")]
+        state: Option<Vec<[f64; 2]>>,
+    },
+    Done {
+        result: SimulationResult,
+    },
+    Cancelled {
+        step: usize,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// Runs a circuit and hands an event to `emit` before the first instruction,
+/// after each one and at the end. The run is cancelled as soon as `emit`
+/// returns false.
+pub fn stream_circuit(
+    circuit: &Circuit,
+    options: SimulationOptions,
+    emit: impl FnMut(StreamEvent) -> bool,
+) {
+    match (options.backend, options.precision) {
+        (BackendKind::Dense, Precision::Single) => {
+            stream::<QuantumRegister<f32>>(circuit, options, emit)
+        }
+        (BackendKind::Dense, Precision::Double) => {
+            stream::<QuantumRegister<f64>>(circuit, options, emit)
+        }
+        (BackendKind::Sparse, Precision::Single) => {
+            stream::<SparseRegister<f32>>(circuit, options, emit)
+        }
+        (BackendKind::Sparse, Precision::Double) => {
+            stream::<SparseRegister<f64>>(circuit, options, emit)
+        }
+    }
+}
+
+fn stream<S: Simulator>(
+    circuit: &Circuit,
+    options: SimulationOptions,
+    mut emit: impl FnMut(StreamEvent) -> bool,
+) {
+    let start = StreamEvent::Start {
+        steps: circuit.instructions.len(),
+        qubits: circuit.labels(),
+        num_clbits: circuit.num_clbits(),
+    };
+    if !emit(start) {
+        return;
+    }
+    let mut execution = Execution::<S>::new(circuit);
+    loop {
+        let index = execution.position;
+        let printed = execution.output.len();
+        let Some(executed) = execution.step(circuit, options) else {
+            break;
+        };
+        let instruction = &circuit.instructions[index];
+        let outcomes = match instruction.operation {
+            Operation::Measure { .. } if executed => instruction
+                .qubits
+                .iter()
+                .map(|&qubit| u8::from(execution.register.probability_of_one(qubit) > 0.5))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let changes_state = executed
+            && matches!(
+                instruction.operation,
+                Operation::Gate { .. } | Operation::Measure { .. } | Operation::Reset
+            );
+        let event = StreamEvent::Step {
+            index,
+            instruction: instruction.clone(),
+            executed,
+            outcomes,
+            output: execution.output.get(printed).cloned(),
+            state: (changes_state && circuit.qubits.len() <= MAX_STREAMED_STATE_QUBITS)
+                .then(|| state_vector(&execution.register)),
+        };
+        if !emit(event) {
+            emit(StreamEvent::Cancelled {
+                step: execution.position,
+            });
+            return;
+        }
+    }
+    let (_, result) = execution.finish(circuit);
+    emit(StreamEvent::Done { result });
+}
+
+fn state_vector<S: Simulator>(register: &S) -> Vec<[f64; 2]> {
+    let mut state = vec![[0.0; 2]; 1 << register.num_qubits()];
+    for (index, amplitude) in register.amplitudes() {
+        state[index] = [amplitude.re, amplitude.im];
+    }
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lower::lower_program, qasm::parse_qasm};
+
+    fn circuit(source: &str) -> Circuit {
+        let program = parse_qasm(&format!(
+            "OPENQASM 2.0;\ninclude \"qelib1.inc\";\n{}",
+            source
+        ))
+        .unwrap();
+        lower_program(&program)
+    }
+
+    #[test]
+    fn test_events() {
+        let circuit = circuit(
+            "qreg q[2];\ncreg c[2];\nx q[0];\nmeasure q -> c;\nif(c==1) x q[1];\nif(c==0) x q[1];",
+        );
+        let mut events = Vec::new();
+        stream_circuit(&circuit, SimulationOptions::default(), |event| {
+            events.push(event);
+            true
+        });
+        assert_eq!(events.len(), 1 + circuit.instructions.len() + 1);
+        assert!(matches!(events[0], StreamEvent::Start { steps: 4, .. }));
+        let StreamEvent::Step { state, .. } = &events[1] else {
+            panic!("expected a step");
+        };
+        assert_eq!(state.as_ref().unwrap()[1], [1.0, 0.0]);
+        let StreamEvent::Step { outcomes, .. } = &events[2] else {
+            panic!("expected a step");
+        };
+        assert_eq!(outcomes, &[1, 0]);
+        let executed: Vec<bool> = events[3..5]
+            .iter()
+            .map(|event| matches!(event, StreamEvent::Step { executed: true, .. }))
+            .collect();
+        assert_eq!(executed, [true, false]);
+        let StreamEvent::Done { result } = &events[5] else {
+            panic!("expected the result");
+        };
+        assert_eq!(result.memory["c"], "01");
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let circuit = circuit("qreg q[1];\nx q[0];\nx q[0];\nx q[0];");
+        let mut events = Vec::new();
+        stream_circuit(&circuit, SimulationOptions::default(), |event| {
+            let keep_going = !matches!(event, StreamEvent::Step { index: 1, .. });
+            events.push(event);
+            keep_going
+        });
+        assert!(matches!(
+            events.last(),
+            Some(StreamEvent::Cancelled { step: 2 })
+        ));
+        assert_eq!(events.len(), 4);
+    }
+
+    #[test]
+    fn test_wide_registers_leave_out_the_state() {
+        let circuit = circuit("qreg q[11];\nh q[0];");
+        let mut states = Vec::new();
+        stream_circuit(&circuit, SimulationOptions::default(), |event| {
+            if let StreamEvent::Step { state, .. } = event {
+                states.push(state);
+            }
+            true
+        });
+        assert_eq!(states, [None]);
+    }
+}
@@ -0,0 +1,349 @@
+//! Counters and histograms of the server's load, rendered in the Prometheus
+//! text exposition format.
+
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{
+    circuit::{Circuit, Operation, ProgramErrorKind},
+    handler::AppState,
+    simulator::BackendKind,
+};
+
+const SECONDS_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0];
+const QUBITS_BUCKETS: &[f64] = &[1.0, 2.0, 4.0, 8.0, 12.0, 16.0, 20.0, 24.0, 32.0, 48.0, 64.0];
+const SHOTS_BUCKETS: &[f64] = &[10.0, 100.0, 1_000.0, 10_000.0, 100_000.0, 1_000_000.0];
+
+/// Why a request or a simulation failed, as counted by
+/// `quanvi_errors_total`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorKind {
+    /// The program could not be read.
+    Parse,
+    /// An instruction of the program failed, by the kind of mistake.
+    Program(ProgramErrorKind),
+    /// The request went over one of the limits, named as in `LimitError`.
+    Limit(&'static str),
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::Parse => write!(f, "parse"),
+            ErrorKind::Program(kind) => write!(f, "program_{}", kind),
+            ErrorKind::Limit(limit) => write!(f, "limit_{}", limit),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Observations at or below each bound, not yet accumulated.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|&bound| value <= bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}le=\"{}\"}} {}",
+                name, labels, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let labels = labels.trim_end_matches(',');
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Debug)]
+struct Recorded {
+    /// Requests by method, route and status.
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// Simulation time by backend.
+    simulation_seconds: BTreeMap<String, Histogram>,
+    qubits: Histogram,
+    shots: Histogram,
+    errors: BTreeMap<ErrorKind, u64>,
+}
+
+/// What the server has done since it started.
+#[derive(Debug)]
+pub struct Telemetry {
+    recorded: Mutex<Recorded>,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Telemetry {
+            recorded: Mutex::new(Recorded {
+                requests: BTreeMap::new(),
+                simulation_seconds: BTreeMap::new(),
+                qubits: Histogram::new(QUBITS_BUCKETS),
+                shots: Histogram::new(SHOTS_BUCKETS),
+                errors: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+impl Telemetry {
+    /// Counts a response. `route` is the route's pattern, such as
+    /// `/api/files/:id`, so that IDs do not each get their own series.
+    pub fn record_request(&self, method: &str, route: &str, status: u16) {
+        let mut recorded = self.recorded.lock().unwrap();
+        *recorded
+            .requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default() += 1;
+    }
+
+    /// Records a finished simulation. `shots` is given for simulations that
+    /// sample expectation values.
+    pub fn record_simulation(
+        &self,
+        backend: BackendKind,
+        qubits: usize,
+        shots: Option<usize>,
+        elapsed: Duration,
+    ) {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded
+            .simulation_seconds
+            .entry(backend.to_string())
+            .or_insert_with(|| Histogram::new(SECONDS_BUCKETS))
+            .observe(elapsed.as_secs_f64());
+        recorded.qubits.observe(qubits as f64);
+        if let Some(shots) = shots {
+            recorded.shots.observe(shots as f64);
+        }
+    }
+
+    pub fn record_errors(&self, kind: ErrorKind, count: usize) {
+        if count > 0 {
+            *self
+                .recorded
+                .lock()
+                .unwrap()
+                .errors
+                .entry(kind)
+                .or_default() += count as u64;
+        }
+    }
+
+    /// Counts the instructions of a circuit that report a program error.
+    pub fn record_program_errors(&self, circuit: &Circuit) {
+        for instruction in &circuit.instructions {
+            if let Operation::Error { kind, .. } = instruction.operation {
+                self.record_errors(ErrorKind::Program(kind), 1);
+            }
+        }
+    }
+
+    /// Renders every metric, with the number of open step-by-step sessions
+    /// as a gauge.
+    pub fn render(&self, active_sessions: usize) -> String {
+        let recorded = self.recorded.lock().unwrap();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "quanvi_http_requests_total",
+            "counter",
+            "HTTP requests by method, route and status.",
+        );
+        for ((method, route, status), count) in &recorded.requests {
+            let _ = writeln!(
+                out,
+                "quanvi_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape(method),
+                escape(route),
+                status,
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "quanvi_simulation_seconds",
+            "histogram",
+            "Time spent simulating circuits, by backend.",
+        );
+        for (backend, histogram) in &recorded.simulation_seconds {
+            histogram.render(
+                &mut out,
+                "quanvi_simulation_seconds",
+                &format!("backend=\"{}\",", backend),
+            );
+        }
+
+        header(
+            &mut out,
+            "quanvi_simulation_qubits",
+            "histogram",
+            "Qubits of the simulated circuits.",
+        );
+        recorded
+            .qubits
+            .render(&mut out, "quanvi_simulation_qubits", "");
+
+        header(
+            &mut out,
+            "quanvi_simulation_shots",
+            "histogram",
+            "Shots per term of simulations that sample expectation values.",
+        );
+        recorded
+            .shots
+            .render(&mut out, "quanvi_simulation_shots", "");
+
+        header(
+            &mut out,
+            "quanvi_errors_total",
+            "counter",
+            "Errors by kind: parse, the kind of program error or the limit that was exceeded.",
+        );
+        for (kind, count) in &recorded.errors {
+            let _ = writeln!(out, "quanvi_errors_total{{kind=\"{}\"}} {}", kind, count);
+        }
+
+        header(
+            &mut out,
+            "quanvi_active_sessions",
+            "gauge",
+            "Open step-by-step simulation sessions.",
+        );
+        let _ = writeln!(out, "quanvi_active_sessions {}", active_sessions);
+        out
+    }
+}
+
+/// Counts each response by the route it matched, or `unmatched`.
+pub async fn count_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+    let response = next.run(request).await;
+    state
+        .telemetry
+        .record_request(&method, &route, response.status().as_u16());
+    response
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let telemetry = Telemetry::default();
+        telemetry.record_request("POST", "/api/", 200);
+        telemetry.record_request("POST", "/api/", 200);
+        telemetry.record_request("GET", "/api/files/:id", 404);
+        telemetry.record_simulation(BackendKind::Dense, 3, None, Duration::from_millis(20));
+        telemetry.record_simulation(BackendKind::Sparse, 40, Some(1000), Duration::from_secs(2));
+        telemetry.record_errors(ErrorKind::Limit("qubits"), 1);
+        telemetry.record_errors(ErrorKind::Program(ProgramErrorKind::UnknownGate), 2);
+        telemetry.record_errors(ErrorKind::Parse, 0);
+
+        let text = telemetry.render(4);
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in [
+            "quanvi_http_requests_total{method=\"POST\",route=\"/api/\",status=\"200\"} 2",
+            "quanvi_http_requests_total{method=\"GET\",route=\"/api/files/:id\",status=\"404\"} 1",
+            "quanvi_simulation_seconds_bucket{backend=\"dense\",le=\"0.01\"} 0",
+            "quanvi_simulation_seconds_bucket{backend=\"dense\",le=\"0.05\"} 1",
+            "quanvi_simulation_seconds_bucket{backend=\"sparse\",le=\"+Inf\"} 1",
+            "quanvi_simulation_seconds_count{backend=\"sparse\"} 1",
+            "quanvi_simulation_qubits_bucket{le=\"4\"} 1",
+            "quanvi_simulation_qubits_bucket{le=\"64\"} 2",
+            "quanvi_simulation_qubits_sum 43",
+            "quanvi_simulation_shots_count 1",
+            "quanvi_errors_total{kind=\"program_unknown_gate\"} 2",
+            "quanvi_errors_total{kind=\"limit_qubits\"} 1",
+            "# TYPE quanvi_active_sessions gauge",
+            "quanvi_active_sessions 4",
+        ] {
+            assert!(lines.contains(&expected), "missing {}\n{}", expected, text);
+        }
+        assert!(!text.contains("kind=\"parse\""));
+    }
+
+    #[test]
+    fn test_program_errors() {
+        let telemetry = Telemetry::default();
+        let mut circuit = Circuit::default();
+        circuit.instructions = vec![
+            crate::circuit::Instruction::error(ProgramErrorKind::BadIndex, "a".to_string()),
+            crate::circuit::Instruction::gate("hadamard", vec![], vec![0]),
+            crate::circuit::Instruction::error(ProgramErrorKind::BadIndex, "b".to_string()),
+        ];
+        telemetry.record_program_errors(&circuit);
+        assert!(telemetry
+            .render(0)
+            .contains("quanvi_errors_total{kind=\"program_bad_index\"} 2"));
+    }
+
+    #[test]
+    fn test_label_values_are_escaped() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
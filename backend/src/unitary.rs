@@ -0,0 +1,290 @@
+use std::sync::atomic::AtomicBool;
+
+use nalgebra::{Complex, DMatrix, DVector};
+use serde::Serialize;
+
+use crate::{
+    analysis,
+    circuit::{Circuit, Operation},
+    gate::{Gate, MatrixGate},
+    limits::check_cancelled,
+    lower::lower_program,
+    models::ProgramNode,
+    quantum_register::QuantumRegister,
+    simulator::Simulator,
+};
+
+/// Widest circuit whose unitary is built; a 10-qubit unitary already has
+/// about a million entries.
+pub const MAX_UNITARY_QUBITS: usize = 10;
+
+/// Gates applied to register positions, in program order.
+pub type GateSequence = Vec<(DMatrix<Complex<f64>>, Vec<usize>)>;
+
+#[derive(Serialize, Debug)]
+pub struct UnitaryResult {
+    /// Qubit names in declaration order; the first one is the most
+    /// significant bit of the row and column index.
+    pub qubits: Vec<String>,
+    pub real: Vec<Vec<f64>>,
+    pub imag: Vec<Vec<f64>>,
+}
+
+/// Composes the gates directly into the `2^n x 2^n` unitary of the circuit.
+///
+/// Uses the register's ordering, i.e. qubit 0 is the least significant bit.
+pub fn compose_gates(
+    num_qubits: usize,
+    gates: &GateSequence,
+) -> Result<DMatrix<Complex<f64>>, String> {
+    compose_gates_cancellable(num_qubits, gates, &AtomicBool::new(false))
+}
+
+/// Composes the gates unless `cancelled` is set before they are all
+/// applied, which is checked between gates.
+pub fn compose_gates_cancellable(
+    num_qubits: usize,
+    gates: &GateSequence,
+    cancelled: &AtomicBool,
+) -> Result<DMatrix<Complex<f64>>, String> {
+    check_width(num_qubits)?;
+    let dim = 1 << num_qubits;
+
+    let mut columns = Vec::with_capacity(dim);
+    for input in 0..dim {
+        let mut state = DVector::zeros(dim);
+        state[input] = Complex::new(1.0, 0.0);
+        let mut register = QuantumRegister::<f64>::from_state(state);
+        for (matrix, targets) in gates {
+            check_cancelled(cancelled)?;
+            register.apply_gate(matrix, targets);
+        }
+        columns.push(register.state().clone());
+    }
+
+    Ok(DMatrix::from_columns(&columns))
+}
+
+/// Simulates the gates of a measurement-free program on every basis input
+/// and collects the outputs as the columns of its unitary.
+///
+/// Qubits are ordered by declaration with the first declared qubit as the
+/// most significant bit, the same convention gate matrices use. The initial
+/// amplitudes in `create` statements are ignored since the unitary acts on
+/// all inputs.
+pub fn program_unitary(program: &ProgramNode) -> Result<(Vec<String>, MatrixGate), String> {
+    circuit_unitary(&lower_program(program))
+}
+
+pub fn circuit_unitary(circuit: &Circuit) -> Result<(Vec<String>, MatrixGate), String> {
+    circuit_unitary_cancellable(circuit, &AtomicBool::new(false))
+}
+
+/// Builds the unitary of a circuit unless `cancelled` is set first.
+pub fn circuit_unitary_cancellable(
+    circuit: &Circuit,
+    cancelled: &AtomicBool,
+) -> Result<(Vec<String>, MatrixGate), String> {
+    let qubits = circuit.labels();
+    let mut gates: GateSequence = vec![];
+    for instruction in &circuit.instructions {
+        let label = |index: usize| &qubits[instruction.qubits[index]];
+        match &instruction.operation {
+            Operation::Error { message, .. } => return Err(message.clone()),
+            Operation::Measure { .. } | Operation::Reset => {
+                return Err(format!(
+                    "Cannot build a unitary: program measures '{}'",
+                    label(0)
+                ));
+            }
+            Operation::Gate { name, .. } if instruction.condition.is_some() => {
+                return Err(format!(
+                    "Cannot build a unitary: gate {} is classically controlled",
+                    name
+                ));
+            }
+            Operation::Gate { .. } => {
+                if let Some(matrix) = instruction.matrix() {
+                    gates.push((matrix, instruction.qubits.clone()));
+                }
+            }
+            // Instructions that only inspect the state do not change the unitary.
+            Operation::Barrier
+            | Operation::Display { .. }
+            | Operation::Expect { .. }
+            | Operation::Print { .. } => {}
+        }
+    }
+
+    let unitary = compose_gates_cancellable(qubits.len(), &gates, cancelled)?;
+    Ok((qubits, MatrixGate::new(to_msb_first(&unitary))))
+}
+
+/// Whether two matrices are equal up to a global phase, entry by entry
+/// within `tolerance`.
+pub fn equal_up_to_phase(
+    a: &DMatrix<Complex<f64>>,
+    b: &DMatrix<Complex<f64>>,
+    tolerance: f64,
+) -> bool {
+    if a.shape() != b.shape() {
+        return false;
+    }
+    // Align the phases on the largest entry of `a`.
+    let Some((index, largest)) = a
+        .iter()
+        .enumerate()
+        .max_by(|x, y| x.1.norm().total_cmp(&y.1.norm()))
+    else {
+        return true;
+    };
+    if largest.norm() < tolerance {
+        return b.iter().all(|entry| entry.norm() < tolerance);
+    }
+    let phase = b[index] / largest;
+    if (phase.norm() - 1.0).abs() > tolerance {
+        return false;
+    }
+    a.iter()
+        .zip(b.iter())
+        .all(|(x, y)| (x * phase - y).norm() < tolerance)
+}
+
+pub fn unitary_result(qubits: Vec<String>, gate: &dyn Gate) -> UnitaryResult {
+    let matrix = gate.matrix_representation();
+    UnitaryResult {
+        qubits,
+        real: analysis::matrix_rows(&matrix, |c| c.re),
+        imag: analysis::matrix_rows(&matrix, |c| c.im),
+    }
+}
+
+fn check_width(num_qubits: usize) -> Result<(), String> {
+    if num_qubits > MAX_UNITARY_QUBITS {
+        Err(format!(
+            "Cannot build a unitary for {} qubits: at most {} are supported",
+            num_qubits, MAX_UNITARY_QUBITS
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Reorders a unitary from register ordering (qubit 0 least significant)
+/// to declaration ordering (qubit 0 most significant).
+fn to_msb_first(matrix: &DMatrix<Complex<f64>>) -> DMatrix<Complex<f64>> {
+    let width = matrix.nrows().trailing_zeros();
+    let reverse = |index: usize| {
+        if width == 0 {
+            index
+        } else {
+            index.reverse_bits() >> (usize::BITS - width)
+        }
+    };
+    DMatrix::from_fn(matrix.nrows(), matrix.ncols(), |row, col| {
+        matrix[(reverse(row), reverse(col))]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gate::{CNot, Hadamard, PauliX};
+    use crate::models::{ComplexArrayNode, Expression, NodeType, StatementNode};
+    use float_cmp::assert_approx_eq;
+
+    fn create(identifier: &str) -> StatementNode {
+        StatementNode::CreateStatement {
+            identifier: identifier.to_string(),
+            complex_array: ComplexArrayNode {
+                r#type: NodeType::ComplexArray,
+                values: vec![
+                    Expression::RealNumber { value: 1.0 },
+                    Expression::RealNumber { value: 0.0 },
+                ],
+            },
+        }
+    }
+
+    fn apply(qubit: &str, gate: &str) -> StatementNode {
+        StatementNode::ApplyStatement {
+            identifier1: qubit.to_string(),
+            identifier2: gate.to_string(),
+        }
+    }
+
+    fn assert_matrix_eq(actual: &DMatrix<Complex<f64>>, expected: &DMatrix<Complex<f64>>) {
+        assert_eq!(actual.shape(), expected.shape());
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert_approx_eq!(f64, a.re, e.re, epsilon = 1e-12);
+            assert_approx_eq!(f64, a.im, e.im, epsilon = 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_compose_matches_gate_matrix() {
+        let cnot = CNot::new().matrix_representation();
+        // Register ordering: targets[0] = qubit 1 is the control, which is
+        // the most significant bit, so the composition is the textbook CNOT.
+        let unitary = compose_gates(2, &vec![(cnot.clone(), vec![1, 0])]).unwrap();
+        assert_matrix_eq(&unitary, &cnot);
+    }
+
+    #[test]
+    fn test_compose_hadamard_sandwich() {
+        let h = Hadamard::new().matrix_representation();
+        let x = PauliX::new().matrix_representation();
+        let z = crate::gate::PauliZ::new().matrix_representation();
+
+        let unitary =
+            compose_gates(1, &vec![(h.clone(), vec![0]), (x, vec![0]), (h, vec![0])]).unwrap();
+        assert_matrix_eq(&unitary, &z);
+    }
+
+    #[test]
+    fn test_cancelled_unitary_stops() {
+        let h = Hadamard::new().matrix_representation();
+        let cancelled = AtomicBool::new(true);
+        assert!(compose_gates_cancellable(1, &vec![(h, vec![0])], &cancelled).is_err());
+    }
+
+    #[test]
+    fn test_program_unitary_orders_qubits_by_declaration() {
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![create("a"), create("b"), apply("a", "pauliX")],
+        };
+
+        let (qubits, gate) = program_unitary(&program).unwrap();
+        let matrix = gate.matrix_representation();
+
+        assert_eq!(qubits, vec!["a", "b"]);
+        // X on the most significant qubit maps |00> to |10>.
+        assert_eq!(matrix[(0b10, 0b00)], Complex::new(1.0, 0.0));
+        assert_eq!(matrix[(0b01, 0b11)], Complex::new(1.0, 0.0));
+        assert_eq!(gate.num_qubits(), 2);
+    }
+
+    #[test]
+    fn test_program_unitary_rejects_measurement() {
+        let program = ProgramNode {
+            r#type: NodeType::Program,
+            statements: vec![
+                create("a"),
+                StatementNode::MeasureStatement {
+                    identifier: "a".to_string(),
+                },
+            ],
+        };
+
+        assert_eq!(
+            program_unitary(&program).err().unwrap(),
+            "Cannot build a unitary: program measures 'a'"
+        );
+    }
+
+    #[test]
+    fn test_unitary_width_limit() {
+        assert!(compose_gates(MAX_UNITARY_QUBITS + 1, &vec![]).is_err());
+    }
+}